@@ -1,9 +1,16 @@
-use golem_llm::error::{error_code_from_status, from_event_source_error, from_reqwest_error};
+use golem_llm::cache::cache_scope;
+use golem_llm::config::{max_request_bytes, user_agent};
+use golem_llm::error::{error_from_status, from_event_source_error, from_reqwest_error};
 use golem_llm::event_source::EventSource;
-use golem_llm::golem::llm::llm::Error;
+use golem_llm::fixtures::{response_fixtures, with_response_fixtures, ResponseFixtures};
+use golem_llm::golem::llm::llm::{Config, Error, ErrorCode};
+use golem_llm::lenient::{integer, optional_integer, parse_response_json};
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{pre_send_body, RawResponse};
+use golem_llm::request::check_request_size;
 use log::trace;
-use reqwest::header::HeaderValue;
-use reqwest::{Client, Method, Response};
+use reqwest::header::{HeaderValue, USER_AGENT};
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Response, StatusCode};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -11,29 +18,70 @@ use std::fmt::Debug;
 
 const BASE_URL: &str = "https://api.anthropic.com";
 
+/// The largest request body the Anthropic Messages API accepts
+const MAX_REQUEST_BYTES: usize = 32 * 1024 * 1024;
+
 /// The Anthropic API client for creating model responses.
 pub struct MessagesApi {
     api_key: String,
+    user_agent: String,
+    max_request_bytes: Option<usize>,
+    response_fixtures: Option<ResponseFixtures>,
     client: Client,
 }
 
 impl MessagesApi {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(api_key: String, config: &Config) -> Self {
         let client = Client::builder()
             .build()
             .expect("Failed to initialize HTTP client");
-        Self { api_key, client }
+        Self {
+            api_key,
+            user_agent: user_agent(config, "anthropic"),
+            max_request_bytes: max_request_bytes(config, Some(MAX_REQUEST_BYTES)),
+            response_fixtures: response_fixtures(config),
+            client,
+        }
     }
 
-    pub fn send_messages(&self, request: MessagesRequest) -> Result<MessagesResponse, Error> {
-        trace!("Sending request to Anthropic API: {request:?}");
+    /// Identifies the server and credentials of this client in response cache keys
+    pub fn cache_scope(&self) -> String {
+        cache_scope(BASE_URL, &self.api_key)
+    }
 
-        let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/v1/messages"))
+    /// Starts a request to the Anthropic API, authenticated with the API key and API version header
+    fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .header(USER_AGENT, &self.user_agent)
             .header("anthropic-version", "2023-06-01")
             .header("x-api-key", &self.api_key)
-            .json(&request)
+    }
+
+    pub fn send_messages(
+        &self,
+        request: MessagesRequest,
+    ) -> Result<RawResponse<MessagesResponse>, Error> {
+        trace!("Sending request to Anthropic API: {}", Redacted(&request));
+        let body = pre_send_body(&request)?;
+        check_request_size(self.max_request_bytes, &body)?;
+
+        with_response_fixtures(self.response_fixtures.as_ref(), "anthropic", &body, || {
+            let response: Response = self
+                .request(Method::POST, format!("{BASE_URL}/v1/messages"))
+                .json(&body)
+                .send()
+                .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+            parse_response(response)
+        })
+    }
+
+    pub fn list_models(&self) -> Result<ModelsResponse, Error> {
+        trace!("Listing models from Anthropic API");
+
+        let response: Response = self
+            .request(Method::GET, format!("{BASE_URL}/v1/models?limit=1000"))
             .send()
             .map_err(|err| from_reqwest_error("Request failed", err))?;
 
@@ -41,18 +89,17 @@ impl MessagesApi {
     }
 
     pub fn stream_send_messages(&self, request: MessagesRequest) -> Result<EventSource, Error> {
-        trace!("Sending request to Anthropic API: {request:?}");
+        trace!("Sending request to Anthropic API: {}", Redacted(&request));
+        let body = pre_send_body(&request)?;
+        check_request_size(self.max_request_bytes, &body)?;
 
         let response: Response = self
-            .client
             .request(Method::POST, format!("{BASE_URL}/v1/messages"))
-            .header("anthropic-version", "2023-06-01")
-            .header("x-api-key", &self.api_key)
             .header(
                 reqwest::header::ACCEPT,
                 HeaderValue::from_static("text/event-stream"),
             )
-            .json(&request)
+            .json(&body)
             .send()
             .map_err(|err| from_reqwest_error("Request failed", err))?;
 
@@ -104,7 +151,10 @@ pub enum Content {
     Text {
         text: String,
         #[serde(skip_serializing_if = "Option::is_none")]
-        cache_control: Option<CacheControl>, // citations
+        cache_control: Option<CacheControl>,
+        /// Sources of the text, only present in responses
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        citations: Vec<TextCitation>,
     },
     #[serde(rename = "image")]
     Image {
@@ -133,7 +183,34 @@ pub enum Content {
     // RedactedThinking
 }
 
+impl Content {
+    /// Marks the content block as the end of a prompt prefix Anthropic caches
+    pub fn set_cache_control(&mut self, value: CacheControl) {
+        match self {
+            Content::Text { cache_control, .. }
+            | Content::Image { cache_control, .. }
+            | Content::ToolUse { cache_control, .. }
+            | Content::ToolResult { cache_control, .. } => *cache_control = Some(value),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum TextCitation {
+    #[serde(rename = "web_search_result_location")]
+    WebSearchResultLocation {
+        url: String,
+        title: Option<String>,
+        cited_text: Option<String>,
+    },
+    // Locations in documents, which cannot be passed to the provider
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum CacheControl {
     #[serde(rename = "ephemeral")]
     Ephemeral,
@@ -204,6 +281,9 @@ pub struct MessagesResponse {
     pub model: String,
     pub role: Role,
     pub stop_reason: Option<StopReason>,
+    /// The stop sequence that ended the response, which is not part of its content
+    #[serde(default)]
+    pub stop_sequence: Option<String>,
     pub usage: Usage,
 }
 
@@ -217,15 +297,22 @@ pub enum StopReason {
     StopSequence,
     #[serde(rename = "tool_use")]
     ToolUse,
+    /// Any reason not known when this was written, kept as reported
+    #[serde(untagged)]
+    Other(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Usage {
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "optional_integer")]
     pub cache_creation_input_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "optional_integer")]
     pub cache_read_input_tokens: Option<u32>,
+    #[serde(default, deserialize_with = "integer")]
     pub input_tokens: u32,
+    #[serde(default, deserialize_with = "integer")]
     pub output_tokens: u32,
 }
 
@@ -249,6 +336,56 @@ pub struct ErrorResponseDetails {
     pub typ: String,
 }
 
+/// The server-sent events of a streaming response.
+///
+/// Based on https://docs.anthropic.com/en/docs/build-with-claude/streaming
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum MessagesStreamEvent {
+    #[serde(rename = "message_start")]
+    MessageStart { message: MessageStart },
+    #[serde(rename = "content_block_start")]
+    ContentBlockStart {
+        index: u64,
+        content_block: ContentBlock,
+    },
+    #[serde(rename = "content_block_delta")]
+    ContentBlockDelta {
+        index: u64,
+        delta: ContentBlockDelta,
+    },
+    #[serde(rename = "content_block_stop")]
+    ContentBlockStop { index: u64 },
+    #[serde(rename = "message_delta")]
+    MessageDelta {
+        delta: MessageDelta,
+        usage: Option<MessageDeltaUsage>,
+    },
+    #[serde(rename = "message_stop")]
+    MessageStop,
+    #[serde(rename = "error")]
+    Error { error: ErrorResponseDetails },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageStart {
+    pub id: String,
+    pub usage: Usage,
+}
+
+/// The start of a content block in a streaming response, only tool uses carry information that is
+/// not repeated in the deltas
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ContentBlock {
+    #[serde(rename = "tool_use")]
+    ToolUse { id: String, name: String },
+    #[serde(other)]
+    Other,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ContentBlockDelta {
@@ -256,29 +393,134 @@ pub enum ContentBlockDelta {
     TextDelta { text: String },
     #[serde(rename = "input_json_delta")]
     InputJsonDelta { partial_json: String },
+    #[serde(rename = "thinking_delta")]
+    ThinkingDelta { thinking: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageDelta {
+    pub stop_reason: Option<StopReason>,
+    pub stop_sequence: Option<String>,
+}
+
+/// The cumulative token counts sent at the end of a streaming response, the input tokens are
+/// only included by newer API versions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageDeltaUsage {
+    #[serde(default, deserialize_with = "optional_integer")]
+    pub input_tokens: Option<u32>,
+    #[serde(default, deserialize_with = "integer")]
+    pub output_tokens: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelsResponse {
+    pub data: Vec<Model>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub id: String,
+    pub display_name: Option<String>,
+    pub created_at: Option<String>,
 }
 
 fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
     let status = response.status();
     if status.is_success() {
         let body = response
-            .json::<T>()
-            .map_err(|err| from_reqwest_error("Failed to decode response body", err))?;
+            .text()
+            .map_err(|err| from_reqwest_error("Failed to receive response body", err))?;
+        let body = parse_response_json::<T>("Anthropic", body)?;
 
-        trace!("Received response from Anthropic API: {body:?}");
+        trace!("Received response from Anthropic API: {}", Redacted(&body));
 
         Ok(body)
     } else {
         let error_body = response
-            .json::<ErrorResponse>()
+            .text()
             .map_err(|err| from_reqwest_error("Failed to receive error response body", err))?;
 
-        trace!("Received {status} response from Anthropic API: {error_body:?}");
+        trace!(
+            "Received {status} response from Anthropic API: {}",
+            Redacted(&error_body)
+        );
 
-        Err(Error {
-            code: error_code_from_status(status),
-            message: format!("Request failed with {status}: {}", error_body.error.message),
-            provider_error_json: Some(serde_json::to_string(&error_body).unwrap()),
-        })
+        Err(error_from_response(status, error_body))
+    }
+}
+
+/// Creates the error for a failed response, keeping its raw body as the provider error, as error
+/// responses from proxies or an overloaded API are not always in Anthropic's error format
+fn error_from_response(status: StatusCode, error_body: String) -> Error {
+    let message = match serde_json::from_str::<ErrorResponse>(&error_body) {
+        Ok(error_response) => format!(
+            "Request failed with {status}: {}",
+            error_response.error.message
+        ),
+        Err(_) => format!("Request failed with {status}"),
+    };
+    error_from_status(status, message, Some(error_body))
+}
+
+/// Maps the type of an error Anthropic reports in the body of a response or as an event of a
+/// streaming response
+///
+/// Based on https://docs.anthropic.com/en/api/errors
+pub fn error_code_from_type(typ: &str) -> ErrorCode {
+    match typ {
+        "invalid_request_error" | "not_found_error" | "request_too_large" => {
+            ErrorCode::InvalidRequest
+        }
+        "authentication_error" | "permission_error" => ErrorCode::AuthenticationFailed,
+        "rate_limit_error" => ErrorCode::RateLimitExceeded,
+        "overloaded_error" => ErrorCode::Overloaded,
+        "api_error" => ErrorCode::InternalError,
+        _ => ErrorCode::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{error_from_response, MessagesApi, BASE_URL};
+    use golem_llm::golem::llm::llm::{Config, ErrorCode, Kv};
+    use reqwest::header::USER_AGENT;
+    use reqwest::{Method, StatusCode};
+
+    #[test]
+    fn overloaded_response_is_retryable() {
+        let body = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        let error = error_from_response(StatusCode::from_u16(529).unwrap(), body.to_string());
+
+        assert_eq!(error.code, ErrorCode::Overloaded);
+        assert!(error.message.ends_with(": Overloaded"));
+        assert_eq!(error.provider_error_json.as_deref(), Some(body));
+    }
+
+    #[test]
+    fn requests_carry_the_user_agent() {
+        let config = Config {
+            model: "claude-3-5-sonnet-20241022".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![Kv {
+                key: "user_agent".to_string(),
+                value: "my-app/1.0".to_string(),
+            }],
+            system: None,
+        };
+        let api = MessagesApi::new("api-key".to_string(), &config);
+
+        let request = api
+            .request(Method::POST, format!("{BASE_URL}/v1/messages"))
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[USER_AGENT], "my-app/1.0");
     }
 }
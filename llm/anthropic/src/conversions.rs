@@ -1,19 +1,86 @@
 use crate::client::{
-    Content, ImageSource as ClientImageSource, MediaType, MessagesRequest, MessagesRequestMetadata,
-    MessagesResponse, StopReason, Tool, ToolChoice,
+    CacheControl, Content, ImageSource as ClientImageSource, MediaType, MessagesRequest,
+    MessagesRequestMetadata, MessagesResponse, ModelsResponse, StopReason, TextCitation, Tool,
+    ToolChoice,
 };
 use base64::{engine::general_purpose, Engine as _};
+use golem_llm::capabilities::capabilities_by_prefix;
+use golem_llm::config::{
+    max_tokens, parallel_tool_calls, provider_option, reject_invalid_certs_option, temperature,
+};
+use golem_llm::context::{fit_context, fit_max_tokens};
+use golem_llm::error::unsupported;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason,
-    ImageReference, ImageSource, ImageUrl, Message, ResponseMetadata, Role, ToolCall,
-    ToolDefinition, ToolResult, Usage,
+    CapabilitySet, ChatEvent, Citation, CompleteResponse, Config, ContentPart, Error, ErrorCode,
+    FinishReason, ImageReference, ImageSource, ImageUrl, Message, ModelInfo, ResponseMetadata,
+    Role, ToolCall, ToolDefinition, ToolResult, Usage,
+};
+use golem_llm::image::{
+    download_image, image_transport, transport_images, unsupported_image_mime_type,
+    validate_image_limits, ImageLimits,
+};
+use golem_llm::model_alias::resolve_model_alias;
+use golem_llm::request::with_system_prompt;
+use golem_llm::stop_sequence::limit_stop_sequences;
+use golem_llm::structured_output::response_schema;
+use golem_llm::tool_calls::tool_image_description;
+use golem_llm::validation::{validate_messages, SystemMessagePlacement};
+use log::trace;
+use std::collections::{HashMap, HashSet};
+
+/// The Anthropic models the provider-agnostic `small` and `large` model aliases resolve to
+const MODEL_ALIASES: &[(&str, &str)] = &[
+    ("small", "claude-3-5-haiku-latest"),
+    ("large", "claude-3-7-sonnet-latest"),
+];
+
+/// The maximum number of `cache_control` breakpoints Anthropic accepts in a request
+const MAX_CACHE_BREAKPOINTS: usize = 4;
+
+/// The temperature Anthropic samples with when none is given
+const DEFAULT_TEMPERATURE: f32 = 1.0;
+
+/// The image limits of the Anthropic API: 100 images per request, each at most 5 MB
+const IMAGE_LIMITS: ImageLimits = ImageLimits {
+    max_count: Some(100),
+    max_bytes: Some(5 * 1024 * 1024),
 };
-use std::collections::HashMap;
 
 pub fn messages_to_request(
     messages: Vec<Message>,
     config: Config,
 ) -> Result<MessagesRequest, Error> {
+    let config = resolve_model_alias(config, MODEL_ALIASES);
+    let breakpoints = cache_breakpoints(&config, messages.len())?;
+    let message_count = messages.len();
+    let messages = with_system_prompt(&config, messages);
+    // Breakpoints refer to the messages as given, before the configured system prompt is inserted
+    let breakpoint_offset = messages.len() - message_count;
+    let messages = fit_context(messages, &config)?;
+    // System messages are hoisted into the top-level system prompt in order, so they may appear
+    // anywhere in the conversation
+    validate_messages(&messages, SystemMessagePlacement::Anywhere)?;
+
+    let messages = transport_images(
+        messages,
+        image_transport(&config)?,
+        true,
+        "Anthropic",
+        download_image,
+    )?;
+    validate_image_limits(&messages, IMAGE_LIMITS, "Anthropic")?;
+    if response_schema(&config)?.is_some() {
+        return Err(unsupported(
+            "The json-schema response format is not supported by the Anthropic provider",
+        ));
+    }
+    let max_tokens = fit_max_tokens(max_tokens(&config), &messages, &config);
+    let temperature = temperature(&config, DEFAULT_TEMPERATURE)?;
+    // Anthropic takes the `parallel_tool_calls` option inverted, as `disable_parallel_tool_use`
+    let disable_parallel_tool_use =
+        parallel_tool_calls(&config)?.map(|parallel_tool_calls| !parallel_tool_calls);
+    reject_invalid_certs_option(&config)?;
+    let stop = limit_stop_sequences(&config, None)?;
     let options = config
         .provider_options
         .into_iter()
@@ -21,8 +88,18 @@ pub fn messages_to_request(
         .collect::<HashMap<_, _>>();
 
     let mut anthropic_messages = Vec::new();
-    for message in &messages {
-        if message.role != Role::System {
+    let mut system_messages = Vec::new();
+    for (index, message) in messages.iter().enumerate() {
+        let mut content = message_to_content(message)?;
+        if index >= breakpoint_offset && breakpoints.contains(&(index - breakpoint_offset)) {
+            if let Some(last) = content.last_mut() {
+                last.set_cache_control(CacheControl::Ephemeral);
+            }
+        }
+
+        if message.role == Role::System {
+            system_messages.extend(content)
+        } else {
             anthropic_messages.push(crate::client::Message {
                 role: match &message.role {
                     Role::User => crate::client::Role::User,
@@ -30,19 +107,20 @@ pub fn messages_to_request(
                     Role::Tool => crate::client::Role::User,
                     Role::System => unreachable!(),
                 },
-                content: message_to_content(message),
+                content,
             })
         }
     }
 
-    let mut system_messages = Vec::new();
-    for message in &messages {
-        if message.role == Role::System {
-            system_messages.extend(message_to_content(message))
-        }
-    }
-
-    let tool_choice = config.tool_choice.map(convert_tool_choice);
+    let tool_choice = match (config.tool_choice, disable_parallel_tool_use) {
+        (Some(tool_choice), _) => Some(convert_tool_choice(tool_choice, disable_parallel_tool_use)),
+        // Anthropic only takes the flag as part of a tool choice, so it is sent with the default one
+        (None, Some(_)) => Some(convert_tool_choice(
+            "auto".to_string(),
+            disable_parallel_tool_use,
+        )),
+        (None, None) => None,
+    };
     let tools = if config.tools.is_empty() {
         None
     } else {
@@ -54,7 +132,7 @@ pub fn messages_to_request(
     };
 
     Ok(MessagesRequest {
-        max_tokens: config.max_tokens.unwrap_or(4096),
+        max_tokens: max_tokens.unwrap_or(4096),
         messages: anthropic_messages,
         model: config.model,
         metadata: options
@@ -62,37 +140,94 @@ pub fn messages_to_request(
             .map(|user_id| MessagesRequestMetadata {
                 user_id: Some(user_id.to_string()),
             }),
-        stop_sequences: config.stop_sequences,
+        stop_sequences: stop,
         stream: false,
         system: system_messages,
-        temperature: config.temperature,
+        temperature,
         tool_choice,
         tools,
         top_k: options
             .get("top_k")
-            .and_then(|top_k_s| top_k_s.parse::<u32>().ok()),
+            .map(|top_k| parse_top_k(top_k))
+            .transpose()?,
         top_p: options
             .get("top_p")
             .and_then(|top_p_s| top_p_s.parse::<f32>().ok()),
     })
 }
 
-fn convert_tool_choice(tool_name: String) -> ToolChoice {
-    if &tool_name == "auto" {
-        ToolChoice::Auto {
-            disable_parallel_tool_use: None,
-        }
-    } else if &tool_name == "none" {
-        ToolChoice::None {}
-    } else if &tool_name == "any" {
-        ToolChoice::Any {
-            disable_parallel_tool_use: None,
+/// Checks the `top_k` provider option, which limits sampling to the most likely tokens and has to
+/// be a positive integer
+fn parse_top_k(top_k: &str) -> Result<u32, Error> {
+    match top_k.trim().parse::<u32>() {
+        Ok(top_k) if top_k > 0 => Ok(top_k),
+        _ => Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("Invalid top_k option: {top_k}, expected a positive integer"),
+            provider_error_json: None,
+        }),
+    }
+}
+
+/// Gets the messages marked as prompt cache breakpoints by the `cache_breakpoints` provider option,
+/// a comma separated list of message indices. The last content block of each of them gets a
+/// `cache_control` marker, so Anthropic caches the prompt up to and including that message.
+fn cache_breakpoints(config: &Config, message_count: usize) -> Result<HashSet<usize>, Error> {
+    let Some(cache_breakpoints) = provider_option(config, "cache_breakpoints") else {
+        return Ok(HashSet::new());
+    };
+
+    let invalid = |message: String| Error {
+        code: ErrorCode::InvalidRequest,
+        message,
+        provider_error_json: None,
+    };
+    // Dropping messages to fit the context would move the marked messages
+    if provider_option(config, "max_context_tokens").is_some() {
+        return Err(invalid(
+            "The cache_breakpoints option cannot be combined with max_context_tokens".to_string(),
+        ));
+    }
+    let mut breakpoints = HashSet::new();
+    for index in cache_breakpoints.split(',') {
+        let index = index
+            .trim()
+            .parse::<usize>()
+            .map_err(|err| invalid(format!("Invalid cache_breakpoints option: {index}: {err}")))?;
+        if index >= message_count {
+            return Err(invalid(format!(
+                "Cache breakpoint {index} is out of range, there are only {message_count} messages"
+            )));
         }
-    } else {
-        ToolChoice::Tool {
+        breakpoints.insert(index);
+    }
+
+    if breakpoints.len() > MAX_CACHE_BREAKPOINTS {
+        return Err(invalid(format!(
+            "Anthropic supports at most {MAX_CACHE_BREAKPOINTS} cache breakpoints, got {}",
+            breakpoints.len()
+        )));
+    }
+    Ok(breakpoints)
+}
+
+/// Converts the tool choice of the config to Anthropic's: `auto`, `none`, `any` (also accepted as
+/// OpenAI's `required`), or the name of the tool that has to be called. The
+/// `disable_parallel_tool_use` flag limits the model to at most one tool call, and is dropped for
+/// `none`, which has no tool calls anyway.
+fn convert_tool_choice(tool_name: String, disable_parallel_tool_use: Option<bool>) -> ToolChoice {
+    match tool_name.as_str() {
+        "auto" => ToolChoice::Auto {
+            disable_parallel_tool_use,
+        },
+        "none" => ToolChoice::None {},
+        "any" | "required" => ToolChoice::Any {
+            disable_parallel_tool_use,
+        },
+        _ => ToolChoice::Tool {
             name: tool_name,
-            disable_parallel_tool_use: None,
-        }
+            disable_parallel_tool_use,
+        },
     }
 }
 
@@ -102,7 +237,23 @@ pub fn process_response(response: MessagesResponse) -> ChatEvent {
 
     for content in response.content {
         match content {
-            Content::Text { text, .. } => contents.push(ContentPart::Text(text)),
+            Content::Text {
+                text, citations, ..
+            } => {
+                contents.push(ContentPart::Text(text));
+                contents.extend(citations.into_iter().filter_map(|citation| match citation {
+                    TextCitation::WebSearchResultLocation {
+                        url,
+                        title,
+                        cited_text,
+                    } => Some(ContentPart::Citation(Citation {
+                        title,
+                        url,
+                        quoted_text: cited_text,
+                    })),
+                    TextCitation::Other => None,
+                }));
+            }
             Content::Image { source, .. } => match source {
                 ClientImageSource::Url { url } => {
                     contents.push(ContentPart::Image(ImageReference::Url(ImageUrl {
@@ -157,6 +308,8 @@ pub fn process_response(response: MessagesResponse) -> ChatEvent {
             provider_id: None,
             timestamp: None,
             provider_metadata_json: None,
+            stop_sequence: response.stop_sequence,
+            tool_calls: None,
         };
 
         ChatEvent::Message(CompleteResponse {
@@ -190,6 +343,7 @@ pub fn tool_results_to_messages(
                 content: vec![Content::Text {
                     text: success.result_json,
                     cache_control: None,
+                    citations: Vec::new(),
                 }],
                 is_error: false,
             },
@@ -199,9 +353,37 @@ pub fn tool_results_to_messages(
                 content: vec![Content::Text {
                     text: error.error_message,
                     cache_control: None,
+                    citations: Vec::new(),
                 }],
                 is_error: true,
             },
+            ToolResult::Image(tool_image) => {
+                let mut content = Vec::new();
+                if let Some(description) = &tool_image.description {
+                    content.push(Content::Text {
+                        text: description.clone(),
+                        cache_control: None,
+                        citations: Vec::new(),
+                    });
+                }
+                match image_to_content(&tool_image.image) {
+                    Ok(image) => content.push(image),
+                    Err(err) => {
+                        trace!("Sending tool image as text: {}", err.message);
+                        content = vec![Content::Text {
+                            text: tool_image_description(&tool_image),
+                            cache_control: None,
+                            citations: Vec::new(),
+                        }];
+                    }
+                }
+                Content::ToolResult {
+                    tool_use_id: tool_call.id,
+                    cache_control: None,
+                    content,
+                    is_error: false,
+                }
+            }
         };
         messages.push(crate::client::Message {
             content: vec![content],
@@ -214,10 +396,11 @@ pub fn tool_results_to_messages(
 
 pub fn stop_reason_to_finish_reason(stop_reason: StopReason) -> FinishReason {
     match stop_reason {
-        StopReason::EndTurn => FinishReason::Other,
+        StopReason::EndTurn => FinishReason::Other("end_turn".to_string()),
         StopReason::MaxTokens => FinishReason::Length,
         StopReason::StopSequence => FinishReason::Stop,
         StopReason::ToolUse => FinishReason::ToolCalls,
+        StopReason::Other(stop_reason) => FinishReason::Other(stop_reason),
     }
 }
 
@@ -229,7 +412,7 @@ pub fn convert_usage(usage: crate::client::Usage) -> Usage {
     }
 }
 
-fn message_to_content(message: &Message) -> Vec<Content> {
+fn message_to_content(message: &Message) -> Result<Vec<Content>, Error> {
     let mut result = Vec::new();
 
     for content_part in &message.content {
@@ -237,37 +420,52 @@ fn message_to_content(message: &Message) -> Vec<Content> {
             ContentPart::Text(text) => result.push(Content::Text {
                 text: text.clone(),
                 cache_control: None,
+                citations: Vec::new(),
             }),
-            ContentPart::Image(image_reference) => match image_reference {
-                ImageReference::Url(image_url) => result.push(Content::Image {
-                    source: ClientImageSource::Url {
-                        url: image_url.url.clone(),
-                    },
-                    cache_control: None,
-                }),
-                ImageReference::Inline(image_source) => {
-                    let base64_data = general_purpose::STANDARD.encode(&image_source.data);
-                    let media_type = match image_source.mime_type.as_str() {
-                        "image/jpeg" => MediaType::Jpeg,
-                        "image/png" => MediaType::Png,
-                        "image/gif" => MediaType::Gif,
-                        "image/webp" => MediaType::Webp,
-                        _ => MediaType::Jpeg,
-                    };
-
-                    result.push(Content::Image {
-                        source: ClientImageSource::Base64 {
-                            data: base64_data,
-                            media_type,
-                        },
-                        cache_control: None,
-                    });
-                }
-            },
+            ContentPart::Image(image_reference) => result.push(image_to_content(image_reference)?),
+            // Citations are only returned in responses
+            ContentPart::Citation(_) => {}
         }
     }
 
-    result
+    Ok(result)
+}
+
+fn image_to_content(image_reference: &ImageReference) -> Result<Content, Error> {
+    match image_reference {
+        ImageReference::Url(image_url) => {
+            if image_url.detail.is_some() {
+                trace!("Ignoring image detail, it is not supported by the Anthropic API");
+            }
+            Ok(Content::Image {
+                source: ClientImageSource::Url {
+                    url: image_url.url.clone(),
+                },
+                cache_control: None,
+            })
+        }
+        ImageReference::Inline(image_source) => {
+            if image_source.detail.is_some() {
+                trace!("Ignoring image detail, it is not supported by the Anthropic API");
+            }
+            let media_type = match image_source.mime_type.as_str() {
+                "image/jpeg" => MediaType::Jpeg,
+                "image/png" => MediaType::Png,
+                "image/gif" => MediaType::Gif,
+                "image/webp" => MediaType::Webp,
+                other => return Err(unsupported_image_mime_type(other)),
+            };
+            let base64_data = general_purpose::STANDARD.encode(&image_source.data);
+
+            Ok(Content::Image {
+                source: ClientImageSource::Base64 {
+                    data: base64_data,
+                    media_type,
+                },
+                cache_control: None,
+            })
+        }
+    }
 }
 
 fn tool_definition_to_tool(tool: &ToolDefinition) -> Result<Tool, Error> {
@@ -285,3 +483,529 @@ fn tool_definition_to_tool(tool: &ToolDefinition) -> Result<Tool, Error> {
         }),
     }
 }
+
+pub fn process_models_response(response: ModelsResponse) -> Vec<ModelInfo> {
+    response
+        .data
+        .into_iter()
+        .map(|model| ModelInfo {
+            id: model.id,
+            context_length: None,
+            capabilities: None,
+        })
+        .collect()
+}
+
+/// The Claude 3 and later models accept images and tools, but have no JSON mode
+const CLAUDE: CapabilitySet = CapabilitySet {
+    images: true,
+    tools: true,
+    streaming: true,
+    json_mode: false,
+};
+
+const LEGACY_CLAUDE: CapabilitySet = CapabilitySet {
+    images: false,
+    tools: false,
+    streaming: true,
+    json_mode: false,
+};
+
+const MODEL_CAPABILITIES: &[(&str, CapabilitySet)] = &[
+    ("claude-2", LEGACY_CLAUDE),
+    ("claude-instant", LEGACY_CLAUDE),
+];
+
+pub fn model_capabilities(model: &str) -> CapabilitySet {
+    capabilities_by_prefix(model, MODEL_CAPABILITIES, CLAUDE)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{MessagesResponse, ModelsResponse};
+    use crate::conversions::{
+        messages_to_request, process_models_response, process_response, tool_results_to_messages,
+    };
+    use crate::AnthropicComponent;
+    use golem_llm::durability::ExtendedGuest;
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, Citation, Config, ContentPart, ErrorCode, FinishReason, ImageDetail,
+        ImageReference, ImageSource, ImageUrl, Kv, Message, ModelInfo, Role, StreamDelta, ToolCall,
+        ToolDefinition, ToolImage, ToolResult,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn models_response() {
+        let response: ModelsResponse = serde_json::from_str(
+            r#"{
+            "data": [
+                {
+                    "type": "model",
+                    "id": "claude-3-7-sonnet-20250219",
+                    "display_name": "Claude 3.7 Sonnet",
+                    "created_at": "2025-02-19T00:00:00Z"
+                }
+            ],
+            "has_more": false,
+            "first_id": "claude-3-7-sonnet-20250219",
+            "last_id": "claude-3-7-sonnet-20250219"
+        }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            process_models_response(response),
+            vec![ModelInfo {
+                id: "claude-3-7-sonnet-20250219".to_string(),
+                context_length: None,
+                capabilities: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn web_search_citations_become_citation_parts() {
+        let response: MessagesResponse = serde_json::from_str(
+            r#"{
+            "id": "msg_01",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-7-sonnet-20250219",
+            "content": [{
+                "type": "text",
+                "text": "Golem 1.3 added an agent framework.",
+                "citations": [{
+                    "type": "web_search_result_location",
+                    "url": "https://golem.cloud/blog/golem-1-3",
+                    "title": "Golem 1.3 release",
+                    "encrypted_index": "EqgfCioIARgB",
+                    "cited_text": "Golem 1.3 introduces agents"
+                }]
+            }],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 20, "output_tokens": 10}
+        }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message");
+        };
+        assert_eq!(
+            response.content,
+            vec![
+                ContentPart::Text("Golem 1.3 added an agent framework.".to_string()),
+                ContentPart::Citation(Citation {
+                    title: Some("Golem 1.3 release".to_string()),
+                    url: "https://golem.cloud/blog/golem-1-3".to_string(),
+                    quoted_text: Some("Golem 1.3 introduces agents".to_string()),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_stop_reason_is_kept_as_other() {
+        let response: MessagesResponse = serde_json::from_str(
+            r#"{
+            "id": "msg_02",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-7-sonnet-20250219",
+            "content": [{"type": "text", "text": "I can't help with that."}],
+            "stop_reason": "refusal",
+            "usage": {"input_tokens": 20, "output_tokens": 6}
+        }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message");
+        };
+        assert_eq!(
+            response.metadata.finish_reason,
+            Some(FinishReason::Other("refusal".to_string()))
+        );
+    }
+
+    #[test]
+    fn reported_stop_sequence_is_kept() {
+        let response: MessagesResponse = serde_json::from_str(
+            r#"{
+            "id": "msg_03",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-7-sonnet-20250219",
+            "content": [{"type": "text", "text": "1, 2, 3, 4"}],
+            "stop_reason": "stop_sequence",
+            "stop_sequence": "5",
+            "usage": {"input_tokens": 14, "output_tokens": 10}
+        }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message");
+        };
+        assert_eq!(response.metadata.finish_reason, Some(FinishReason::Stop));
+        assert_eq!(response.metadata.stop_sequence, Some("5".to_string()));
+    }
+
+    fn image_message(image: ImageReference) -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Image(image)],
+        }]
+    }
+
+    fn config() -> Config {
+        Config {
+            model: "claude-3-5-sonnet-latest".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![],
+            system: None,
+        }
+    }
+
+    #[test]
+    fn tool_choice_is_sent_in_anthropic_shape() {
+        let tool_choice = |tool_choice: Option<&str>, parallel_tool_calls: Option<&str>| {
+            let config = Config {
+                tools: vec![ToolDefinition {
+                    name: "get_weather".to_string(),
+                    description: None,
+                    parameters_schema: r#"{"type":"object"}"#.to_string(),
+                }],
+                tool_choice: tool_choice.map(|tool_choice| tool_choice.to_string()),
+                provider_options: parallel_tool_calls
+                    .map(|value| Kv {
+                        key: "parallel_tool_calls".to_string(),
+                        value: value.to_string(),
+                    })
+                    .into_iter()
+                    .collect(),
+                ..config()
+            };
+            let messages = vec![Message {
+                role: Role::User,
+                name: None,
+                content: vec![ContentPart::Text(
+                    "What is the weather in Berlin?".to_string(),
+                )],
+            }];
+            let request = messages_to_request(messages, config).unwrap();
+            serde_json::to_value(request).unwrap()["tool_choice"].clone()
+        };
+
+        assert_eq!(tool_choice(Some("auto"), None), json!({"type": "auto"}));
+        assert_eq!(tool_choice(Some("none"), None), json!({"type": "none"}));
+        assert_eq!(tool_choice(Some("any"), None), json!({"type": "any"}));
+        assert_eq!(tool_choice(Some("required"), None), json!({"type": "any"}));
+        assert_eq!(
+            tool_choice(Some("get_weather"), None),
+            json!({"type": "tool", "name": "get_weather"})
+        );
+        assert_eq!(
+            tool_choice(Some("any"), Some("false")),
+            json!({"type": "any", "disable_parallel_tool_use": true})
+        );
+        assert_eq!(
+            tool_choice(Some("get_weather"), Some("false")),
+            json!({"type": "tool", "name": "get_weather", "disable_parallel_tool_use": true})
+        );
+        assert_eq!(
+            tool_choice(None, Some("false")),
+            json!({"type": "auto", "disable_parallel_tool_use": true})
+        );
+        assert_eq!(
+            tool_choice(Some("auto"), Some("true")),
+            json!({"type": "auto", "disable_parallel_tool_use": false})
+        );
+        assert_eq!(tool_choice(None, None), serde_json::Value::Null);
+    }
+
+    #[test]
+    fn system_prompt_is_sent_as_top_level_system() {
+        let config = Config {
+            system: Some("You are a helpful assistant.".to_string()),
+            ..config()
+        };
+        let messages = vec![
+            Message {
+                role: Role::System,
+                name: None,
+                content: vec![ContentPart::Text("Answer in French.".to_string())],
+            },
+            Message {
+                role: Role::User,
+                name: None,
+                content: vec![ContentPart::Text("Hello".to_string())],
+            },
+        ];
+
+        let request = serde_json::to_value(messages_to_request(messages, config).unwrap()).unwrap();
+
+        assert_eq!(request["system"][0]["text"], "You are a helpful assistant.");
+        assert_eq!(request["system"][1]["text"], "Answer in French.");
+        assert_eq!(request["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(request["messages"][0]["role"], "user");
+    }
+
+    #[test]
+    fn interleaved_system_messages_are_hoisted_into_the_system_prompt() {
+        let messages = vec![
+            text_message(Role::System, "Answer in French."),
+            text_message(Role::User, "Hello"),
+            text_message(Role::Assistant, "Bonjour"),
+            text_message(Role::System, "Keep answers short."),
+            text_message(Role::User, "How are you?"),
+        ];
+
+        let request =
+            serde_json::to_value(messages_to_request(messages, config()).unwrap()).unwrap();
+
+        assert_eq!(request["system"][0]["text"], "Answer in French.");
+        assert_eq!(request["system"][1]["text"], "Keep answers short.");
+        let roles = request["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["role"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(roles, vec!["user", "assistant", "user"]);
+    }
+
+    #[test]
+    fn top_k_is_sent_to_anthropic_and_validated() {
+        let config_with_top_k = |top_k: &str| Config {
+            provider_options: vec![Kv {
+                key: "top_k".to_string(),
+                value: top_k.to_string(),
+            }],
+            ..config()
+        };
+        let messages = vec![text_message(Role::User, "Hello")];
+
+        let request = messages_to_request(messages.clone(), config_with_top_k("40")).unwrap();
+        let body = serde_json::to_value(request).unwrap();
+        assert_eq!(body["top_k"], 40);
+
+        for invalid in ["0", "-1", "a few"] {
+            let error =
+                messages_to_request(messages.clone(), config_with_top_k(invalid)).unwrap_err();
+            assert_eq!(error.code, ErrorCode::InvalidRequest);
+        }
+    }
+
+    #[test]
+    fn too_many_images_are_rejected_naming_the_limit() {
+        let image = ContentPart::Image(ImageReference::Inline(ImageSource {
+            data: vec![0x89, b'P', b'N', b'G'],
+            mime_type: "image/png".to_string(),
+            detail: None,
+        }));
+        let messages_with_images = |count: usize| {
+            vec![Message {
+                role: Role::User,
+                name: None,
+                content: vec![image.clone(); count],
+            }]
+        };
+
+        assert!(messages_to_request(messages_with_images(100), config()).is_ok());
+
+        let error = messages_to_request(messages_with_images(101), config()).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(
+            error.message,
+            "The request contains 101 images, but Anthropic accepts at most 100 images per request"
+        );
+    }
+
+    #[test]
+    fn stop_sequences_are_limited_by_the_max_stop_sequences_option() {
+        let stop_sequences = |max_stop_sequences: Option<&str>| Config {
+            stop_sequences: Some(vec!["END".to_string(), "STOP".to_string()]),
+            provider_options: max_stop_sequences
+                .map(|value| Kv {
+                    key: "max_stop_sequences".to_string(),
+                    value: value.to_string(),
+                })
+                .into_iter()
+                .collect(),
+            ..config()
+        };
+        let messages = vec![text_message(Role::User, "Count to ten.")];
+
+        let request = messages_to_request(messages.clone(), stop_sequences(None)).unwrap();
+        assert_eq!(request.stop_sequences.map(|stop| stop.len()), Some(2));
+
+        let error = messages_to_request(messages, stop_sequences(Some("1"))).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    fn text_message(role: Role, text: &str) -> Message {
+        Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        }
+    }
+
+    fn cache_breakpoints_config(cache_breakpoints: &str) -> Config {
+        Config {
+            provider_options: vec![Kv {
+                key: "cache_breakpoints".to_string(),
+                value: cache_breakpoints.to_string(),
+            }],
+            ..config()
+        }
+    }
+
+    #[test]
+    fn cache_breakpoints_are_sent_as_cache_control() {
+        let messages = vec![
+            text_message(Role::System, "Answer based on the documents."),
+            text_message(Role::User, "Document 1"),
+            text_message(Role::User, "Document 2"),
+            text_message(Role::User, "What do they have in common?"),
+        ];
+
+        let request = serde_json::to_value(
+            messages_to_request(messages.clone(), cache_breakpoints_config("0,2")).unwrap(),
+        )
+        .unwrap();
+
+        let cache_control = serde_json::json!({"type": "ephemeral"});
+        assert_eq!(request["system"][0]["cache_control"], cache_control);
+        assert!(request["messages"][0].get("cache_control").is_none());
+        assert!(request["messages"][0]["content"][0]
+            .get("cache_control")
+            .is_none());
+        assert_eq!(
+            request["messages"][1]["content"][0]["cache_control"],
+            cache_control
+        );
+        assert!(request["messages"][2]["content"][0]
+            .get("cache_control")
+            .is_none());
+
+        let mut messages = messages;
+        messages.push(text_message(Role::Assistant, "Both are about caching."));
+        let error =
+            messages_to_request(messages, cache_breakpoints_config("0,1,2,3,4")).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(error.message.contains("at most 4"));
+    }
+
+    #[test]
+    fn small_model_alias_resolves_to_an_anthropic_model() {
+        let config = Config {
+            model: "small".to_string(),
+            ..config()
+        };
+
+        let request = messages_to_request(
+            vec![Message {
+                role: Role::User,
+                name: None,
+                content: vec![ContentPart::Text("Hello".to_string())],
+            }],
+            config,
+        )
+        .unwrap();
+
+        assert_eq!(request.model, "claude-3-5-haiku-latest");
+    }
+
+    #[test]
+    fn image_detail_is_ignored() {
+        let messages = image_message(ImageReference::Url(ImageUrl {
+            url: "https://example.com/cat.png".to_string(),
+            detail: Some(ImageDetail::High),
+        }));
+
+        let request =
+            serde_json::to_value(messages_to_request(messages, config()).unwrap()).unwrap();
+
+        let image = &request["messages"][0]["content"][0];
+        assert_eq!(image["type"], "image");
+        assert_eq!(image["source"]["url"], "https://example.com/cat.png");
+        assert!(!request.to_string().contains("detail"));
+    }
+
+    #[test]
+    fn unsupported_image_mime_type() {
+        let messages = image_message(ImageReference::Inline(ImageSource {
+            data: vec![0x42, 0x4d],
+            mime_type: "image/bmp".to_string(),
+            detail: None,
+        }));
+
+        let error = messages_to_request(messages, config()).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(error.message.contains("image/bmp"));
+    }
+
+    #[test]
+    fn image_tool_result_is_sent_as_image() {
+        let tool_call = ToolCall {
+            id: "toolu_1".to_string(),
+            name: "render_chart".to_string(),
+            arguments_json: "{}".to_string(),
+        };
+        let tool_result = ToolResult::Image(ToolImage {
+            id: "toolu_1".to_string(),
+            name: "render_chart".to_string(),
+            image: ImageReference::Inline(ImageSource {
+                data: vec![0x89, 0x50, 0x4e, 0x47],
+                mime_type: "image/png".to_string(),
+                detail: None,
+            }),
+            description: Some("Revenue chart".to_string()),
+            execution_time_ms: None,
+        });
+
+        let messages =
+            serde_json::to_value(tool_results_to_messages(vec![(tool_call, tool_result)])).unwrap();
+
+        let result = &messages[1]["content"][0];
+        assert_eq!(result["type"], "tool_result");
+        assert_eq!(result["content"][0]["text"], "Revenue chart");
+        assert_eq!(result["content"][1]["type"], "image");
+        assert_eq!(result["content"][1]["source"]["media_type"], "image/png");
+        assert_eq!(result["content"][1]["source"]["data"], "iVBORw==");
+    }
+
+    #[test]
+    fn retry_prompt_is_accepted() {
+        let message = |role: Role, text: &str| Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        };
+        let original_messages = vec![
+            message(Role::System, "Answer briefly"),
+            message(Role::User, "What is the weather like?"),
+            message(Role::Assistant, "Which city?"),
+            message(Role::User, "Berlin"),
+        ];
+        let partial_result = vec![StreamDelta {
+            content: Some(vec![ContentPart::Text("It is sunny".to_string())]),
+            tool_calls: None,
+            reasoning: None,
+            choice_index: None,
+        }];
+
+        let messages = AnthropicComponent::retry_prompt(&original_messages, &partial_result);
+
+        assert!(messages_to_request(messages, config()).is_ok());
+    }
+}
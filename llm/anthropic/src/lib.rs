@@ -2,25 +2,38 @@ mod client;
 mod conversions;
 
 use crate::client::{
-    Content, ContentBlockDelta, ErrorResponse, MessagesApi, MessagesRequest, StopReason, Usage,
+    error_code_from_type, ContentBlock, ContentBlockDelta, MessagesApi, MessagesRequest,
+    MessagesStreamEvent,
 };
 use crate::conversions::{
-    convert_usage, messages_to_request, process_response, stop_reason_to_finish_reason,
-    tool_results_to_messages,
+    convert_usage, messages_to_request, model_capabilities, process_models_response,
+    process_response, stop_reason_to_finish_reason, tool_results_to_messages,
 };
+use golem_llm::cache::{cache_ttl, cached_response, response_cache_key};
 use golem_llm::chat_stream::{LlmChatStream, LlmChatStreamState};
-use golem_llm::config::with_config_key;
-use golem_llm::durability::{DurableLLM, ExtendedGuest};
+use golem_llm::concurrency::acquire_request_slot;
+use golem_llm::config::{stream_replay_file, with_config_key};
+use golem_llm::context::reserve_context_for_tool_results;
+use golem_llm::dry_run::{dry_run_response, is_dry_run, with_api_key_unless_dry_run};
+use golem_llm::durability::{retry_prompt_with_style, DurableLLM, ExtendedGuest, RetryPromptStyle};
+use golem_llm::error::{reject_empty_response, unsupported};
 use golem_llm::event_source::EventSource;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, ChatStream, Config, ContentPart, Error, ErrorCode, Guest, Message, ResponseMetadata,
-    Role, StreamDelta, StreamEvent, ToolCall, ToolResult,
+    BatchInfo, BatchRequest, BatchResult, CapabilitySet, ChatEvent, ChatStream, Config,
+    ContentPart, EmbeddingResponse, Error, Guest, ImageReference, Message, ModelInfo,
+    ResponseMetadata, StreamDelta, StreamEvent, ToolCall, ToolResult, Usage,
 };
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{include_raw_response, with_raw_response};
+use golem_llm::summarize::summarize_history;
+use golem_llm::tool_calls::reject_malformed_tool_calls;
+use golem_llm::usage::sum_usage;
 use golem_llm::LOGGING_STATE;
 use golem_rust::wasm_rpc::Pollable;
 use log::trace;
 use std::cell::{Ref, RefCell, RefMut};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::time::Duration;
 
 #[derive(Default)]
 struct JsonFragment {
@@ -33,41 +46,39 @@ struct AnthropicChatStream {
     stream: RefCell<Option<EventSource>>,
     failure: Option<Error>,
     finished: RefCell<bool>,
-    json_fragments: RefCell<HashMap<u64, JsonFragment>>,
+    json_fragments: RefCell<BTreeMap<u64, JsonFragment>>,
     response_metadata: RefCell<ResponseMetadata>,
 }
 
 impl AnthropicChatStream {
     pub fn new(stream: EventSource) -> LlmChatStream<Self> {
-        LlmChatStream::new(AnthropicChatStream {
-            stream: RefCell::new(Some(stream)),
-            failure: None,
-            finished: RefCell::new(false),
-            json_fragments: RefCell::new(HashMap::new()),
-            response_metadata: RefCell::new(ResponseMetadata {
-                finish_reason: None,
-                usage: None,
-                provider_id: None,
-                timestamp: None,
-                provider_metadata_json: None,
-            }),
-        })
+        LlmChatStream::new(Self::state(Some(stream), None))
     }
 
     pub fn failed(error: Error) -> LlmChatStream<Self> {
-        LlmChatStream::new(AnthropicChatStream {
-            stream: RefCell::new(None),
-            failure: Some(error),
+        LlmChatStream::new(Self::state(None, Some(error)))
+    }
+
+    pub fn replayed() -> LlmChatStream<Self> {
+        LlmChatStream::new(Self::state(None, None))
+    }
+
+    fn state(stream: Option<EventSource>, failure: Option<Error>) -> Self {
+        AnthropicChatStream {
+            stream: RefCell::new(stream),
+            failure,
             finished: RefCell::new(false),
-            json_fragments: RefCell::new(HashMap::new()),
+            json_fragments: RefCell::new(BTreeMap::new()),
             response_metadata: RefCell::new(ResponseMetadata {
                 finish_reason: None,
                 usage: None,
                 provider_id: None,
                 timestamp: None,
                 provider_metadata_json: None,
+                stop_sequence: None,
+                tool_calls: None,
             }),
-        })
+        }
     }
 }
 
@@ -93,143 +104,122 @@ impl LlmChatStreamState for AnthropicChatStream {
     }
 
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
-        trace!("Received raw stream event: {raw}");
-        let json: serde_json::Value = serde_json::from_str(raw)
+        trace!("Received raw stream event: {}", Redacted(&raw));
+        let event: MessagesStreamEvent = serde_json::from_str(raw)
             .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
 
-        let typ = json
-            .as_object()
-            .and_then(|obj| obj.get("type"))
-            .and_then(|v| v.as_str());
-        match typ {
-            Some("error") => {
-                let error = serde_json::from_value::<ErrorResponse>(json)
-                    .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
-                Ok(Some(StreamEvent::Error(Error {
-                    code: ErrorCode::InternalError,
-                    message: error.error.message,
-                    provider_error_json: None,
-                })))
+        match event {
+            MessagesStreamEvent::MessageStart { message } => {
+                let mut response_metadata = self.response_metadata.borrow_mut();
+                response_metadata.provider_id = Some(message.id);
+                response_metadata.usage = Some(convert_usage(message.usage));
+                Ok(None)
             }
-            Some("content_block_start") => {
-                let index = json
-                    .as_object()
-                    .and_then(|obj| obj.get("index"))
-                    .and_then(|v| v.as_u64())
-                    .ok_or_else(|| {
-                        "Unexpected stream event format, does not have 'index' field".to_string()
-                    })?;
-
-                let raw_content_block = json
-                    .as_object()
-                    .and_then(|obj| obj.get("content_block"))
-                    .ok_or_else(|| {
-                    "Unexpected stream event format, does not have 'content_block' field"
-                        .to_string()
-                })?;
-
-                let content_block = serde_json::from_value::<Content>(raw_content_block.clone())
-                    .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
-
-                if let Content::ToolUse { id, name, .. } = content_block {
-                    self.json_fragments.borrow_mut().insert(
-                        index,
-                        JsonFragment {
-                            id,
-                            name,
-                            json: String::new(),
-                        },
-                    );
-                }
-
+            MessagesStreamEvent::ContentBlockStart {
+                index,
+                content_block: ContentBlock::ToolUse { id, name },
+            } => {
+                self.json_fragments.borrow_mut().insert(
+                    index,
+                    JsonFragment {
+                        id,
+                        name,
+                        json: String::new(),
+                    },
+                );
                 Ok(None)
             }
-            Some("content_block_delta") => {
-                let raw_delta = json
-                    .as_object()
-                    .and_then(|obj| obj.get("delta"))
-                    .ok_or_else(|| {
-                        "Unexpected stream event format, does not have 'delta' field".to_string()
-                    })?;
-                let delta = serde_json::from_value::<ContentBlockDelta>(raw_delta.clone())
-                    .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
-
-                match delta {
-                    ContentBlockDelta::TextDelta { text } => {
-                        Ok(Some(StreamEvent::Delta(StreamDelta {
-                            content: Some(vec![ContentPart::Text(text)]),
-                            tool_calls: None,
-                        })))
-                    }
-                    ContentBlockDelta::InputJsonDelta { partial_json } => {
-                        let index = json
-                            .as_object()
-                            .and_then(|obj| obj.get("index"))
-                            .and_then(|v| v.as_u64())
-                            .ok_or_else(|| {
-                                "Unexpected stream event format, does not have 'index' field"
-                                    .to_string()
-                            })?;
-
-                        let mut json_fragments = self.json_fragments.borrow_mut();
-                        let fragment = json_fragments.entry(index).or_default();
-                        fragment.json.push_str(&partial_json);
-
-                        Ok(None)
-                    }
+            MessagesStreamEvent::ContentBlockDelta { index, delta } => match delta {
+                ContentBlockDelta::TextDelta { text } => {
+                    Ok(Some(StreamEvent::Delta(StreamDelta {
+                        content: Some(vec![ContentPart::Text(text)]),
+                        tool_calls: None,
+                        reasoning: None,
+                        choice_index: None,
+                    })))
                 }
-            }
-            Some("content_block_stop") => {
-                let index = json
-                    .as_object()
-                    .and_then(|obj| obj.get("index"))
-                    .and_then(|v| v.as_u64())
-                    .ok_or_else(|| {
-                        "Unexpected stream event format, does not have 'index' field".to_string()
-                    })?;
-
-                if let Some(tool_use) = self.json_fragments.borrow_mut().remove(&index) {
+                ContentBlockDelta::InputJsonDelta { partial_json } => {
+                    let mut json_fragments = self.json_fragments.borrow_mut();
+                    let fragment = json_fragments.entry(index).or_default();
+                    fragment.json.push_str(&partial_json);
+                    Ok(None)
+                }
+                ContentBlockDelta::ThinkingDelta { thinking } => {
                     Ok(Some(StreamEvent::Delta(StreamDelta {
+                        content: None,
+                        tool_calls: None,
+                        reasoning: Some(thinking),
+                        choice_index: None,
+                    })))
+                }
+                ContentBlockDelta::Other => Ok(None),
+            },
+            MessagesStreamEvent::ContentBlockStop { index } => {
+                match self.json_fragments.borrow_mut().remove(&index) {
+                    Some(tool_use) => Ok(Some(StreamEvent::Delta(StreamDelta {
                         content: None,
                         tool_calls: Some(vec![ToolCall {
                             id: tool_use.id,
                             name: tool_use.name,
-                            arguments_json: tool_use.json,
+                            // Tools without parameters are called without any input JSON deltas
+                            arguments_json: if tool_use.json.is_empty() {
+                                "{}".to_string()
+                            } else {
+                                tool_use.json
+                            },
                         }]),
-                    })))
-                } else {
-                    Ok(None)
+                        reasoning: None,
+                        choice_index: None,
+                    }))),
+                    None => Ok(None),
                 }
             }
-            Some("message_delta") => {
-                let stop_reason = json
-                    .as_object()
-                    .and_then(|obj| obj.get("delta"))
-                    .and_then(|v| v.as_object())
-                    .and_then(|obj| obj.get("stop_reason"))
-                    .and_then(|v| serde_json::from_value::<StopReason>(v.clone()).ok());
-                let usage = json
-                    .as_object()
-                    .and_then(|obj| obj.get("usage"))
-                    .and_then(|v| serde_json::from_value::<Usage>(v.clone()).ok());
-
-                if let Some(stop_reason) = stop_reason {
-                    self.response_metadata.borrow_mut().finish_reason =
+            MessagesStreamEvent::MessageDelta { delta, usage } => {
+                let mut response_metadata = self.response_metadata.borrow_mut();
+                if let Some(stop_reason) = delta.stop_reason {
+                    response_metadata.finish_reason =
                         Some(stop_reason_to_finish_reason(stop_reason));
                 }
+                if delta.stop_sequence.is_some() {
+                    response_metadata.stop_sequence = delta.stop_sequence;
+                }
+                // The usage of the delta is cumulative, the input tokens are kept from the
+                // `message_start` event unless they are repeated here
                 if let Some(usage) = usage {
-                    self.response_metadata.borrow_mut().usage = Some(convert_usage(usage));
+                    let previous = response_metadata.usage.take();
+                    response_metadata.usage = Some(Usage {
+                        input_tokens: usage
+                            .input_tokens
+                            .or(previous.and_then(|previous| previous.input_tokens)),
+                        output_tokens: Some(usage.output_tokens),
+                        total_tokens: None,
+                    });
                 }
                 Ok(None)
             }
-            Some("message_stop") => {
-                let response_metadata = self.response_metadata.borrow().clone();
-                Ok(Some(StreamEvent::Finish(response_metadata)))
-            }
-            Some(_) => Ok(None),
-            None => Err("Unexpected stream event format, does not have 'type' field".to_string()),
+            MessagesStreamEvent::MessageStop => Ok(Some(StreamEvent::Finish(
+                self.response_metadata.borrow().clone(),
+            ))),
+            MessagesStreamEvent::Error { error } => Ok(Some(StreamEvent::Error(Error {
+                code: error_code_from_type(&error.typ),
+                message: error.message,
+                provider_error_json: Some(raw.to_string()),
+            }))),
+            MessagesStreamEvent::ContentBlockStart { .. } | MessagesStreamEvent::Other => Ok(None),
         }
     }
+
+    fn pending_finish(&self) -> ResponseMetadata {
+        self.response_metadata.borrow().clone()
+    }
+
+    fn tool_call_order(&self) -> Vec<String> {
+        self.json_fragments
+            .borrow()
+            .values()
+            .map(|fragment| fragment.id.clone())
+            .collect()
+    }
 }
 
 struct AnthropicComponent;
@@ -237,11 +227,25 @@ struct AnthropicComponent;
 impl AnthropicComponent {
     const ENV_VAR_NAME: &'static str = "ANTHROPIC_API_KEY";
 
-    fn request(client: MessagesApi, request: MessagesRequest) -> ChatEvent {
-        match client.send_messages(request) {
-            Ok(response) => process_response(response),
-            Err(err) => ChatEvent::Error(err),
+    fn request(
+        client: MessagesApi,
+        request: MessagesRequest,
+        dry_run: bool,
+        include_raw_response: bool,
+        cache_ttl: Option<Duration>,
+    ) -> ChatEvent {
+        if dry_run {
+            return dry_run_response("anthropic", &request);
         }
+
+        let cache_key = response_cache_key(cache_ttl, &client.cache_scope(), &request);
+        cached_response(cache_key, || match client.send_messages(request) {
+            Ok(response) => with_raw_response(
+                reject_malformed_tool_calls(reject_empty_response(process_response(response.body))),
+                include_raw_response.then_some(response.raw),
+            ),
+            Err(err) => ChatEvent::Error(err),
+        })
     }
 
     fn streaming_request(
@@ -261,14 +265,25 @@ impl Guest for AnthropicComponent {
 
     fn send(messages: Vec<Message>, config: Config) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
-        with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |anthropic_api_key| {
-            let client = MessagesApi::new(anthropic_api_key);
+        let dry_run = is_dry_run(&config);
+        let include_raw_response = include_raw_response(&config);
+        let cache_ttl = cache_ttl(&config);
+        with_api_key_unless_dry_run(
+            Self::ENV_VAR_NAME,
+            dry_run,
+            ChatEvent::Error,
+            |anthropic_api_key| {
+                let _permit = acquire_request_slot(&config, "anthropic", &anthropic_api_key);
+                let client = MessagesApi::new(anthropic_api_key, &config);
 
-            match messages_to_request(messages, config) {
-                Ok(request) => Self::request(client, request),
-                Err(err) => ChatEvent::Error(err),
-            }
-        })
+                match messages_to_request(messages, config) {
+                    Ok(request) => {
+                        Self::request(client, request, dry_run, include_raw_response, cache_ttl)
+                    }
+                    Err(err) => ChatEvent::Error(err),
+                }
+            },
+        )
     }
 
     fn continue_(
@@ -277,42 +292,136 @@ impl Guest for AnthropicComponent {
         config: Config,
     ) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
+        let dry_run = is_dry_run(&config);
+        let include_raw_response = include_raw_response(&config);
+        let cache_ttl = cache_ttl(&config);
 
-        with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |anthropic_api_key| {
-            let client = MessagesApi::new(anthropic_api_key);
-
-            match messages_to_request(messages, config) {
-                Ok(mut request) => {
-                    request
-                        .messages
-                        .extend(tool_results_to_messages(tool_results));
-                    Self::request(client, request)
+        with_api_key_unless_dry_run(
+            Self::ENV_VAR_NAME,
+            dry_run,
+            ChatEvent::Error,
+            |anthropic_api_key| {
+                let _permit = acquire_request_slot(&config, "anthropic", &anthropic_api_key);
+                let client = MessagesApi::new(anthropic_api_key, &config);
+
+                match messages_to_request(
+                    messages,
+                    reserve_context_for_tool_results(config, &tool_results),
+                ) {
+                    Ok(mut request) => {
+                        request
+                            .messages
+                            .extend(tool_results_to_messages(tool_results));
+                        Self::request(client, request, dry_run, include_raw_response, cache_ttl)
+                    }
+                    Err(err) => ChatEvent::Error(err),
                 }
-                Err(err) => ChatEvent::Error(err),
-            }
-        })
+            },
+        )
     }
 
     fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
         ChatStream::new(Self::unwrapped_stream(messages, config))
     }
+
+    fn embed(_inputs: Vec<String>, _config: Config) -> Result<EmbeddingResponse, Error> {
+        Err(unsupported(
+            "Embeddings are not supported by the Anthropic provider",
+        ))
+    }
+
+    fn generate_image(_prompt: String, _config: Config) -> Result<Vec<ImageReference>, Error> {
+        Err(unsupported(
+            "Image generation is not supported by the Anthropic provider",
+        ))
+    }
+
+    fn list_models(config: Config) -> Result<Vec<ModelInfo>, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        with_config_key(Self::ENV_VAR_NAME, Err, |anthropic_api_key| {
+            let client = MessagesApi::new(anthropic_api_key, &config);
+            client.list_models().map(process_models_response)
+        })
+    }
+
+    fn validate_credentials(config: Config) -> Result<(), Error> {
+        Self::list_models(config).map(|_| ())
+    }
+
+    fn capabilities(config: Config) -> CapabilitySet {
+        model_capabilities(&config.model)
+    }
+
+    fn batch_submit(_requests: Vec<BatchRequest>, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Anthropic provider",
+        ))
+    }
+
+    fn batch_status(_batch_id: String, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Anthropic provider",
+        ))
+    }
+
+    fn batch_results(_batch_id: String, _config: Config) -> Result<Vec<BatchResult>, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Anthropic provider",
+        ))
+    }
+
+    fn summarize_history(
+        messages: Vec<Message>,
+        config: Config,
+        keep_last_n: u32,
+    ) -> Result<Vec<Message>, Error> {
+        summarize_history(messages, config, keep_last_n, Self::send)
+    }
+
+    fn sum_usage(usages: Vec<Usage>) -> Usage {
+        sum_usage(usages)
+    }
 }
 
 impl ExtendedGuest for AnthropicComponent {
     fn unwrapped_stream(
         messages: Vec<Message>,
         config: Config,
+    ) -> LlmChatStream<AnthropicChatStream> {
+        Self::unwrapped_continue_stream(messages, Vec::new(), config)
+    }
+
+    fn unwrapped_continue_stream(
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
     ) -> LlmChatStream<AnthropicChatStream> {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
+        if stream_replay_file(&config).is_some() {
+            return AnthropicChatStream::replayed().with_stream_options(&config);
+        }
+
         with_config_key(
             Self::ENV_VAR_NAME,
             AnthropicChatStream::failed,
             |anthropic_api_key| {
-                let client = MessagesApi::new(anthropic_api_key);
-
-                match messages_to_request(messages, config) {
-                    Ok(request) => Self::streaming_request(client, request),
+                let permit = acquire_request_slot(&config, "anthropic", &anthropic_api_key);
+                let client = MessagesApi::new(anthropic_api_key, &config);
+
+                match messages_to_request(
+                    messages,
+                    reserve_context_for_tool_results(config.clone(), &tool_results),
+                ) {
+                    Ok(mut request) => {
+                        request
+                            .messages
+                            .extend(tool_results_to_messages(tool_results));
+                        Self::streaming_request(client, request)
+                            .with_request_permit(permit)
+                            .with_stream_options(&config)
+                    }
                     Err(err) => AnthropicChatStream::failed(err),
                 }
             },
@@ -320,59 +429,177 @@ impl ExtendedGuest for AnthropicComponent {
     }
 
     fn retry_prompt(original_messages: &[Message], partial_result: &[StreamDelta]) -> Vec<Message> {
-        let mut extended_messages = Vec::new();
-        extended_messages.push(Message {
-            role: Role::System,
-            name: None,
-            content: vec![
-                ContentPart::Text(
-                    "You were asked the same question previously, but the response was interrupted before completion. \
-                     Please continue your response from where you left off. \
-                     Do not include the part of the response that was already seen.".to_string()),
-            ],
-        });
-        extended_messages.push(Message {
-            role: Role::User,
-            name: None,
-            content: vec![ContentPart::Text(
-                "Here is the original question:".to_string(),
-            )],
-        });
-        extended_messages.extend_from_slice(original_messages);
-
-        let mut partial_result_as_content = Vec::new();
-        for delta in partial_result {
-            if let Some(contents) = &delta.content {
-                partial_result_as_content.extend_from_slice(contents);
-            }
-            if let Some(tool_calls) = &delta.tool_calls {
-                for tool_call in tool_calls {
-                    partial_result_as_content.push(ContentPart::Text(format!(
-                        "<tool-call id=\"{}\" name=\"{}\" arguments=\"{}\"/>",
-                        tool_call.id, tool_call.name, tool_call.arguments_json,
-                    )));
-                }
-            }
-        }
-
-        extended_messages.push(Message {
-            role: Role::User,
-            name: None,
-            content: vec![ContentPart::Text(
-                "Here is the partial response that was successfully received:".to_string(),
-            )]
-            .into_iter()
-            .chain(partial_result_as_content)
-            .collect(),
-        });
-        extended_messages
+        retry_prompt_with_style(
+            original_messages,
+            partial_result,
+            RetryPromptStyle::UserMessage,
+        )
     }
 
     fn subscribe(stream: &Self::ChatStream) -> Pollable {
         stream.subscribe()
     }
+
+    fn idle_timer(stream: &Self::ChatStream) -> Option<Pollable> {
+        Some(stream.idle_timer())
+    }
 }
 
 type DurableAnthropicComponent = DurableLLM<AnthropicComponent>;
 
 golem_llm::export_llm!(DurableAnthropicComponent with_types_in golem_llm);
+
+#[cfg(test)]
+mod tests {
+    use crate::client::error_code_from_type;
+    use crate::AnthropicChatStream;
+    use golem_llm::chat_stream::LlmChatStreamState;
+    use golem_llm::golem::llm::llm::{
+        ContentPart, Error, ErrorCode, FinishReason, GuestChatStream, ResponseMetadata,
+        StreamDelta, StreamEvent, ToolCall, Usage,
+    };
+
+    fn decode_all(raw_events: &[&str]) -> Vec<StreamEvent> {
+        let stream = AnthropicChatStream::state(None, None);
+        raw_events
+            .iter()
+            .filter_map(|raw| stream.decode_message(raw).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn streamed_text_and_tool_use_are_assembled() {
+        let events = decode_all(&[
+            r#"{"type":"message_start","message":{"id":"msg_01","type":"message","role":"assistant","content":[],"model":"claude-3-7-sonnet-20250219","stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":472,"output_tokens":2}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"ping"}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Let me check"}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":" the weather."}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_01","name":"get_weather","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":""}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"location\": \"San Fra"}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"ncisco, CA\"}"}}"#,
+            r#"{"type":"content_block_stop","index":1}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"tool_use","stop_sequence":null},"usage":{"output_tokens":89}}"#,
+            r#"{"type":"message_stop"}"#,
+        ]);
+
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Delta(StreamDelta {
+                    content: Some(vec![ContentPart::Text("Let me check".to_string())]),
+                    tool_calls: None,
+                    reasoning: None,
+                    choice_index: None,
+                }),
+                StreamEvent::Delta(StreamDelta {
+                    content: Some(vec![ContentPart::Text(" the weather.".to_string())]),
+                    tool_calls: None,
+                    reasoning: None,
+                    choice_index: None,
+                }),
+                StreamEvent::Delta(StreamDelta {
+                    content: None,
+                    tool_calls: Some(vec![ToolCall {
+                        id: "toolu_01".to_string(),
+                        name: "get_weather".to_string(),
+                        arguments_json: r#"{"location": "San Francisco, CA"}"#.to_string(),
+                    }]),
+                    reasoning: None,
+                    choice_index: None,
+                }),
+                StreamEvent::Finish(ResponseMetadata {
+                    finish_reason: Some(FinishReason::ToolCalls),
+                    usage: Some(Usage {
+                        input_tokens: Some(472),
+                        output_tokens: Some(89),
+                        total_tokens: None,
+                    }),
+                    provider_id: Some("msg_01".to_string()),
+                    timestamp: None,
+                    provider_metadata_json: None,
+                    stop_sequence: None,
+                    tool_calls: None,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn thinking_deltas_and_tool_use_without_input() {
+        let events = decode_all(&[
+            r#"{"type":"message_start","message":{"id":"msg_02","type":"message","role":"assistant","content":[],"model":"claude-3-7-sonnet-20250219","stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":12,"output_tokens":1}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"thinking","thinking":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"The user wants the time."}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"signature_delta","signature":"EqQBCgIYAh"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_02","name":"get_time","input":{}}}"#,
+            r#"{"type":"content_block_stop","index":1}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"tool_use","stop_sequence":null},"usage":{"output_tokens":30}}"#,
+        ]);
+
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Delta(StreamDelta {
+                    content: None,
+                    tool_calls: None,
+                    reasoning: Some("The user wants the time.".to_string()),
+                    choice_index: None,
+                }),
+                StreamEvent::Delta(StreamDelta {
+                    content: None,
+                    tool_calls: Some(vec![ToolCall {
+                        id: "toolu_02".to_string(),
+                        name: "get_time".to_string(),
+                        arguments_json: "{}".to_string(),
+                    }]),
+                    reasoning: None,
+                    choice_index: None,
+                })
+            ]
+        );
+    }
+
+    #[test]
+    fn overloaded_error_event_is_retryable() {
+        let recording_path =
+            std::env::temp_dir().join(format!("anthropic-overloaded-{}.sse", std::process::id()));
+        let error_event =
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        std::fs::write(
+            &recording_path,
+            format!(
+                "event: message_start\ndata: {}\n\nevent: error\ndata: {error_event}\n\n",
+                r#"{"type":"message_start","message":{"id":"msg_03","type":"message","role":"assistant","content":[],"model":"claude-3-7-sonnet-20250219","stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":8,"output_tokens":1}}}"#
+            ),
+        )
+        .unwrap();
+
+        let stream = AnthropicChatStream::replayed().with_replay(recording_path.clone());
+        let mut events = Vec::new();
+        loop {
+            match stream.get_next() {
+                Some(batch) if batch.is_empty() => break,
+                Some(batch) => events.extend(batch),
+                None => {}
+            }
+        }
+        std::fs::remove_file(recording_path).unwrap();
+
+        assert_eq!(
+            events.last(),
+            Some(&StreamEvent::Error(Error {
+                code: ErrorCode::Overloaded,
+                message: "Overloaded".to_string(),
+                provider_error_json: Some(error_event.to_string()),
+            }))
+        );
+        assert_eq!(
+            error_code_from_type("rate_limit_error"),
+            ErrorCode::RateLimitExceeded
+        );
+    }
+}
@@ -1,9 +1,16 @@
-use golem_llm::error::{error_code_from_status, from_event_source_error, from_reqwest_error};
+use golem_llm::cache::cache_scope;
+use golem_llm::config::{max_request_bytes, user_agent};
+use golem_llm::error::{error_from_status, from_event_source_error, from_reqwest_error};
 use golem_llm::event_source::EventSource;
-use golem_llm::golem::llm::llm::Error;
+use golem_llm::fixtures::{response_fixtures, with_response_fixtures, ResponseFixtures};
+use golem_llm::golem::llm::llm::{Config, Error};
+use golem_llm::lenient::{integer, optional_integer, parse_response_json};
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{pre_send_body, RawResponse};
+use golem_llm::request::check_request_size;
 use log::trace;
-use reqwest::header::HeaderValue;
-use reqwest::{Client, Method, Response};
+use reqwest::header::{HeaderValue, USER_AGENT};
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Response};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
@@ -14,25 +21,63 @@ const BASE_URL: &str = "https://api.x.ai";
 /// The Completions API client for creating model responses.
 pub struct CompletionsApi {
     api_key: String,
+    user_agent: String,
+    max_request_bytes: Option<usize>,
+    response_fixtures: Option<ResponseFixtures>,
     client: Client,
 }
 
 impl CompletionsApi {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(api_key: String, config: &Config) -> Self {
         let client = Client::builder()
             .build()
             .expect("Failed to initialize HTTP client");
-        Self { api_key, client }
+        Self {
+            api_key,
+            user_agent: user_agent(config, "grok"),
+            max_request_bytes: max_request_bytes(config, None),
+            response_fixtures: response_fixtures(config),
+            client,
+        }
     }
 
-    pub fn send_messages(&self, request: CompletionsRequest) -> Result<CompletionsResponse, Error> {
-        trace!("Sending request to xAI API: {request:?}");
+    /// Identifies the server and credentials of this client in response cache keys
+    pub fn cache_scope(&self) -> String {
+        cache_scope(BASE_URL, &self.api_key)
+    }
+
+    /// Starts a request to the xAI API with the user agent and bearer token of this client
+    fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .header(USER_AGENT, &self.user_agent)
+            .bearer_auth(&self.api_key)
+    }
+
+    pub fn send_messages(
+        &self,
+        request: CompletionsRequest,
+    ) -> Result<RawResponse<CompletionsResponse>, Error> {
+        trace!("Sending request to xAI API: {}", Redacted(&request));
+        let body = pre_send_body(&request)?;
+        check_request_size(self.max_request_bytes, &body)?;
+
+        with_response_fixtures(self.response_fixtures.as_ref(), "grok", &body, || {
+            let response: Response = self
+                .request(Method::POST, format!("{BASE_URL}/v1/chat/completions"))
+                .json(&body)
+                .send()
+                .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+            parse_response(response)
+        })
+    }
+
+    pub fn list_models(&self) -> Result<ModelsResponse, Error> {
+        trace!("Listing models from xAI API");
 
         let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/v1/chat/completions"))
-            .bearer_auth(self.api_key.clone())
-            .json(&request)
+            .request(Method::GET, format!("{BASE_URL}/v1/models"))
             .send()
             .map_err(|err| from_reqwest_error("Request failed", err))?;
 
@@ -40,17 +85,17 @@ impl CompletionsApi {
     }
 
     pub fn stream_send_messages(&self, request: CompletionsRequest) -> Result<EventSource, Error> {
-        trace!("Sending request to xAI API: {request:?}");
+        trace!("Sending request to xAI API: {}", Redacted(&request));
+        let body = pre_send_body(&request)?;
+        check_request_size(self.max_request_bytes, &body)?;
 
         let response: Response = self
-            .client
             .request(Method::POST, format!("{BASE_URL}/v1/chat/completions"))
-            .bearer_auth(self.api_key.clone())
             .header(
                 reqwest::header::ACCEPT,
                 HeaderValue::from_static("text/event-stream"),
             )
-            .json(&request)
+            .json(&body)
             .send()
             .map_err(|err| from_reqwest_error("Request failed", err))?;
 
@@ -76,6 +121,10 @@ pub struct CompletionsRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning_effort: Option<Effort>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_parameters: Option<SearchParameters>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub seed: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stop: Option<Vec<String>>,
@@ -97,11 +146,69 @@ pub struct CompletionsRequest {
     pub user: Option<String>,
 }
 
+/// Structured output, which Grok accepts in the same shape as OpenAI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ResponseFormat {
+    #[serde(rename = "json_schema")]
+    JsonSchema { json_schema: JsonSchema },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonSchema {
+    pub name: String,
+    pub schema: serde_json::Value,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StreamOptions {
     pub include_usage: bool,
 }
 
+/// Enables xAI's live search, which lets the model search the web, X and news sources while
+/// answering
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchParameters {
+    pub mode: SearchMode,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<SearchSource>,
+    pub return_citations: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SearchMode {
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "on")]
+    On,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SearchSource {
+    #[serde(rename = "web")]
+    Web,
+    #[serde(rename = "x")]
+    X,
+    #[serde(rename = "news")]
+    News,
+}
+
+impl FromStr for SearchSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "web" => Ok(SearchSource::Web),
+            "x" => Ok(SearchSource::X),
+            "news" => Ok(SearchSource::News),
+            _ => Err(format!(
+                "Invalid search source: {s}, expected web, x or news"
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum Tool {
@@ -212,6 +319,7 @@ pub enum ToolCall {
     #[serde(rename = "function")]
     Function {
         function: FunctionCall,
+        #[serde(default)]
         id: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         index: Option<u32>,
@@ -227,11 +335,14 @@ pub struct FunctionCall {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompletionsResponse {
     pub choices: Vec<Choice>,
+    #[serde(default, deserialize_with = "integer")]
     pub created: u64,
     pub id: String,
     pub model: String,
     pub system_fingerprint: Option<String>,
     pub usage: Option<Usage>,
+    #[serde(default)]
+    pub citations: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -251,6 +362,9 @@ pub enum FinishReason {
     EndTurn,
     #[serde(rename = "tool_calls")]
     ToolCalls,
+    /// Any reason not known when this was written, kept as reported
+    #[serde(untagged)]
+    Other(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -263,37 +377,53 @@ pub struct ResponseMessage {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Usage {
+    #[serde(default, deserialize_with = "integer")]
     pub completion_tokens: u32,
+    #[serde(default)]
     pub completion_tokens_details: CompletionTokenDetails,
+    #[serde(default, deserialize_with = "integer")]
     pub prompt_tokens: u32,
+    #[serde(default)]
     pub prompt_tokens_details: PromptTokenDetails,
-    pub total_tokens: u32,
+    #[serde(default, deserialize_with = "optional_integer")]
+    pub total_tokens: Option<u32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CompletionTokenDetails {
+    #[serde(default, deserialize_with = "integer")]
     pub accepted_prediction_tokens: u32,
+    #[serde(default, deserialize_with = "integer")]
     pub audio_tokens: u32,
+    #[serde(default, deserialize_with = "integer")]
     pub reasoning_tokens: u32,
+    #[serde(default, deserialize_with = "integer")]
     pub rejected_prediction_tokens: u32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PromptTokenDetails {
+    #[serde(default, deserialize_with = "integer")]
     pub audio_tokens: u32,
+    #[serde(default, deserialize_with = "integer")]
     pub cached_tokens: u32,
+    #[serde(default, deserialize_with = "integer")]
     pub image_tokens: u32,
+    #[serde(default, deserialize_with = "integer")]
     pub text_tokens: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionChunk {
     pub id: String,
+    #[serde(default, deserialize_with = "integer")]
     pub created: u64,
     pub model: String,
     pub choices: Vec<ChoiceChunk>,
     pub usage: Option<Usage>,
     pub system_fingerprint: String,
+    #[serde(default)]
+    pub citations: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -306,18 +436,50 @@ pub struct ChoiceChunk {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChoiceDelta {
     pub content: Option<String>,
-    pub tool_calls: Option<Vec<ToolCall>>,
+    pub tool_calls: Option<Vec<ToolCallChunk>>,
+    #[serde(default)]
+    pub reasoning_content: Option<String>,
     pub role: String,
 }
 
+/// A streamed tool call. xAI usually sends a call in a single chunk, but a call split over
+/// several ones only carries its id and name in the first of them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallChunk {
+    #[serde(default)]
+    pub index: u32,
+    pub id: Option<String>,
+    pub function: Option<FunctionCallChunk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCallChunk {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelsResponse {
+    pub data: Vec<Model>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub id: String,
+    pub created: Option<u64>,
+    pub owned_by: Option<String>,
+}
+
 fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
     let status = response.status();
     if status.is_success() {
         let body = response
-            .json::<T>()
-            .map_err(|err| from_reqwest_error("Failed to decode response body", err))?;
+            .text()
+            .map_err(|err| from_reqwest_error("Failed to receive response body", err))?;
+        let body = parse_response_json::<T>("xAI", body)?;
 
-        trace!("Received response from xAI API: {body:?}");
+        trace!("Received response from xAI API: {}", Redacted(&body));
 
         Ok(body)
     } else {
@@ -325,12 +487,47 @@ fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T,
             .text()
             .map_err(|err| from_reqwest_error("Failed to receive error response body", err))?;
 
-        trace!("Received {status} response from xAI API: {error_body:?}");
+        trace!(
+            "Received {status} response from xAI API: {}",
+            Redacted(&error_body)
+        );
 
-        Err(Error {
-            code: error_code_from_status(status),
-            message: format!("Request failed with {status}"),
-            provider_error_json: Some(serde_json::to_string(&error_body).unwrap()),
-        })
+        Err(error_from_status(
+            status,
+            format!("Request failed with {status}"),
+            Some(serde_json::to_string(&error_body).unwrap()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{CompletionsApi, BASE_URL};
+    use golem_llm::golem::llm::llm::{Config, Kv};
+    use reqwest::header::USER_AGENT;
+    use reqwest::Method;
+
+    #[test]
+    fn requests_carry_the_user_agent() {
+        let config = Config {
+            model: "grok-3".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![Kv {
+                key: "user_agent".to_string(),
+                value: "my-app/1.0".to_string(),
+            }],
+            system: None,
+        };
+        let api = CompletionsApi::new("api-key".to_string(), &config);
+
+        let request = api
+            .request(Method::POST, format!("{BASE_URL}/v1/chat/completions"))
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[USER_AGENT], "my-app/1.0");
     }
 }
@@ -1,20 +1,80 @@
-use crate::client::{CompletionsRequest, CompletionsResponse, Detail, Effort};
+use crate::client::{
+    CompletionsRequest, CompletionsResponse, Detail, Effort, JsonSchema, ModelsResponse,
+    ResponseFormat, SearchMode, SearchParameters, SearchSource,
+};
 use base64::{engine::general_purpose, Engine as _};
+use golem_llm::capabilities::capabilities_by_prefix;
+use golem_llm::config::{max_tokens, reject_invalid_certs_option, temperature};
+use golem_llm::context::{fit_context, fit_max_tokens};
+use golem_llm::error::unsupported;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason, ImageDetail,
-    ImageReference, Message, ResponseMetadata, Role, ToolCall, ToolDefinition, ToolResult, Usage,
+    CapabilitySet, ChatEvent, Citation, CompleteResponse, Config, ContentPart, Error, ErrorCode,
+    FinishReason, ImageDetail, ImageReference, Message, ModelInfo, ResponseMetadata, Role,
+    ToolCall, ToolDefinition, ToolResult, Usage,
+};
+use golem_llm::image::{
+    download_image, image_transport, transport_images, validate_image_limits, ImageLimits,
 };
+use golem_llm::model_alias::resolve_model_alias;
+use golem_llm::request::with_system_prompt;
+use golem_llm::stop_sequence::limit_stop_sequences;
+use golem_llm::structured_output::response_schema;
+use golem_llm::tool_calls::{tool_call_id, tool_image_description};
+use golem_llm::validation::{validate_messages, SystemMessagePlacement};
+use serde_json::json;
 use std::collections::HashMap;
 
+/// The xAI models the provider-agnostic `small` and `large` model aliases resolve to
+const MODEL_ALIASES: &[(&str, &str)] = &[("small", "grok-3-mini"), ("large", "grok-3")];
+
+/// Prefixes of the xAI models which support live search
+const LIVE_SEARCH_MODELS: &[&str] = &["grok-3", "grok-4"];
+
+/// The maximum number of stop sequences accepted by the xAI API
+const MAX_STOP_SEQUENCES: usize = 4;
+
+/// The default temperature of the xAI API
+const DEFAULT_TEMPERATURE: f32 = 1.0;
+
+/// xAI documents no image count limit, only a maximum image size of 20 MiB
+const IMAGE_LIMITS: ImageLimits = ImageLimits {
+    max_count: None,
+    max_bytes: Some(20 * 1024 * 1024),
+};
+
 pub fn messages_to_request(
     messages: Vec<Message>,
     config: Config,
 ) -> Result<CompletionsRequest, Error> {
+    let config = resolve_model_alias(config, MODEL_ALIASES);
+    let messages = fit_context(with_system_prompt(&config, messages), &config)?;
+    validate_messages(&messages, SystemMessagePlacement::Anywhere)?;
+    let messages = transport_images(
+        messages,
+        image_transport(&config)?,
+        true,
+        "xAI",
+        download_image,
+    )?;
+    validate_image_limits(&messages, IMAGE_LIMITS, "xAI")?;
+
+    let max_tokens = fit_max_tokens(max_tokens(&config), &messages, &config);
+    let temperature = temperature(&config, DEFAULT_TEMPERATURE)?;
+    let response_format =
+        response_schema(&config)?.map(|response_schema| ResponseFormat::JsonSchema {
+            json_schema: JsonSchema {
+                name: "response".to_string(),
+                schema: response_schema.schema,
+            },
+        });
+    reject_invalid_certs_option(&config)?;
+    let stop = limit_stop_sequences(&config, Some(MAX_STOP_SEQUENCES))?;
     let options = config
         .provider_options
         .into_iter()
         .map(|kv| (kv.key, kv.value))
         .collect::<HashMap<_, _>>();
+    let search_parameters = search_parameters(&config.model, &options)?;
 
     let mut completion_messages = Vec::new();
     for message in messages {
@@ -51,7 +111,7 @@ pub fn messages_to_request(
         frequency_penalty: options
             .get("frequency_penalty")
             .and_then(|fp_s| fp_s.parse::<f32>().ok()),
-        max_completion_tokens: config.max_tokens,
+        max_completion_tokens: max_tokens,
         n: options.get("n").and_then(|n_s| n_s.parse::<u32>().ok()),
         presence_penalty: options
             .get("presence_penalty")
@@ -59,13 +119,15 @@ pub fn messages_to_request(
         reasoning_effort: options
             .get("reasoning_effort")
             .and_then(|effort_s| effort_s.parse::<Effort>().ok()),
+        response_format,
+        search_parameters,
         seed: options
             .get("seed")
             .and_then(|seed_s| seed_s.parse::<u32>().ok()),
-        stop: config.stop_sequences,
+        stop,
         stream: Some(false),
         stream_options: None,
-        temperature: config.temperature,
+        temperature,
         tool_choice: config.tool_choice,
         tools,
         top_logprobs: options
@@ -78,6 +140,63 @@ pub fn messages_to_request(
     })
 }
 
+/// Builds the live search parameters from the `live_search` provider option, which is `true` to
+/// always search or `auto` to let the model decide, and the comma separated `search_sources`
+fn search_parameters(
+    model: &str,
+    options: &HashMap<String, String>,
+) -> Result<Option<SearchParameters>, Error> {
+    let mode = match options.get("live_search").map(String::as_str) {
+        None | Some("false") => return Ok(None),
+        Some("true") => SearchMode::On,
+        Some("auto") => SearchMode::Auto,
+        Some(other) => {
+            return Err(Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!(
+                    "Invalid live_search option: {other}, expected true, false or auto"
+                ),
+                provider_error_json: None,
+            })
+        }
+    };
+
+    if !LIVE_SEARCH_MODELS
+        .iter()
+        .any(|prefix| model.starts_with(prefix))
+    {
+        return Err(unsupported(format!("live search with model {model}")));
+    }
+
+    let sources = match options.get("search_sources") {
+        Some(sources) => sources
+            .split(',')
+            .map(|source| source.trim().parse::<SearchSource>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|message| Error {
+                code: ErrorCode::InvalidRequest,
+                message,
+                provider_error_json: None,
+            })?,
+        None => vec![],
+    };
+
+    Ok(Some(SearchParameters {
+        mode,
+        sources,
+        return_citations: true,
+    }))
+}
+
+/// Puts the sources live search cited into the provider metadata of a response
+pub fn citations_metadata(citations: &[String]) -> Option<String> {
+    if citations.is_empty() {
+        None
+    } else {
+        Some(json!({ "citations": citations }).to_string())
+    }
+}
+
 pub fn process_response(response: CompletionsResponse) -> ChatEvent {
     let choice = response.choices.first();
     if let Some(choice) = choice {
@@ -86,11 +205,26 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
 
         if let Some(content) = &choice.message.content {
             contents.push(ContentPart::Text(content.clone()));
+            // Live search only reports the URLs of its sources
+            contents.extend(response.citations.iter().map(|url| {
+                ContentPart::Citation(Citation {
+                    title: None,
+                    url: url.clone(),
+                    quoted_text: None,
+                })
+            }));
         }
 
         let empty = Vec::new();
-        for tool_call in choice.message.tool_calls.as_ref().unwrap_or(&empty) {
-            tool_calls.push(convert_tool_call(tool_call));
+        for (position, tool_call) in choice
+            .message
+            .tool_calls
+            .as_ref()
+            .unwrap_or(&empty)
+            .iter()
+            .enumerate()
+        {
+            tool_calls.push(convert_tool_call(tool_call, position));
         }
 
         if contents.is_empty() {
@@ -101,7 +235,9 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
                 usage: response.usage.as_ref().map(convert_usage),
                 provider_id: None,
                 timestamp: Some(response.created.to_string()),
-                provider_metadata_json: None,
+                provider_metadata_json: citations_metadata(&response.citations),
+                stop_sequence: None,
+                tool_calls: None,
             };
 
             ChatEvent::Message(CompleteResponse {
@@ -144,6 +280,9 @@ pub fn tool_results_to_messages(
             ToolResult::Error(failure) => crate::client::ContentPart::TextInput {
                 text: failure.error_message,
             },
+            ToolResult::Image(tool_image) => crate::client::ContentPart::TextInput {
+                text: tool_image_description(&tool_image),
+            },
         };
         messages.push(crate::client::Message::Tool {
             name: None,
@@ -154,10 +293,14 @@ pub fn tool_results_to_messages(
     messages
 }
 
-pub fn convert_tool_call(tool_call: &crate::client::ToolCall) -> ToolCall {
+pub fn convert_tool_call(tool_call: &crate::client::ToolCall, position: usize) -> ToolCall {
     match tool_call {
-        crate::client::ToolCall::Function { function, id, .. } => ToolCall {
-            id: id.clone(),
+        crate::client::ToolCall::Function {
+            function,
+            id,
+            index,
+        } => ToolCall {
+            id: tool_call_id(Some(id), index.unwrap_or(position as u32)),
             name: function.name.clone(),
             arguments_json: function.arguments.clone(),
         },
@@ -189,6 +332,7 @@ fn convert_content_parts(contents: Vec<ContentPart>) -> crate::client::Content {
                     });
                 }
             },
+            ContentPart::Citation(_) => {}
         }
     }
     crate::client::Content::List(result)
@@ -208,8 +352,9 @@ pub fn convert_finish_reason(value: &crate::client::FinishReason) -> FinishReaso
     match value {
         crate::client::FinishReason::Stop => FinishReason::Stop,
         crate::client::FinishReason::Length => FinishReason::Length,
-        crate::client::FinishReason::EndTurn => FinishReason::Other,
+        crate::client::FinishReason::EndTurn => FinishReason::Other("end_turn".to_string()),
         crate::client::FinishReason::ToolCalls => FinishReason::ToolCalls,
+        crate::client::FinishReason::Other(value) => FinishReason::Other(value.clone()),
     }
 }
 
@@ -217,7 +362,9 @@ pub fn convert_usage(value: &crate::client::Usage) -> Usage {
     Usage {
         input_tokens: Some(value.prompt_tokens),
         output_tokens: Some(value.completion_tokens),
-        total_tokens: Some(value.total_tokens),
+        total_tokens: value
+            .total_tokens
+            .or_else(|| value.prompt_tokens.checked_add(value.completion_tokens)),
     }
 }
 
@@ -237,3 +384,199 @@ fn tool_definition_to_tool(tool: ToolDefinition) -> Result<crate::client::Tool,
         }),
     }
 }
+
+pub fn process_models_response(response: ModelsResponse) -> Vec<ModelInfo> {
+    response
+        .data
+        .into_iter()
+        .map(|model| ModelInfo {
+            id: model.id,
+            context_length: None,
+            capabilities: None,
+        })
+        .collect()
+}
+
+const TEXT: CapabilitySet = CapabilitySet {
+    images: false,
+    tools: true,
+    streaming: true,
+    json_mode: true,
+};
+
+const VISION: CapabilitySet = CapabilitySet {
+    images: true,
+    tools: true,
+    streaming: true,
+    json_mode: true,
+};
+
+const MODEL_CAPABILITIES: &[(&str, CapabilitySet)] = &[
+    ("grok-2-vision", VISION),
+    ("grok-vision-beta", VISION),
+    ("grok-4", VISION),
+];
+
+pub fn model_capabilities(model: &str) -> CapabilitySet {
+    capabilities_by_prefix(model, MODEL_CAPABILITIES, TEXT)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{CompletionsResponse, ModelsResponse};
+    use crate::conversions::{messages_to_request, process_models_response, process_response};
+    use crate::GrokComponent;
+    use golem_llm::durability::ExtendedGuest;
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, Citation, Config, ContentPart, ErrorCode, Kv, Message, ModelInfo, Role,
+        StreamDelta,
+    };
+
+    #[test]
+    fn models_response() {
+        let response: ModelsResponse = serde_json::from_str(
+            r#"{
+            "object": "list",
+            "data": [
+                {"id": "grok-3", "object": "model", "created": 1743724800, "owned_by": "xai"}
+            ]
+        }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            process_models_response(response),
+            vec![ModelInfo {
+                id: "grok-3".to_string(),
+                context_length: None,
+                capabilities: None,
+            }]
+        );
+    }
+
+    fn live_search_config(model: &str) -> Config {
+        Config {
+            model: model.to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![
+                Kv {
+                    key: "live_search".to_string(),
+                    value: "true".to_string(),
+                },
+                Kv {
+                    key: "search_sources".to_string(),
+                    value: "web, x".to_string(),
+                },
+            ],
+            system: None,
+        }
+    }
+
+    #[test]
+    fn live_search_parameters_are_sent() {
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("What is new on X?".to_string())],
+        }];
+
+        let request = serde_json::to_value(
+            messages_to_request(messages.clone(), live_search_config("grok-3")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            request["search_parameters"],
+            serde_json::json!({
+                "mode": "on",
+                "sources": [{"type": "web"}, {"type": "x"}],
+                "return_citations": true
+            })
+        );
+
+        let error = messages_to_request(messages, live_search_config("grok-2-vision")).unwrap_err();
+        assert_eq!(error.code, ErrorCode::Unsupported);
+    }
+
+    #[test]
+    fn citations_are_returned_as_content_and_in_provider_metadata() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1743724800,
+            "model": "grok-3",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Golem 1.3 was released."},
+                "finish_reason": "stop"
+            }],
+            "system_fingerprint": null,
+            "usage": null,
+            "citations": ["https://x.com/golemcloud/status/1", "https://golem.cloud/"]
+        }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message");
+        };
+        assert_eq!(
+            response.content[1..],
+            [
+                ContentPart::Citation(Citation {
+                    title: None,
+                    url: "https://x.com/golemcloud/status/1".to_string(),
+                    quoted_text: None,
+                }),
+                ContentPart::Citation(Citation {
+                    title: None,
+                    url: "https://golem.cloud/".to_string(),
+                    quoted_text: None,
+                }),
+            ]
+        );
+        assert_eq!(
+            response.metadata.provider_metadata_json.as_deref(),
+            Some(r#"{"citations":["https://x.com/golemcloud/status/1","https://golem.cloud/"]}"#)
+        );
+    }
+
+    #[test]
+    fn retry_prompt_is_accepted() {
+        let message = |role: Role, text: &str| Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        };
+        let original_messages = vec![
+            message(Role::System, "Answer briefly"),
+            message(Role::User, "What is the weather like?"),
+            message(Role::Assistant, "Which city?"),
+            message(Role::User, "Berlin"),
+        ];
+        let partial_result = vec![StreamDelta {
+            content: Some(vec![ContentPart::Text("It is sunny".to_string())]),
+            tool_calls: None,
+            reasoning: None,
+            choice_index: None,
+        }];
+
+        let messages = GrokComponent::retry_prompt(&original_messages, &partial_result);
+
+        let config = Config {
+            model: "grok-3".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![],
+            system: None,
+        };
+        assert!(messages_to_request(messages, config).is_ok());
+    }
+}
@@ -1,29 +1,54 @@
 mod client;
 mod conversions;
 
-use crate::client::{ChatCompletionChunk, CompletionsApi, CompletionsRequest, StreamOptions};
+use crate::client::{
+    ChatCompletionChunk, CompletionsApi, CompletionsRequest, StreamOptions, ToolCallChunk,
+};
 use crate::conversions::{
-    convert_finish_reason, convert_tool_call, convert_usage, messages_to_request, process_response,
-    tool_results_to_messages,
+    citations_metadata, convert_finish_reason, convert_usage, messages_to_request,
+    model_capabilities, process_models_response, process_response, tool_results_to_messages,
 };
+use golem_llm::cache::{cache_ttl, cached_response, response_cache_key};
 use golem_llm::chat_stream::{LlmChatStream, LlmChatStreamState};
-use golem_llm::config::with_config_key;
+use golem_llm::concurrency::acquire_request_slot;
+use golem_llm::config::{stream_replay_file, with_config_key};
+use golem_llm::context::reserve_context_for_tool_results;
+use golem_llm::dry_run::{dry_run_response, is_dry_run, with_api_key_unless_dry_run};
 use golem_llm::durability::{DurableLLM, ExtendedGuest};
+use golem_llm::error::{reject_empty_response, unsupported};
 use golem_llm::event_source::EventSource;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, ChatStream, Config, ContentPart, Error, FinishReason, Guest, Message,
-    ResponseMetadata, StreamDelta, StreamEvent, ToolCall, ToolResult,
+    BatchInfo, BatchRequest, BatchResult, CapabilitySet, ChatEvent, ChatStream, Config,
+    ContentPart, EmbeddingResponse, Error, FinishReason, Guest, ImageReference, Message, ModelInfo,
+    ResponseMetadata, StreamDelta, StreamEvent, ToolCall, ToolResult, Usage,
 };
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{include_raw_response, with_raw_response};
+use golem_llm::structured_output::{response_schema, validate_structured_output, ResponseSchema};
+use golem_llm::summarize::summarize_history;
+use golem_llm::tool_calls::{reject_malformed_tool_calls, tool_call_id};
+use golem_llm::usage::sum_usage;
 use golem_llm::LOGGING_STATE;
 use golem_rust::wasm_rpc::Pollable;
 use log::trace;
 use std::cell::{Ref, RefCell, RefMut};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+struct JsonFragment {
+    id: String,
+    name: String,
+    json: String,
+}
 
 struct GrokChatStream {
     stream: RefCell<Option<EventSource>>,
     failure: Option<Error>,
     finished: RefCell<bool>,
     finish_reason: RefCell<Option<FinishReason>>,
+    citations: RefCell<Vec<String>>,
+    /// Tool calls received so far by their index
+    json_fragments: RefCell<BTreeMap<u32, JsonFragment>>,
 }
 
 impl GrokChatStream {
@@ -33,6 +58,8 @@ impl GrokChatStream {
             failure: None,
             finished: RefCell::new(false),
             finish_reason: RefCell::new(None),
+            citations: RefCell::new(Vec::new()),
+            json_fragments: RefCell::new(BTreeMap::new()),
         })
     }
 
@@ -42,10 +69,52 @@ impl GrokChatStream {
             failure: Some(error),
             finished: RefCell::new(false),
             finish_reason: RefCell::new(None),
+            citations: RefCell::new(Vec::new()),
+            json_fragments: RefCell::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn replayed() -> LlmChatStream<Self> {
+        LlmChatStream::new(GrokChatStream {
+            stream: RefCell::new(None),
+            failure: None,
+            finished: RefCell::new(false),
+            finish_reason: RefCell::new(None),
+            citations: RefCell::new(Vec::new()),
+            json_fragments: RefCell::new(BTreeMap::new()),
         })
     }
 }
 
+impl GrokChatStream {
+    /// Adds the received tool call chunks to the ones of the same index, returning the calls
+    /// known so far with their arguments up to this chunk
+    fn merge_tool_calls(&self, calls: &[ToolCallChunk]) -> Vec<ToolCall> {
+        let mut fragments = self.json_fragments.borrow_mut();
+        calls
+            .iter()
+            .filter_map(|call| {
+                let fragment = fragments.entry(call.index).or_insert_with(|| JsonFragment {
+                    id: tool_call_id(call.id.as_deref(), call.index),
+                    name: String::new(),
+                    json: String::new(),
+                });
+                if let Some(function) = &call.function {
+                    if let Some(name) = &function.name {
+                        fragment.name.clone_from(name);
+                    }
+                    fragment.json.push_str(&function.arguments);
+                }
+                (!fragment.name.is_empty()).then(|| ToolCall {
+                    id: fragment.id.clone(),
+                    name: fragment.name.clone(),
+                    arguments_json: fragment.json.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
 impl LlmChatStreamState for GrokChatStream {
     fn failure(&self) -> &Option<Error> {
         &self.failure
@@ -68,7 +137,7 @@ impl LlmChatStreamState for GrokChatStream {
     }
 
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
-        trace!("Received raw stream event: {raw}");
+        trace!("Received raw stream event: {}", Redacted(&raw));
         let json: serde_json::Value = serde_json::from_str(raw)
             .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
 
@@ -80,6 +149,9 @@ impl LlmChatStreamState for GrokChatStream {
             Some("chat.completion.chunk") => {
                 let message: ChatCompletionChunk = serde_json::from_value(json)
                     .map_err(|err| format!("Failed to parse stream event: {err}"))?;
+                if !message.citations.is_empty() {
+                    *self.citations.borrow_mut() = message.citations;
+                }
                 if let Some(choice) = message.choices.into_iter().next() {
                     if let Some(finish_reason) = choice.finish_reason {
                         *self.finish_reason.borrow_mut() =
@@ -93,16 +165,24 @@ impl LlmChatStreamState for GrokChatStream {
                         tool_calls: choice
                             .delta
                             .tool_calls
-                            .map(|calls| calls.iter().map(convert_tool_call).collect()),
+                            .map(|calls| self.merge_tool_calls(&calls))
+                            .filter(|calls| !calls.is_empty()),
+                        reasoning: choice
+                            .delta
+                            .reasoning_content
+                            .filter(|reasoning| !reasoning.is_empty()),
+                        choice_index: None,
                     })))
                 } else if let Some(usage) = message.usage {
                     let finish_reason = self.finish_reason.borrow();
                     Ok(Some(StreamEvent::Finish(ResponseMetadata {
-                        finish_reason: *finish_reason,
+                        finish_reason: finish_reason.clone(),
                         usage: Some(convert_usage(&usage)),
                         provider_id: None,
                         timestamp: Some(message.created.to_string()),
-                        provider_metadata_json: None,
+                        provider_metadata_json: citations_metadata(&self.citations.borrow()),
+                        stop_sequence: None,
+                        tool_calls: None,
                     })))
                 } else {
                     Ok(None)
@@ -112,6 +192,26 @@ impl LlmChatStreamState for GrokChatStream {
             None => Err("Unexpected stream event format, does not have 'object' field".to_string()),
         }
     }
+
+    fn pending_finish(&self) -> ResponseMetadata {
+        ResponseMetadata {
+            finish_reason: self.finish_reason.borrow().clone(),
+            usage: None,
+            provider_id: None,
+            timestamp: None,
+            provider_metadata_json: citations_metadata(&self.citations.borrow()),
+            stop_sequence: None,
+            tool_calls: None,
+        }
+    }
+
+    fn tool_call_order(&self) -> Vec<String> {
+        self.json_fragments
+            .borrow()
+            .values()
+            .map(|fragment| fragment.id.clone())
+            .collect()
+    }
 }
 
 struct GrokComponent;
@@ -119,11 +219,32 @@ struct GrokComponent;
 impl GrokComponent {
     const ENV_VAR_NAME: &'static str = "XAI_API_KEY";
 
-    fn request(client: CompletionsApi, request: CompletionsRequest) -> ChatEvent {
-        match client.send_messages(request) {
-            Ok(response) => process_response(response),
-            Err(err) => ChatEvent::Error(err),
+    fn request(
+        client: CompletionsApi,
+        request: CompletionsRequest,
+        dry_run: bool,
+        include_raw_response: bool,
+        cache_ttl: Option<Duration>,
+        response_schema: Option<ResponseSchema>,
+    ) -> ChatEvent {
+        if dry_run {
+            return dry_run_response("grok", &request);
         }
+
+        let cache_key = response_cache_key(cache_ttl, &client.cache_scope(), &request);
+        cached_response(cache_key, || {
+            validate_structured_output(response_schema.as_ref(), || {
+                match client.send_messages(request.clone()) {
+                    Ok(response) => with_raw_response(
+                        reject_malformed_tool_calls(reject_empty_response(process_response(
+                            response.body,
+                        ))),
+                        include_raw_response.then_some(response.raw),
+                    ),
+                    Err(err) => ChatEvent::Error(err),
+                }
+            })
+        })
     }
 
     fn streaming_request(
@@ -146,15 +267,32 @@ impl Guest for GrokComponent {
 
     fn send(messages: Vec<Message>, config: Config) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
+        let dry_run = is_dry_run(&config);
+        let include_raw_response = include_raw_response(&config);
+        let cache_ttl = cache_ttl(&config);
+        let response_schema = response_schema(&config).ok().flatten();
 
-        with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |xai_api_key| {
-            let client = CompletionsApi::new(xai_api_key);
+        with_api_key_unless_dry_run(
+            Self::ENV_VAR_NAME,
+            dry_run,
+            ChatEvent::Error,
+            |xai_api_key| {
+                let _permit = acquire_request_slot(&config, "grok", &xai_api_key);
+                let client = CompletionsApi::new(xai_api_key, &config);
 
-            match messages_to_request(messages, config) {
-                Ok(request) => Self::request(client, request),
-                Err(err) => ChatEvent::Error(err),
-            }
-        })
+                match messages_to_request(messages, config) {
+                    Ok(request) => Self::request(
+                        client,
+                        request,
+                        dry_run,
+                        include_raw_response,
+                        cache_ttl,
+                        response_schema,
+                    ),
+                    Err(err) => ChatEvent::Error(err),
+                }
+            },
+        )
     }
 
     fn continue_(
@@ -163,36 +301,138 @@ impl Guest for GrokComponent {
         config: Config,
     ) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
+        let dry_run = is_dry_run(&config);
+        let include_raw_response = include_raw_response(&config);
+        let cache_ttl = cache_ttl(&config);
+        let response_schema = response_schema(&config).ok().flatten();
 
-        with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |xai_api_key| {
-            let client = CompletionsApi::new(xai_api_key);
+        with_api_key_unless_dry_run(
+            Self::ENV_VAR_NAME,
+            dry_run,
+            ChatEvent::Error,
+            |xai_api_key| {
+                let _permit = acquire_request_slot(&config, "grok", &xai_api_key);
+                let client = CompletionsApi::new(xai_api_key, &config);
 
-            match messages_to_request(messages, config) {
-                Ok(mut request) => {
-                    request
-                        .messages
-                        .extend(tool_results_to_messages(tool_results));
-                    Self::request(client, request)
+                match messages_to_request(
+                    messages,
+                    reserve_context_for_tool_results(config, &tool_results),
+                ) {
+                    Ok(mut request) => {
+                        request
+                            .messages
+                            .extend(tool_results_to_messages(tool_results));
+                        Self::request(
+                            client,
+                            request,
+                            dry_run,
+                            include_raw_response,
+                            cache_ttl,
+                            response_schema,
+                        )
+                    }
+                    Err(err) => ChatEvent::Error(err),
                 }
-                Err(err) => ChatEvent::Error(err),
-            }
-        })
+            },
+        )
     }
 
     fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
         ChatStream::new(Self::unwrapped_stream(messages, config))
     }
+
+    fn embed(_inputs: Vec<String>, _config: Config) -> Result<EmbeddingResponse, Error> {
+        Err(unsupported(
+            "Embeddings are not supported by the Grok provider",
+        ))
+    }
+
+    fn generate_image(_prompt: String, _config: Config) -> Result<Vec<ImageReference>, Error> {
+        Err(unsupported(
+            "Image generation is not supported by the Grok provider",
+        ))
+    }
+
+    fn list_models(config: Config) -> Result<Vec<ModelInfo>, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        with_config_key(Self::ENV_VAR_NAME, Err, |xai_api_key| {
+            let client = CompletionsApi::new(xai_api_key, &config);
+            client.list_models().map(process_models_response)
+        })
+    }
+
+    fn validate_credentials(config: Config) -> Result<(), Error> {
+        Self::list_models(config).map(|_| ())
+    }
+
+    fn capabilities(config: Config) -> CapabilitySet {
+        model_capabilities(&config.model)
+    }
+
+    fn batch_submit(_requests: Vec<BatchRequest>, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Grok provider",
+        ))
+    }
+
+    fn batch_status(_batch_id: String, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Grok provider",
+        ))
+    }
+
+    fn batch_results(_batch_id: String, _config: Config) -> Result<Vec<BatchResult>, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Grok provider",
+        ))
+    }
+
+    fn summarize_history(
+        messages: Vec<Message>,
+        config: Config,
+        keep_last_n: u32,
+    ) -> Result<Vec<Message>, Error> {
+        summarize_history(messages, config, keep_last_n, Self::send)
+    }
+
+    fn sum_usage(usages: Vec<Usage>) -> Usage {
+        sum_usage(usages)
+    }
 }
 
 impl ExtendedGuest for GrokComponent {
     fn unwrapped_stream(messages: Vec<Message>, config: Config) -> LlmChatStream<GrokChatStream> {
+        Self::unwrapped_continue_stream(messages, Vec::new(), config)
+    }
+
+    fn unwrapped_continue_stream(
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
+    ) -> LlmChatStream<GrokChatStream> {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
+        if stream_replay_file(&config).is_some() {
+            return GrokChatStream::replayed().with_stream_options(&config);
+        }
+
         with_config_key(Self::ENV_VAR_NAME, GrokChatStream::failed, |xai_api_key| {
-            let client = CompletionsApi::new(xai_api_key);
+            let permit = acquire_request_slot(&config, "grok", &xai_api_key);
+            let client = CompletionsApi::new(xai_api_key, &config);
 
-            match messages_to_request(messages, config) {
-                Ok(request) => Self::streaming_request(client, request),
+            match messages_to_request(
+                messages,
+                reserve_context_for_tool_results(config.clone(), &tool_results),
+            ) {
+                Ok(mut request) => {
+                    request
+                        .messages
+                        .extend(tool_results_to_messages(tool_results));
+                    Self::streaming_request(client, request)
+                        .with_request_permit(permit)
+                        .with_stream_options(&config)
+                }
                 Err(err) => GrokChatStream::failed(err),
             }
         })
@@ -201,8 +441,73 @@ impl ExtendedGuest for GrokComponent {
     fn subscribe(stream: &Self::ChatStream) -> Pollable {
         stream.subscribe()
     }
+
+    fn idle_timer(stream: &Self::ChatStream) -> Option<Pollable> {
+        Some(stream.idle_timer())
+    }
 }
 
 type DurableGrokComponent = DurableLLM<GrokComponent>;
 
 golem_llm::export_llm!(DurableGrokComponent with_types_in golem_llm);
+
+#[cfg(test)]
+mod tests {
+    use crate::GrokChatStream;
+    use golem_llm::golem::llm::llm::{GuestChatStream, StreamEvent, ToolCall};
+
+    #[test]
+    fn tool_call_split_over_chunks_keeps_its_id() {
+        let recording_path =
+            std::env::temp_dir().join(format!("grok-tool-call-{}.sse", std::process::id()));
+        let chunks = [
+            r#"{"index":0,"delta":{"role":"assistant","tool_calls":[{"index":0,"id":"call_91b2","type":"function","function":{"name":"get_weather","arguments":"{\"city\":"}}]}}"#,
+            r#"{"index":0,"delta":{"role":"assistant","tool_calls":[{"index":0,"function":{"arguments":"\"Paris\"}"}}]}}"#,
+            r#"{"index":0,"delta":{"role":"assistant"},"finish_reason":"tool_calls"}"#,
+        ];
+        let mut recording = chunks
+            .iter()
+            .map(|choice| {
+                format!(
+                    r#"data: {{"id":"grok-1","object":"chat.completion.chunk","created":1700000000,"model":"grok-3","system_fingerprint":"fp_1","choices":[{choice}]}}"#
+                ) + "\n\n"
+            })
+            .collect::<String>();
+        recording.push_str("data: [DONE]\n\n");
+        std::fs::write(&recording_path, recording).unwrap();
+
+        let stream = GrokChatStream::replayed().with_replay(recording_path.clone());
+        let mut events = Vec::new();
+        loop {
+            match stream.get_next() {
+                Some(batch) if batch.is_empty() => break,
+                Some(batch) => events.extend(batch),
+                None => {}
+            }
+        }
+        std::fs::remove_file(recording_path).unwrap();
+
+        let delta_ids = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::Delta(delta) => delta.tool_calls.as_ref(),
+                _ => None,
+            })
+            .flatten()
+            .map(|tool_call| tool_call.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(delta_ids, vec!["call_91b2"; 2]);
+
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| matches!(event, StreamEvent::ToolCallComplete(_)))
+                .collect::<Vec<_>>(),
+            vec![&StreamEvent::ToolCallComplete(ToolCall {
+                id: "call_91b2".to_string(),
+                name: "get_weather".to_string(),
+                arguments_json: r#"{"city":"Paris"}"#.to_string(),
+            })]
+        );
+    }
+}
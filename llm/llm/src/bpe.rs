@@ -0,0 +1,113 @@
+use base64::{engine::general_purpose, Engine as _};
+use std::collections::HashMap;
+
+/// A byte-level BPE tokenizer built from a vocabulary in the tiktoken format, where each line holds
+/// a base64 encoded token and its rank, like the `o200k_base.tiktoken` file of OpenAI models.
+pub struct Bpe {
+    ranks: HashMap<Vec<u8>, u32>,
+}
+
+impl Bpe {
+    pub fn from_tiktoken(vocabulary: &str) -> Result<Self, String> {
+        let mut ranks = HashMap::new();
+        for (line_number, line) in vocabulary.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid_line = || format!("invalid tiktoken vocabulary line {}", line_number + 1);
+            let (token, rank) = line.split_once(' ').ok_or_else(invalid_line)?;
+            let token = general_purpose::STANDARD
+                .decode(token)
+                .map_err(|_| invalid_line())?;
+            let rank = rank.trim().parse().map_err(|_| invalid_line())?;
+            ranks.insert(token, rank);
+        }
+        Ok(Self { ranks })
+    }
+
+    /// Encodes a text into token ids. Words are encoded separately, each with its leading space,
+    /// which approximates the pre-tokenization of the OpenAI tokenizers. Bytes missing from the
+    /// vocabulary are skipped.
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        words(text)
+            .flat_map(|word| self.encode_word(word.as_bytes()))
+            .collect()
+    }
+
+    /// Merges the bytes of a word pair by pair, always merging the adjacent pair with the lowest
+    /// rank first, until no adjacent pair is in the vocabulary
+    fn encode_word(&self, word: &[u8]) -> Vec<u32> {
+        let mut parts = word.iter().map(|byte| vec![*byte]).collect::<Vec<_>>();
+        loop {
+            let lowest = parts
+                .windows(2)
+                .enumerate()
+                .filter_map(|(index, pair)| {
+                    self.ranks
+                        .get(&[pair[0].as_slice(), pair[1].as_slice()].concat())
+                        .map(|rank| (*rank, index))
+                })
+                .min();
+            let Some((_, index)) = lowest else {
+                break;
+            };
+            let next = parts.remove(index + 1);
+            parts[index].extend(next);
+        }
+        parts
+            .iter()
+            .filter_map(|part| self.ranks.get(part).copied())
+            .collect()
+    }
+}
+
+/// Splits a text before each space that follows a non-space character, so every word keeps the
+/// space preceding it
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    let mut start = 0;
+    let mut previous = None;
+    let mut boundaries = Vec::new();
+    for (index, char) in text.char_indices() {
+        if char == ' ' && previous.is_some_and(|previous| previous != ' ') {
+            boundaries.push(start..index);
+            start = index;
+        }
+        previous = Some(char);
+    }
+    boundaries.push(start..text.len());
+    boundaries
+        .into_iter()
+        .map(move |range| &text[range])
+        .filter(|word| !word.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bpe::Bpe;
+    use base64::{engine::general_purpose, Engine as _};
+
+    fn vocabulary(tokens: &[&str]) -> Bpe {
+        let vocabulary = tokens
+            .iter()
+            .enumerate()
+            .map(|(rank, token)| format!("{} {rank}", general_purpose::STANDARD.encode(token)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Bpe::from_tiktoken(&vocabulary).unwrap()
+    }
+
+    #[test]
+    fn words_are_merged_by_rank() {
+        let bpe = vocabulary(&[
+            "a", "n", " ", "I", "A", "s", "an", " a", " an", " A", " I", "as", " as",
+        ]);
+
+        assert_eq!(bpe.encode("as an AI"), vec![11, 8, 9, 3]);
+        assert_eq!(bpe.encode(" an"), vec![8]);
+    }
+
+    #[test]
+    fn invalid_vocabulary_is_rejected() {
+        assert!(Bpe::from_tiktoken("YQ== 0\nnot a line").is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use crate::canonical::{request_hash, sha256_hex};
+use crate::config::provider_option;
+use crate::golem::llm::llm::{ChatEvent, Config};
+use log::trace;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Gets how long responses are cached from the `cache_ttl_ms` provider option. Responses are not
+/// cached if it is not set.
+pub fn cache_ttl(config: &Config) -> Option<Duration> {
+    provider_option(config, "cache_ttl_ms")
+        .and_then(|value| value.parse().ok())
+        .filter(|ttl_ms| *ttl_ms > 0)
+        .map(Duration::from_millis)
+}
+
+/// Identifies a request in the response cache by the client it is sent with and the hash of its
+/// body
+pub struct CacheKey {
+    key: String,
+    ttl: Duration,
+}
+
+/// Gets the part of the cache keys identifying a client: the base URL it sends requests to and a
+/// hash of its credentials. Responses are never shared between servers or API keys, and the
+/// credentials themselves are not kept in the cache.
+pub fn cache_scope(base_url: &str, credentials: &str) -> String {
+    format!("{base_url}#{}", sha256_hex(credentials.as_bytes()))
+}
+
+/// Gets the cache key of a provider request body sent by the client with the given
+/// [`cache_scope`], if responses are cached
+pub fn response_cache_key(
+    cache_ttl: Option<Duration>,
+    scope: &str,
+    request: &impl Serialize,
+) -> Option<CacheKey> {
+    let ttl = cache_ttl?;
+    let hash = request_hash(request)
+        .inspect_err(|err| trace!("Not caching the response: {}", err.message))
+        .ok()?;
+
+    Some(CacheKey {
+        key: format!("{scope}:{hash}"),
+        ttl,
+    })
+}
+
+/// Returns the response of an identical request made less than the cache TTL ago, or calls `send`
+/// and caches its response. Errors are never cached, and without a cache key the request is always
+/// sent.
+///
+/// The cache lives in the memory of the worker, so it is empty again after the worker restarts.
+pub fn cached_response(cache_key: Option<CacheKey>, send: impl FnOnce() -> ChatEvent) -> ChatEvent {
+    let Some(CacheKey { key, ttl }) = cache_key else {
+        return send();
+    };
+
+    static RESPONSES: OnceLock<Mutex<HashMap<String, (Instant, ChatEvent)>>> = OnceLock::new();
+    let responses = RESPONSES.get_or_init(Default::default);
+
+    if let Some((cached_at, response)) = responses.lock().unwrap().get(&key) {
+        if cached_at.elapsed() < ttl {
+            trace!("Returning cached response for request {key}");
+            return response.clone();
+        }
+    }
+
+    let response = send();
+    if !matches!(response, ChatEvent::Error(_)) {
+        let mut responses = responses.lock().unwrap();
+        responses.retain(|_, (cached_at, _)| cached_at.elapsed() < ttl);
+        responses.insert(key, (Instant::now(), response.clone()));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::{cache_scope, cached_response, response_cache_key};
+    use crate::golem::llm::llm::{ChatEvent, CompleteResponse, ResponseMetadata};
+    use serde_json::json;
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    fn message(id: &str) -> ChatEvent {
+        ChatEvent::Message(CompleteResponse {
+            id: id.to_string(),
+            content: vec![],
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                stop_sequence: None,
+                tool_calls: None,
+            },
+        })
+    }
+
+    #[test]
+    fn identical_request_is_answered_from_the_cache() {
+        let ttl = Some(Duration::from_secs(60));
+        let request = json!({"model": "gpt-4o", "seed": 42, "messages": ["What is 2 + 2?"]});
+        let sent = Cell::new(0);
+        let send = |id: &str| {
+            sent.set(sent.get() + 1);
+            message(id)
+        };
+
+        let first = cached_response(response_cache_key(ttl, "test", &request), || send("1"));
+        let second = cached_response(response_cache_key(ttl, "test", &request), || send("2"));
+        assert_eq!(sent.get(), 1);
+        assert_eq!(first, second);
+
+        let other_request = json!({"model": "gpt-4o", "seed": 43, "messages": ["What is 2 + 2?"]});
+        cached_response(response_cache_key(ttl, "test", &other_request), || {
+            send("3")
+        });
+        cached_response(response_cache_key(None, "test", &request), || send("4"));
+        assert_eq!(sent.get(), 3);
+    }
+
+    #[test]
+    fn responses_are_not_shared_between_api_keys_or_servers() {
+        let ttl = Some(Duration::from_secs(60));
+        let request = json!({"model": "gpt-4o", "messages": ["Who am I?"]});
+        let sent = Cell::new(0);
+        let send = || {
+            sent.set(sent.get() + 1);
+            message(&sent.get().to_string())
+        };
+
+        let first_key = cache_scope("https://api.openai.com", "sk-first");
+        let second_key = cache_scope("https://api.openai.com", "sk-second");
+        let other_server = cache_scope("https://proxy.example.com", "sk-first");
+        assert!(!first_key.contains("sk-first"));
+
+        cached_response(response_cache_key(ttl, &first_key, &request), send);
+        cached_response(response_cache_key(ttl, &first_key, &request), send);
+        cached_response(response_cache_key(ttl, &second_key, &request), send);
+        cached_response(response_cache_key(ttl, &other_server, &request), send);
+        assert_eq!(sent.get(), 3);
+    }
+}
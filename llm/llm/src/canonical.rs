@@ -0,0 +1,78 @@
+use crate::golem::llm::llm::{Error, ErrorCode};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+/// Serializes a request body to its canonical JSON form: object keys are sorted, arrays keep their
+/// order and there is no insignificant whitespace. Equivalent requests have byte-identical canonical
+/// forms, even when they were built from maps with a different iteration order.
+pub fn canonical_json(request: &impl Serialize) -> Result<String, Error> {
+    let value = serde_json::to_value(request).map_err(|err| Error {
+        code: ErrorCode::InternalError,
+        message: format!("Failed to serialize request body: {err}"),
+        provider_error_json: None,
+    })?;
+    Ok(canonicalize(value).to_string())
+}
+
+/// Hashes the canonical JSON form of a request body with SHA-256, for keying caches and
+/// deduplicating requests
+pub fn request_hash(request: &impl Serialize) -> Result<String, Error> {
+    Ok(sha256_hex(canonical_json(request)?.as_bytes()))
+}
+
+/// Gets the SHA-256 digest of some bytes as lowercase hex
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Rebuilds the objects of a JSON value with their keys inserted in sorted order, which keeps them
+/// sorted even if `serde_json` is built with its `preserve_order` feature
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => {
+            let mut fields = fields.into_iter().collect::<Vec<_>>();
+            fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (key, canonicalize(value)))
+                    .collect::<Map<_, _>>(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(canonicalize).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::canonical::{canonical_json, request_hash};
+    use serde_json::json;
+
+    #[test]
+    fn nested_keys_are_sorted_and_arrays_kept_in_order() {
+        let request = json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Hi"}, {"content": "Hello", "role": "assistant"}],
+            "metadata": {"b": "2", "a": "1"}
+        });
+
+        assert_eq!(
+            canonical_json(&request).unwrap(),
+            r#"{"messages":[{"content":"Hi","role":"user"},{"content":"Hello","role":"assistant"}],"metadata":{"a":"1","b":"2"},"model":"gpt-4o"}"#
+        );
+        assert_eq!(
+            request_hash(&request).unwrap(),
+            request_hash(&json!({
+                "metadata": {"a": "1", "b": "2"},
+                "model": "gpt-4o",
+                "messages": [{"role": "user", "content": "Hi"}, {"role": "assistant", "content": "Hello"}]
+            }))
+            .unwrap()
+        );
+    }
+}
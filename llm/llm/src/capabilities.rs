@@ -0,0 +1,52 @@
+use crate::golem::llm::llm::CapabilitySet;
+use crate::model_alias::find_by_model_prefix;
+
+/// Gets the capabilities of a model from a provider specific table of model name prefixes, falling
+/// back to `default` for unknown models
+pub fn capabilities_by_prefix(
+    model: &str,
+    known_models: &[(&str, CapabilitySet)],
+    default: CapabilitySet,
+) -> CapabilitySet {
+    find_by_model_prefix(model, known_models, |(prefix, _)| prefix)
+        .map(|(_, capabilities)| *capabilities)
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capabilities::capabilities_by_prefix;
+    use crate::golem::llm::llm::CapabilitySet;
+
+    const TEXT: CapabilitySet = CapabilitySet {
+        images: false,
+        tools: true,
+        streaming: true,
+        json_mode: true,
+    };
+
+    const VISION: CapabilitySet = CapabilitySet {
+        images: true,
+        tools: true,
+        streaming: true,
+        json_mode: true,
+    };
+
+    #[test]
+    fn longest_prefix_wins() {
+        let known_models = [("llama3", TEXT), ("llama3.2-vision", VISION)];
+
+        assert_eq!(
+            capabilities_by_prefix("llama3.2-vision:11b", &known_models, TEXT),
+            VISION
+        );
+        assert_eq!(
+            capabilities_by_prefix("meta/llama3.1:8b", &known_models, VISION),
+            TEXT
+        );
+        assert_eq!(
+            capabilities_by_prefix("mistral", &known_models, VISION),
+            VISION
+        );
+    }
+}
@@ -1,8 +1,35 @@
+use crate::concurrency::RequestPermit;
+use crate::config::{
+    debug_finish, finish_reason_hints, heartbeat_interval, heartbeats, idle_timeout,
+    max_buffered_events, stream_output_file, stream_record_file, stream_replay_file,
+};
 use crate::event_source::{Event, EventSource, MessageEvent};
-use crate::golem::llm::llm::{Error, ErrorCode, GuestChatStream, StreamEvent};
+use crate::golem::llm::llm::{
+    ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, GuestChatStream,
+    ResponseMetadata, StreamDelta, StreamEvent, ToolCall,
+};
+use crate::sse::{error_code_name, finish_reason_name};
+use crate::tool_calls::validate_tool_arguments;
+use golem_rust::bindings::wasi::clocks::monotonic_clock::subscribe_duration;
+use golem_rust::wasm_rpc::wasi::io::poll::poll;
 use golem_rust::wasm_rpc::Pollable;
-use std::cell::{Ref, RefMut};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::task::Poll;
+use std::time::{Duration, Instant};
+
+/// The default time a stream waits for the next event before failing with a timeout error
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The default number of raw events read from the provider each time the stream is polled. Only
+/// what the consumer asks for is read, so a slow consumer holds back a fast provider.
+pub const DEFAULT_MAX_BUFFERED_EVENTS: usize = 1;
+
+/// How often `collect_stream_until` checks a stream with no events ready for new ones
+const DEADLINE_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 pub trait LlmChatStreamState: 'static {
     fn failure(&self) -> &Option<Error>;
@@ -11,90 +38,558 @@ pub trait LlmChatStreamState: 'static {
     fn stream(&self) -> Ref<Option<EventSource>>;
     fn stream_mut(&self) -> RefMut<Option<EventSource>>;
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String>;
+
+    /// The metadata collected so far, used for the `Finish` event emitted when the stream ends
+    /// without the provider sending one
+    fn pending_finish(&self) -> ResponseMetadata {
+        ResponseMetadata {
+            finish_reason: None,
+            usage: None,
+            provider_id: None,
+            timestamp: None,
+            provider_metadata_json: None,
+            stop_sequence: None,
+            tool_calls: None,
+        }
+    }
+
+    /// The ids of the streamed tool calls in the order the provider assigned them, for providers
+    /// numbering the tool calls of a response. The assembled tool calls are sorted in this order,
+    /// and otherwise stay in the order their first fragment arrived in.
+    fn tool_call_order(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Asks the provider to stop generating the response, for providers with a cancellation
+    /// endpoint. Called by `cancel` before the connection is dropped, unless the response is
+    /// finished already.
+    fn cancel_response(&self) {}
+}
+
+/// Tells a stream that the worker is asked to stop
+pub trait ShutdownSignal: 'static {
+    /// Whether the worker was asked to stop
+    fn is_raised(&self) -> bool;
+
+    /// A pollable becoming ready when the signal is raised, so a blocked stream wakes up for it
+    fn pollable(&self) -> Option<&Pollable>;
+}
+
+impl ShutdownSignal for Pollable {
+    fn is_raised(&self) -> bool {
+        self.ready()
+    }
+
+    fn pollable(&self) -> Option<&Pollable> {
+        Some(self)
+    }
 }
 
 pub struct LlmChatStream<T> {
     implementation: T,
+    idle_timeout: Duration,
+    last_activity: Cell<Instant>,
+    shutdown_signal: Option<Box<dyn ShutdownSignal>>,
+    finish_emitted: Cell<bool>,
+    finish_reason_hints: bool,
+    finish_reason_hint_emitted: Cell<bool>,
+    heartbeats: bool,
+    heartbeat_interval: Option<Duration>,
+    last_heartbeat: Cell<Instant>,
+    output: RefCell<Option<StreamOutput>>,
+    max_buffered_events: usize,
+    tool_calls: RefCell<Vec<ToolCall>>,
+    record_path: Option<PathBuf>,
+    record_file: RefCell<Option<File>>,
+    replay_path: Option<PathBuf>,
+    replayed_messages: RefCell<Option<VecDeque<Event>>>,
+    request_permit: Option<RequestPermit>,
 }
 
 impl<T: LlmChatStreamState> LlmChatStream<T> {
     pub fn new(implementation: T) -> Self {
-        Self { implementation }
+        Self {
+            implementation,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            last_activity: Cell::new(Instant::now()),
+            shutdown_signal: None,
+            finish_emitted: Cell::new(false),
+            finish_reason_hints: false,
+            finish_reason_hint_emitted: Cell::new(false),
+            heartbeats: false,
+            heartbeat_interval: None,
+            last_heartbeat: Cell::new(Instant::now()),
+            output: RefCell::new(None),
+            max_buffered_events: DEFAULT_MAX_BUFFERED_EVENTS,
+            tool_calls: RefCell::new(Vec::new()),
+            record_path: None,
+            record_file: RefCell::new(None),
+            replay_path: None,
+            replayed_messages: RefCell::new(None),
+            request_permit: None,
+        }
+    }
+
+    /// Applies the streaming provider options of the configuration: the idle timeout, output file,
+    /// buffered events, finish reason hints and heartbeats, and either the recording or, when a
+    /// `stream_replay_file` is set, the replay of a recorded stream
+    pub fn with_stream_options(self, config: &Config) -> Self {
+        let stream = self
+            .with_idle_timeout(idle_timeout(config))
+            .with_output_file(stream_output_file(config))
+            .with_max_buffered_events(max_buffered_events(config))
+            .with_finish_reason_hints(finish_reason_hints(config))
+            .with_heartbeats(heartbeats(config), heartbeat_interval(config));
+        match stream_replay_file(config) {
+            Some(replay_file) => stream.with_replay(replay_file),
+            None => stream.with_recording(stream_record_file(config)),
+        }
+    }
+
+    /// Overrides how long the stream waits for the next event before it is considered dead
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Aborts the stream as soon as the given signal is raised, for example when the worker is
+    /// asked to stop. The underlying HTTP connection is released and a terminal error is emitted.
+    ///
+    /// The components do not set a signal themselves, as the LLM interface has no way to pass one.
+    /// It is meant for code using a provider's stream directly, which can pass for example the
+    /// pollable of a Golem promise that is completed when the worker should stop.
+    pub fn with_shutdown_signal(mut self, shutdown_signal: impl ShutdownSignal) -> Self {
+        self.shutdown_signal = Some(Box::new(shutdown_signal));
+        self
+    }
+
+    /// Holds the request slot taken for the stream until the stream is dropped, so a stream counts
+    /// against `max_concurrent_requests` for as long as it is open
+    pub fn with_request_permit(mut self, request_permit: Option<RequestPermit>) -> Self {
+        self.request_permit = request_permit;
+        self
+    }
+
+    /// Writes the text deltas of the stream to the given file as they arrive instead of returning
+    /// them, so very long responses are not held in memory. All other events are returned as usual.
+    pub fn with_output_file(mut self, output_path: Option<PathBuf>) -> Self {
+        self.output = RefCell::new(output_path.map(StreamOutput::new));
+        self
+    }
+
+    /// Appends every raw message received from the provider to the given file as an SSE `data:`
+    /// line, so the stream can later be replayed with `with_replay`
+    pub fn with_recording(mut self, record_path: Option<PathBuf>) -> Self {
+        self.record_path = record_path;
+        self
+    }
+
+    /// Reads the raw messages from a file written by `with_recording` instead of the connection
+    /// to the provider. They are decoded by the provider as if they were received again, which
+    /// makes it possible to test stream consumers against a snapshot of a real stream offline.
+    pub fn with_replay(mut self, replay_path: PathBuf) -> Self {
+        self.replay_path = Some(replay_path);
+        self
+    }
+
+    /// Emits a `finish-reason-hint` event as soon as the provider reports the finish reason, before
+    /// the terminal `finish` event, so consumers can for example prepare to execute tool calls
+    pub fn with_finish_reason_hints(mut self, finish_reason_hints: bool) -> Self {
+        self.finish_reason_hints = finish_reason_hints;
+        self
+    }
+
+    /// Emits a `heartbeat` event for every keep-alive the provider sends and, with an interval,
+    /// whenever no event arrived for that long, so consumers can tell a model that is still
+    /// thinking from a stalled stream. An interval enables heartbeats on its own.
+    pub fn with_heartbeats(
+        mut self,
+        heartbeats: bool,
+        heartbeat_interval: Option<Duration>,
+    ) -> Self {
+        self.heartbeats = heartbeats || heartbeat_interval.is_some();
+        self.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    /// Overrides how many raw events are read from the provider in one step. When the limit is
+    /// reached the rest is left unread in the connection until the consumer asks for more events.
+    pub fn with_max_buffered_events(mut self, max_buffered_events: usize) -> Self {
+        self.max_buffered_events = max_buffered_events.max(1);
+        self
     }
 
     pub fn subscribe(&self) -> Pollable {
         if let Some(stream) = self.implementation.stream().as_ref() {
             stream.subscribe()
         } else {
-            golem_rust::bindings::wasi::clocks::monotonic_clock::subscribe_duration(0)
+            subscribe_duration(0)
         }
     }
-}
 
-impl<T: LlmChatStreamState> GuestChatStream for LlmChatStream<T> {
-    fn get_next(&self) -> Option<Vec<StreamEvent>> {
+    /// Creates a pollable that becomes ready when the idle timeout of the stream expires, or when
+    /// the next heartbeat is due if that is earlier
+    pub fn idle_timer(&self) -> Pollable {
+        let remaining = match self.heartbeat_interval {
+            Some(interval) => self
+                .remaining_idle_time()
+                .min(self.remaining_heartbeat_time(interval)),
+            None => self.remaining_idle_time(),
+        };
+        subscribe_duration(remaining.as_nanos() as u64)
+    }
+
+    fn remaining_idle_time(&self) -> Duration {
+        self.idle_timeout
+            .saturating_sub(self.last_activity.get().elapsed())
+    }
+
+    fn remaining_heartbeat_time(&self, interval: Duration) -> Duration {
+        let last_sign_of_life = self.last_activity.get().max(self.last_heartbeat.get());
+        interval.saturating_sub(last_sign_of_life.elapsed())
+    }
+
+    /// The `heartbeat` event for a keep-alive received from the provider, if heartbeats are enabled
+    fn heartbeat(&self) -> Vec<StreamEvent> {
+        if self.heartbeats {
+            self.last_heartbeat.set(Instant::now());
+            vec![StreamEvent::Heartbeat]
+        } else {
+            vec![]
+        }
+    }
+
+    fn check_heartbeat_interval(&self) -> Option<Vec<StreamEvent>> {
+        let interval = self.heartbeat_interval?;
+        if self.remaining_heartbeat_time(interval).is_zero() {
+            self.last_heartbeat.set(Instant::now());
+            Some(vec![StreamEvent::Heartbeat])
+        } else {
+            None
+        }
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.shutdown_signal
+            .as_ref()
+            .is_some_and(|shutdown_signal| shutdown_signal.is_raised())
+    }
+
+    fn check_shutdown(&self) -> Option<Vec<StreamEvent>> {
+        match self.is_shutting_down() {
+            true => {
+                let _ = self.implementation.stream_mut().take();
+                self.implementation.set_finished();
+                Some(vec![StreamEvent::Error(Error {
+                    code: ErrorCode::InternalError,
+                    message: "Stream aborted because the worker is shutting down".to_string(),
+                    provider_error_json: None,
+                })])
+            }
+            false => None,
+        }
+    }
+
+    /// Handles a keep-alive comment of the provider, which only shows the stream is still alive
+    fn process_comment(&self, comment: &str) -> Vec<StreamEvent> {
+        if let Err(events) = self.record(":", comment) {
+            return events;
+        }
+        self.heartbeat()
+    }
+
+    fn process_message(&self, data: &str) -> Vec<StreamEvent> {
+        if let Err(events) = self.record("data:", data) {
+            return events;
+        }
+        if data == "[DONE]" {
+            return self.finish_stream();
+        }
+
+        let mut events = match self.implementation.decode_message(data) {
+            Ok(Some(stream_event)) => {
+                match stream_event {
+                    StreamEvent::Finish(_) => {
+                        self.implementation.set_finished();
+                        self.finish_emitted.set(true);
+                    }
+                    // An error reported by the provider ends the response
+                    StreamEvent::Error(_) => self.implementation.set_finished(),
+                    _ => {}
+                }
+                vec![stream_event]
+            }
+            Ok(None) => {
+                // Ignored event
+                vec![]
+            }
+            Err(error) => {
+                return vec![StreamEvent::Error(Error {
+                    code: ErrorCode::InternalError,
+                    message: error,
+                    provider_error_json: None,
+                })]
+            }
+        };
+
+        if self.finish_reason_hints
+            && !self.finish_emitted.get()
+            && !self.finish_reason_hint_emitted.get()
+        {
+            if let Some(finish_reason) = self.implementation.pending_finish().finish_reason {
+                self.finish_reason_hint_emitted.set(true);
+                events.push(StreamEvent::FinishReasonHint(finish_reason));
+            }
+        }
+        events
+    }
+
+    /// Marks the stream as finished, emitting a `Finish` event with the metadata collected so far
+    /// if the provider did not send one
+    fn finish_stream(&self) -> Vec<StreamEvent> {
+        self.implementation.set_finished();
+        if self.finish_emitted.replace(true) {
+            vec![]
+        } else {
+            vec![StreamEvent::Finish(self.implementation.pending_finish())]
+        }
+    }
+
+    /// Merges the tool call fragments of the deltas, and once their arguments are checked to be
+    /// valid JSON emits each assembled tool call, sorted by `tool_call_order`, as a
+    /// `ToolCallComplete` event and attaches them to the `Finish` event. A tool call with malformed
+    /// arguments replaces the `Finish` event with an error, as the response cannot be acted upon.
+    ///
+    /// When several completions are streamed, only the tool calls of the first one are assembled,
+    /// as it is the completion the `Finish` event describes.
+    fn assemble_tool_calls(&self, events: Vec<StreamEvent>) -> Vec<StreamEvent> {
+        let mut tool_calls = self.tool_calls.borrow_mut();
+        let mut result = Vec::new();
+        for event in events {
+            match event {
+                StreamEvent::Delta(delta) => {
+                    if delta.choice_index.unwrap_or(0) == 0 {
+                        for tool_call in delta.tool_calls.iter().flatten() {
+                            merge_tool_call(&mut tool_calls, tool_call.clone());
+                        }
+                    }
+                    result.push(StreamEvent::Delta(delta));
+                }
+                StreamEvent::Finish(mut metadata) if !tool_calls.is_empty() => {
+                    let mut assembled = std::mem::take(&mut *tool_calls);
+                    let order = self.implementation.tool_call_order();
+                    assembled.sort_by_key(|tool_call| {
+                        order
+                            .iter()
+                            .position(|id| *id == tool_call.id)
+                            .unwrap_or(usize::MAX)
+                    });
+                    for tool_call in &mut assembled {
+                        // Tools without parameters are streamed without any argument fragments
+                        if tool_call.arguments_json.trim().is_empty() {
+                            tool_call.arguments_json = "{}".to_string();
+                        }
+                        if let Err(error) = validate_tool_arguments(tool_call) {
+                            self.implementation.set_finished();
+                            result.push(StreamEvent::Error(error));
+                            return result;
+                        }
+                    }
+                    result.extend(assembled.iter().cloned().map(StreamEvent::ToolCallComplete));
+                    metadata.tool_calls = Some(assembled);
+                    result.push(StreamEvent::Finish(metadata));
+                }
+                event => result.push(event),
+            }
+        }
+        result
+    }
+
+    /// Appends the text of the deltas to the output file, if there is one, and removes it from the
+    /// events
+    fn write_output(&self, events: Vec<StreamEvent>) -> Vec<StreamEvent> {
+        match self.output.borrow_mut().as_mut() {
+            Some(output) => output.write(events).unwrap_or_else(|error| {
+                self.implementation.set_finished();
+                vec![StreamEvent::Error(error)]
+            }),
+            None => events,
+        }
+    }
+
+    /// Appends a raw event to the recording as SSE lines starting with `field`, so a stream that is
+    /// retried or resumed adds to the recording of its earlier attempts instead of replacing it
+    fn record(&self, field: &str, text: &str) -> Result<(), Vec<StreamEvent>> {
+        let Some(record_path) = &self.record_path else {
+            return Ok(());
+        };
+
+        let mut record_file = self.record_file.borrow_mut();
+        if record_file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(record_path)
+                .map_err(|err| self.record_failed(record_path, err))?;
+            *record_file = Some(file);
+        }
+        let mut line = String::new();
+        for text_line in text.split('\n') {
+            line.push_str(field);
+            line.push(' ');
+            line.push_str(text_line);
+            line.push('\n');
+        }
+        line.push('\n');
+        record_file
+            .as_mut()
+            .unwrap()
+            .write_all(line.as_bytes())
+            .map_err(|err| self.record_failed(record_path, err))
+    }
+
+    fn record_failed(&self, record_path: &Path, err: std::io::Error) -> Vec<StreamEvent> {
+        self.implementation.set_finished();
+        vec![StreamEvent::Error(Error {
+            code: ErrorCode::InternalError,
+            message: format!(
+                "Failed to record stream to {}: {err}",
+                record_path.display()
+            ),
+            provider_error_json: None,
+        })]
+    }
+
+    /// Processes the next recorded messages of a replayed stream, reading the recording on the
+    /// first call. The end of the recording ends the stream like the end of a connection would.
+    fn replay_events(&self, replay_path: &Path) -> Option<Vec<StreamEvent>> {
+        let mut replayed_messages = self.replayed_messages.borrow_mut();
+        if replayed_messages.is_none() {
+            match std::fs::read_to_string(replay_path) {
+                Ok(recording) => *replayed_messages = Some(recorded_messages(&recording)),
+                Err(err) => {
+                    self.implementation.set_finished();
+                    return Some(vec![StreamEvent::Error(Error {
+                        code: ErrorCode::InternalError,
+                        message: format!(
+                            "Failed to read stream recording {}: {err}",
+                            replay_path.display()
+                        ),
+                        provider_error_json: None,
+                    })]);
+                }
+            }
+        }
+        let replayed_messages = replayed_messages.as_mut().unwrap();
+
+        let mut events = Vec::new();
+        for _ in 0..self.max_buffered_events {
+            if self.implementation.is_finished() {
+                break;
+            }
+            match replayed_messages.pop_front() {
+                Some(Event::Message(MessageEvent { data, .. })) => {
+                    events.extend(self.process_message(&data))
+                }
+                Some(Event::Comment(comment)) => events.extend(self.process_comment(&comment)),
+                Some(Event::Open) => {}
+                None => events.extend(self.finish_stream()),
+            }
+        }
+        (!events.is_empty()).then_some(events)
+    }
+
+    fn next_events(&self) -> Option<Vec<StreamEvent>> {
         if self.implementation.is_finished() {
             return Some(vec![]);
         }
 
+        if let Some(events) = self.check_shutdown() {
+            return Some(events);
+        }
+
+        if let Some(replay_path) = &self.replay_path {
+            return self.replay_events(replay_path);
+        }
+
         let mut stream = self.implementation.stream_mut();
         if let Some(stream) = stream.as_mut() {
-            match stream.poll_next() {
-                Poll::Ready(None) => {
-                    self.implementation.set_finished();
-                    Some(vec![])
+            let ready = read_ready_events(self.max_buffered_events, || stream.poll_next());
+            if ready.is_empty() {
+                return self
+                    .check_idle_timeout()
+                    .or_else(|| self.check_heartbeat_interval());
+            }
+            self.last_activity.set(Instant::now());
+
+            let mut events = vec![];
+            for event in ready.events {
+                // Anything the provider sends after finishing the response is ignored
+                if self.implementation.is_finished() {
+                    break;
                 }
-                Poll::Ready(Some(Err(crate::event_source::error::Error::StreamEnded))) => {
-                    self.implementation.set_finished();
-                    Some(vec![])
+                match event {
+                    Event::Open => {}
+                    Event::Message(MessageEvent { data, .. }) => {
+                        events.extend(self.process_message(&data));
+                    }
+                    Event::Comment(comment) => events.extend(self.process_comment(&comment)),
+                }
+            }
+            match ready.end.filter(|_| !self.implementation.is_finished()) {
+                Some(Err(crate::event_source::error::Error::StreamEnded)) | Some(Ok(())) => {
+                    events.extend(self.finish_stream())
                 }
-                Poll::Ready(Some(Err(error))) => Some(vec![StreamEvent::Error(Error {
+                Some(Err(error)) => events.push(StreamEvent::Error(Error {
                     code: ErrorCode::InternalError,
                     message: error.to_string(),
                     provider_error_json: None,
-                })]),
-                Poll::Ready(Some(Ok(event))) => {
-                    let mut events = vec![];
-
-                    match event {
-                        Event::Open => {}
-                        Event::Message(MessageEvent { data, .. }) => {
-                            if data != "[DONE]" {
-                                match self.implementation.decode_message(&data) {
-                                    Ok(Some(stream_event)) => {
-                                        if matches!(stream_event, StreamEvent::Finish(_)) {
-                                            self.implementation.set_finished();
-                                        }
-                                        events.push(stream_event);
-                                    }
-                                    Ok(None) => {
-                                        // Ignored event
-                                    }
-                                    Err(error) => {
-                                        events.push(StreamEvent::Error(Error {
-                                            code: ErrorCode::InternalError,
-                                            message: error,
-                                            provider_error_json: None,
-                                        }));
-                                    }
-                                }
-                            }
-                        }
-                    }
+                })),
+                None => {}
+            }
 
-                    if events.is_empty() {
-                        None
-                    } else {
-                        Some(events)
-                    }
-                }
-                Poll::Pending => None,
+            if events.is_empty() {
+                None
+            } else {
+                Some(events)
             }
         } else if let Some(error) = self.implementation.failure().clone() {
             self.implementation.set_finished();
             Some(vec![StreamEvent::Error(error)])
         } else {
+            self.check_idle_timeout()
+        }
+    }
+
+    fn check_idle_timeout(&self) -> Option<Vec<StreamEvent>> {
+        if self.remaining_idle_time().is_zero() {
+            self.implementation.set_finished();
+            Some(vec![StreamEvent::Error(Error {
+                code: ErrorCode::Timeout,
+                message: format!(
+                    "No stream event received in {} seconds",
+                    self.idle_timeout.as_secs()
+                ),
+                provider_error_json: None,
+            })])
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: LlmChatStreamState> GuestChatStream for LlmChatStream<T> {
+    fn get_next(&self) -> Option<Vec<StreamEvent>> {
+        let events = self.next_events()?;
+        if events.is_empty() {
+            return Some(events);
+        }
+
+        let events = self.write_output(self.assemble_tool_calls(events));
+        if events.is_empty() {
             None
+        } else {
+            Some(events)
         }
     }
 
@@ -102,7 +597,20 @@ impl<T: LlmChatStreamState> GuestChatStream for LlmChatStream<T> {
         let pollable = self.subscribe();
         let mut result = Vec::new();
         loop {
-            pollable.block();
+            let idle_timer = self.idle_timer();
+            match self
+                .shutdown_signal
+                .as_ref()
+                .and_then(|shutdown_signal| shutdown_signal.pollable())
+            {
+                Some(shutdown_signal) => poll(&[&pollable, &idle_timer, shutdown_signal]),
+                None => poll(&[&pollable, &idle_timer]),
+            };
+            if self.is_shutting_down() {
+                // The subscription has to be dropped before the stream it was created from
+                drop(pollable);
+                break self.get_next().unwrap_or_default();
+            }
             match self.get_next() {
                 Some(events) => {
                     result.extend(events);
@@ -112,4 +620,1667 @@ impl<T: LlmChatStreamState> GuestChatStream for LlmChatStream<T> {
             }
         }
     }
+
+    fn cancel(&self) {
+        if self.implementation.is_finished() {
+            return;
+        }
+        self.implementation.cancel_response();
+        let _ = self.implementation.stream_mut().take();
+        self.implementation.set_finished();
+    }
+}
+
+/// The raw events that were ready to be read from an event source, and how the source ended if it
+/// did
+struct ReadyEvents<T, E> {
+    events: Vec<T>,
+    end: Option<Result<(), E>>,
+}
+
+impl<T, E> ReadyEvents<T, E> {
+    fn is_empty(&self) -> bool {
+        self.events.is_empty() && self.end.is_none()
+    }
+}
+
+/// Reads the events that are ready without blocking, at most `limit` of them. Nothing more is read
+/// from the underlying connection once the limit is reached, so a provider sending faster than the
+/// consumer drains the stream is held back by the connection instead of filling up memory.
+fn read_ready_events<T, E>(
+    limit: usize,
+    mut poll_next: impl FnMut() -> Poll<Option<Result<T, E>>>,
+) -> ReadyEvents<T, E> {
+    let mut events = Vec::new();
+    while events.len() < limit {
+        match poll_next() {
+            Poll::Ready(Some(Ok(event))) => events.push(event),
+            Poll::Ready(Some(Err(error))) => {
+                return ReadyEvents {
+                    events,
+                    end: Some(Err(error)),
+                }
+            }
+            Poll::Ready(None) => {
+                return ReadyEvents {
+                    events,
+                    end: Some(Ok(())),
+                }
+            }
+            Poll::Pending => break,
+        }
+    }
+    ReadyEvents { events, end: None }
+}
+
+/// Writes the text deltas of a stream to a file instead of returning them.
+///
+/// The file is opened for appending at an offset given by the text written before, which is empty
+/// for a new stream. A stream recovered after an interruption continues after the text received
+/// until then, so the file holds the text of the whole response exactly once.
+pub struct StreamOutput {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl StreamOutput {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, file: None }
+    }
+
+    /// Opens the file to continue after `written_text`. Anything written after it, for example
+    /// text received but not persisted before the worker was interrupted, is cut off, and the end
+    /// of `written_text` is written again if the file does not hold all of it.
+    pub fn resume(&mut self, written_text: &str) -> Result<(), Error> {
+        let offset = written_text.len() as u64;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| {
+                let length = file.metadata()?.len();
+                if length > offset {
+                    file.set_len(offset)?;
+                } else if length < offset {
+                    file.write_all(&written_text.as_bytes()[length as usize..])?;
+                }
+                Ok(file)
+            })
+            .map_err(|err| self.error(err))?;
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Appends the text of the deltas to the file and removes it from the events. Deltas left
+    /// without content, tool calls or reasoning are dropped.
+    pub fn write(&mut self, events: Vec<StreamEvent>) -> Result<Vec<StreamEvent>, Error> {
+        if self.file.is_none() {
+            self.resume("")?;
+        }
+        let (text, events) = split_text(events);
+        self.file
+            .as_mut()
+            .unwrap()
+            .write_all(text.as_bytes())
+            .map_err(|err| self.error(err))?;
+        Ok(events)
+    }
+
+    /// Removes the text from the events without writing it, for events whose text is in the file
+    /// already
+    pub fn skip(events: Vec<StreamEvent>) -> Vec<StreamEvent> {
+        split_text(events).1
+    }
+
+    fn error(&self, err: std::io::Error) -> Error {
+        Error {
+            code: ErrorCode::InternalError,
+            message: format!(
+                "Failed to write stream output to {}: {err}",
+                self.path.display()
+            ),
+            provider_error_json: None,
+        }
+    }
+}
+
+/// Separates the text of the deltas from the rest of the events
+fn split_text(events: Vec<StreamEvent>) -> (String, Vec<StreamEvent>) {
+    let mut text = String::new();
+    let mut result = Vec::new();
+    for event in events {
+        match event {
+            StreamEvent::Delta(StreamDelta {
+                content,
+                tool_calls,
+                reasoning,
+                choice_index,
+            }) => {
+                let mut remaining_content = Vec::new();
+                for part in content.unwrap_or_default() {
+                    match part {
+                        ContentPart::Text(delta_text) => text.push_str(&delta_text),
+                        part => remaining_content.push(part),
+                    }
+                }
+                if !remaining_content.is_empty() || tool_calls.is_some() || reasoning.is_some() {
+                    result.push(StreamEvent::Delta(StreamDelta {
+                        content: (!remaining_content.is_empty()).then_some(remaining_content),
+                        tool_calls,
+                        reasoning,
+                        choice_index,
+                    }));
+                }
+            }
+            event => result.push(event),
+        }
+    }
+    (text, result)
+}
+
+/// Splits a stream recording into its SSE events. Data split over several `data:` lines is joined
+/// with newlines, as the event source does when it receives it, and comment lines are kept as the
+/// keep-alives they stand for.
+fn recorded_messages(recording: &str) -> VecDeque<Event> {
+    recording
+        .split("\n\n")
+        .flat_map(|event| {
+            let comments = event
+                .lines()
+                .filter_map(|line| line.strip_prefix(':'))
+                .map(|comment| Event::Comment(comment.trim_start().to_string()));
+            let data = event
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                .collect::<Vec<_>>();
+            let message = (!data.is_empty()).then(|| {
+                Event::Message(MessageEvent {
+                    data: data.join("\n"),
+                    ..MessageEvent::default()
+                })
+            });
+            comments.chain(message).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Drains a chat stream and assembles the received events into a single `ChatEvent`, in the same
+/// shape as the non-streaming `send` call returns it.
+///
+/// Consecutive text deltas are concatenated, tool call fragments with the same id are merged, and
+/// the metadata of the `finish` event is attached to the result. Streams do not carry a response
+/// id, so the `id` of the collected response is left empty. Like `send`, only the first completion
+/// is collected when several are streamed.
+pub fn collect_stream(stream: &impl GuestChatStream) -> ChatEvent {
+    let mut collector = StreamCollector::default();
+    loop {
+        let events = stream.blocking_get_next();
+        if events.is_empty() {
+            break;
+        }
+        for event in events {
+            if let Err(error) = collector.add(event) {
+                return ChatEvent::Error(error);
+            }
+        }
+    }
+    collector.into_event()
+}
+
+/// The response assembled by `collect_stream_until`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialResponse {
+    pub event: ChatEvent,
+    /// Whether the stream was stopped before it finished, so the response only contains what was
+    /// received until then
+    pub cut_short: bool,
+}
+
+/// Collects a chat stream like `collect_stream`, but only until the `timeout` has passed, for
+/// latency bound callers that prefer a partial answer to none. With `max_events`, collecting also
+/// stops once that many content deltas have been received. Unlike time, that budget cuts a
+/// replayed stream at the same place as the live one was.
+///
+/// If the stream has not finished by then, the response assembled so far is returned without a
+/// finish reason and with `cut_short` set. Events already received in the same batch as the last
+/// counted delta are kept, so a stream finishing in it is returned in full. Tool calls whose
+/// arguments were not complete yet are left out of a partial response. The stream is cancelled and
+/// dropped in that case, which stops the response and closes its connection to the provider.
+pub fn collect_stream_until(
+    stream: impl GuestChatStream,
+    timeout: Duration,
+    max_events: Option<usize>,
+) -> PartialResponse {
+    let deadline = Instant::now() + timeout;
+    let mut collector = StreamCollector::default();
+    let mut received = 0;
+    loop {
+        match stream.get_next() {
+            Some(events) if events.is_empty() => {
+                return PartialResponse {
+                    event: collector.into_event(),
+                    cut_short: false,
+                };
+            }
+            Some(events) => {
+                for event in events {
+                    if matches!(event, StreamEvent::Delta(_)) {
+                        received += 1;
+                    }
+                    if let Err(error) = collector.add(event) {
+                        return PartialResponse {
+                            event: ChatEvent::Error(error),
+                            cut_short: false,
+                        };
+                    }
+                }
+            }
+            None => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                std::thread::sleep(remaining.min(DEADLINE_POLL_INTERVAL));
+            }
+        }
+
+        if collector.metadata.is_some() {
+            continue;
+        }
+        let budget_spent = max_events.is_some_and(|max_events| received >= max_events);
+        if budget_spent || Instant::now() >= deadline {
+            stream.cancel();
+            drop(stream);
+            return PartialResponse {
+                event: collector.into_partial_event(),
+                cut_short: true,
+            };
+        }
+    }
+}
+
+/// Assembles the events of a stream into the response `collect_stream` returns
+#[derive(Default)]
+struct StreamCollector {
+    content: Vec<ContentPart>,
+    tool_calls: Vec<ToolCall>,
+    completed_tool_calls: Vec<ToolCall>,
+    metadata: Option<ResponseMetadata>,
+}
+
+impl StreamCollector {
+    fn add(&mut self, event: StreamEvent) -> Result<(), Error> {
+        match event {
+            StreamEvent::Delta(delta) if delta.choice_index.unwrap_or(0) == 0 => {
+                for part in delta.content.unwrap_or_default() {
+                    match (self.content.last_mut(), part) {
+                        (Some(ContentPart::Text(text)), ContentPart::Text(delta_text)) => {
+                            text.push_str(&delta_text);
+                        }
+                        (_, part) => self.content.push(part),
+                    }
+                }
+                for tool_call in delta.tool_calls.unwrap_or_default() {
+                    merge_tool_call(&mut self.tool_calls, tool_call);
+                }
+            }
+            StreamEvent::ToolCallComplete(tool_call) => self.completed_tool_calls.push(tool_call),
+            StreamEvent::Finish(finish) => self.metadata = Some(finish),
+            StreamEvent::Error(error) => return Err(error),
+            StreamEvent::Delta(_) | StreamEvent::FinishReasonHint(_) | StreamEvent::Heartbeat => {}
+        }
+        Ok(())
+    }
+
+    /// Like `into_event`, but only with the tool calls that were completed, for a stream that was
+    /// stopped while their arguments may still have been arriving
+    fn into_partial_event(mut self) -> ChatEvent {
+        self.tool_calls = std::mem::take(&mut self.completed_tool_calls);
+        self.into_event()
+    }
+
+    fn into_event(self) -> ChatEvent {
+        if self.content.is_empty() && !self.tool_calls.is_empty() {
+            ChatEvent::ToolRequest(self.tool_calls)
+        } else {
+            ChatEvent::Message(CompleteResponse {
+                id: String::new(),
+                content: self.content,
+                tool_calls: self.tool_calls,
+                metadata: self.metadata.unwrap_or(ResponseMetadata {
+                    finish_reason: None,
+                    usage: None,
+                    provider_id: None,
+                    timestamp: None,
+                    provider_metadata_json: None,
+                    stop_sequence: None,
+                    tool_calls: None,
+                }),
+            })
+        }
+    }
+}
+
+/// Renders a response, like the one assembled by `collect_stream`, as plain text for logging and
+/// debugging: the text of its content, followed by its tool calls or its error.
+///
+/// With the `debug_finish` provider option set to `true`, a `[finish: <reason>]` marker is
+/// appended on a separate line. The marker only ever appears in this text, never in the content of
+/// the response itself.
+pub fn debug_text(event: &ChatEvent, config: &Config) -> String {
+    let (content, tool_calls, metadata) = match event {
+        ChatEvent::Message(response) => (
+            response.content.as_slice(),
+            response.tool_calls.as_slice(),
+            Some(&response.metadata),
+        ),
+        ChatEvent::ToolRequest(tool_calls) => (&[][..], tool_calls.as_slice(), None),
+        ChatEvent::Error(error) => {
+            return format!(
+                "[error: {}: {}]",
+                error_code_name(error.code),
+                error.message
+            )
+        }
+    };
+
+    let mut lines = content
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::Text(text) => Some(text.clone()),
+            ContentPart::Image(_) | ContentPart::Citation(_) => None,
+        })
+        .collect::<Vec<_>>();
+    lines.extend(tool_calls.iter().map(|tool_call| {
+        format!(
+            "[tool call: {}({})]",
+            tool_call.name, tool_call.arguments_json
+        )
+    }));
+    if debug_finish(config) {
+        let finish_reason = metadata
+            .and_then(|metadata| metadata.finish_reason.as_ref())
+            .map_or("none", finish_reason_name);
+        lines.push(format!("[finish: {finish_reason}]"));
+    }
+    lines.join("\n")
+}
+
+/// Iterates over the text deltas of a chat stream, for consumers only interested in the text of
+/// the response as it arrives.
+///
+/// Tool call and image deltas are skipped and the `finish` event ends the iteration. An error
+/// received from the stream is yielded as the last item.
+pub struct TextStream<'a, S: GuestChatStream> {
+    events: StreamEvents<'a, S>,
+    pending: VecDeque<String>,
+}
+
+impl<'a, S: GuestChatStream> TextStream<'a, S> {
+    pub fn new(stream: &'a S) -> Self {
+        Self {
+            events: StreamEvents::new(stream),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<S: GuestChatStream> Iterator for TextStream<'_, S> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            match self.events.next()? {
+                StreamEvent::Delta(delta) => {
+                    self.pending
+                        .extend(
+                            delta
+                                .content
+                                .unwrap_or_default()
+                                .into_iter()
+                                .filter_map(|part| match part {
+                                    ContentPart::Text(text) => Some(text),
+                                    ContentPart::Image(_) | ContentPart::Citation(_) => None,
+                                }),
+                        )
+                }
+                StreamEvent::Error(error) => return Some(Err(error)),
+                StreamEvent::Finish(_)
+                | StreamEvent::FinishReasonHint(_)
+                | StreamEvent::ToolCallComplete(_)
+                | StreamEvent::Heartbeat => {}
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+/// Iterates over the events of a chat stream one by one, replacing the `blocking_get_next` loop.
+///
+/// The iteration ends after the `finish` event, or with an error received from the stream as the
+/// last item. Events following either in the same batch are dropped.
+pub struct StreamEvents<'a, S: GuestChatStream> {
+    stream: &'a S,
+    pending: VecDeque<StreamEvent>,
+    finished: bool,
+}
+
+impl<'a, S: GuestChatStream> StreamEvents<'a, S> {
+    pub fn new(stream: &'a S) -> Self {
+        Self {
+            stream,
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+}
+
+impl<S: GuestChatStream> Iterator for StreamEvents<'_, S> {
+    type Item = StreamEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() && !self.finished {
+            let events = self.stream.blocking_get_next();
+            if events.is_empty() {
+                self.finished = true;
+            }
+
+            for event in events {
+                let last = matches!(event, StreamEvent::Finish(_) | StreamEvent::Error(_));
+                self.pending.push_back(event);
+                if last {
+                    self.finished = true;
+                    break;
+                }
+            }
+        }
+
+        self.pending.pop_front()
+    }
+}
+
+/// Adds `for event in stream.events()` iteration to every chat stream
+pub trait ChatStreamExt: GuestChatStream + Sized {
+    fn events(&self) -> StreamEvents<'_, Self> {
+        StreamEvents::new(self)
+    }
+}
+
+impl<S: GuestChatStream> ChatStreamExt for S {}
+
+/// Merges a streamed tool call fragment into the tool calls received so far.
+///
+/// Some providers stream the arguments of a tool call in increments while others repeat the
+/// arguments accumulated so far, so a fragment that extends the already received arguments
+/// replaces them instead of being appended.
+pub(crate) fn merge_tool_call(tool_calls: &mut Vec<ToolCall>, tool_call: ToolCall) {
+    match tool_calls
+        .iter_mut()
+        .find(|existing| existing.id == tool_call.id)
+    {
+        Some(existing) => {
+            if existing.name.is_empty() {
+                existing.name = tool_call.name;
+            }
+            if tool_call
+                .arguments_json
+                .starts_with(&existing.arguments_json)
+            {
+                existing.arguments_json = tool_call.arguments_json;
+            } else {
+                existing.arguments_json.push_str(&tool_call.arguments_json);
+            }
+        }
+        None => tool_calls.push(tool_call),
+    }
+}
+
+/// The shortest repeated text that is considered an overlap between the response received before a
+/// crash and the resumed stream. Shorter matches are too likely to be accidental.
+const MIN_RESUME_OVERLAP: usize = 5;
+
+/// Removes text the model repeats at the beginning of a resumed stream although it was already
+/// emitted before the stream was interrupted.
+///
+/// Text deltas of the resumed stream are held back as long as they could still be part of the
+/// already emitted text. As soon as they diverge, the longest prefix of the held back text that is
+/// a suffix of the already emitted text is dropped, and all following events are passed through
+/// unchanged, so the overlap is trimmed at most once.
+pub struct ResumedStreamFilter {
+    emitted_text: String,
+    pending_text: String,
+    done: bool,
+}
+
+impl ResumedStreamFilter {
+    pub fn new(emitted_text: String) -> Self {
+        let done = emitted_text.len() < MIN_RESUME_OVERLAP;
+        Self {
+            emitted_text,
+            pending_text: String::new(),
+            done,
+        }
+    }
+
+    /// Filters the result of a `get_next` call of the resumed stream
+    pub fn filter(&mut self, events: Option<Vec<StreamEvent>>) -> Option<Vec<StreamEvent>> {
+        if self.done {
+            return events;
+        }
+
+        let events = events?;
+        let end_of_stream = events.is_empty();
+        let mut result = Vec::new();
+
+        for event in events {
+            if self.done {
+                result.push(event);
+                continue;
+            }
+
+            match event {
+                StreamEvent::Delta(StreamDelta {
+                    content: Some(content),
+                    tool_calls: None,
+                    reasoning: None,
+                    choice_index: None,
+                }) if content
+                    .iter()
+                    .all(|part| matches!(part, ContentPart::Text(_))) =>
+                {
+                    for part in content {
+                        if let ContentPart::Text(text) = part {
+                            self.pending_text.push_str(&text);
+                        }
+                    }
+                    if !self.emitted_text.contains(&self.pending_text) {
+                        result.extend(self.resolve());
+                    }
+                }
+                event => {
+                    result.extend(self.resolve());
+                    result.push(event);
+                }
+            }
+        }
+
+        if end_of_stream {
+            result.extend(self.resolve());
+            Some(result)
+        } else if result.is_empty() {
+            None
+        } else {
+            Some(result)
+        }
+    }
+
+    fn resolve(&mut self) -> Option<StreamEvent> {
+        self.done = true;
+
+        let pending_text = std::mem::take(&mut self.pending_text);
+        let overlap = (MIN_RESUME_OVERLAP..=pending_text.len())
+            .rev()
+            .find(|&length| {
+                pending_text.is_char_boundary(length)
+                    && self.emitted_text.ends_with(&pending_text[..length])
+            })
+            .unwrap_or(0);
+
+        let remaining_text = &pending_text[overlap..];
+        if remaining_text.is_empty() {
+            None
+        } else {
+            Some(StreamEvent::Delta(StreamDelta {
+                content: Some(vec![ContentPart::Text(remaining_text.to_string())]),
+                tool_calls: None,
+                reasoning: None,
+                choice_index: None,
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat_stream::{
+        collect_stream, collect_stream_until, debug_text, read_ready_events, ChatStreamExt,
+        LlmChatStream, LlmChatStreamState, PartialResponse, ResumedStreamFilter, ShutdownSignal,
+        StreamOutput, TextStream,
+    };
+    use crate::event_source::EventSource;
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, ContentPart, Error, ErrorCode, FinishReason, GuestChatStream,
+        ImageReference, ImageUrl, ResponseMetadata, StreamDelta, StreamEvent, ToolCall, Usage,
+    };
+    use crate::testing::config;
+    use golem_rust::wasm_rpc::Pollable;
+    use std::cell::{Cell, Ref, RefCell, RefMut};
+    use std::collections::{BTreeMap, VecDeque};
+    use std::rc::Rc;
+    use std::task::Poll;
+    use std::time::{Duration, Instant};
+
+    struct StalledChatStream {
+        stream: RefCell<Option<EventSource>>,
+        failure: Option<Error>,
+        finished: RefCell<bool>,
+        cancelled: Cell<u32>,
+    }
+
+    impl StalledChatStream {
+        fn new() -> Self {
+            Self {
+                stream: RefCell::new(None),
+                failure: None,
+                finished: RefCell::new(false),
+                cancelled: Cell::new(0),
+            }
+        }
+    }
+
+    impl LlmChatStreamState for StalledChatStream {
+        fn failure(&self) -> &Option<Error> {
+            &self.failure
+        }
+
+        fn is_finished(&self) -> bool {
+            *self.finished.borrow()
+        }
+
+        fn set_finished(&self) {
+            *self.finished.borrow_mut() = true;
+        }
+
+        fn stream(&self) -> Ref<Option<EventSource>> {
+            self.stream.borrow()
+        }
+
+        fn stream_mut(&self) -> RefMut<Option<EventSource>> {
+            self.stream.borrow_mut()
+        }
+
+        fn decode_message(&self, _raw: &str) -> Result<Option<StreamEvent>, String> {
+            Ok(None)
+        }
+
+        fn cancel_response(&self) {
+            self.cancelled.set(self.cancelled.get() + 1);
+        }
+    }
+
+    #[test]
+    fn stalled_stream_waits_before_idle_timeout() {
+        let stream = LlmChatStream::new(StalledChatStream::new());
+        assert_eq!(stream.get_next(), None);
+    }
+
+    #[test]
+    fn stalled_stream_fails_after_idle_timeout() {
+        let stream = LlmChatStream::new(StalledChatStream::new()).with_idle_timeout(Duration::ZERO);
+
+        let events = stream.get_next().unwrap();
+        assert!(matches!(
+            events.as_slice(),
+            [StreamEvent::Error(Error {
+                code: ErrorCode::Timeout,
+                ..
+            })]
+        ));
+        assert_eq!(stream.get_next(), Some(vec![]));
+    }
+
+    /// A shutdown signal raised by the test, without a pollable to wait on
+    struct FakeShutdownSignal(Rc<Cell<bool>>);
+
+    impl ShutdownSignal for FakeShutdownSignal {
+        fn is_raised(&self) -> bool {
+            self.0.get()
+        }
+
+        fn pollable(&self) -> Option<&Pollable> {
+            None
+        }
+    }
+
+    #[test]
+    fn stream_terminates_on_shutdown_signal() {
+        let shutdown = Rc::new(Cell::new(false));
+        let stream = LlmChatStream::new(StalledChatStream::new())
+            .with_shutdown_signal(FakeShutdownSignal(shutdown.clone()));
+        assert_eq!(stream.get_next(), None);
+
+        shutdown.set(true);
+
+        let events = stream.get_next().unwrap();
+        assert!(matches!(
+            events.as_slice(),
+            [StreamEvent::Error(Error {
+                code: ErrorCode::InternalError,
+                ..
+            })]
+        ));
+        assert_eq!(stream.get_next(), Some(vec![]));
+    }
+
+    #[test]
+    fn cancel_asks_the_provider_to_stop_the_response_once() {
+        let stream = LlmChatStream::new(StalledChatStream::new());
+
+        stream.cancel();
+        stream.cancel();
+
+        assert_eq!(stream.implementation.cancelled.get(), 1);
+        assert_eq!(stream.get_next(), Some(vec![]));
+        assert_eq!(stream.get_next(), Some(vec![]));
+    }
+
+    /// Decodes `stop` as a finish reason without emitting an event, `finish` as an explicit
+    /// `Finish` event, `tool:<arguments>` as a fragment of a tool call, `tool<index>:<arguments>`
+    /// as a fragment of the tool call with the given index, and anything else as a text delta
+    struct DecodingChatStream {
+        stream: RefCell<Option<EventSource>>,
+        failure: Option<Error>,
+        finished: RefCell<bool>,
+        finish_reason: RefCell<Option<FinishReason>>,
+        tool_call_ids: RefCell<BTreeMap<u32, String>>,
+    }
+
+    impl DecodingChatStream {
+        fn new() -> Self {
+            Self {
+                stream: RefCell::new(None),
+                failure: None,
+                finished: RefCell::new(false),
+                finish_reason: RefCell::new(None),
+                tool_call_ids: RefCell::new(BTreeMap::new()),
+            }
+        }
+    }
+
+    impl LlmChatStreamState for DecodingChatStream {
+        fn failure(&self) -> &Option<Error> {
+            &self.failure
+        }
+
+        fn is_finished(&self) -> bool {
+            *self.finished.borrow()
+        }
+
+        fn set_finished(&self) {
+            *self.finished.borrow_mut() = true;
+        }
+
+        fn stream(&self) -> Ref<Option<EventSource>> {
+            self.stream.borrow()
+        }
+
+        fn stream_mut(&self) -> RefMut<Option<EventSource>> {
+            self.stream.borrow_mut()
+        }
+
+        fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
+            match raw {
+                "stop" => {
+                    *self.finish_reason.borrow_mut() = Some(FinishReason::Stop);
+                    Ok(None)
+                }
+                "finish" => Ok(Some(StreamEvent::Finish(metadata(FinishReason::Length)))),
+                raw => match raw.strip_prefix("tool").and_then(|raw| raw.split_once(':')) {
+                    Some(("", arguments)) => {
+                        Ok(Some(tool_call_delta("call-1", "get_weather", arguments)))
+                    }
+                    Some((index, arguments)) => {
+                        let id = format!("call-{index}");
+                        self.tool_call_ids
+                            .borrow_mut()
+                            .insert(index.parse().unwrap(), id.clone());
+                        Ok(Some(tool_call_delta(&id, "get_weather", arguments)))
+                    }
+                    None => Ok(Some(text_delta(raw))),
+                },
+            }
+        }
+
+        fn pending_finish(&self) -> ResponseMetadata {
+            ResponseMetadata {
+                finish_reason: self.finish_reason.borrow().clone(),
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                stop_sequence: None,
+                tool_calls: None,
+            }
+        }
+
+        fn tool_call_order(&self) -> Vec<String> {
+            self.tool_call_ids.borrow().values().cloned().collect()
+        }
+    }
+
+    #[test]
+    fn finish_is_emitted_on_done_without_usage() {
+        let stream = LlmChatStream::new(DecodingChatStream::new());
+
+        assert_eq!(stream.process_message("Hello"), vec![text_delta("Hello")]);
+        assert_eq!(stream.process_message("stop"), vec![]);
+        assert_eq!(
+            stream.process_message("[DONE]"),
+            vec![StreamEvent::Finish(ResponseMetadata {
+                finish_reason: Some(FinishReason::Stop),
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                stop_sequence: None,
+                tool_calls: None,
+            })]
+        );
+        assert_eq!(stream.get_next(), Some(vec![]));
+    }
+
+    #[test]
+    fn finish_reason_hint_is_emitted_before_finish() {
+        let stream = LlmChatStream::new(DecodingChatStream::new()).with_finish_reason_hints(true);
+
+        assert_eq!(stream.process_message("Hello"), vec![text_delta("Hello")]);
+        assert_eq!(
+            stream.process_message("stop"),
+            vec![StreamEvent::FinishReasonHint(FinishReason::Stop)]
+        );
+        assert_eq!(stream.process_message("stop"), vec![]);
+        assert!(matches!(
+            stream.process_message("[DONE]").as_slice(),
+            [StreamEvent::Finish(ResponseMetadata {
+                finish_reason: Some(FinishReason::Stop),
+                ..
+            })]
+        ));
+    }
+
+    #[test]
+    fn finish_includes_assembled_tool_calls() {
+        let stream = LlmChatStream::new(DecodingChatStream::new());
+
+        let mut events = Vec::new();
+        for message in ["tool:{\"city\":", "tool:\"Paris\"}", "[DONE]"] {
+            events.extend(stream.assemble_tool_calls(stream.process_message(message)));
+        }
+
+        assert!(matches!(
+            events.last(),
+            Some(StreamEvent::Finish(ResponseMetadata {
+                tool_calls: Some(tool_calls),
+                ..
+            })) if tool_calls == &vec![ToolCall {
+                id: "call-1".to_string(),
+                name: "get_weather".to_string(),
+                arguments_json: "{\"city\":\"Paris\"}".to_string(),
+            }]
+        ));
+    }
+
+    #[test]
+    fn partial_tool_call_fragments_are_followed_by_the_complete_call() {
+        let stream = LlmChatStream::new(DecodingChatStream::new());
+
+        let mut events = Vec::new();
+        for message in ["tool:{\"city\":", "tool:\"Paris\"}", "[DONE]"] {
+            events.extend(stream.assemble_tool_calls(stream.process_message(message)));
+        }
+
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events[..3],
+            [
+                tool_call_delta("call-1", "get_weather", "{\"city\":"),
+                tool_call_delta("call-1", "get_weather", "\"Paris\"}"),
+                StreamEvent::ToolCallComplete(ToolCall {
+                    id: "call-1".to_string(),
+                    name: "get_weather".to_string(),
+                    arguments_json: "{\"city\":\"Paris\"}".to_string(),
+                }),
+            ]
+        );
+        assert!(matches!(events[3], StreamEvent::Finish(_)));
+    }
+
+    #[test]
+    fn assembled_tool_calls_are_sorted_by_index() {
+        let stream = LlmChatStream::new(DecodingChatStream::new());
+
+        let mut events = Vec::new();
+        for message in [
+            "tool2:{\"city\":",
+            "tool0:{\"city\":",
+            "tool1:{\"city\":\"Rome\"}",
+            "tool0:\"Paris\"}",
+            "tool2:\"Oslo\"}",
+            "[DONE]",
+        ] {
+            events.extend(stream.assemble_tool_calls(stream.process_message(message)));
+        }
+
+        let completed = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::ToolCallComplete(tool_call) => Some(tool_call.id.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(completed, vec!["call-0", "call-1", "call-2"]);
+        assert!(matches!(
+            events.last(),
+            Some(StreamEvent::Finish(ResponseMetadata {
+                tool_calls: Some(tool_calls),
+                ..
+            })) if tool_calls
+                .iter()
+                .map(|tool_call| tool_call.id.as_str())
+                .eq(["call-0", "call-1", "call-2"])
+        ));
+    }
+
+    #[test]
+    fn malformed_tool_call_arguments_fail_the_stream() {
+        let stream = LlmChatStream::new(DecodingChatStream::new());
+
+        let mut events = Vec::new();
+        for message in ["tool:{\"city\":", "tool:\"Par", "[DONE]"] {
+            events.extend(stream.assemble_tool_calls(stream.process_message(message)));
+        }
+
+        assert!(matches!(
+            events.last(),
+            Some(StreamEvent::Error(Error {
+                code: ErrorCode::InvalidToolArguments,
+                ..
+            }))
+        ));
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, StreamEvent::Finish(_))));
+        assert_eq!(stream.get_next(), Some(vec![]));
+    }
+
+    #[test]
+    fn provider_finish_is_not_repeated_on_done() {
+        let stream = LlmChatStream::new(DecodingChatStream::new());
+
+        assert_eq!(
+            stream.process_message("finish"),
+            vec![StreamEvent::Finish(metadata(FinishReason::Length))]
+        );
+        assert_eq!(stream.process_message("[DONE]"), vec![]);
+    }
+
+    #[test]
+    fn text_is_written_to_output_file() {
+        let recording_path =
+            std::env::temp_dir().join(format!("golem-llm-output-{}.sse", std::process::id()));
+        let output_path =
+            std::env::temp_dir().join(format!("golem-llm-output-{}.txt", std::process::id()));
+        std::fs::write(
+            &recording_path,
+            "data: Hello\n\ndata: , \n\ndata: tool:{}\n\ndata: world\n\ndata: stop\n\ndata: [DONE]\n\n",
+        )
+        .unwrap();
+        std::fs::write(&output_path, "left over from an earlier stream").unwrap();
+        let stream = LlmChatStream::new(DecodingChatStream::new())
+            .with_replay(recording_path.clone())
+            .with_output_file(Some(output_path.clone()))
+            .with_max_buffered_events(2);
+
+        let mut events = Vec::new();
+        loop {
+            match stream.get_next() {
+                Some(batch) if batch.is_empty() => break,
+                Some(batch) => events.extend(batch),
+                None => {}
+            }
+        }
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], tool_call_delta("call-1", "get_weather", "{}"));
+        assert!(matches!(events[1], StreamEvent::ToolCallComplete(_)));
+        assert!(matches!(
+            events[2],
+            StreamEvent::Finish(ResponseMetadata {
+                finish_reason: Some(FinishReason::Stop),
+                ..
+            })
+        ));
+        assert_eq!(
+            std::fs::read_to_string(&output_path).unwrap(),
+            "Hello, world"
+        );
+        std::fs::remove_file(recording_path).unwrap();
+        std::fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn output_of_a_recovered_stream_continues_after_the_persisted_text() {
+        let output_path =
+            std::env::temp_dir().join(format!("golem-llm-resumed-{}.txt", std::process::id()));
+
+        // Text received after the last persisted events is cut off
+        std::fs::write(&output_path, "Hello, world and more").unwrap();
+        let mut output = StreamOutput::new(output_path.clone());
+        output.resume("Hello, world").unwrap();
+        assert_eq!(output.write(vec![text_delta("!")]).unwrap(), vec![]);
+        assert_eq!(
+            std::fs::read_to_string(&output_path).unwrap(),
+            "Hello, world!"
+        );
+
+        // Persisted text missing from the file is written again
+        std::fs::write(&output_path, "Hello").unwrap();
+        let mut output = StreamOutput::new(output_path.clone());
+        output.resume("Hello, world").unwrap();
+        output.write(vec![text_delta("!")]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output_path).unwrap(),
+            "Hello, world!"
+        );
+
+        assert_eq!(
+            StreamOutput::skip(vec![text_delta("Hello"), StreamEvent::Heartbeat]),
+            vec![StreamEvent::Heartbeat]
+        );
+        std::fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn recorded_stream_is_replayed_identically() {
+        let recording_path =
+            std::env::temp_dir().join(format!("golem-llm-recording-{}.sse", std::process::id()));
+        let _ = std::fs::remove_file(&recording_path);
+        let recorder = || {
+            LlmChatStream::new(DecodingChatStream::new())
+                .with_recording(Some(recording_path.clone()))
+                .with_heartbeats(true, None)
+        };
+
+        // A retried stream appends to the recording of the first attempt
+        let first_attempt = recorder();
+        let mut recorded_events = first_attempt.process_message("Hello");
+        drop(first_attempt);
+        let recorded = recorder();
+        recorded_events.extend(recorded.process_comment("keep-alive"));
+        for message in ["multi\nline", "tool:{}", "stop", "[DONE]"] {
+            recorded_events.extend(recorded.assemble_tool_calls(recorded.process_message(message)));
+        }
+        drop(recorded);
+
+        let recording = std::fs::read_to_string(&recording_path).unwrap();
+        assert!(recording.starts_with("data: Hello\n\n: keep-alive\n\ndata: multi\ndata: line\n\n"));
+
+        let replayed = LlmChatStream::new(DecodingChatStream::new())
+            .with_replay(recording_path.clone())
+            .with_heartbeats(true, None)
+            .with_max_buffered_events(2);
+        let mut replayed_events = Vec::new();
+        loop {
+            match replayed.get_next() {
+                Some(events) if events.is_empty() => break,
+                Some(events) => replayed_events.extend(events),
+                None => {}
+            }
+        }
+
+        assert_eq!(recorded_events.len(), 6);
+        assert_eq!(recorded_events[1], StreamEvent::Heartbeat);
+        assert_eq!(replayed_events, recorded_events);
+        std::fs::remove_file(recording_path).unwrap();
+    }
+
+    #[test]
+    fn stream_options_are_read_from_the_config() {
+        let options = [
+            ("idle_timeout_secs", "5"),
+            ("max_buffered_events", "3"),
+            ("finish_reason_hints", "true"),
+            ("heartbeats", "true"),
+            ("heartbeat_interval_ms", "250"),
+            ("stream_record_file", "recording.sse"),
+        ];
+        let recorded = LlmChatStream::new(DecodingChatStream::new())
+            .with_stream_options(&config("model", &options));
+        assert_eq!(recorded.idle_timeout, Duration::from_secs(5));
+        assert_eq!(recorded.max_buffered_events, 3);
+        assert!(recorded.finish_reason_hints);
+        assert!(recorded.heartbeats);
+        assert_eq!(
+            recorded.heartbeat_interval,
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(recorded.record_path, Some("recording.sse".into()));
+        assert_eq!(recorded.replay_path, None);
+
+        // A replayed stream is not recorded again
+        let replayed = LlmChatStream::new(DecodingChatStream::new()).with_stream_options(&config(
+            "model",
+            &[
+                ("stream_record_file", "recording.sse"),
+                ("stream_replay_file", "recording.sse"),
+            ],
+        ));
+        assert_eq!(replayed.record_path, None);
+        assert_eq!(replayed.replay_path, Some("recording.sse".into()));
+    }
+
+    #[test]
+    fn keep_alive_comments_become_heartbeats() {
+        let recording_path =
+            std::env::temp_dir().join(format!("golem-llm-keep-alive-{}.sse", std::process::id()));
+        std::fs::write(
+            &recording_path,
+            ": OPENROUTER PROCESSING\n\ndata: Hello\n\n: ping\n\ndata: stop\n\ndata: [DONE]\n\n",
+        )
+        .unwrap();
+        let replay = |heartbeats: bool| {
+            let stream = LlmChatStream::new(DecodingChatStream::new())
+                .with_replay(recording_path.clone())
+                .with_heartbeats(heartbeats, None);
+            let mut events = Vec::new();
+            loop {
+                match stream.get_next() {
+                    Some(batch) if batch.is_empty() => break events,
+                    Some(batch) => events.extend(batch),
+                    None => {}
+                }
+            }
+        };
+
+        let with_heartbeats = replay(true);
+        let without_heartbeats = replay(false);
+        std::fs::remove_file(&recording_path).unwrap();
+
+        assert_eq!(with_heartbeats[0], StreamEvent::Heartbeat);
+        assert_eq!(with_heartbeats[1], text_delta("Hello"));
+        assert_eq!(with_heartbeats[2], StreamEvent::Heartbeat);
+        assert_eq!(
+            with_heartbeats
+                .into_iter()
+                .filter(|event| *event != StreamEvent::Heartbeat)
+                .collect::<Vec<_>>(),
+            without_heartbeats
+        );
+        assert_eq!(without_heartbeats.len(), 2);
+    }
+
+    #[test]
+    fn fast_stream_is_read_in_bounded_steps() {
+        // A provider that always has the next event ready
+        let mut sent = 0;
+        let mut poll_next = || {
+            sent += 1;
+            Poll::Ready(Some(Ok::<_, ()>(format!("event {sent}"))))
+        };
+
+        for step in 1..=3 {
+            let ready = read_ready_events(16, &mut poll_next);
+            assert_eq!(ready.events.len(), 16);
+            assert_eq!(
+                ready.events.last().unwrap(),
+                &format!("event {}", step * 16)
+            );
+            assert!(ready.end.is_none());
+        }
+        assert_eq!(sent, 48);
+    }
+
+    #[test]
+    fn ready_events_stop_at_the_end_of_the_stream() {
+        let mut remaining = VecDeque::from([
+            Poll::Ready(Some(Ok("first"))),
+            Poll::Ready(Some(Ok("second"))),
+            Poll::Ready(Some(Err("connection reset"))),
+        ]);
+
+        let ready = read_ready_events(16, || remaining.pop_front().unwrap_or(Poll::Pending));
+        assert_eq!(ready.events, vec!["first", "second"]);
+        assert_eq!(ready.end, Some(Err("connection reset")));
+
+        let ready = read_ready_events(16, || remaining.pop_front().unwrap_or(Poll::Pending));
+        assert!(ready.is_empty());
+    }
+
+    struct ReplayedChatStream {
+        batches: RefCell<VecDeque<Vec<StreamEvent>>>,
+    }
+
+    impl ReplayedChatStream {
+        fn new(batches: Vec<Vec<StreamEvent>>) -> Self {
+            Self {
+                batches: RefCell::new(batches.into()),
+            }
+        }
+    }
+
+    impl GuestChatStream for ReplayedChatStream {
+        fn get_next(&self) -> Option<Vec<StreamEvent>> {
+            Some(self.batches.borrow_mut().pop_front().unwrap_or_default())
+        }
+
+        fn blocking_get_next(&self) -> Vec<StreamEvent> {
+            self.get_next().unwrap()
+        }
+
+        fn cancel(&self) {}
+    }
+
+    fn text_delta(text: &str) -> StreamEvent {
+        StreamEvent::Delta(StreamDelta {
+            content: Some(vec![ContentPart::Text(text.to_string())]),
+            tool_calls: None,
+            reasoning: None,
+            choice_index: None,
+        })
+    }
+
+    fn tool_call_delta(id: &str, name: &str, arguments_json: &str) -> StreamEvent {
+        StreamEvent::Delta(StreamDelta {
+            content: None,
+            tool_calls: Some(vec![ToolCall {
+                id: id.to_string(),
+                name: name.to_string(),
+                arguments_json: arguments_json.to_string(),
+            }]),
+            reasoning: None,
+            choice_index: None,
+        })
+    }
+
+    fn metadata(finish_reason: FinishReason) -> ResponseMetadata {
+        ResponseMetadata {
+            finish_reason: Some(finish_reason),
+            usage: Some(Usage {
+                input_tokens: Some(10),
+                output_tokens: Some(5),
+                total_tokens: Some(15),
+            }),
+            provider_id: Some("provider".to_string()),
+            timestamp: None,
+            provider_metadata_json: None,
+            stop_sequence: None,
+            tool_calls: None,
+        }
+    }
+
+    #[test]
+    fn collect_stream_assembles_complete_response() {
+        let stream = ReplayedChatStream::new(vec![
+            vec![text_delta("Hello"), text_delta(", ")],
+            vec![text_delta("world!")],
+            vec![tool_call_delta("call-1", "get_weather", "{\"city\":")],
+            vec![tool_call_delta("call-1", "", "\"Berlin\"}")],
+            vec![StreamEvent::Finish(metadata(FinishReason::Stop))],
+        ]);
+
+        assert_eq!(
+            collect_stream(&stream),
+            ChatEvent::Message(CompleteResponse {
+                id: String::new(),
+                content: vec![ContentPart::Text("Hello, world!".to_string())],
+                tool_calls: vec![ToolCall {
+                    id: "call-1".to_string(),
+                    name: "get_weather".to_string(),
+                    arguments_json: "{\"city\":\"Berlin\"}".to_string(),
+                }],
+                metadata: metadata(FinishReason::Stop),
+            })
+        );
+    }
+
+    #[test]
+    fn collect_stream_does_not_repeat_cumulative_tool_arguments() {
+        // OpenAI compatible streams repeat the arguments received so far in every fragment
+        let stream = ReplayedChatStream::new(vec![
+            vec![tool_call_delta("call-1", "get_weather", "")],
+            vec![tool_call_delta("call-1", "get_weather", "{\"city\":")],
+            vec![tool_call_delta(
+                "call-1",
+                "get_weather",
+                "{\"city\":\"Berlin\"}",
+            )],
+            vec![StreamEvent::Finish(metadata(FinishReason::ToolCalls))],
+        ]);
+
+        assert_eq!(
+            collect_stream(&stream),
+            ChatEvent::ToolRequest(vec![ToolCall {
+                id: "call-1".to_string(),
+                name: "get_weather".to_string(),
+                arguments_json: "{\"city\":\"Berlin\"}".to_string(),
+            }])
+        );
+    }
+
+    #[test]
+    fn events_are_iterated_until_finish() {
+        let batches = vec![
+            vec![text_delta("Hello"), text_delta(", ")],
+            vec![tool_call_delta("call-1", "get_weather", "{}")],
+            vec![
+                StreamEvent::Finish(metadata(FinishReason::ToolCalls)),
+                text_delta("ignored"),
+            ],
+            vec![text_delta("after finish")],
+        ];
+
+        let mut expected = Vec::new();
+        let manual = ReplayedChatStream::new(batches.clone());
+        'outer: loop {
+            for event in manual.blocking_get_next() {
+                let finished = matches!(event, StreamEvent::Finish(_));
+                expected.push(event);
+                if finished {
+                    break 'outer;
+                }
+            }
+        }
+
+        let stream = ReplayedChatStream::new(batches);
+        let events = stream.events().collect::<Vec<_>>();
+        assert_eq!(events, expected);
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events.last(), Some(StreamEvent::Finish(_))));
+    }
+
+    /// Sends a word with every call, and never finishes
+    struct EndlessChatStream {
+        cancelled: Rc<Cell<bool>>,
+        dropped: Rc<Cell<bool>>,
+    }
+
+    impl GuestChatStream for EndlessChatStream {
+        fn get_next(&self) -> Option<Vec<StreamEvent>> {
+            Some(vec![text_delta("word ")])
+        }
+
+        fn blocking_get_next(&self) -> Vec<StreamEvent> {
+            self.get_next().unwrap()
+        }
+
+        fn cancel(&self) {
+            self.cancelled.set(true);
+        }
+    }
+
+    impl Drop for EndlessChatStream {
+        fn drop(&mut self) {
+            self.dropped.set(true);
+        }
+    }
+
+    #[test]
+    fn collect_stream_until_returns_partial_response_when_budget_is_spent() {
+        let cancelled = Rc::new(Cell::new(false));
+        let dropped = Rc::new(Cell::new(false));
+        let stream = EndlessChatStream {
+            cancelled: cancelled.clone(),
+            dropped: dropped.clone(),
+        };
+
+        let PartialResponse { event, cut_short } =
+            collect_stream_until(stream, Duration::from_secs(60), Some(3));
+
+        assert!(cut_short);
+        assert!(cancelled.get());
+        assert!(dropped.get());
+        let ChatEvent::Message(response) = event else {
+            panic!("expected a partial message");
+        };
+        assert_eq!(
+            response.content,
+            vec![ContentPart::Text("word word word ".to_string())]
+        );
+        assert_eq!(response.metadata.finish_reason, None);
+    }
+
+    #[test]
+    fn collect_stream_until_leaves_out_incomplete_tool_calls() {
+        let stream = ReplayedChatStream::new(vec![
+            vec![text_delta("Let me check. ")],
+            vec![tool_call_delta("call-1", "get_weather", r#"{"city": "#)],
+            vec![tool_call_delta("call-1", "", r#""Paris"}"#)],
+        ]);
+
+        let partial = collect_stream_until(stream, Duration::from_secs(60), Some(2));
+
+        assert!(partial.cut_short);
+        let ChatEvent::Message(response) = partial.event else {
+            panic!("expected a partial message");
+        };
+        assert_eq!(
+            response.content,
+            vec![ContentPart::Text("Let me check. ".to_string())]
+        );
+        assert!(response.tool_calls.is_empty());
+    }
+
+    #[test]
+    fn collect_stream_until_returns_finished_stream_in_full() {
+        let stream = ReplayedChatStream::new(vec![
+            vec![text_delta("Hello")],
+            vec![StreamEvent::Finish(metadata(FinishReason::Stop))],
+        ]);
+
+        let partial = collect_stream_until(stream, Duration::from_secs(60), Some(2));
+
+        assert!(!partial.cut_short);
+        assert!(matches!(
+            partial.event,
+            ChatEvent::Message(CompleteResponse { ref content, .. })
+                if *content == vec![ContentPart::Text("Hello".to_string())]
+        ));
+    }
+
+    #[test]
+    fn collect_stream_until_keeps_the_finish_of_the_last_batch() {
+        let stream = ReplayedChatStream::new(vec![vec![
+            text_delta("Hello"),
+            text_delta(" there"),
+            StreamEvent::Finish(metadata(FinishReason::Stop)),
+        ]]);
+
+        let partial = collect_stream_until(stream, Duration::from_secs(60), Some(1));
+
+        assert!(!partial.cut_short);
+        let ChatEvent::Message(response) = partial.event else {
+            panic!("expected a message");
+        };
+        assert_eq!(
+            response.content,
+            vec![ContentPart::Text("Hello there".to_string())]
+        );
+        assert_eq!(response.metadata.finish_reason, Some(FinishReason::Stop));
+    }
+
+    /// Sends a word right away, and the next ones only a second apart
+    struct SlowChatStream {
+        next_word_at: Cell<Instant>,
+        cancelled: Rc<Cell<bool>>,
+    }
+
+    impl GuestChatStream for SlowChatStream {
+        fn get_next(&self) -> Option<Vec<StreamEvent>> {
+            if Instant::now() < self.next_word_at.get() {
+                return None;
+            }
+            self.next_word_at
+                .set(Instant::now() + Duration::from_secs(1));
+            Some(vec![text_delta("word ")])
+        }
+
+        fn blocking_get_next(&self) -> Vec<StreamEvent> {
+            unimplemented!()
+        }
+
+        fn cancel(&self) {
+            self.cancelled.set(true);
+        }
+    }
+
+    #[test]
+    fn collect_stream_until_returns_partial_response_at_the_deadline() {
+        let cancelled = Rc::new(Cell::new(false));
+        let stream = SlowChatStream {
+            next_word_at: Cell::new(Instant::now()),
+            cancelled: cancelled.clone(),
+        };
+
+        let partial = collect_stream_until(stream, Duration::from_millis(50), None);
+
+        assert!(partial.cut_short);
+        assert!(cancelled.get());
+        let ChatEvent::Message(response) = partial.event else {
+            panic!("expected a partial message");
+        };
+        assert_eq!(
+            response.content,
+            vec![ContentPart::Text("word ".to_string())]
+        );
+        assert_eq!(response.metadata.finish_reason, None);
+    }
+
+    #[test]
+    fn collect_stream_returns_tool_request_without_content() {
+        let stream = ReplayedChatStream::new(vec![
+            vec![tool_call_delta("call-1", "get_time", "{}")],
+            vec![StreamEvent::Finish(metadata(FinishReason::ToolCalls))],
+        ]);
+
+        assert_eq!(
+            collect_stream(&stream),
+            ChatEvent::ToolRequest(vec![ToolCall {
+                id: "call-1".to_string(),
+                name: "get_time".to_string(),
+                arguments_json: "{}".to_string(),
+            }])
+        );
+    }
+
+    #[test]
+    fn collect_stream_returns_stream_error() {
+        let error = Error {
+            code: ErrorCode::RateLimitExceeded,
+            message: "Too many requests".to_string(),
+            provider_error_json: None,
+        };
+        let stream = ReplayedChatStream::new(vec![
+            vec![text_delta("Hel")],
+            vec![StreamEvent::Error(error.clone())],
+        ]);
+
+        assert_eq!(collect_stream(&stream), ChatEvent::Error(error));
+    }
+
+    #[test]
+    fn finish_marker_only_appears_in_debug_text() {
+        let stream = ReplayedChatStream::new(vec![
+            vec![text_delta("Once upon a")],
+            vec![StreamEvent::Finish(metadata(FinishReason::Length))],
+        ]);
+        let response = collect_stream(&stream);
+        assert_eq!(
+            debug_text(&response, &config("gpt-4o", &[("debug_finish", "true")])),
+            "Once upon a\n[finish: length]"
+        );
+        assert_eq!(debug_text(&response, &config("gpt-4o", &[])), "Once upon a");
+        assert!(matches!(
+            response,
+            ChatEvent::Message(CompleteResponse { content, .. })
+                if content == vec![ContentPart::Text("Once upon a".to_string())]
+        ));
+    }
+
+    #[test]
+    fn text_stream_yields_only_text() {
+        let error = Error {
+            code: ErrorCode::InternalError,
+            message: "Connection reset".to_string(),
+            provider_error_json: None,
+        };
+        let stream = ReplayedChatStream::new(vec![
+            vec![
+                text_delta("Hello"),
+                tool_call_delta("call-1", "get_time", "{}"),
+            ],
+            vec![StreamEvent::Delta(StreamDelta {
+                content: Some(vec![
+                    ContentPart::Image(ImageReference::Url(ImageUrl {
+                        url: "https://example.com/image.png".to_string(),
+                        detail: None,
+                    })),
+                    ContentPart::Text(", world!".to_string()),
+                ]),
+                tool_calls: None,
+                reasoning: None,
+                choice_index: None,
+            })],
+            vec![StreamEvent::Error(error.clone())],
+        ]);
+
+        assert_eq!(
+            TextStream::new(&stream).collect::<Vec<_>>(),
+            vec![
+                Ok("Hello".to_string()),
+                Ok(", world!".to_string()),
+                Err(error)
+            ]
+        );
+    }
+
+    fn filtered_text(filter: &mut ResumedStreamFilter, deltas: &[&str]) -> String {
+        let mut text = String::new();
+        for delta in deltas {
+            for event in filter
+                .filter(Some(vec![text_delta(delta)]))
+                .unwrap_or_default()
+            {
+                if let StreamEvent::Delta(StreamDelta {
+                    content: Some(content),
+                    ..
+                }) = event
+                {
+                    for part in content {
+                        if let ContentPart::Text(part) = part {
+                            text.push_str(&part);
+                        }
+                    }
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn resumed_stream_overlap_is_trimmed_once() {
+        let mut filter = ResumedStreamFilter::new("The weather in Berlin is".to_string());
+
+        assert_eq!(filter.filter(Some(vec![text_delta("Berlin")])), None);
+        assert_eq!(
+            filtered_text(&mut filter, &[" is sun", "ny. Berlin is", " warm."]),
+            " sunny. Berlin is warm."
+        );
+    }
+
+    #[test]
+    fn resumed_stream_without_overlap_is_unchanged() {
+        let mut filter = ResumedStreamFilter::new("The weather in Berlin is".to_string());
+
+        assert_eq!(
+            filtered_text(&mut filter, &["s", "unny", " today."]),
+            "sunny today."
+        );
+    }
+
+    #[test]
+    fn pending_resumed_text_is_flushed_at_end_of_stream() {
+        let mut filter = ResumedStreamFilter::new("The weather in Berlin is".to_string());
+
+        assert_eq!(filter.filter(Some(vec![text_delta("Berl")])), None);
+        assert_eq!(filter.filter(Some(vec![])), Some(vec![text_delta("Berl")]));
+        assert_eq!(filter.filter(Some(vec![])), Some(vec![]));
+    }
 }
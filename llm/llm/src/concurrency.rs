@@ -0,0 +1,186 @@
+use crate::config::provider_option;
+use crate::golem::llm::llm::Config;
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+
+/// Caps the number of requests in flight to a provider. Requests over the cap wait until one of
+/// the running requests releases its permit, and the waiting request with the highest priority
+/// gets it. Requests of the same priority are served in the order they started waiting.
+pub struct RequestLimiter {
+    max_concurrent_requests: usize,
+    state: Mutex<LimiterState>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct LimiterState {
+    in_flight: usize,
+    /// The requests waiting for a permit, ordered by descending priority and then by arrival
+    waiting: BTreeSet<(Reverse<i64>, u64)>,
+    next_ticket: u64,
+}
+
+impl RequestLimiter {
+    pub fn new(max_concurrent_requests: usize) -> Self {
+        Self {
+            max_concurrent_requests: max_concurrent_requests.max(1),
+            state: Mutex::new(LimiterState::default()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits until fewer than the maximum number of requests are in flight and no waiting request
+    /// has a higher priority, then takes a permit, which is released when it is dropped
+    pub fn acquire(self: &Arc<Self>, priority: i64) -> RequestPermit {
+        let mut state = self.state.lock().unwrap();
+        let ticket = (Reverse(priority), state.next_ticket);
+        state.next_ticket += 1;
+        state.waiting.insert(ticket);
+        while state.in_flight >= self.max_concurrent_requests
+            || state.waiting.first() != Some(&ticket)
+        {
+            state = self.released.wait(state).unwrap();
+        }
+        state.waiting.remove(&ticket);
+        state.in_flight += 1;
+        // The next waiting request may fit as well
+        self.released.notify_all();
+
+        RequestPermit {
+            limiter: self.clone(),
+        }
+    }
+}
+
+/// A request slot of a `RequestLimiter`, held for the duration of a request
+pub struct RequestPermit {
+    limiter: Arc<RequestLimiter>,
+}
+
+impl Drop for RequestPermit {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().in_flight -= 1;
+        // Only the waiting request with the highest priority may take the permit, so all of them
+        // are woken up to check
+        self.limiter.released.notify_all();
+    }
+}
+
+/// Takes a request slot for the provider and API key if the `max_concurrent_requests` provider
+/// option is set, waiting while the configured number of requests are already in flight. Waiting
+/// requests with a higher `priority` provider option, an integer defaulting to 0, get a slot first.
+///
+/// The limit is shared by all requests using the same provider and API key. Only a hash of the
+/// key is kept to tell them apart.
+pub fn acquire_request_slot(
+    config: &Config,
+    provider: &str,
+    api_key: &str,
+) -> Option<RequestPermit> {
+    let max_concurrent_requests: usize = provider_option(config, "max_concurrent_requests")?
+        .parse()
+        .ok()?;
+
+    let mut hasher = DefaultHasher::new();
+    api_key.hash(&mut hasher);
+    let key = format!("{provider}:{:x}", hasher.finish());
+
+    static LIMITERS: OnceLock<Mutex<HashMap<String, Arc<RequestLimiter>>>> = OnceLock::new();
+    let limiter = {
+        let mut limiters = LIMITERS.get_or_init(Default::default).lock().unwrap();
+        let limiter = limiters
+            .entry(key)
+            .or_insert_with(|| Arc::new(RequestLimiter::new(max_concurrent_requests)));
+        if limiter.max_concurrent_requests != max_concurrent_requests.max(1) {
+            *limiter = Arc::new(RequestLimiter::new(max_concurrent_requests));
+        }
+        limiter.clone()
+    };
+
+    let priority = provider_option(config, "priority")
+        .and_then(|priority| priority.parse().ok())
+        .unwrap_or(0);
+    Some(limiter.acquire(priority))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concurrency::{acquire_request_slot, RequestLimiter};
+    use crate::testing::config;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn in_flight_requests_never_exceed_the_cap() {
+        let limiter = Arc::new(RequestLimiter::new(3));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let requests = (0..12)
+            .map(|_| {
+                let limiter = limiter.clone();
+                let in_flight = in_flight.clone();
+                let peak = peak.clone();
+                thread::spawn(move || {
+                    let _permit = limiter.acquire(0);
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(current, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect::<Vec<_>>();
+        for request in requests {
+            request.join().unwrap();
+        }
+
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+        assert_eq!(limiter.state.lock().unwrap().in_flight, 0);
+    }
+
+    #[test]
+    fn requests_with_other_api_keys_do_not_wait() {
+        let config = config("model", &[("max_concurrent_requests", "1")]);
+        let _running = acquire_request_slot(&config, "limited", "first-key");
+
+        let other =
+            thread::spawn(move || acquire_request_slot(&config, "limited", "second-key").is_some());
+
+        assert!(other.join().unwrap());
+    }
+
+    #[test]
+    fn higher_priority_requests_are_dispatched_first() {
+        let limiter = Arc::new(RequestLimiter::new(1));
+        let dispatched = Arc::new(Mutex::new(Vec::new()));
+        let running = limiter.acquire(0);
+
+        let requests = [1, -5, 10, 0, 3]
+            .into_iter()
+            .enumerate()
+            .map(|(index, priority)| {
+                let request_limiter = limiter.clone();
+                let dispatched = dispatched.clone();
+                let request = thread::spawn(move || {
+                    let _permit = request_limiter.acquire(priority);
+                    dispatched.lock().unwrap().push(priority);
+                });
+                while limiter.state.lock().unwrap().waiting.len() <= index {
+                    thread::yield_now();
+                }
+                request
+            })
+            .collect::<Vec<_>>();
+        drop(running);
+        for request in requests {
+            request.join().unwrap();
+        }
+
+        assert_eq!(*dispatched.lock().unwrap(), vec![10, 3, 1, 0, -5]);
+    }
+}
@@ -1,5 +1,10 @@
-use crate::golem::llm::llm::{Error, ErrorCode};
+use crate::error::unsupported;
+use crate::golem::llm::llm::{Config, Error, ErrorCode};
+use crate::model_alias::find_by_model_prefix;
+use log::trace;
 use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// Gets an expected configuration value from the environment, and fails if its is not found
 /// using the `fail` function. Otherwise, it runs `succeed` with the configuration value.
@@ -21,3 +26,301 @@ pub fn with_config_key<R>(
         }
     }
 }
+
+/// Gets the value of a provider option from the configuration, if it is set
+pub fn provider_option<'a>(config: &'a Config, key: &str) -> Option<&'a str> {
+    config
+        .provider_options
+        .iter()
+        .find(|kv| kv.key == key)
+        .map(|kv| kv.value.as_str())
+}
+
+/// Gets the streaming idle timeout from the `idle_timeout_secs` provider option, falling back to
+/// `DEFAULT_IDLE_TIMEOUT` if it is not set or invalid.
+pub fn idle_timeout(config: &Config) -> Duration {
+    provider_option(config, "idle_timeout_secs")
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(crate::chat_stream::DEFAULT_IDLE_TIMEOUT)
+}
+
+/// Gets how many raw events a stream reads from the provider at once from the
+/// `max_buffered_events` provider option, falling back to `DEFAULT_MAX_BUFFERED_EVENTS` if it is
+/// not set or invalid.
+pub fn max_buffered_events(config: &Config) -> usize {
+    provider_option(config, "max_buffered_events")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(crate::chat_stream::DEFAULT_MAX_BUFFERED_EVENTS)
+}
+
+/// Whether streams emit a `finish-reason-hint` event as soon as the finish reason is known, set
+/// by the `finish_reason_hints` provider option
+pub fn finish_reason_hints(config: &Config) -> bool {
+    provider_option(config, "finish_reason_hints") == Some("true")
+}
+
+/// Whether streams emit a `heartbeat` event for every keep-alive the provider sends, set by the
+/// `heartbeats` provider option
+pub fn heartbeats(config: &Config) -> bool {
+    provider_option(config, "heartbeats") == Some("true")
+}
+
+/// Gets after how long without any event a stream emits a `heartbeat` from the
+/// `heartbeat_interval_ms` provider option, if it is set
+pub fn heartbeat_interval(config: &Config) -> Option<Duration> {
+    provider_option(config, "heartbeat_interval_ms")
+        .and_then(|value| value.parse().ok())
+        .filter(|interval_ms| *interval_ms > 0)
+        .map(Duration::from_millis)
+}
+
+/// Whether `debug_text` marks the end of a response with its finish reason, set by the
+/// `debug_finish` provider option
+pub fn debug_finish(config: &Config) -> bool {
+    provider_option(config, "debug_finish") == Some("true")
+}
+
+/// Gets the largest request body sent to the provider from the `max_request_bytes` provider
+/// option, falling back to the provider's documented limit, if there is one
+pub fn max_request_bytes(config: &Config, default: Option<usize>) -> Option<usize> {
+    provider_option(config, "max_request_bytes")
+        .and_then(|value| value.parse().ok())
+        .or(default)
+}
+
+/// Gets whether the model may return more than one tool call in a single response from the
+/// `parallel_tool_calls` provider option. It is ignored for requests without tools.
+pub fn parallel_tool_calls(config: &Config) -> Result<Option<bool>, Error> {
+    if config.tools.is_empty() {
+        return Ok(None);
+    }
+    provider_option(config, "parallel_tool_calls")
+        .map(|value| {
+            value.parse::<bool>().map_err(|_| Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!(
+                    "Invalid parallel_tool_calls option: {value}, expected true or false"
+                ),
+                provider_error_json: None,
+            })
+        })
+        .transpose()
+}
+
+/// Fails with an `unsupported` error if the `danger_accept_invalid_certs` provider option is set.
+/// Requests are sent through the host's WASI HTTP implementation, which does not let the component
+/// turn off certificate verification, so the option cannot be honoured.
+pub fn reject_invalid_certs_option(config: &Config) -> Result<(), Error> {
+    match provider_option(config, "danger_accept_invalid_certs") {
+        Some(_) => Err(unsupported(
+            "danger_accept_invalid_certs, certificates are verified by the host's HTTP client",
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Gets the file the text of a streamed response is written to from the `stream_output_file`
+/// provider option, if it is set
+pub fn stream_output_file(config: &Config) -> Option<PathBuf> {
+    provider_option(config, "stream_output_file").map(PathBuf::from)
+}
+
+/// Removes the `stream_output_file` provider option from the configuration, for streams whose
+/// text is written to the output file by a wrapper instead
+pub fn without_stream_output_file(config: &Config) -> Config {
+    let mut config = config.clone();
+    config
+        .provider_options
+        .retain(|kv| kv.key != "stream_output_file");
+    config
+}
+
+/// Gets the file the raw events of a stream are recorded to from the `stream_record_file` provider
+/// option, if it is set
+pub fn stream_record_file(config: &Config) -> Option<PathBuf> {
+    provider_option(config, "stream_record_file").map(PathBuf::from)
+}
+
+/// Gets the recording a stream is replayed from instead of calling the provider from the
+/// `stream_replay_file` provider option, if it is set
+pub fn stream_replay_file(config: &Config) -> Option<PathBuf> {
+    provider_option(config, "stream_replay_file").map(PathBuf::from)
+}
+
+/// Gets the `User-Agent` sent with the requests to a provider from the `user_agent` provider
+/// option, defaulting to `golem-llm/<version> (<provider>)`
+pub fn user_agent(config: &Config, provider: &str) -> String {
+    provider_option(config, "user_agent")
+        .map(|user_agent| user_agent.to_string())
+        .unwrap_or_else(|| format!("golem-llm/{} ({provider})", env!("CARGO_PKG_VERSION")))
+}
+
+/// Default completion limits of known models by the prefix of their name, used when `max_tokens`
+/// is not set, as some providers apply a surprisingly small default otherwise
+const DEFAULT_MAX_TOKENS: [(&str, u32); 15] = [
+    ("claude-3-haiku", 4096),
+    ("claude-3-opus", 4096),
+    ("claude-3-5-haiku", 8192),
+    ("claude-3-5-sonnet", 8192),
+    ("claude-3-7-sonnet", 16384),
+    ("claude-sonnet-4", 16384),
+    ("claude-opus-4", 16384),
+    ("gpt-4-turbo", 4096),
+    ("gpt-4o", 16384),
+    ("gpt-4.1", 32768),
+    ("o1", 32768),
+    ("o3", 32768),
+    ("o4-mini", 32768),
+    ("grok-2", 8192),
+    ("grok-3", 16384),
+];
+
+/// Gets the default completion limit of a known model
+pub fn default_max_tokens(model: &str) -> Option<u32> {
+    find_by_model_prefix(model, &DEFAULT_MAX_TOKENS, |(prefix, _)| prefix)
+        .map(|(_, max_tokens)| *max_tokens)
+}
+
+/// Gets the completion limit to send to the provider: the configured `max_tokens` if set, otherwise
+/// the default of the model, unless the `no_default_max_tokens` provider option is `true`.
+pub fn max_tokens(config: &Config) -> Option<u32> {
+    config.max_tokens.or_else(|| {
+        if provider_option(config, "no_default_max_tokens") == Some("true") {
+            None
+        } else {
+            default_max_tokens(&config.model)
+        }
+    })
+}
+
+/// Prefixes of the names of models rejecting any temperature, like OpenAI's reasoning models
+const FIXED_TEMPERATURE_MODELS: [&str; 4] = ["o1", "o3", "o4-mini", "gpt-5"];
+
+/// Gets the temperature to send to the provider: the configured `temperature` if set. Otherwise,
+/// none is sent unless the `default_temperature` provider option is a number, or `provider` for the
+/// recommended default of the provider.
+///
+/// No temperature is sent to models known to reject it, unless the `strip_temperature` provider
+/// option is `false`.
+pub fn temperature(config: &Config, provider_default: f32) -> Result<Option<f32>, Error> {
+    let default = match provider_option(config, "default_temperature") {
+        None => None,
+        Some("provider") => Some(provider_default),
+        Some(value) => Some(value.parse::<f32>().map_err(|_| Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "Invalid default_temperature option: {value}, expected a number or provider"
+            ),
+            provider_error_json: None,
+        })?),
+    };
+    let temperature = config.temperature.or(default);
+
+    if temperature.is_some()
+        && find_by_model_prefix(&config.model, &FIXED_TEMPERATURE_MODELS, |prefix| prefix).is_some()
+        && provider_option(config, "strip_temperature") != Some("false")
+    {
+        trace!(
+            "Not sending a temperature, as {} does not support it",
+            config.model
+        );
+        return Ok(None);
+    }
+    Ok(temperature)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{
+        default_max_tokens, max_tokens, reject_invalid_certs_option, temperature, user_agent,
+    };
+    use crate::golem::llm::llm::{Config, ErrorCode, Kv};
+    use crate::testing::config;
+
+    #[test]
+    fn default_max_tokens_of_known_models() {
+        assert_eq!(default_max_tokens("claude-3-5-sonnet-20241022"), Some(8192));
+        assert_eq!(default_max_tokens("gpt-4o-mini"), Some(16384));
+        assert_eq!(default_max_tokens("openai/gpt-4.1"), Some(32768));
+        assert_eq!(default_max_tokens("llama3.2:latest"), None);
+    }
+
+    #[test]
+    fn default_max_tokens_is_applied_when_unset() {
+        assert_eq!(max_tokens(&config("gpt-4o", &[])), Some(16384));
+        assert_eq!(
+            max_tokens(&Config {
+                max_tokens: Some(100),
+                ..config("gpt-4o", &[])
+            }),
+            Some(100)
+        );
+    }
+
+    #[test]
+    fn default_max_tokens_can_be_disabled() {
+        assert_eq!(
+            max_tokens(&config("gpt-4o", &[("no_default_max_tokens", "true")])),
+            None
+        );
+    }
+
+    #[test]
+    fn user_agent_defaults_to_the_crate_version() {
+        assert_eq!(
+            user_agent(&config("gpt-4o", &[]), "openai"),
+            format!("golem-llm/{} (openai)", env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(
+            user_agent(&config("gpt-4o", &[("user_agent", "my-app/1.0")]), "openai"),
+            "my-app/1.0"
+        );
+    }
+
+    #[test]
+    fn temperature_defaults_are_only_injected_when_requested() {
+        assert_eq!(temperature(&config("gpt-4o", &[]), 1.0), Ok(None));
+
+        let provider = config("gpt-4o", &[("default_temperature", "provider")]);
+        assert_eq!(temperature(&provider, 1.0), Ok(Some(1.0)));
+
+        let mut configured = config("gpt-4o", &[("default_temperature", "0.3")]);
+        assert_eq!(temperature(&configured, 1.0), Ok(Some(0.3)));
+        configured.temperature = Some(0.9);
+        assert_eq!(temperature(&configured, 1.0), Ok(Some(0.9)));
+
+        let invalid = config("gpt-4o", &[("default_temperature", "warm")]);
+        assert_eq!(
+            temperature(&invalid, 1.0).unwrap_err().code,
+            ErrorCode::InvalidRequest
+        );
+    }
+
+    #[test]
+    fn temperature_is_stripped_for_models_rejecting_it() {
+        let mut reasoning = config("openai/o3-mini", &[]);
+        reasoning.temperature = Some(0.2);
+        assert_eq!(temperature(&reasoning, 1.0), Ok(None));
+
+        reasoning.provider_options.push(Kv {
+            key: "strip_temperature".to_string(),
+            value: "false".to_string(),
+        });
+        assert_eq!(temperature(&reasoning, 1.0), Ok(Some(0.2)));
+    }
+
+    #[test]
+    fn accepting_invalid_certs_is_rejected() {
+        assert_eq!(
+            reject_invalid_certs_option(&config("llama3.2", &[])),
+            Ok(())
+        );
+
+        let relaxed = config("llama3.2", &[("danger_accept_invalid_certs", "true")]);
+        assert_eq!(
+            reject_invalid_certs_option(&relaxed).unwrap_err().code,
+            ErrorCode::Unsupported
+        );
+    }
+}
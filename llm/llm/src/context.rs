@@ -0,0 +1,403 @@
+use crate::config::provider_option;
+use crate::golem::llm::llm::{
+    Config, ContentPart, Error, ErrorCode, Kv, Message, Role, ToolCall, ToolResult,
+};
+use log::trace;
+
+/// The average number of characters per token the size of a conversation is estimated with. Real
+/// tokenizers differ by model and language, so the estimate is only approximate.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// The estimated tokens taken by the role and framing of each message
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// The estimated tokens of an image, regardless of its size
+const IMAGE_TOKENS: usize = 765;
+
+/// What is shortened first when a conversation exceeds the `max_context_tokens` provider option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextOverflow {
+    /// The oldest messages are dropped, and the system prompt is kept intact
+    PreserveSystem,
+    /// The system prompt is truncated first, and messages are only dropped if that is not enough
+    TrimSystemFirst,
+}
+
+/// Gets the overflow policy from the `context_overflow` provider option, defaulting to
+/// `preserve-system`
+pub fn context_overflow(config: &Config) -> Result<ContextOverflow, Error> {
+    match provider_option(config, "context_overflow") {
+        None | Some("preserve-system") => Ok(ContextOverflow::PreserveSystem),
+        Some("trim-system-first") => Ok(ContextOverflow::TrimSystemFirst),
+        Some(other) => Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "Invalid context_overflow option: {other}, expected preserve-system or trim-system-first"
+            ),
+            provider_error_json: None,
+        }),
+    }
+}
+
+/// Estimates the number of tokens a conversation takes in the context window of the model
+pub fn estimate_tokens(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .map(|message| {
+            MESSAGE_OVERHEAD_TOKENS
+                + message
+                    .content
+                    .iter()
+                    .map(|part| match part {
+                        ContentPart::Text(text) => text.chars().count().div_ceil(CHARS_PER_TOKEN),
+                        ContentPart::Image(_) => IMAGE_TOKENS,
+                        ContentPart::Citation(_) => 0,
+                    })
+                    .sum::<usize>()
+        })
+        .sum()
+}
+
+/// Estimates the number of tokens tool calls and their results take once they are sent as
+/// messages, an assistant message with the call followed by a message with the result
+pub fn estimate_tool_result_tokens(tool_results: &[(ToolCall, ToolResult)]) -> usize {
+    let text_tokens = |text: &str| text.chars().count().div_ceil(CHARS_PER_TOKEN);
+    tool_results
+        .iter()
+        .map(|(tool_call, tool_result)| {
+            let result_tokens = match tool_result {
+                ToolResult::Success(success) => text_tokens(&success.result_json),
+                ToolResult::Error(failure) => text_tokens(&failure.error_message),
+                ToolResult::Image(_) => IMAGE_TOKENS,
+            };
+            2 * MESSAGE_OVERHEAD_TOKENS
+                + text_tokens(&tool_call.name)
+                + text_tokens(&tool_call.arguments_json)
+                + result_tokens
+        })
+        .sum()
+}
+
+/// Sets aside room for tool results that are appended to a conversation after it has been fitted
+/// by `fit_context` and `fit_max_tokens`, by lowering the `max_context_tokens` and
+/// `context_window` provider options by their estimated size. The limits then hold for the
+/// conversation together with the tool results.
+pub fn reserve_context_for_tool_results(
+    mut config: Config,
+    tool_results: &[(ToolCall, ToolResult)],
+) -> Config {
+    let reserved = estimate_tool_result_tokens(tool_results);
+    if reserved == 0 {
+        return config;
+    }
+    for Kv { key, value } in &mut config.provider_options {
+        if key == "max_context_tokens" || key == "context_window" {
+            if let Ok(limit) = value.parse::<usize>() {
+                *value = limit.saturating_sub(reserved).to_string();
+            }
+        }
+    }
+    config
+}
+
+/// Splits a long text into consecutive text parts estimated to take at most `max_tokens` tokens
+/// each, for example to pass a document as labeled chunks. Parts end after whitespace where
+/// possible, so words are not cut, and concatenating them gives back the original text.
+pub fn split_text(text: &str, max_tokens: usize) -> Vec<ContentPart> {
+    let max_chars = max_tokens.max(1) * CHARS_PER_TOKEN;
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let Some((limit, _)) = rest.char_indices().nth(max_chars) else {
+            parts.push(ContentPart::Text(rest.to_string()));
+            break;
+        };
+        let end = rest[..limit]
+            .rfind(char::is_whitespace)
+            .map(|index| index + rest[index..].chars().next().map_or(1, char::len_utf8))
+            .unwrap_or(limit);
+        let (part, remaining) = rest.split_at(end);
+        parts.push(ContentPart::Text(part.to_string()));
+        rest = remaining;
+    }
+    parts
+}
+
+/// Shortens a conversation estimated to exceed the `max_context_tokens` provider option, if it is
+/// set, according to the `context_overflow` policy.
+///
+/// Messages are dropped from the beginning of the conversation, after its leading system
+/// messages, and the kept messages always start at a user message. With `trim-system-first`, the
+/// text of the system messages is truncated from its end before any message is dropped. A
+/// conversation that does not fit even then fails with an `invalid-request` error.
+pub fn fit_context(messages: Vec<Message>, config: &Config) -> Result<Vec<Message>, Error> {
+    let Some(max_tokens) =
+        provider_option(config, "max_context_tokens").and_then(|value| value.parse().ok())
+    else {
+        return Ok(messages);
+    };
+    let overflow = context_overflow(config)?;
+    if estimate_tokens(&messages) <= max_tokens {
+        return Ok(messages);
+    }
+
+    let mut messages = messages;
+    if overflow == ContextOverflow::TrimSystemFirst {
+        truncate_system_messages(&mut messages, max_tokens);
+    }
+    drop_oldest_messages(&mut messages, max_tokens);
+
+    let estimate = estimate_tokens(&messages);
+    if estimate > max_tokens {
+        return Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "The conversation does not fit in the context limit of {max_tokens} tokens, it takes about {estimate} tokens even after dropping older messages"
+            ),
+            provider_error_json: None,
+        });
+    }
+    Ok(messages)
+}
+
+/// Caps the completion limit to the room left in the context window of the model after the
+/// estimated size of the conversation, if the window is given by the `context_window` provider
+/// option, so a large `max_tokens` does not get the request rejected. If the conversation alone
+/// is estimated to fill the window, the limit is left for the provider to reject.
+pub fn fit_max_tokens(
+    max_tokens: Option<u32>,
+    messages: &[Message],
+    config: &Config,
+) -> Option<u32> {
+    let max_tokens = max_tokens?;
+    let Some(context_window) =
+        provider_option(config, "context_window").and_then(|value| value.parse::<usize>().ok())
+    else {
+        return Some(max_tokens);
+    };
+
+    let prompt_tokens = estimate_tokens(messages);
+    let remaining = u32::try_from(context_window.saturating_sub(prompt_tokens)).unwrap_or(u32::MAX);
+    if remaining == 0 || max_tokens <= remaining {
+        return Some(max_tokens);
+    }
+    trace!(
+        "Capping max_tokens from {max_tokens} to {remaining}, the conversation takes about {prompt_tokens} of the {context_window} tokens of the context window"
+    );
+    Some(remaining)
+}
+
+/// Removes text from the end of the system messages, starting with the last one, until the
+/// conversation fits or no system text is left. Emptied system messages are removed.
+fn truncate_system_messages(messages: &mut Vec<Message>, max_tokens: usize) {
+    let excess_tokens = estimate_tokens(messages).saturating_sub(max_tokens);
+    let mut excess_chars = excess_tokens * CHARS_PER_TOKEN;
+
+    for message in messages
+        .iter_mut()
+        .rev()
+        .filter(|message| message.role == Role::System)
+    {
+        for part in message.content.iter_mut().rev() {
+            if excess_chars == 0 {
+                break;
+            }
+            if let ContentPart::Text(text) = part {
+                let length = text.chars().count();
+                let kept = length.saturating_sub(excess_chars);
+                excess_chars -= length - kept;
+                *text = text.chars().take(kept).collect();
+            }
+        }
+        message
+            .content
+            .retain(|part| !matches!(part, ContentPart::Text(text) if text.is_empty()));
+    }
+    messages.retain(|message| message.role != Role::System || !message.content.is_empty());
+}
+
+/// Drops the fewest messages after the leading system messages needed for the conversation to
+/// fit, keeping the remaining messages starting at a user message. If no such cut fits, the
+/// messages are left unchanged.
+fn drop_oldest_messages(messages: &mut Vec<Message>, max_tokens: usize) {
+    let system_count = messages
+        .iter()
+        .take_while(|message| message.role == Role::System)
+        .count();
+    let system_tokens = estimate_tokens(&messages[..system_count]);
+
+    let kept_from = (system_count..messages.len()).find(|&index| {
+        messages[index].role == Role::User
+            && system_tokens + estimate_tokens(&messages[index..]) <= max_tokens
+    });
+    if let Some(kept_from) = kept_from {
+        messages.drain(system_count..kept_from);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::{
+        estimate_tokens, estimate_tool_result_tokens, fit_context, fit_max_tokens,
+        reserve_context_for_tool_results, split_text,
+    };
+    use crate::golem::llm::llm::{
+        ContentPart, ErrorCode, Message, Role, ToolCall, ToolResult, ToolSuccess,
+    };
+    use crate::testing::config;
+
+    fn message(role: Role, text: &str) -> Message {
+        Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        }
+    }
+
+    /// A system prompt of about 110 tokens followed by three exchanges of about 10 tokens per
+    /// message, about 175 tokens in total
+    fn conversation() -> Vec<Message> {
+        vec![
+            message(Role::System, &"Use the tools as follows. ".repeat(16)),
+            message(Role::User, "What is the weather in Paris?"),
+            message(Role::Assistant, "It is sunny in Paris today."),
+            message(Role::User, "And what about Berlin, then?"),
+            message(Role::Assistant, "It is raining in Berlin now."),
+            message(Role::User, "Should I take an umbrella?"),
+            message(Role::Assistant, "Yes, take one to Berlin."),
+        ]
+    }
+
+    #[test]
+    fn system_prompt_is_shortened_first_under_system_first_policy() {
+        let messages = conversation();
+
+        let fitted = fit_context(
+            messages.clone(),
+            &config(
+                "gpt-4o",
+                &[
+                    ("max_context_tokens", "150"),
+                    ("context_overflow", "trim-system-first"),
+                ],
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(fitted.len(), messages.len());
+        assert_eq!(fitted[1..], messages[1..]);
+        let (ContentPart::Text(original), ContentPart::Text(shortened)) =
+            (&messages[0].content[0], &fitted[0].content[0])
+        else {
+            panic!("expected a text system prompt");
+        };
+        assert!(shortened.len() < original.len());
+        assert!(original.starts_with(shortened.as_str()));
+    }
+
+    #[test]
+    fn oldest_messages_are_dropped_under_preserve_system_policy() {
+        let messages = conversation();
+
+        let fitted = fit_context(
+            messages.clone(),
+            &config("gpt-4o", &[("max_context_tokens", "160")]),
+        )
+        .unwrap();
+
+        assert_eq!(fitted[0], messages[0]);
+        assert_eq!(fitted[1..], messages[3..]);
+    }
+
+    #[test]
+    fn conversation_without_a_fitting_cut_is_rejected() {
+        let error = fit_context(
+            conversation(),
+            &config("gpt-4o", &[("max_context_tokens", "50")]),
+        )
+        .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+
+        let error = fit_context(
+            conversation(),
+            &config(
+                "gpt-4o",
+                &[
+                    ("max_context_tokens", "150"),
+                    ("context_overflow", "drop-all"),
+                ],
+            ),
+        )
+        .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn room_is_kept_for_tool_results_appended_after_fitting() {
+        let tool_results = vec![(
+            ToolCall {
+                id: "call-1".to_string(),
+                name: "get_weather".to_string(),
+                arguments_json: r#"{"city": "Berlin"}"#.to_string(),
+            },
+            ToolResult::Success(ToolSuccess {
+                id: "call-1".to_string(),
+                name: "get_weather".to_string(),
+                result_json: r#"{"forecast": "rain", "temperature": 12}"#.to_string(),
+                execution_time_ms: None,
+            }),
+        )];
+        let config = config("gpt-4o", &[("max_context_tokens", "190")]);
+        assert_eq!(
+            fit_context(conversation(), &config).unwrap(),
+            conversation()
+        );
+
+        let config = reserve_context_for_tool_results(config, &tool_results);
+        let fitted = fit_context(conversation(), &config).unwrap();
+
+        assert!(fitted.len() < conversation().len());
+        assert!(estimate_tokens(&fitted) + estimate_tool_result_tokens(&tool_results) <= 190);
+    }
+
+    #[test]
+    fn max_tokens_exceeding_the_context_window_is_capped() {
+        let messages = conversation();
+        let config = config(
+            "gpt-4o",
+            &[("max_context_tokens", "1000"), ("context_window", "1000")],
+        );
+
+        let remaining = 1000 - estimate_tokens(&messages) as u32;
+        assert_eq!(
+            fit_max_tokens(Some(4096), &messages, &config),
+            Some(remaining)
+        );
+        assert_eq!(fit_max_tokens(Some(500), &messages, &config), Some(500));
+        assert_eq!(fit_max_tokens(None, &messages, &config), None);
+    }
+
+    #[test]
+    fn split_text_reassembles_to_the_original() {
+        let text =
+            "The quick brown fox jumps over the lazy dog. ".repeat(20) + "Supercalifragilistic";
+
+        let parts = split_text(&text, 10);
+
+        assert!(parts.len() > 1);
+        let texts = parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text(text) => text.as_str(),
+                _ => panic!("expected text parts"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(texts.concat(), text);
+        for text in &texts {
+            assert!(text.chars().count() <= 40);
+        }
+        assert!(texts[..texts.len() - 1]
+            .iter()
+            .all(|text| text.ends_with(' ')));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::config::{provider_option, with_config_key};
+use crate::golem::llm::llm::{
+    ChatEvent, CompleteResponse, Config, Error, ErrorCode, ResponseMetadata,
+};
+use serde::Serialize;
+use std::ffi::OsStr;
+
+/// Checks whether the `dry_run` provider option is enabled. In dry run mode providers return the
+/// request they would send instead of calling the provider's API.
+pub fn is_dry_run(config: &Config) -> bool {
+    provider_option(config, "dry_run").is_some_and(|value| value == "true")
+}
+
+/// Creates the response returned in dry run mode: an empty message with the serialized provider
+/// request in its `provider_metadata_json` field.
+pub fn dry_run_response(provider_id: &str, request: &impl Serialize) -> ChatEvent {
+    match serde_json::to_string(request) {
+        Ok(request_json) => ChatEvent::Message(CompleteResponse {
+            id: format!("{provider_id}-dry-run"),
+            content: vec![],
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: Some(provider_id.to_string()),
+                timestamp: None,
+                provider_metadata_json: Some(request_json),
+                stop_sequence: None,
+                tool_calls: None,
+            },
+        }),
+        Err(err) => ChatEvent::Error(Error {
+            code: ErrorCode::InternalError,
+            message: format!("Failed to serialize request body: {err}"),
+            provider_error_json: None,
+        }),
+    }
+}
+
+/// Gets the API key of the provider from the environment like `with_config_key`. In dry run mode
+/// no request is sent, so a missing key is not an error, and `succeed` runs with an empty key.
+pub fn with_api_key_unless_dry_run<R>(
+    key: impl AsRef<OsStr>,
+    dry_run: bool,
+    fail: impl FnOnce(Error) -> R,
+    succeed: impl FnOnce(String) -> R,
+) -> R {
+    if dry_run && std::env::var_os(key.as_ref()).is_none() {
+        return succeed(String::new());
+    }
+    with_config_key(key, fail, succeed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dry_run::with_api_key_unless_dry_run;
+
+    #[test]
+    fn dry_run_does_not_need_an_api_key() {
+        const KEY: &str = "GOLEM_LLM_DRY_RUN_TEST_API_KEY";
+
+        assert_eq!(
+            with_api_key_unless_dry_run(KEY, true, |_| None, Some),
+            Some(String::new())
+        );
+        assert_eq!(
+            with_api_key_unless_dry_run(KEY, false, |_| None, Some),
+            None
+        );
+    }
+}
@@ -1,4 +1,8 @@
-use crate::golem::llm::llm::{Config, ContentPart, Guest, Message, Role, StreamDelta};
+use crate::chat_stream::merge_tool_call;
+use crate::golem::llm::llm::{
+    Config, ContentPart, Guest, Message, Role, StreamDelta, ToolCall, ToolFailure, ToolResult,
+};
+use crate::tool_calls::tool_image_description;
 use golem_rust::wasm_rpc::Pollable;
 use std::marker::PhantomData;
 
@@ -12,53 +16,248 @@ pub trait ExtendedGuest: Guest + 'static {
     /// Creates an instance of the LLM specific `ChatStream` without wrapping it in a `Resource`
     fn unwrapped_stream(messages: Vec<Message>, config: Config) -> Self::ChatStream;
 
+    /// Creates an instance of the LLM specific `ChatStream` continuing the conversation after the given
+    /// tool calls and their results, without wrapping it in a `Resource`. Used to resend the tool calls
+    /// of an interrupted response as structured tool calls. The default implementation passes them as
+    /// text, for providers without tool calling.
+    fn unwrapped_continue_stream(
+        mut messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
+    ) -> Self::ChatStream {
+        messages.extend(tool_results_as_text(tool_results));
+        Self::unwrapped_stream(messages, config)
+    }
+
     /// Creates the retry prompt with a combination of the original messages, and the partially received
     /// streaming responses. There is a default implementation here, but it can be overridden with provider-specific
-    /// prompts if needed.
+    /// prompts if needed. Tool calls of the partial response are not part of the prompt, they are resent
+    /// as structured tool calls after it using `retry_tool_results`.
     fn retry_prompt(original_messages: &[Message], partial_result: &[StreamDelta]) -> Vec<Message> {
-        let mut extended_messages = Vec::new();
-        extended_messages.push(Message {
-            role: Role::System,
-            name: None,
-            content: vec![
-                ContentPart::Text(
-                    "You were asked the same question previously, but the response was interrupted before completion. \
-                                        Please continue your response from where you left off. \
-                                        Do not include the part of the response that was already seen.".to_string()),
-                ContentPart::Text("Here is the original question:".to_string()),
-            ],
-        });
-        extended_messages.extend_from_slice(original_messages);
-
-        let mut partial_result_as_content = Vec::new();
-        for delta in partial_result {
-            if let Some(contents) = &delta.content {
-                partial_result_as_content.extend_from_slice(contents);
+        retry_prompt_with_style(
+            original_messages,
+            partial_result,
+            RetryPromptStyle::SystemMessage,
+        )
+    }
+
+    fn subscribe(stream: &Self::ChatStream) -> Pollable;
+
+    /// The id by which the provider can resume the response of the stream, once it is known.
+    /// Returning an id makes the recovery of an interrupted stream use `resume_stream` instead of
+    /// the retry prompt.
+    fn resumable_response_id(_stream: &Self::ChatStream) -> Option<String> {
+        None
+    }
+
+    /// Resumes a response reported by `resumable_response_id`, continuing after the first
+    /// `last_position` events of the stream, which were already received. Returns `None` if the
+    /// provider cannot resume responses, in which case the retry prompt is used instead.
+    fn resume_stream(
+        _response_id: String,
+        _last_position: u32,
+        _config: Config,
+    ) -> Option<Self::ChatStream> {
+        None
+    }
+
+    /// Creates a pollable that becomes ready when the stream's idle timeout expires, so waiting for
+    /// the next event does not block forever on a dead connection. Returns `None` if the stream
+    /// has no idle timeout.
+    fn idle_timer(_stream: &Self::ChatStream) -> Option<Pollable> {
+        None
+    }
+}
+
+/// Describes how the retry instructions are passed to the model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryPromptStyle {
+    /// The instructions are sent in a separate system message preceding the original messages
+    SystemMessage,
+    /// The instructions are folded into the user turns of the original conversation, for providers
+    /// and models that do not accept additional or interleaved system messages. No new system
+    /// messages are added and no two consecutive user messages are created.
+    UserMessage,
+}
+
+/// Builds a retry prompt from the original messages and the text of the partially received
+/// streaming response, in a layout accepted by providers using the given style.
+pub fn retry_prompt_with_style(
+    original_messages: &[Message],
+    partial_result: &[StreamDelta],
+    style: RetryPromptStyle,
+) -> Vec<Message> {
+    let instructions = vec![ContentPart::Text(
+        "You were asked the same question previously, but the response was interrupted before completion. \
+         Please continue your response from where you left off. \
+         Do not include the part of the response that was already seen.".to_string(),
+    )];
+
+    let partial_text = partial_result
+        .iter()
+        .flat_map(|delta| delta.content.iter().flatten().cloned())
+        .collect::<Vec<_>>();
+
+    // The resent tool calls have to directly follow the assistant turn that made them, so the text
+    // received before them stays in that turn instead of being quoted in a new user message
+    let resends_tool_calls = !retry_tool_results(partial_result, &[]).is_empty();
+    let (partial_response_role, partial_response) = if resends_tool_calls {
+        (Role::Assistant, partial_text)
+    } else {
+        let mut partial_response = vec![ContentPart::Text(
+            "Here is the partial response that was successfully received:".to_string(),
+        )];
+        partial_response.extend(partial_text);
+        (Role::User, partial_response)
+    };
+
+    match style {
+        RetryPromptStyle::SystemMessage => {
+            let mut extended_messages = Vec::new();
+            extended_messages.push(Message {
+                role: Role::System,
+                name: None,
+                content: instructions,
+            });
+            extended_messages.extend_from_slice(original_messages);
+            if !partial_response.is_empty() {
+                extended_messages.push(Message {
+                    role: partial_response_role,
+                    name: None,
+                    content: partial_response,
+                });
+            }
+            extended_messages
+        }
+        RetryPromptStyle::UserMessage => {
+            let mut extended_messages = original_messages.to_vec();
+            match extended_messages
+                .iter_mut()
+                .rev()
+                .find(|message| message.role == Role::User)
+            {
+                Some(last_user_message) => {
+                    last_user_message.content.splice(0..0, instructions);
+                }
+                None => {
+                    let position = extended_messages
+                        .iter()
+                        .position(|message| message.role != Role::System)
+                        .unwrap_or(extended_messages.len());
+                    extended_messages.insert(
+                        position,
+                        Message {
+                            role: Role::User,
+                            name: None,
+                            content: instructions,
+                        },
+                    );
+                }
             }
-            if let Some(tool_calls) = &delta.tool_calls {
-                for tool_call in tool_calls {
-                    partial_result_as_content.push(ContentPart::Text(format!(
-                        "<tool-call id=\"{}\" name=\"{}\" arguments=\"{}\"/>",
-                        tool_call.id, tool_call.name, tool_call.arguments_json,
-                    )));
+            match extended_messages.last_mut() {
+                _ if partial_response.is_empty() => {}
+                Some(last_message) if last_message.role == partial_response_role => {
+                    last_message.content.extend(partial_response);
                 }
+                _ => extended_messages.push(Message {
+                    role: partial_response_role,
+                    name: None,
+                    content: partial_response,
+                }),
             }
+            extended_messages
         }
+    }
+}
 
-        extended_messages.push(Message {
-            role: Role::System,
-            name: None,
-            content: vec![ContentPart::Text(
-                "Here is the partial response that was successfully received:".to_string(),
-            )]
-            .into_iter()
-            .chain(partial_result_as_content)
-            .collect(),
-        });
-        extended_messages
+/// Reconstructs the tool calls of an interrupted streaming response, paired with placeholder results,
+/// so they can be sent back to the provider as structured tool calls when the response is retried.
+///
+/// Only tool calls that were never handed to the caller get a placeholder result: the ids in
+/// `dispatched` belong to tool calls already emitted as `tool-call-complete` events, which the caller
+/// may have executed. Tool calls whose arguments were only partially received are discarded.
+pub fn retry_tool_results(
+    partial_result: &[StreamDelta],
+    dispatched: &[String],
+) -> Vec<(ToolCall, ToolResult)> {
+    let mut tool_calls = Vec::new();
+    for delta in partial_result {
+        for tool_call in delta.tool_calls.iter().flatten() {
+            merge_tool_call(&mut tool_calls, tool_call.clone());
+        }
     }
 
-    fn subscribe(stream: &Self::ChatStream) -> Pollable;
+    tool_calls
+        .into_iter()
+        .filter(|tool_call| {
+            !tool_call.name.is_empty()
+                && !dispatched.contains(&tool_call.id)
+                && serde_json::from_str::<serde_json::Value>(&tool_call.arguments_json).is_ok()
+        })
+        .map(|tool_call| {
+            let result = ToolResult::Error(ToolFailure {
+                id: tool_call.id.clone(),
+                name: tool_call.name.clone(),
+                error_message:
+                    "The response was interrupted before this tool call could be executed"
+                        .to_string(),
+                error_code: None,
+            });
+            (tool_call, result)
+        })
+        .collect()
+}
+
+/// Describes tool calls and their results as text messages, for providers without tool calling
+fn tool_results_as_text(tool_results: Vec<(ToolCall, ToolResult)>) -> Vec<Message> {
+    let mut calls = Vec::new();
+    let mut results = Vec::new();
+    for (tool_call, tool_result) in tool_results {
+        calls.push(ContentPart::Text(format!(
+            "<tool-call id=\"{}\" name=\"{}\" arguments=\"{}\"/>",
+            tool_call.id, tool_call.name, tool_call.arguments_json,
+        )));
+        let result = match tool_result {
+            ToolResult::Success(success) => success.result_json,
+            ToolResult::Error(failure) => failure.error_message,
+            ToolResult::Image(tool_image) => tool_image_description(&tool_image),
+        };
+        results.push(ContentPart::Text(format!(
+            "<tool-result id=\"{}\">{result}</tool-result>",
+            tool_call.id
+        )));
+    }
+    if calls.is_empty() {
+        return Vec::new();
+    }
+
+    vec![
+        Message {
+            role: Role::Assistant,
+            name: None,
+            content: calls,
+        },
+        Message {
+            role: Role::User,
+            name: None,
+            content: results,
+        },
+    ]
+}
+
+/// Continues an interrupted stream, resuming the response with `resume` if the provider reported a
+/// resumable id for it and falling back to `retry` otherwise, or if the provider fails to resume.
+/// Returns the new stream, and whether it resumes the original response.
+pub fn recover_stream<S>(
+    resumable_response_id: Option<String>,
+    last_position: u32,
+    resume: impl FnOnce(String, u32) -> Option<S>,
+    retry: impl FnOnce() -> S,
+) -> (S, bool) {
+    match resumable_response_id.and_then(|response_id| resume(response_id, last_position)) {
+        Some(stream) => (stream, true),
+        None => (retry(), false),
+    }
 }
 
 /// When the durability feature flag is off, wrapping with `DurableLLM` is just a passthrough
@@ -66,7 +265,9 @@ pub trait ExtendedGuest: Guest + 'static {
 mod passthrough_impl {
     use crate::durability::{DurableLLM, ExtendedGuest};
     use crate::golem::llm::llm::{
-        ChatEvent, ChatStream, Config, Guest, Message, ToolCall, ToolResult,
+        BatchInfo, BatchRequest, BatchResult, CapabilitySet, ChatEvent, ChatStream, Config,
+        EmbeddingResponse, Error, Guest, ImageReference, Message, ModelInfo, ToolCall, ToolResult,
+        Usage,
     };
 
     impl<Impl: ExtendedGuest> Guest for DurableLLM<Impl> {
@@ -87,6 +288,50 @@ mod passthrough_impl {
         fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
             Impl::stream(messages, config)
         }
+
+        fn embed(inputs: Vec<String>, config: Config) -> Result<EmbeddingResponse, Error> {
+            Impl::embed(inputs, config)
+        }
+
+        fn generate_image(prompt: String, config: Config) -> Result<Vec<ImageReference>, Error> {
+            Impl::generate_image(prompt, config)
+        }
+
+        fn list_models(config: Config) -> Result<Vec<ModelInfo>, Error> {
+            Impl::list_models(config)
+        }
+
+        fn validate_credentials(config: Config) -> Result<(), Error> {
+            Impl::validate_credentials(config)
+        }
+
+        fn capabilities(config: Config) -> CapabilitySet {
+            Impl::capabilities(config)
+        }
+
+        fn batch_submit(requests: Vec<BatchRequest>, config: Config) -> Result<BatchInfo, Error> {
+            Impl::batch_submit(requests, config)
+        }
+
+        fn batch_status(batch_id: String, config: Config) -> Result<BatchInfo, Error> {
+            Impl::batch_status(batch_id, config)
+        }
+
+        fn batch_results(batch_id: String, config: Config) -> Result<Vec<BatchResult>, Error> {
+            Impl::batch_results(batch_id, config)
+        }
+
+        fn summarize_history(
+            messages: Vec<Message>,
+            config: Config,
+            keep_last_n: u32,
+        ) -> Result<Vec<Message>, Error> {
+            Impl::summarize_history(messages, config, keep_last_n)
+        }
+
+        fn sum_usage(usages: Vec<Usage>) -> Usage {
+            Impl::sum_usage(usages)
+        }
     }
 }
 
@@ -100,16 +345,21 @@ mod passthrough_impl {
 /// which is implemented using the type classes and builder in the `golem-rust` library.
 #[cfg(feature = "durability")]
 mod durable_impl {
-    use crate::durability::{DurableLLM, ExtendedGuest};
+    use crate::chat_stream::{ResumedStreamFilter, StreamOutput};
+    use crate::config::{stream_output_file, without_stream_output_file};
+    use crate::durability::{recover_stream, retry_tool_results, DurableLLM, ExtendedGuest};
     use crate::golem::llm::llm::{
-        ChatEvent, ChatStream, Config, Guest, GuestChatStream, Message, StreamDelta, StreamEvent,
-        ToolCall, ToolResult,
+        BatchInfo, BatchRequest, BatchResult, CapabilitySet, ChatEvent, ChatStream, Config,
+        ContentPart, EmbeddingResponse, Error, Guest, GuestChatStream, ImageReference, Message,
+        ModelInfo, StreamDelta, StreamEvent, ToolCall, ToolResult, Usage,
     };
+    use crate::summarize::summarize_history;
     use golem_rust::bindings::golem::durability::durability::{
         DurableFunctionType, LazyInitializedPollable,
     };
     use golem_rust::durability::Durability;
-    use golem_rust::wasm_rpc::Pollable;
+    use golem_rust::wasm_rpc::wasi::io::poll::poll;
+    use golem_rust::wasm_rpc::{Pollable, WitValueExtractor};
     use golem_rust::{with_persistence_level, FromValueAndType, IntoValue, PersistenceLevel};
     use std::cell::RefCell;
     use std::fmt::{Display, Formatter};
@@ -168,10 +418,13 @@ mod durable_impl {
             );
             if durability.is_live() {
                 let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
-                    ChatStream::new(DurableChatStream::<Impl>::live(Impl::unwrapped_stream(
-                        messages.clone(),
-                        config.clone(),
-                    )))
+                    ChatStream::new(DurableChatStream::<Impl>::live(
+                        Impl::unwrapped_stream(
+                            messages.clone(),
+                            without_stream_output_file(&config),
+                        ),
+                        stream_output_file(&config).map(StreamOutput::new),
+                    ))
                 });
                 let _ = durability.persist_infallible(SendInput { messages, config }, NoOutput);
                 result
@@ -180,6 +433,143 @@ mod durable_impl {
                 ChatStream::new(DurableChatStream::<Impl>::replay(messages, config))
             }
         }
+
+        fn embed(inputs: Vec<String>, config: Config) -> Result<EmbeddingResponse, Error> {
+            let durability = Durability::<Result<EmbeddingResponse, Error>, UnusedError>::new(
+                "golem_llm",
+                "embed",
+                DurableFunctionType::WriteRemote,
+            );
+            if durability.is_live() {
+                let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
+                    Impl::embed(inputs.clone(), config.clone())
+                });
+                durability.persist_infallible(EmbedInput { inputs, config }, result)
+            } else {
+                durability.replay_infallible()
+            }
+        }
+
+        fn generate_image(prompt: String, config: Config) -> Result<Vec<ImageReference>, Error> {
+            let durability = Durability::<Result<Vec<ImageReference>, Error>, UnusedError>::new(
+                "golem_llm",
+                "generate_image",
+                DurableFunctionType::WriteRemote,
+            );
+            if durability.is_live() {
+                let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
+                    Impl::generate_image(prompt.clone(), config.clone())
+                });
+                durability.persist_infallible(GenerateImageInput { prompt, config }, result)
+            } else {
+                durability.replay_infallible()
+            }
+        }
+
+        fn list_models(config: Config) -> Result<Vec<ModelInfo>, Error> {
+            let durability = Durability::<Result<Vec<ModelInfo>, Error>, UnusedError>::new(
+                "golem_llm",
+                "list_models",
+                DurableFunctionType::ReadRemote,
+            );
+            if durability.is_live() {
+                let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
+                    Impl::list_models(config.clone())
+                });
+                durability.persist_infallible(ListModelsInput { config }, result)
+            } else {
+                durability.replay_infallible()
+            }
+        }
+
+        fn validate_credentials(config: Config) -> Result<(), Error> {
+            let durability = Durability::<Option<Error>, UnusedError>::new(
+                "golem_llm",
+                "validate_credentials",
+                DurableFunctionType::ReadRemote,
+            );
+            let error = if durability.is_live() {
+                let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
+                    Impl::validate_credentials(config.clone())
+                });
+                durability.persist_infallible(ValidateCredentialsInput { config }, result.err())
+            } else {
+                durability.replay_infallible()
+            };
+            match error {
+                Some(error) => Err(error),
+                None => Ok(()),
+            }
+        }
+
+        fn capabilities(config: Config) -> CapabilitySet {
+            // Capabilities are derived from the model name without calling the provider, so there
+            // is nothing to persist
+            Impl::capabilities(config)
+        }
+
+        fn batch_submit(requests: Vec<BatchRequest>, config: Config) -> Result<BatchInfo, Error> {
+            let durability = Durability::<Result<BatchInfo, Error>, UnusedError>::new(
+                "golem_llm",
+                "batch_submit",
+                DurableFunctionType::WriteRemote,
+            );
+            if durability.is_live() {
+                let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
+                    Impl::batch_submit(requests.clone(), config.clone())
+                });
+                durability.persist_infallible(BatchSubmitInput { requests, config }, result)
+            } else {
+                durability.replay_infallible()
+            }
+        }
+
+        fn batch_status(batch_id: String, config: Config) -> Result<BatchInfo, Error> {
+            let durability = Durability::<Result<BatchInfo, Error>, UnusedError>::new(
+                "golem_llm",
+                "batch_status",
+                DurableFunctionType::ReadRemote,
+            );
+            if durability.is_live() {
+                let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
+                    Impl::batch_status(batch_id.clone(), config.clone())
+                });
+                durability.persist_infallible(BatchInput { batch_id, config }, result)
+            } else {
+                durability.replay_infallible()
+            }
+        }
+
+        fn batch_results(batch_id: String, config: Config) -> Result<Vec<BatchResult>, Error> {
+            let durability = Durability::<Result<Vec<BatchResult>, Error>, UnusedError>::new(
+                "golem_llm",
+                "batch_results",
+                DurableFunctionType::ReadRemote,
+            );
+            if durability.is_live() {
+                let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
+                    Impl::batch_results(batch_id.clone(), config.clone())
+                });
+                durability.persist_infallible(BatchInput { batch_id, config }, result)
+            } else {
+                durability.replay_infallible()
+            }
+        }
+
+        /// The summary is requested through the durable `send`, so it is replayed from the oplog
+        /// like any other completion
+        fn summarize_history(
+            messages: Vec<Message>,
+            config: Config,
+            keep_last_n: u32,
+        ) -> Result<Vec<Message>, Error> {
+            summarize_history(messages, config, keep_last_n, Self::send)
+        }
+
+        fn sum_usage(usages: Vec<Usage>) -> Usage {
+            // Summing usages does not call the provider, so there is nothing to persist
+            Impl::sum_usage(usages)
+        }
     }
 
     /// Represents the durable chat stream's state
@@ -191,9 +581,10 @@ mod durable_impl {
     /// to be able to reattach them to the new live stream when the switch to live mode
     /// happens.
     ///
-    /// When reaching the end of the replay mode, if the replayed stream was not finished yet,
-    /// the replay prompt implemented in `ExtendedGuest` is used to create a new LLM response
-    /// stream and continue the response seamlessly.
+    /// When reaching the end of the replay mode, if the replayed stream was not finished yet, the
+    /// response is resumed by the provider if it reported a resumable response id. Otherwise the
+    /// replay prompt implemented in `ExtendedGuest` is used to create a new LLM response stream and
+    /// continue the response seamlessly.
     enum DurableChatStreamState<Impl: ExtendedGuest> {
         Live {
             stream: Impl::ChatStream,
@@ -204,36 +595,77 @@ mod durable_impl {
             config: Config,
             pollables: Vec<LazyInitializedPollable>,
             partial_result: Vec<StreamDelta>,
+            /// The ids of the tool calls already emitted as complete, which are not resent
+            dispatched_tool_calls: Vec<String>,
+            /// The number of events replayed so far, where a resumed response continues
+            received_events: u32,
+            /// The last resumable response id the provider reported for the stream
+            resumable_response_id: Option<String>,
             finished: bool,
         },
     }
 
+    /// The text of the stream is written to the `stream_output_file` here rather than by the
+    /// underlying stream, once the events are persisted, so the persisted events keep their text
+    /// for the retry prompt of a recovered stream.
     pub struct DurableChatStream<Impl: ExtendedGuest> {
         state: RefCell<Option<DurableChatStreamState<Impl>>>,
         subscription: RefCell<Option<Pollable>>,
+        resume_filter: RefCell<Option<ResumedStreamFilter>>,
+        output: RefCell<Option<StreamOutput>>,
     }
 
     impl<Impl: ExtendedGuest> DurableChatStream<Impl> {
-        fn live(stream: Impl::ChatStream) -> Self {
+        fn live(stream: Impl::ChatStream, output: Option<StreamOutput>) -> Self {
             Self {
                 state: RefCell::new(Some(DurableChatStreamState::Live {
                     stream,
                     pollables: Vec::new(),
                 })),
                 subscription: RefCell::new(None),
+                resume_filter: RefCell::new(None),
+                output: RefCell::new(output),
             }
         }
 
         fn replay(original_messages: Vec<Message>, config: Config) -> Self {
+            let output = stream_output_file(&config).map(StreamOutput::new);
             Self {
                 state: RefCell::new(Some(DurableChatStreamState::Replay {
                     original_messages,
-                    config,
+                    config: without_stream_output_file(&config),
                     pollables: Vec::new(),
                     partial_result: Vec::new(),
+                    dispatched_tool_calls: Vec::new(),
+                    received_events: 0,
+                    resumable_response_id: None,
                     finished: false,
                 })),
                 subscription: RefCell::new(None),
+                resume_filter: RefCell::new(None),
+                output: RefCell::new(output),
+            }
+        }
+
+        /// Writes the text of persisted events to the output file, if there is one, or only
+        /// removes it from replayed events, whose text was written when they were received live
+        fn output_events(
+            &self,
+            events: Option<Vec<StreamEvent>>,
+            live: bool,
+        ) -> Option<Vec<StreamEvent>> {
+            match (self.output.borrow_mut().as_mut(), events) {
+                (Some(output), Some(events)) if !events.is_empty() => {
+                    let events = if live {
+                        output
+                            .write(events)
+                            .unwrap_or_else(|error| vec![StreamEvent::Error(error)])
+                    } else {
+                        StreamOutput::skip(events)
+                    };
+                    (!events.is_empty()).then_some(events)
+                }
+                (_, events) => events,
             }
         }
 
@@ -252,6 +684,13 @@ mod durable_impl {
                 }
             }
         }
+
+        fn idle_timer(&self) -> Option<Pollable> {
+            match &*self.state.borrow() {
+                Some(DurableChatStreamState::Live { stream, .. }) => Impl::idle_timer(stream),
+                _ => None,
+            }
+        }
     }
 
     impl<Impl: ExtendedGuest> Drop for DurableChatStream<Impl> {
@@ -277,7 +716,7 @@ mod durable_impl {
 
     impl<Impl: ExtendedGuest> GuestChatStream for DurableChatStream<Impl> {
         fn get_next(&self) -> Option<Vec<StreamEvent>> {
-            let durability = Durability::<Option<Vec<StreamEvent>>, UnusedError>::new(
+            let durability = Durability::<GetNextOutput, UnusedError>::new(
                 "golem_llm",
                 "get_next",
                 DurableFunctionType::ReadRemote,
@@ -290,26 +729,55 @@ mod durable_impl {
                             with_persistence_level(PersistenceLevel::PersistNothing, || {
                                 stream.get_next()
                             });
-                        (durability.persist_infallible(NoInput, result.clone()), None)
+                        let result = match self.resume_filter.borrow_mut().as_mut() {
+                            Some(resume_filter) => resume_filter.filter(result),
+                            None => result,
+                        };
+                        let output = GetNextOutput {
+                            events: result,
+                            resumable_response_id: Impl::resumable_response_id(stream),
+                        };
+                        let result = durability.persist_infallible(NoInput, output).events;
+                        (self.output_events(result, true), None)
                     }
                     Some(DurableChatStreamState::Replay {
                         original_messages,
                         config,
                         pollables,
                         partial_result,
+                        dispatched_tool_calls,
+                        received_events,
+                        resumable_response_id,
                         finished,
                     }) => {
                         if *finished {
                             (None, None)
                         } else {
-                            let extended_messages =
-                                Impl::retry_prompt(original_messages, partial_result);
-
-                            let (stream, first_live_result) =
+                            let (stream, resumed, first_live_result) =
                                 with_persistence_level(PersistenceLevel::PersistNothing, || {
-                                    let stream = <Impl as ExtendedGuest>::unwrapped_stream(
-                                        extended_messages,
-                                        config.clone(),
+                                    let (stream, resumed) = recover_stream(
+                                        resumable_response_id.clone(),
+                                        *received_events,
+                                        |response_id, last_position| {
+                                            Impl::resume_stream(
+                                                response_id,
+                                                last_position,
+                                                config.clone(),
+                                            )
+                                        },
+                                        || {
+                                            <Impl as ExtendedGuest>::unwrapped_continue_stream(
+                                                Impl::retry_prompt(
+                                                    original_messages,
+                                                    partial_result,
+                                                ),
+                                                retry_tool_results(
+                                                    partial_result,
+                                                    dispatched_tool_calls,
+                                                ),
+                                                config.clone(),
+                                            )
+                                        },
                                     );
 
                                     for lazy_initialized_pollable in pollables {
@@ -317,9 +785,44 @@ mod durable_impl {
                                     }
 
                                     let next = stream.get_next();
-                                    (stream, next)
+                                    (stream, resumed, next)
                                 });
-                            durability.persist_infallible(NoInput, first_live_result.clone());
+
+                            let emitted_text = partial_result
+                                .iter()
+                                .flat_map(|delta| delta.content.iter().flatten())
+                                .filter_map(|part| match part {
+                                    ContentPart::Text(text) => Some(text.as_str()),
+                                    _ => None,
+                                })
+                                .collect::<String>();
+
+                            // A resumed response continues exactly after the received events,
+                            // while a retried one may repeat some of the text already emitted
+                            let first_live_result = if resumed {
+                                first_live_result
+                            } else {
+                                let mut resume_filter =
+                                    ResumedStreamFilter::new(emitted_text.clone());
+                                let first_live_result = resume_filter.filter(first_live_result);
+                                *self.resume_filter.borrow_mut() = Some(resume_filter);
+                                first_live_result
+                            };
+
+                            let output = GetNextOutput {
+                                events: first_live_result,
+                                resumable_response_id: Impl::resumable_response_id(&stream),
+                            };
+                            let first_live_result =
+                                durability.persist_infallible(NoInput, output).events;
+                            let resumed_output = match self.output.borrow_mut().as_mut() {
+                                Some(output) => output.resume(&emitted_text),
+                                None => Ok(()),
+                            };
+                            let first_live_result = match resumed_output {
+                                Ok(()) => self.output_events(first_live_result, true),
+                                Err(error) => Some(vec![StreamEvent::Error(error)]),
+                            };
 
                             (first_live_result, Some(stream))
                         }
@@ -342,7 +845,8 @@ mod durable_impl {
 
                 result
             } else {
-                let result: Option<Vec<StreamEvent>> = durability.replay_infallible();
+                let output: GetNextOutput = durability.replay_infallible();
+                let result = output.events;
                 let mut state = self.state.borrow_mut();
                 match &mut *state {
                     Some(DurableChatStreamState::Live { .. }) => {
@@ -350,10 +854,17 @@ mod durable_impl {
                     }
                     Some(DurableChatStreamState::Replay {
                         partial_result,
+                        dispatched_tool_calls,
+                        received_events,
+                        resumable_response_id,
                         finished,
                         ..
                     }) => {
+                        if output.resumable_response_id.is_some() {
+                            *resumable_response_id = output.resumable_response_id;
+                        }
                         if let Some(result) = &result {
+                            *received_events += result.len() as u32;
                             for event in result {
                                 match event {
                                     StreamEvent::Delta(delta) => {
@@ -365,6 +876,10 @@ mod durable_impl {
                                     StreamEvent::Error(_) => {
                                         *finished = true;
                                     }
+                                    StreamEvent::ToolCallComplete(tool_call) => {
+                                        dispatched_tool_calls.push(tool_call.id.clone());
+                                    }
+                                    StreamEvent::FinishReasonHint(_) | StreamEvent::Heartbeat => {}
                                 }
                             }
                         }
@@ -373,7 +888,7 @@ mod durable_impl {
                         unreachable!()
                     }
                 }
-                result
+                self.output_events(result, false)
             }
         }
 
@@ -385,7 +900,12 @@ mod durable_impl {
             let subscription = subscription.as_mut().unwrap();
             let mut result = Vec::new();
             loop {
-                subscription.block();
+                match self.idle_timer() {
+                    Some(idle_timer) => {
+                        poll(&[subscription, &idle_timer]);
+                    }
+                    None => subscription.block(),
+                }
                 match self.get_next() {
                     Some(events) => {
                         result.extend(events);
@@ -395,6 +915,22 @@ mod durable_impl {
                 }
             }
         }
+
+        fn cancel(&self) {
+            match &mut *self.state.borrow_mut() {
+                Some(DurableChatStreamState::Live { stream, .. }) => {
+                    with_persistence_level(PersistenceLevel::PersistNothing, || stream.cancel());
+                }
+                // A stream cancelled while replaying was already cancelled live, so it is only
+                // kept from being resumed
+                Some(DurableChatStreamState::Replay { finished, .. }) => {
+                    *finished = true;
+                }
+                None => {
+                    unreachable!()
+                }
+            }
+        }
     }
 
     #[derive(Debug, Clone, PartialEq, IntoValue)]
@@ -410,9 +946,74 @@ mod durable_impl {
         config: Config,
     }
 
+    #[derive(Debug, IntoValue)]
+    struct EmbedInput {
+        inputs: Vec<String>,
+        config: Config,
+    }
+
+    #[derive(Debug, IntoValue)]
+    struct GenerateImageInput {
+        prompt: String,
+        config: Config,
+    }
+
+    #[derive(Debug, IntoValue)]
+    struct ListModelsInput {
+        config: Config,
+    }
+
+    #[derive(Debug, IntoValue)]
+    struct ValidateCredentialsInput {
+        config: Config,
+    }
+
+    #[derive(Debug, IntoValue)]
+    struct BatchSubmitInput {
+        requests: Vec<BatchRequest>,
+        config: Config,
+    }
+
+    #[derive(Debug, IntoValue)]
+    struct BatchInput {
+        batch_id: String,
+        config: Config,
+    }
+
     #[derive(Debug, IntoValue)]
     struct NoInput;
 
+    /// The events returned by `get_next`, persisted together with the resumable response id known
+    /// at that point, so a recovered stream can be resumed
+    #[derive(Debug, Clone, IntoValue)]
+    struct GetNextOutput {
+        events: Option<Vec<StreamEvent>>,
+        resumable_response_id: Option<String>,
+    }
+
+    /// Oplogs written before the resumable response id was persisted contain only the events, which
+    /// are read back as an output without an id
+    impl golem_rust::value_and_type::FromValueAndType for GetNextOutput {
+        fn from_extractor<'a, 'b>(
+            extractor: &'a impl WitValueExtractor<'a, 'b>,
+        ) -> Result<Self, String> {
+            use golem_rust::value_and_type::FromValueAndType;
+
+            match (extractor.field(0), extractor.field(1)) {
+                (Some(events), Some(resumable_response_id)) => Ok(Self {
+                    events: FromValueAndType::from_extractor(&events)?,
+                    resumable_response_id: FromValueAndType::from_extractor(
+                        &resumable_response_id,
+                    )?,
+                }),
+                _ => Ok(Self {
+                    events: FromValueAndType::from_extractor(extractor)?,
+                    resumable_response_id: None,
+                }),
+            }
+        }
+    }
+
     #[derive(Debug, Clone, FromValueAndType, IntoValue)]
     struct NoOutput;
 
@@ -532,6 +1133,8 @@ mod durable_impl {
                 provider_id: Some("provider_id".to_string()),
                 timestamp: Some("2023-10-01T00:00:00Z".to_string()),
                 provider_metadata_json: Some("{\"key\": \"value\"}".to_string()),
+                stop_sequence: None,
+                tool_calls: None,
             });
             roundtrip_test(ResponseMetadata {
                 finish_reason: None,
@@ -539,6 +1142,8 @@ mod durable_impl {
                 provider_id: None,
                 timestamp: None,
                 provider_metadata_json: None,
+                stop_sequence: None,
+                tool_calls: None,
             });
         }
 
@@ -564,6 +1169,8 @@ mod durable_impl {
                     provider_id: None,
                     timestamp: None,
                     provider_metadata_json: None,
+                    stop_sequence: None,
+                    tool_calls: None,
                 },
             });
         }
@@ -590,6 +1197,8 @@ mod durable_impl {
                     provider_id: None,
                     timestamp: None,
                     provider_metadata_json: None,
+                    stop_sequence: None,
+                    tool_calls: None,
                 },
             }));
             roundtrip_test(ChatEvent::ToolRequest(vec![ToolCall {
@@ -642,6 +1251,7 @@ mod durable_impl {
                     tools: vec![],
                     tool_choice: None,
                     provider_options: vec![],
+                    system: None,
                 },
             };
 
@@ -656,3 +1266,218 @@ mod durable_impl {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::durability::{
+        recover_stream, retry_prompt_with_style, retry_tool_results, RetryPromptStyle,
+    };
+    use crate::golem::llm::llm::{ContentPart, Message, Role, StreamDelta, ToolCall, ToolResult};
+    use crate::request::normalize_system_messages;
+    use crate::validation::{validate_messages, SystemMessagePlacement};
+
+    fn tool_call_delta(id: &str, name: &str, arguments_json: &str) -> StreamDelta {
+        StreamDelta {
+            content: None,
+            tool_calls: Some(vec![ToolCall {
+                id: id.to_string(),
+                name: name.to_string(),
+                arguments_json: arguments_json.to_string(),
+            }]),
+            reasoning: None,
+            choice_index: None,
+        }
+    }
+
+    #[test]
+    fn retry_keeps_complete_tool_calls() {
+        let partial_result = vec![
+            tool_call_delta("call-1", "get_weather", "{\"city\":"),
+            tool_call_delta("call-1", "", "\"Berlin\"}"),
+            tool_call_delta("call-2", "get_time", "{\"zone\":"),
+            tool_call_delta("call-2", "get_time", "{\"zone\":\"UTC\"}"),
+        ];
+
+        let tool_results = retry_tool_results(&partial_result, &[]);
+
+        assert_eq!(
+            tool_results
+                .iter()
+                .map(|(tool_call, _)| tool_call.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                ToolCall {
+                    id: "call-1".to_string(),
+                    name: "get_weather".to_string(),
+                    arguments_json: "{\"city\":\"Berlin\"}".to_string(),
+                },
+                ToolCall {
+                    id: "call-2".to_string(),
+                    name: "get_time".to_string(),
+                    arguments_json: "{\"zone\":\"UTC\"}".to_string(),
+                },
+            ]
+        );
+        assert!(tool_results.iter().all(|(tool_call, result)| matches!(
+            result,
+            ToolResult::Error(failure) if failure.id == tool_call.id && failure.name == tool_call.name
+        )));
+    }
+
+    #[test]
+    fn retry_discards_partially_received_tool_calls() {
+        let partial_result = vec![
+            tool_call_delta("call-1", "get_weather", "{\"city\":\"Berlin\"}"),
+            tool_call_delta("call-2", "get_time", "{\"zone\":\"U"),
+        ];
+
+        let tool_results = retry_tool_results(&partial_result, &[]);
+
+        assert_eq!(tool_results.len(), 1);
+        assert_eq!(tool_results[0].0.id, "call-1");
+    }
+
+    #[test]
+    fn retry_does_not_resend_dispatched_tool_calls() {
+        let partial_result = vec![
+            tool_call_delta("call-1", "get_weather", "{\"city\":\"Berlin\"}"),
+            tool_call_delta("call-2", "get_time", "{\"zone\":\"UTC\"}"),
+        ];
+
+        let tool_results = retry_tool_results(&partial_result, &["call-1".to_string()]);
+
+        assert_eq!(tool_results.len(), 1);
+        assert_eq!(tool_results[0].0.id, "call-2");
+    }
+
+    #[test]
+    fn text_before_resent_tool_calls_stays_in_the_assistant_turn() {
+        let original_messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text(
+                "What is the weather in Berlin?".to_string(),
+            )],
+        }];
+        let partial_result = vec![
+            StreamDelta {
+                content: Some(vec![ContentPart::Text("Let me check.".to_string())]),
+                tool_calls: None,
+                reasoning: None,
+                choice_index: None,
+            },
+            tool_call_delta("call-1", "get_weather", "{\"city\":\"Berlin\"}"),
+        ];
+
+        for style in [
+            RetryPromptStyle::SystemMessage,
+            RetryPromptStyle::UserMessage,
+        ] {
+            let messages = retry_prompt_with_style(&original_messages, &partial_result, style);
+
+            let last = messages.last().unwrap();
+            assert_eq!(last.role, Role::Assistant);
+            assert_eq!(
+                last.content,
+                vec![ContentPart::Text("Let me check.".to_string())]
+            );
+        }
+    }
+
+    fn roles(messages: &[Message]) -> Vec<Role> {
+        messages.iter().map(|message| message.role).collect()
+    }
+
+    #[test]
+    fn retry_prompt_is_accepted() {
+        let message = |role: Role, text: &str| Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        };
+        let original_messages = vec![
+            message(Role::System, "Answer briefly"),
+            message(Role::User, "What is the weather like?"),
+            message(Role::Assistant, "Which city?"),
+            message(Role::User, "Berlin"),
+        ];
+        let partial_result = vec![StreamDelta {
+            content: Some(vec![ContentPart::Text("It is sunny".to_string())]),
+            tool_calls: None,
+            reasoning: None,
+            choice_index: None,
+        }];
+
+        let messages = retry_prompt_with_style(
+            &original_messages,
+            &partial_result,
+            RetryPromptStyle::SystemMessage,
+        );
+        assert!(validate_messages(&messages, SystemMessagePlacement::Anywhere).is_ok());
+        assert_eq!(
+            roles(&messages),
+            vec![
+                Role::System,
+                Role::System,
+                Role::User,
+                Role::Assistant,
+                Role::User,
+                Role::User
+            ]
+        );
+
+        // Accepted by providers taking only leading or a single system message, without adding
+        // system messages or consecutive user messages
+        let messages = retry_prompt_with_style(
+            &original_messages,
+            &partial_result,
+            RetryPromptStyle::UserMessage,
+        );
+        assert!(validate_messages(&messages, SystemMessagePlacement::Leading).is_ok());
+        let normalized =
+            normalize_system_messages(messages.clone(), SystemMessagePlacement::Single);
+        assert!(validate_messages(&normalized, SystemMessagePlacement::Single).is_ok());
+        assert_eq!(
+            roles(&messages),
+            vec![Role::System, Role::User, Role::Assistant, Role::User]
+        );
+        // The instructions precede the question being answered, in the last user turn
+        assert_eq!(messages[1].content, original_messages[1].content);
+        assert!(matches!(
+            &messages[3].content[0],
+            ContentPart::Text(text) if text.starts_with("You were asked the same question previously")
+        ));
+    }
+
+    #[test]
+    fn stream_with_resumable_id_is_resumed_instead_of_retried() {
+        let (stream, resumed) = recover_stream(
+            Some("resp_123".to_string()),
+            7,
+            |response_id, last_position| {
+                Some(format!("resumed {response_id} after {last_position}"))
+            },
+            || panic!("The response should not be retried"),
+        );
+        assert_eq!(stream, "resumed resp_123 after 7");
+        assert!(resumed);
+
+        let (stream, resumed) = recover_stream(
+            Some("resp_123".to_string()),
+            7,
+            |_, _| None,
+            || "retried".to_string(),
+        );
+        assert_eq!(stream, "retried");
+        assert!(!resumed);
+
+        let (stream, resumed) = recover_stream(
+            None,
+            7,
+            |_, _| panic!("Nothing can be resumed without a response id"),
+            || "retried".to_string(),
+        );
+        assert_eq!(stream, "retried");
+        assert!(!resumed);
+    }
+}
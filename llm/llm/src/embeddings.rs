@@ -0,0 +1,85 @@
+use crate::config::provider_option;
+use crate::golem::llm::llm::{Config, EmbeddingResponse, Error};
+use crate::usage::sum_usage;
+
+/// Gets the maximum number of inputs embedded with a single provider request from the
+/// `embedding_batch_size` provider option, falling back to the provider's `default`
+pub fn embedding_batch_size(config: &Config, default: usize) -> usize {
+    provider_option(config, "embedding_batch_size")
+        .and_then(|value| value.parse().ok())
+        .filter(|batch_size| *batch_size > 0)
+        .unwrap_or(default)
+}
+
+/// Embeds `inputs` with as many requests as needed to send at most `batch_size` inputs in each,
+/// and concatenates the results in the order of the inputs.
+///
+/// The batches are sent one after the other, as the HTTP client of the providers blocks until a
+/// response arrives, and the first failing batch fails the whole call.
+/// The token usage of the batches is summed up.
+pub fn embed_in_batches(
+    inputs: Vec<String>,
+    batch_size: usize,
+    mut embed_batch: impl FnMut(Vec<String>) -> Result<EmbeddingResponse, Error>,
+) -> Result<EmbeddingResponse, Error> {
+    if inputs.len() <= batch_size {
+        return embed_batch(inputs);
+    }
+
+    let mut result: Option<EmbeddingResponse> = None;
+    let mut inputs = inputs.into_iter().peekable();
+    while inputs.peek().is_some() {
+        let batch = inputs.by_ref().take(batch_size).collect();
+        let response = embed_batch(batch)?;
+
+        result = Some(match result {
+            None => response,
+            Some(mut result) => {
+                result.embeddings.extend(response.embeddings);
+                result.usage = match (result.usage, response.usage) {
+                    (Some(total), Some(usage)) => Some(sum_usage(vec![total, usage])),
+                    (total, usage) => total.or(usage),
+                };
+                result
+            }
+        });
+    }
+
+    Ok(result.expect("at least one batch is embedded"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embeddings::embed_in_batches;
+    use crate::golem::llm::llm::{EmbeddingResponse, Usage};
+
+    #[test]
+    fn large_inputs_are_embedded_in_order_in_batches() {
+        let inputs = (0..10).map(|i| i.to_string()).collect::<Vec<_>>();
+        let mut batch_sizes = Vec::new();
+
+        let response = embed_in_batches(inputs, 4, |batch| {
+            batch_sizes.push(batch.len());
+            Ok(EmbeddingResponse {
+                embeddings: batch
+                    .iter()
+                    .map(|input| vec![input.parse::<f32>().unwrap()])
+                    .collect(),
+                usage: Some(Usage {
+                    input_tokens: Some(batch.len() as u32),
+                    output_tokens: None,
+                    total_tokens: Some(batch.len() as u32),
+                }),
+                model: "nomic-embed-text".to_string(),
+            })
+        })
+        .unwrap();
+
+        assert_eq!(batch_sizes, vec![4, 4, 2]);
+        assert_eq!(
+            response.embeddings,
+            (0..10).map(|i| vec![i as f32]).collect::<Vec<_>>()
+        );
+        assert_eq!(response.usage.unwrap().input_tokens, Some(10));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::event_source;
-use crate::golem::llm::llm::{Error, ErrorCode};
+use crate::golem::llm::llm::{ChatEvent, ContentPart, Error, ErrorCode};
 use reqwest::StatusCode;
+use serde_json::Value;
 
 /// Creates an `Error` value representing that something is unsuported
 pub fn unsupported(what: impl AsRef<str>) -> Error {
@@ -27,9 +28,14 @@ pub fn from_event_source_error(details: impl AsRef<str>, err: event_source::erro
     }
 }
 
+/// The non-standard status Anthropic responds with when its API is temporarily overloaded
+const OVERLOADED: u16 = 529;
+
 pub fn error_code_from_status(status: StatusCode) -> ErrorCode {
     if status == StatusCode::TOO_MANY_REQUESTS {
         ErrorCode::RateLimitExceeded
+    } else if status.as_u16() == OVERLOADED {
+        ErrorCode::Overloaded
     } else if status == StatusCode::UNAUTHORIZED
         || status == StatusCode::FORBIDDEN
         || status == StatusCode::PAYMENT_REQUIRED
@@ -41,3 +47,151 @@ pub fn error_code_from_status(status: StatusCode) -> ErrorCode {
         ErrorCode::InternalError
     }
 }
+
+/// The error code OpenAI and compatible APIs report when the account has no quota or credits left
+const INSUFFICIENT_QUOTA: &str = "insufficient_quota";
+
+/// Determines the error code of a failed HTTP response. Running out of quota is reported with the
+/// same `429` status as rate limiting, so the `code` and `type` fields of the error body are checked
+/// to tell the two apart.
+pub fn error_code_from_response(status: StatusCode, body: Option<&str>) -> ErrorCode {
+    if body.is_some_and(is_quota_exhausted) {
+        ErrorCode::QuotaExceeded
+    } else {
+        error_code_from_status(status)
+    }
+}
+
+fn is_quota_exhausted(body: &str) -> bool {
+    let Ok(mut json) = serde_json::from_str::<Value>(body) else {
+        return false;
+    };
+    // Some clients keep the error body as a JSON encoded string
+    if let Value::String(encoded) = &json {
+        match serde_json::from_str(encoded) {
+            Ok(decoded) => json = decoded,
+            Err(_) => return false,
+        }
+    }
+
+    let error = json.get("error").unwrap_or(&json);
+    ["code", "type"]
+        .iter()
+        .any(|field| error.get(field).and_then(Value::as_str) == Some(INSUFFICIENT_QUOTA))
+}
+
+/// Creates an `Error` for a failed HTTP response. A rejected API key is only detected by the
+/// provider, so authentication failures get a hint pointing at the key instead of just the status.
+pub fn error_from_status(
+    status: StatusCode,
+    message: String,
+    provider_error_json: Option<String>,
+) -> Error {
+    let code = error_code_from_response(status, provider_error_json.as_deref());
+    let message = if code == ErrorCode::AuthenticationFailed {
+        format!("{message}. Check that the configured API key is correct and still valid")
+    } else {
+        message
+    };
+    Error {
+        code,
+        message,
+        provider_error_json,
+    }
+}
+
+/// Replaces a message without any text, image or tool call with an `empty-response` error. Some
+/// providers occasionally answer with empty or whitespace-only content and a `stop` finish reason,
+/// which callers would otherwise take for a successful answer instead of retrying.
+pub fn reject_empty_response(event: ChatEvent) -> ChatEvent {
+    match event {
+        ChatEvent::Message(response)
+            if response.tool_calls.is_empty()
+                && response.content.iter().all(|part| match part {
+                    ContentPart::Text(text) => text.trim().is_empty(),
+                    ContentPart::Image(_) => false,
+                    ContentPart::Citation(_) => true,
+                }) =>
+        {
+            ChatEvent::Error(Error {
+                code: ErrorCode::EmptyResponse,
+                message: format!("Response {} has no content", response.id),
+                provider_error_json: None,
+            })
+        }
+        event => event,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::with_config_key;
+    use crate::error::{error_code_from_response, error_from_status};
+    use crate::golem::llm::llm::ErrorCode;
+    use reqwest::StatusCode;
+
+    #[test]
+    fn missing_and_rejected_api_keys_are_distinguished() {
+        let missing = with_config_key("GOLEM_LLM_TEST_MISSING_API_KEY", Err, Ok).unwrap_err();
+        assert_eq!(missing.code, ErrorCode::InternalError);
+        assert_eq!(
+            missing.message,
+            "Missing config key: GOLEM_LLM_TEST_MISSING_API_KEY"
+        );
+
+        let rejected = error_from_status(
+            StatusCode::UNAUTHORIZED,
+            "Request failed with 401 Unauthorized".to_string(),
+            None,
+        );
+        assert_eq!(rejected.code, ErrorCode::AuthenticationFailed);
+        assert_eq!(
+            rejected.message,
+            "Request failed with 401 Unauthorized. Check that the configured API key is correct and still valid"
+        );
+    }
+
+    #[test]
+    fn other_failures_are_not_blamed_on_the_api_key() {
+        let error = error_from_status(
+            StatusCode::BAD_REQUEST,
+            "Request failed with 400 Bad Request".to_string(),
+            None,
+        );
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(error.message, "Request failed with 400 Bad Request");
+    }
+
+    #[test]
+    fn insufficient_quota_is_distinguished_from_rate_limiting() {
+        let rate_limited = error_from_status(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Request failed with 429 Too Many Requests".to_string(),
+            Some(
+                r#"{"error":{"message":"Rate limit reached for gpt-4o","type":"requests","code":"rate_limit_exceeded"}}"#
+                    .to_string(),
+            ),
+        );
+        assert_eq!(rate_limited.code, ErrorCode::RateLimitExceeded);
+
+        let out_of_quota = error_from_status(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Request failed with 429 Too Many Requests".to_string(),
+            Some(
+                r#"{"error":{"message":"You exceeded your current quota","type":"insufficient_quota","code":"insufficient_quota"}}"#
+                    .to_string(),
+            ),
+        );
+        assert_eq!(out_of_quota.code, ErrorCode::QuotaExceeded);
+
+        let encoded = serde_json::to_string(r#"{"code":"insufficient_quota"}"#).unwrap();
+        assert_eq!(
+            error_code_from_response(StatusCode::TOO_MANY_REQUESTS, Some(&encoded)),
+            ErrorCode::QuotaExceeded
+        );
+        assert_eq!(
+            error_code_from_response(StatusCode::TOO_MANY_REQUESTS, Some("Too Many Requests")),
+            ErrorCode::RateLimitExceeded
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use crate::event_source::parser::{is_bom, is_lf, line, RawEventLine};
-use crate::event_source::utf8_stream::Utf8Stream;
-use crate::event_source::MessageEvent;
+use crate::event_source::utf8_stream::{Utf8Stream, Utf8StreamError};
+use crate::event_source::{Event, MessageEvent};
 use core::time::Duration;
 use golem_rust::bindings::wasi::io::streams::{InputStream, StreamError};
 use golem_rust::wasm_rpc::Pollable;
@@ -159,78 +159,105 @@ impl LlmStream for EventStream {
         self.stream.subscribe()
     }
 
-    fn poll_next(&mut self) -> Poll<Option<Result<MessageEvent, EventStreamError<StreamError>>>> {
+    fn poll_next(&mut self) -> Poll<Option<Result<Event, EventStreamError<StreamError>>>> {
         trace!("Polling for next event");
 
-        match parse_event(&mut self.buffer, &mut self.builder) {
-            Ok(Some(event)) => {
-                self.last_event_id = event.id.clone();
-                return Poll::Ready(Some(Ok(event)));
-            }
-            Err(err) => return Poll::Ready(Some(Err(err))),
-            _ => {}
+        let Self {
+            stream,
+            buffer,
+            builder,
+            state,
+            last_event_id,
+        } = self;
+        let next = next_event(buffer, builder, state, || stream.poll_next());
+        if let Poll::Ready(Some(Ok(Event::Message(message)))) = &next {
+            *last_event_id = message.id.clone();
         }
+        next
+    }
+}
 
-        if self.state.is_terminated() {
-            return Poll::Ready(None);
-        }
+/// Takes the next event from the buffer, reading more text with `read` only while the buffer does
+/// not hold a complete event. Nothing is read ahead of the consumer, so the buffer never holds more
+/// than the event being parsed and the rest of the last chunk read, however fast the provider
+/// sends, and the provider is held back by the connection instead.
+fn next_event<E>(
+    buffer: &mut String,
+    builder: &mut EventBuilder,
+    state: &mut EventStreamState,
+    mut read: impl FnMut() -> Poll<Option<Result<String, Utf8StreamError<E>>>>,
+) -> Poll<Option<Result<Event, EventStreamError<E>>>> {
+    match parse_event(buffer, builder) {
+        Ok(Some(event)) => return Poll::Ready(Some(Ok(event))),
+        Err(err) => return Poll::Ready(Some(Err(err))),
+        _ => {}
+    }
 
-        loop {
-            match self.stream.poll_next() {
-                Poll::Ready(Some(Ok(string))) => {
-                    if string.is_empty() {
-                        continue;
-                    }
+    if state.is_terminated() {
+        return Poll::Ready(None);
+    }
 
-                    let slice = if self.state.is_started() {
-                        &string
-                    } else {
-                        self.state = EventStreamState::Started;
-                        if is_bom(string.chars().next().unwrap()) {
-                            &string[1..]
-                        } else {
-                            &string
-                        }
-                    };
-                    self.buffer.push_str(slice);
+    loop {
+        match read() {
+            Poll::Ready(Some(Ok(string))) => {
+                if string.is_empty() {
+                    continue;
+                }
 
-                    match parse_event(&mut self.buffer, &mut self.builder) {
-                        Ok(Some(event)) => {
-                            self.last_event_id = event.id.clone();
-                            return Poll::Ready(Some(Ok(event)));
-                        }
-                        Err(err) => return Poll::Ready(Some(Err(err))),
-                        _ => {}
+                let slice = if state.is_started() {
+                    &string
+                } else {
+                    *state = EventStreamState::Started;
+                    if is_bom(string.chars().next().unwrap()) {
+                        &string[1..]
+                    } else {
+                        &string
                     }
+                };
+                buffer.push_str(slice);
+
+                match parse_event(buffer, builder) {
+                    Ok(Some(event)) => return Poll::Ready(Some(Ok(event))),
+                    Err(err) => return Poll::Ready(Some(Err(err))),
+                    _ => {}
                 }
-                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err.into()))),
-                Poll::Ready(None) => {
-                    self.state = EventStreamState::Terminated;
-                    return Poll::Ready(None);
-                }
-                Poll::Pending => return Poll::Pending,
             }
+            Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err.into()))),
+            Poll::Ready(None) => {
+                *state = EventStreamState::Terminated;
+                return Poll::Ready(None);
+            }
+            Poll::Pending => return Poll::Pending,
         }
     }
 }
 
+/// Parses the next event from the buffer. Comment lines are returned as soon as they are parsed,
+/// as servers send them to keep the connection alive.
 fn parse_event<E>(
     buffer: &mut String,
     builder: &mut EventBuilder,
-) -> Result<Option<MessageEvent>, EventStreamError<E>> {
+) -> Result<Option<Event>, EventStreamError<E>> {
     if buffer.is_empty() {
         return Ok(None);
     }
     loop {
         match line(buffer.as_ref()) {
             Ok((rem, next_line)) => {
+                let comment = match next_line {
+                    RawEventLine::Comment(comment) => Some(comment.to_string()),
+                    _ => None,
+                };
                 builder.add(next_line);
                 let consumed = buffer.len() - rem.len();
                 let rem = buffer.split_off(consumed);
                 *buffer = rem;
+                if let Some(comment) = comment {
+                    return Ok(Some(Event::Comment(comment)));
+                }
                 if builder.is_complete {
                     if let Some(event) = builder.dispatch() {
-                        return Ok(Some(event));
+                        return Ok(Some(Event::Message(event)));
                     }
                 }
             }
@@ -239,3 +266,44 @@ fn parse_event<E>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::event_source::event_stream::{next_event, EventBuilder, EventStreamState};
+    use crate::event_source::utf8_stream::Utf8StreamError;
+    use crate::event_source::Event;
+    use std::cell::Cell;
+    use std::task::Poll;
+
+    #[test]
+    fn buffer_stays_bounded_while_a_fast_provider_is_consumed_slowly() {
+        // A provider that always has another chunk of ten events ready
+        let chunks_read = Cell::new(0);
+        let read = || -> Poll<Option<Result<String, Utf8StreamError<()>>>> {
+            chunks_read.set(chunks_read.get() + 1);
+            let chunk = (0..10)
+                .map(|event| {
+                    format!(
+                        "data: {{\"chunk\": {}, \"event\": {event}}}\n\n",
+                        chunks_read.get()
+                    )
+                })
+                .collect();
+            Poll::Ready(Some(Ok(chunk)))
+        };
+        let chunk_len = "data: {\"chunk\": 100, \"event\": 0}\n\n".len() * 10;
+
+        let mut buffer = String::new();
+        let mut builder = EventBuilder::default();
+        let mut state = EventStreamState::NotStarted;
+        for consumed in 1..=1000_usize {
+            let Poll::Ready(Some(Ok(Event::Message(_)))) =
+                next_event(&mut buffer, &mut builder, &mut state, &read)
+            else {
+                panic!("expected an event");
+            };
+            assert!(buffer.len() <= chunk_len);
+            assert_eq!(chunks_read.get(), consumed.div_ceil(10));
+        }
+    }
+}
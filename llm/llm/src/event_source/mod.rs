@@ -100,13 +100,13 @@ impl EventSource {
 
         match &mut self.stream {
             StreamType::EventStream(stream) => match stream.poll_next() {
-                Poll::Ready(Some(Ok(event))) => Poll::Ready(Some(Ok(Event::Message(event)))),
+                Poll::Ready(Some(Ok(event))) => Poll::Ready(Some(Ok(event))),
                 Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err.into()))),
                 Poll::Ready(None) => Poll::Ready(None),
                 Poll::Pending => Poll::Pending,
             },
             StreamType::NdJsonStream(stream) => match stream.poll_next() {
-                Poll::Ready(Some(Ok(event))) => Poll::Ready(Some(Ok(Event::Message(event)))),
+                Poll::Ready(Some(Ok(event))) => Poll::Ready(Some(Ok(event))),
                 Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err.into()))),
                 Poll::Ready(None) => Poll::Ready(None),
                 Poll::Pending => Poll::Pending,
@@ -157,6 +157,8 @@ pub enum Event {
     Open,
     /// The event fired when a [`MessageEvent`] is received
     Message(MessageEvent),
+    /// The event fired when a comment line is received, which servers send as a keep-alive
+    Comment(String),
 }
 
 impl From<MessageEvent> for Event {
@@ -1,6 +1,6 @@
 use super::stream::{LlmStream, StreamError as NdJsonStreamError};
 use crate::event_source::utf8_stream::Utf8Stream;
-use crate::event_source::MessageEvent;
+use crate::event_source::{Event, MessageEvent};
 use golem_rust::bindings::wasi::io::streams::{InputStream, StreamError};
 use golem_rust::wasm_rpc::Pollable;
 use log::trace;
@@ -52,12 +52,12 @@ impl LlmStream for NdJsonStream {
         self.stream.subscribe()
     }
 
-    fn poll_next(&mut self) -> Poll<Option<Result<MessageEvent, NdJsonStreamError<StreamError>>>> {
+    fn poll_next(&mut self) -> Poll<Option<Result<Event, NdJsonStreamError<StreamError>>>> {
         trace!("Polling for next NDJSON event");
 
         // Try to parse a complete line from the current buffer
         if let Some(event) = try_parse_line(self)? {
-            return Poll::Ready(Some(Ok(event)));
+            return Poll::Ready(Some(Ok(Event::Message(event))));
         }
 
         if self.state.is_terminated() {
@@ -79,7 +79,7 @@ impl LlmStream for NdJsonStream {
 
                     // Try to parse complete lines from the updated buffer
                     if let Some(event) = try_parse_line(self)? {
-                        return Poll::Ready(Some(Ok(event)));
+                        return Poll::Ready(Some(Ok(Event::Message(event))));
                     }
                 }
                 Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err.into()))),
@@ -95,7 +95,7 @@ impl LlmStream for NdJsonStream {
                             id: self.last_event_id.clone(),
                             retry: None,
                         };
-                        return Poll::Ready(Some(Ok(event)));
+                        return Poll::Ready(Some(Ok(Event::Message(event))));
                     }
 
                     return Poll::Ready(None);
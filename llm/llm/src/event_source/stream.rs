@@ -2,8 +2,7 @@ use core::fmt;
 use std::{string::FromUtf8Error, task::Poll};
 
 use super::{
-    event_stream::EventStream, ndjson_stream::NdJsonStream, utf8_stream::Utf8StreamError,
-    MessageEvent,
+    event_stream::EventStream, ndjson_stream::NdJsonStream, utf8_stream::Utf8StreamError, Event,
 };
 use golem_rust::{
     bindings::wasi::io::streams::{InputStream, StreamError as WasiStreamError},
@@ -21,7 +20,7 @@ pub trait LlmStream {
     fn set_last_event_id(&mut self, id: impl Into<String>);
     fn last_event_id(&self) -> &str;
     fn subscribe(&self) -> Pollable;
-    fn poll_next(&mut self) -> Poll<Option<Result<MessageEvent, StreamError<WasiStreamError>>>>;
+    fn poll_next(&mut self) -> Poll<Option<Result<Event, StreamError<WasiStreamError>>>>;
 }
 
 /// Error thrown while parsing an event line
@@ -7,7 +7,7 @@ use std::task::Poll;
 pub struct Utf8Stream {
     subscription: Pollable,
     stream: InputStream,
-    buffer: Vec<u8>,
+    decoder: Utf8Decoder,
     terminated: bool,
 }
 
@@ -19,7 +19,7 @@ impl Utf8Stream {
         Self {
             stream,
             subscription,
-            buffer: Vec::new(),
+            decoder: Utf8Decoder::default(),
             terminated: false,
         }
     }
@@ -34,31 +34,17 @@ impl Utf8Stream {
                 Ok(bytes) => {
                     trace!("Read {} bytes from response stream", bytes.len());
 
-                    self.buffer.extend_from_slice(bytes.as_ref());
-                    let bytes = core::mem::take(&mut self.buffer);
-                    match String::from_utf8(bytes) {
-                        Ok(string) => Poll::Ready(Some(Ok(string))),
-                        Err(err) => {
-                            let valid_size = err.utf8_error().valid_up_to();
-                            let mut bytes = err.into_bytes();
-                            let rem = bytes.split_off(valid_size);
-                            self.buffer = rem;
-                            Poll::Ready(Some(Ok(unsafe { String::from_utf8_unchecked(bytes) })))
-                        }
-                    }
+                    Poll::Ready(Some(Ok(self.decoder.decode(bytes.as_ref()))))
                 }
                 Err(StreamError::Closed) => {
                     trace!("Response stream closed");
 
                     self.terminated = true;
-                    if self.buffer.is_empty() {
-                        Poll::Ready(None)
-                    } else {
-                        Poll::Ready(Some(
-                            String::from_utf8(core::mem::take(&mut self.buffer))
-                                .map_err(Utf8StreamError::Utf8),
-                        ))
-                    }
+                    Poll::Ready(
+                        self.decoder
+                            .finish()
+                            .map(|remainder| remainder.map_err(Utf8StreamError::Utf8)),
+                    )
                 }
                 Err(err) => Poll::Ready(Some(Err(Utf8StreamError::Transport(err)))),
             }
@@ -68,6 +54,60 @@ impl Utf8Stream {
     }
 }
 
+/// Decodes UTF-8 text from chunks of bytes which may split a multi-byte character at their
+/// boundaries. The trailing bytes of an incomplete character are kept until the next chunk completes
+/// it, so only whole characters are emitted.
+#[derive(Default)]
+struct Utf8Decoder {
+    incomplete: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    fn decode(&mut self, chunk: &[u8]) -> String {
+        self.incomplete.extend_from_slice(chunk);
+        let bytes = core::mem::take(&mut self.incomplete);
+
+        let mut text = String::with_capacity(bytes.len());
+        let mut rest = bytes.as_slice();
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    break;
+                }
+                Err(err) => {
+                    let (valid, invalid) = rest.split_at(err.valid_up_to());
+                    text.push_str(unsafe { std::str::from_utf8_unchecked(valid) });
+                    match err.error_len() {
+                        Some(len) => {
+                            // Bytes that can never form a valid character are replaced instead
+                            // of failing the whole stream
+                            trace!("Replacing {len} invalid UTF-8 bytes in response stream");
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &invalid[len..];
+                        }
+                        None => {
+                            self.incomplete = invalid.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        text
+    }
+
+    /// Returns the bytes left over at the end of the stream, which is an error if they do not form
+    /// a complete character
+    fn finish(&mut self) -> Option<Result<String, FromUtf8Error>> {
+        if self.incomplete.is_empty() {
+            None
+        } else {
+            Some(String::from_utf8(core::mem::take(&mut self.incomplete)))
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Utf8StreamError<E> {
     Utf8(FromUtf8Error),
@@ -79,3 +119,30 @@ impl<E> From<FromUtf8Error> for Utf8StreamError<E> {
         Self::Utf8(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::event_source::utf8_stream::Utf8Decoder;
+
+    #[test]
+    fn multi_byte_character_split_between_chunks_is_reassembled() {
+        let bytes = "Hello 👋 world".as_bytes();
+        let split = "Hello ".len() + 2;
+
+        let mut decoder = Utf8Decoder::default();
+        let first = decoder.decode(&bytes[..split]);
+        let second = decoder.decode(&bytes[split..]);
+
+        assert_eq!(first, "Hello ");
+        assert_eq!(second, "👋 world");
+        assert_eq!(decoder.finish(), None);
+    }
+
+    #[test]
+    fn invalid_bytes_are_replaced_and_truncated_character_fails_at_the_end() {
+        let mut decoder = Utf8Decoder::default();
+
+        assert_eq!(decoder.decode(b"a\xffb\xf0\x9f"), "a\u{fffd}b");
+        assert!(decoder.finish().unwrap().is_err());
+    }
+}
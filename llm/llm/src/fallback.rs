@@ -0,0 +1,391 @@
+use crate::config::provider_option;
+use crate::durability::ExtendedGuest;
+use crate::golem::llm::llm::{
+    BatchInfo, BatchRequest, BatchResult, CapabilitySet, ChatEvent, ChatStream, Config,
+    EmbeddingResponse, Error, ErrorCode, Guest, ImageReference, Message, ModelInfo, StreamDelta,
+    ToolCall, ToolResult, Usage,
+};
+use golem_rust::wasm_rpc::Pollable;
+use log::warn;
+use std::marker::PhantomData;
+
+type SendFn = fn(Vec<Message>, Config) -> ChatEvent;
+type ContinueFn = fn(Vec<Message>, Vec<(ToolCall, ToolResult)>, Config) -> ChatEvent;
+
+/// A provider implementation requests can fail over to, identified by the name used in the
+/// `fallbacks` provider option
+#[derive(Clone, Copy)]
+pub struct FallbackProvider {
+    pub name: &'static str,
+    pub send: SendFn,
+    pub continue_: ContinueFn,
+}
+
+impl FallbackProvider {
+    pub fn new<Impl: ExtendedGuest>(name: &'static str) -> Self {
+        Self {
+            name,
+            send: Impl::send,
+            continue_: Impl::continue_,
+        }
+    }
+}
+
+/// Sends requests to an ordered list of providers. The first one is the primary provider, and if a
+/// request fails with a retryable error or times out, it fails over to the `provider:model` pairs
+/// listed in the `fallbacks` provider option, in order.
+///
+/// Only `send` and `continue` fail over; streams are always served by the primary provider.
+pub struct ProviderFallback {
+    providers: Vec<FallbackProvider>,
+}
+
+impl ProviderFallback {
+    pub fn new(providers: Vec<FallbackProvider>) -> Self {
+        assert!(!providers.is_empty(), "a primary provider is required");
+        Self { providers }
+    }
+
+    pub fn send(&self, messages: Vec<Message>, config: Config) -> ChatEvent {
+        self.run(config, |provider, config| {
+            (provider.send)(messages.clone(), config)
+        })
+    }
+
+    pub fn continue_(
+        &self,
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
+    ) -> ChatEvent {
+        self.run(config, |provider, config| {
+            (provider.continue_)(messages.clone(), tool_results.clone(), config)
+        })
+    }
+
+    fn run(
+        &self,
+        config: Config,
+        call: impl Fn(&FallbackProvider, Config) -> ChatEvent,
+    ) -> ChatEvent {
+        let fallbacks = match self.fallbacks(&config) {
+            Ok(fallbacks) => fallbacks,
+            Err(err) => return ChatEvent::Error(err),
+        };
+
+        let mut config = config;
+        config.provider_options.retain(|kv| kv.key != "fallbacks");
+
+        let primary = &self.providers[0];
+        let mut current = primary.name;
+        let mut event = call(primary, config.clone());
+        for (provider, model) in fallbacks {
+            match &event {
+                ChatEvent::Error(error) if is_retryable(error) => {
+                    warn!(
+                        "Request to {current} failed with {:?}: {}, failing over to {}:{model}",
+                        error.code, error.message, provider.name
+                    );
+                }
+                _ => break,
+            }
+
+            let fallback_config = Config {
+                model,
+                ..config.clone()
+            };
+            current = provider.name;
+            event = call(provider, fallback_config);
+        }
+        event
+    }
+
+    /// Parses the `fallbacks` provider option, a comma separated list of `provider:model` pairs
+    fn fallbacks(&self, config: &Config) -> Result<Vec<(&FallbackProvider, String)>, Error> {
+        let Some(fallbacks) = provider_option(config, "fallbacks") else {
+            return Ok(Vec::new());
+        };
+
+        fallbacks
+            .split(',')
+            .map(|fallback| fallback.trim())
+            .filter(|fallback| !fallback.is_empty())
+            .map(|fallback| {
+                let (name, model) = fallback.split_once(':').ok_or_else(|| {
+                    invalid_fallback(format!(
+                        "Invalid fallback: {fallback}, expected provider:model"
+                    ))
+                })?;
+                let provider = self
+                    .providers
+                    .iter()
+                    .find(|provider| provider.name == name)
+                    .ok_or_else(|| {
+                        invalid_fallback(format!("Unknown fallback provider: {name}"))
+                    })?;
+                Ok((provider, model.to_string()))
+            })
+            .collect()
+    }
+}
+
+/// Checks whether a request failing with the given error may succeed with another provider
+fn is_retryable(error: &Error) -> bool {
+    matches!(
+        error.code,
+        ErrorCode::RateLimitExceeded
+            | ErrorCode::Overloaded
+            | ErrorCode::InternalError
+            | ErrorCode::Timeout
+            | ErrorCode::EmptyResponse
+            | ErrorCode::Unknown
+    )
+}
+
+fn invalid_fallback(message: String) -> Error {
+    Error {
+        code: ErrorCode::InvalidRequest,
+        message,
+        provider_error_json: None,
+    }
+}
+
+/// Lists the providers a component can fail over between, the first being the component's own
+pub trait FallbackProviders {
+    fn fallback_providers() -> Vec<FallbackProvider>;
+}
+
+/// Wraps an LLM implementation so its `send` and `continue` fail over between the providers it
+/// lists, as configured by the `fallbacks` provider option. Everything else is passed through.
+pub struct FallbackLLM<Impl> {
+    phantom: PhantomData<Impl>,
+}
+
+impl<Impl: ExtendedGuest + FallbackProviders> Guest for FallbackLLM<Impl> {
+    type ChatStream = Impl::ChatStream;
+
+    fn send(messages: Vec<Message>, config: Config) -> ChatEvent {
+        ProviderFallback::new(Impl::fallback_providers()).send(messages, config)
+    }
+
+    fn continue_(
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
+    ) -> ChatEvent {
+        ProviderFallback::new(Impl::fallback_providers()).continue_(messages, tool_results, config)
+    }
+
+    fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
+        Impl::stream(messages, config)
+    }
+
+    fn embed(inputs: Vec<String>, config: Config) -> Result<EmbeddingResponse, Error> {
+        Impl::embed(inputs, config)
+    }
+
+    fn generate_image(prompt: String, config: Config) -> Result<Vec<ImageReference>, Error> {
+        Impl::generate_image(prompt, config)
+    }
+
+    fn list_models(config: Config) -> Result<Vec<ModelInfo>, Error> {
+        Impl::list_models(config)
+    }
+
+    fn validate_credentials(config: Config) -> Result<(), Error> {
+        Impl::validate_credentials(config)
+    }
+
+    fn capabilities(config: Config) -> CapabilitySet {
+        Impl::capabilities(config)
+    }
+
+    fn batch_submit(requests: Vec<BatchRequest>, config: Config) -> Result<BatchInfo, Error> {
+        Impl::batch_submit(requests, config)
+    }
+
+    fn batch_status(batch_id: String, config: Config) -> Result<BatchInfo, Error> {
+        Impl::batch_status(batch_id, config)
+    }
+
+    fn batch_results(batch_id: String, config: Config) -> Result<Vec<BatchResult>, Error> {
+        Impl::batch_results(batch_id, config)
+    }
+
+    fn summarize_history(
+        messages: Vec<Message>,
+        config: Config,
+        keep_last_n: u32,
+    ) -> Result<Vec<Message>, Error> {
+        Impl::summarize_history(messages, config, keep_last_n)
+    }
+
+    fn sum_usage(usages: Vec<Usage>) -> Usage {
+        Impl::sum_usage(usages)
+    }
+}
+
+impl<Impl: ExtendedGuest + FallbackProviders> ExtendedGuest for FallbackLLM<Impl> {
+    fn unwrapped_stream(messages: Vec<Message>, config: Config) -> Self::ChatStream {
+        Impl::unwrapped_stream(messages, config)
+    }
+
+    fn unwrapped_continue_stream(
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
+    ) -> Self::ChatStream {
+        Impl::unwrapped_continue_stream(messages, tool_results, config)
+    }
+
+    fn retry_prompt(original_messages: &[Message], partial_result: &[StreamDelta]) -> Vec<Message> {
+        Impl::retry_prompt(original_messages, partial_result)
+    }
+
+    fn subscribe(stream: &Self::ChatStream) -> Pollable {
+        Impl::subscribe(stream)
+    }
+
+    fn resumable_response_id(stream: &Self::ChatStream) -> Option<String> {
+        Impl::resumable_response_id(stream)
+    }
+
+    fn resume_stream(
+        response_id: String,
+        last_position: u32,
+        config: Config,
+    ) -> Option<Self::ChatStream> {
+        Impl::resume_stream(response_id, last_position, config)
+    }
+
+    fn idle_timer(stream: &Self::ChatStream) -> Option<Pollable> {
+        Impl::idle_timer(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fallback::{FallbackProvider, ProviderFallback};
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, Message,
+        ResponseMetadata, Role, ToolCall, ToolResult,
+    };
+    use crate::testing::config;
+
+    fn messages() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("Hello".to_string())],
+        }]
+    }
+
+    fn failing_send(_messages: Vec<Message>, _config: Config) -> ChatEvent {
+        ChatEvent::Error(Error {
+            code: ErrorCode::RateLimitExceeded,
+            message: "Request failed with 429 Too Many Requests".to_string(),
+            provider_error_json: None,
+        })
+    }
+
+    fn answering_send(_messages: Vec<Message>, config: Config) -> ChatEvent {
+        ChatEvent::Message(CompleteResponse {
+            id: "response".to_string(),
+            content: vec![ContentPart::Text(format!("Answer from {}", config.model))],
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: config
+                    .provider_options
+                    .iter()
+                    .find(|kv| kv.key == "fallbacks")
+                    .map(|kv| kv.value.clone()),
+                stop_sequence: None,
+                tool_calls: None,
+            },
+        })
+    }
+
+    fn unused_continue(
+        _messages: Vec<Message>,
+        _tool_results: Vec<(ToolCall, ToolResult)>,
+        _config: Config,
+    ) -> ChatEvent {
+        unreachable!()
+    }
+
+    fn fallback(primary_send: fn(Vec<Message>, Config) -> ChatEvent) -> ProviderFallback {
+        ProviderFallback::new(vec![
+            FallbackProvider {
+                name: "primary",
+                send: primary_send,
+                continue_: unused_continue,
+            },
+            FallbackProvider {
+                name: "secondary",
+                send: answering_send,
+                continue_: unused_continue,
+            },
+        ])
+    }
+
+    #[test]
+    fn fails_over_to_the_next_provider() {
+        let event = fallback(failing_send).send(
+            messages(),
+            config(
+                "primary-model",
+                &[("fallbacks", "secondary:fallback-model")],
+            ),
+        );
+
+        let ChatEvent::Message(response) = event else {
+            panic!("expected a message response");
+        };
+        assert_eq!(
+            response.content,
+            vec![ContentPart::Text("Answer from fallback-model".to_string())]
+        );
+        assert_eq!(response.metadata.provider_metadata_json, None);
+    }
+
+    #[test]
+    fn exhausted_quota_does_not_fail_over() {
+        fn out_of_quota_send(_messages: Vec<Message>, _config: Config) -> ChatEvent {
+            ChatEvent::Error(Error {
+                code: ErrorCode::QuotaExceeded,
+                message: "Request failed with 429 Too Many Requests".to_string(),
+                provider_error_json: None,
+            })
+        }
+
+        let event = fallback(out_of_quota_send).send(
+            messages(),
+            config(
+                "primary-model",
+                &[("fallbacks", "secondary:fallback-model")],
+            ),
+        );
+
+        let ChatEvent::Error(error) = event else {
+            panic!("expected an error");
+        };
+        assert_eq!(error.code, ErrorCode::QuotaExceeded);
+    }
+
+    #[test]
+    fn unknown_fallback_provider_is_rejected() {
+        let event = fallback(failing_send).send(
+            messages(),
+            config("primary-model", &[("fallbacks", "tertiary:model")]),
+        );
+
+        let ChatEvent::Error(error) = event else {
+            panic!("expected an error");
+        };
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::canonical::request_hash;
+use crate::config::provider_option;
+use crate::golem::llm::llm::{Config, Error, ErrorCode};
+use crate::raw_body::RawResponse;
+use log::trace;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// A directory of recorded non-streaming responses, one JSON file per request body
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResponseFixtures {
+    /// Responses received from the provider are written to the directory
+    Record(PathBuf),
+    /// Responses are read from the directory instead of calling the provider
+    Replay(PathBuf),
+}
+
+/// Gets the fixture directory of `send` and `continue` responses from the `response_replay_dir` or
+/// `response_record_dir` provider option. Replaying takes precedence if both are set.
+pub fn response_fixtures(config: &Config) -> Option<ResponseFixtures> {
+    provider_option(config, "response_replay_dir")
+        .map(|dir| ResponseFixtures::Replay(PathBuf::from(dir)))
+        .or_else(|| {
+            provider_option(config, "response_record_dir")
+                .map(|dir| ResponseFixtures::Record(PathBuf::from(dir)))
+        })
+}
+
+/// Replays the recorded response to the request body, or calls `send` and records its response,
+/// depending on the fixture mode. Without fixtures `send` is just called.
+///
+/// Fixtures are named after the provider and the hash of the request body, so a replayed request
+/// has to be identical to the recorded one.
+pub fn with_response_fixtures<T: DeserializeOwned>(
+    fixtures: Option<&ResponseFixtures>,
+    provider: &str,
+    body: &impl Serialize,
+    send: impl FnOnce() -> Result<RawResponse<T>, Error>,
+) -> Result<RawResponse<T>, Error> {
+    match fixtures {
+        None => send(),
+        Some(ResponseFixtures::Replay(dir)) => {
+            let path = fixture_path(dir, provider, body)?;
+            trace!("Replaying response from {}", path.display());
+            let fixture = std::fs::read_to_string(&path).map_err(|err| {
+                fixture_error(format!(
+                    "No recorded response for this request in {}: {err}",
+                    path.display()
+                ))
+            })?;
+            serde_json::from_str(&fixture).map_err(|err| {
+                fixture_error(format!(
+                    "Failed to parse recorded response {}: {err}",
+                    path.display()
+                ))
+            })
+        }
+        Some(ResponseFixtures::Record(dir)) => {
+            let path = fixture_path(dir, provider, body)?;
+            let response = send()?;
+            trace!("Recording response to {}", path.display());
+            std::fs::create_dir_all(dir)
+                .and_then(|_| {
+                    std::fs::write(
+                        &path,
+                        serde_json::to_string_pretty(&response.raw).unwrap_or_default(),
+                    )
+                })
+                .map_err(|err| {
+                    fixture_error(format!(
+                        "Failed to record response to {}: {err}",
+                        path.display()
+                    ))
+                })?;
+            Ok(response)
+        }
+    }
+}
+
+fn fixture_path(dir: &Path, provider: &str, body: &impl Serialize) -> Result<PathBuf, Error> {
+    Ok(dir.join(format!("{provider}-{}.json", request_hash(body)?)))
+}
+
+fn fixture_error(message: String) -> Error {
+    Error {
+        code: ErrorCode::InternalError,
+        message,
+        provider_error_json: None,
+    }
+}
@@ -0,0 +1,61 @@
+use crate::golem::llm::llm::{CompleteResponse, ContentPart, Message, Role};
+
+/// Converts a response to the assistant message continuing the conversation it answers, for
+/// building the history of a multi-turn conversation.
+///
+/// Citations are left out, as they are never sent to the provider. Messages cannot carry tool
+/// calls, so the tool calls of the response are passed to `continue` together with their results
+/// instead, which sends them to the provider as part of the assistant turn.
+pub fn response_to_message(response: CompleteResponse) -> Message {
+    Message {
+        role: Role::Assistant,
+        name: None,
+        content: response
+            .content
+            .into_iter()
+            .filter(|part| !matches!(part, ContentPart::Citation(_)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        Citation, CompleteResponse, ContentPart, Message, ResponseMetadata, Role,
+    };
+    use crate::history::response_to_message;
+
+    #[test]
+    fn citations_are_left_out_of_the_message() {
+        let response = CompleteResponse {
+            id: "response-1".to_string(),
+            content: vec![
+                ContentPart::Text("Golem 1.3 added agents.".to_string()),
+                ContentPart::Citation(Citation {
+                    title: None,
+                    url: "https://golem.cloud/blog/golem-1-3".to_string(),
+                    quoted_text: None,
+                }),
+            ],
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                stop_sequence: None,
+                tool_calls: None,
+            },
+        };
+
+        assert_eq!(
+            response_to_message(response),
+            Message {
+                role: Role::Assistant,
+                name: None,
+                content: vec![ContentPart::Text("Golem 1.3 added agents.".to_string())],
+            }
+        );
+    }
+}
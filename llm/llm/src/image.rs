@@ -0,0 +1,391 @@
+use crate::config::provider_option;
+use crate::error::{from_reqwest_error, unsupported};
+use crate::golem::llm::llm::{
+    Config, ContentPart, Error, ErrorCode, ImageReference, ImageSource, Message,
+};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+
+/// Mime types of inline images accepted by all the supported providers
+pub const SUPPORTED_IMAGE_MIME_TYPES: [&str; 4] =
+    ["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+/// Creates an `Error` value representing that an inline image has an unsupported mime type
+pub fn unsupported_image_mime_type(mime_type: &str) -> Error {
+    Error {
+        code: ErrorCode::InvalidRequest,
+        message: format!(
+            "Unsupported image mime type: {mime_type}, expected one of {}",
+            SUPPORTED_IMAGE_MIME_TYPES.join(", ")
+        ),
+        provider_error_json: None,
+    }
+}
+
+/// Checks that the mime type of an inline image is one of `SUPPORTED_IMAGE_MIME_TYPES`
+pub fn validate_image_mime_type(mime_type: &str) -> Result<(), Error> {
+    if SUPPORTED_IMAGE_MIME_TYPES.contains(&mime_type) {
+        Ok(())
+    } else {
+        Err(unsupported_image_mime_type(mime_type))
+    }
+}
+
+/// The documented limits a provider puts on the images of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageLimits {
+    /// The most images accepted in one request
+    pub max_count: Option<usize>,
+    /// The largest inline image accepted, in bytes
+    pub max_bytes: Option<usize>,
+}
+
+/// Checks the images of a request against the limits of the provider before it is sent, so
+/// exceeding them fails with an `invalid-request` error naming the limit instead of an opaque
+/// provider error. Only inline images can be checked for their size.
+pub fn validate_image_limits(
+    messages: &[Message],
+    limits: ImageLimits,
+    provider_name: &str,
+) -> Result<(), Error> {
+    let images = messages
+        .iter()
+        .flat_map(|message| &message.content)
+        .filter_map(|part| match part {
+            ContentPart::Image(image) => Some(image),
+            ContentPart::Text(_) | ContentPart::Citation(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(max_count) = limits
+        .max_count
+        .filter(|max_count| images.len() > *max_count)
+    {
+        return Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "The request contains {} images, but {provider_name} accepts at most {max_count} images per request",
+                images.len()
+            ),
+            provider_error_json: None,
+        });
+    }
+    if let Some(max_bytes) = limits.max_bytes {
+        for (index, image) in images.iter().enumerate() {
+            if let ImageReference::Inline(image) = image {
+                if image.data.len() > max_bytes {
+                    return Err(Error {
+                        code: ErrorCode::InvalidRequest,
+                        message: format!(
+                            "Image {} is {} bytes, but {provider_name} accepts images of at most {max_bytes} bytes",
+                            index + 1,
+                            image.data.len()
+                        ),
+                        provider_error_json: None,
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How images referenced by URL are passed to the provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageTransport {
+    /// The URL is sent, and the provider fetches the image itself
+    Url,
+    /// The image is downloaded and sent inline, which avoids the fetch by the provider but makes the
+    /// request larger
+    Inline,
+    /// URLs are sent to providers able to fetch them, other providers get the images inline
+    Auto,
+}
+
+/// Reads the `image_transport` provider option (`url`, `inline` or `auto`), defaulting to `auto`.
+/// The older `inline_remote_images` option set to `true` is still accepted as `inline`.
+pub fn image_transport(config: &Config) -> Result<ImageTransport, Error> {
+    match provider_option(config, "image_transport") {
+        Some("url") => Ok(ImageTransport::Url),
+        Some("inline") => Ok(ImageTransport::Inline),
+        Some("auto") => Ok(ImageTransport::Auto),
+        Some(other) => Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "Invalid image_transport option: {other}, expected url, inline or auto"
+            ),
+            provider_error_json: None,
+        }),
+        None if provider_option(config, "inline_remote_images") == Some("true") => {
+            Ok(ImageTransport::Inline)
+        }
+        None => Ok(ImageTransport::Auto),
+    }
+}
+
+/// Applies the image transport to the messages of a request. `remote_urls` tells whether the
+/// provider can fetch images referenced by URL; if it cannot, the `url` transport is rejected and
+/// `auto` inlines the images using `fetch`. Inline images are always sent inline.
+pub fn transport_images(
+    messages: Vec<Message>,
+    transport: ImageTransport,
+    remote_urls: bool,
+    provider_name: &str,
+    fetch: impl Fn(&str) -> Result<(Vec<u8>, Option<String>), Error>,
+) -> Result<Vec<Message>, Error> {
+    match transport {
+        ImageTransport::Url | ImageTransport::Auto if remote_urls => Ok(messages),
+        ImageTransport::Url => {
+            let has_image_urls = messages.iter().any(|message| {
+                message
+                    .content
+                    .iter()
+                    .any(|part| matches!(part, ContentPart::Image(ImageReference::Url(_))))
+            });
+            if has_image_urls {
+                Err(unsupported(format!(
+                    "The {provider_name} provider cannot fetch images by URL, use the inline or \
+                     auto image_transport"
+                )))
+            } else {
+                Ok(messages)
+            }
+        }
+        ImageTransport::Inline | ImageTransport::Auto => inline_remote_images(messages, fetch),
+    }
+}
+
+/// Replaces every image referenced by URL in the messages with an inline image, using `fetch` to
+/// download it. `fetch` returns the image data and the mime type reported by the server, if any;
+/// without a reported mime type it is detected from the image data.
+pub fn inline_remote_images(
+    messages: Vec<Message>,
+    fetch: impl Fn(&str) -> Result<(Vec<u8>, Option<String>), Error>,
+) -> Result<Vec<Message>, Error> {
+    let mut result = Vec::with_capacity(messages.len());
+    for message in messages {
+        let mut content = Vec::with_capacity(message.content.len());
+        for part in message.content {
+            match part {
+                ContentPart::Image(ImageReference::Url(image_url)) => {
+                    let (data, mime_type) = fetch(&image_url.url)?;
+                    let mime_type = mime_type
+                        .map(|mime_type| {
+                            mime_type
+                                .split(';')
+                                .next()
+                                .unwrap_or_default()
+                                .trim()
+                                .to_ascii_lowercase()
+                        })
+                        .filter(|mime_type| mime_type.starts_with("image/"))
+                        .or_else(|| detect_image_mime_type(&data).map(|m| m.to_string()))
+                        .ok_or_else(|| Error {
+                            code: ErrorCode::InvalidRequest,
+                            message: format!(
+                                "Failed to detect the image type of {}",
+                                image_url.url
+                            ),
+                            provider_error_json: None,
+                        })?;
+                    validate_image_mime_type(&mime_type)?;
+
+                    content.push(ContentPart::Image(ImageReference::Inline(ImageSource {
+                        data,
+                        mime_type,
+                        detail: image_url.detail,
+                    })));
+                }
+                part => content.push(part),
+            }
+        }
+        result.push(Message { content, ..message });
+    }
+    Ok(result)
+}
+
+/// Downloads an image, returning its data and the mime type from the `Content-Type` header
+pub fn download_image(url: &str) -> Result<(Vec<u8>, Option<String>), Error> {
+    let response = Client::new()
+        .get(url)
+        .send()
+        .map_err(|err| from_reqwest_error(format!("Failed to download image {url}"), err))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("Failed to download image {url}: HTTP {status}"),
+            provider_error_json: None,
+        });
+    }
+
+    let mime_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let data = response
+        .bytes()
+        .map_err(|err| from_reqwest_error(format!("Failed to download image {url}"), err))?;
+
+    Ok((data.to_vec(), mime_type))
+}
+
+/// Detects the mime type of an image from the signature at the beginning of its data
+fn detect_image_mime_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0x89, b'P', b'N', b'G']) {
+        Some("image/png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ContentPart, Error, ErrorCode, ImageDetail, ImageReference, ImageSource, ImageUrl, Message,
+        Role,
+    };
+    use crate::image::{inline_remote_images, transport_images, ImageTransport};
+
+    const PNG: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+    fn image_message(url: &str) -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![
+                ContentPart::Text("What is on this image?".to_string()),
+                ContentPart::Image(ImageReference::Url(ImageUrl {
+                    url: url.to_string(),
+                    detail: Some(ImageDetail::Low),
+                })),
+            ],
+        }]
+    }
+
+    #[test]
+    fn remote_image_becomes_inline() {
+        let messages = inline_remote_images(image_message("https://example.com/cat"), |url| {
+            assert_eq!(url, "https://example.com/cat");
+            Ok((PNG.to_vec(), None))
+        })
+        .unwrap();
+
+        assert_eq!(
+            messages[0].content,
+            vec![
+                ContentPart::Text("What is on this image?".to_string()),
+                ContentPart::Image(ImageReference::Inline(ImageSource {
+                    data: PNG.to_vec(),
+                    mime_type: "image/png".to_string(),
+                    detail: Some(ImageDetail::Low),
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn reported_mime_type_is_used() {
+        let messages = inline_remote_images(image_message("https://example.com/cat"), |_| {
+            Ok((
+                vec![1, 2, 3],
+                Some("image/webp; charset=binary".to_string()),
+            ))
+        })
+        .unwrap();
+
+        assert!(matches!(
+            &messages[0].content[1],
+            ContentPart::Image(ImageReference::Inline(ImageSource { mime_type, .. })) if mime_type == "image/webp"
+        ));
+    }
+
+    fn images(url: &str) -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![
+                ContentPart::Image(ImageReference::Url(ImageUrl {
+                    url: url.to_string(),
+                    detail: None,
+                })),
+                ContentPart::Image(ImageReference::Inline(ImageSource {
+                    data: PNG.to_vec(),
+                    mime_type: "image/png".to_string(),
+                    detail: None,
+                })),
+            ],
+        }]
+    }
+
+    fn fetch_png(_url: &str) -> Result<(Vec<u8>, Option<String>), Error> {
+        Ok((PNG.to_vec(), Some("image/png".to_string())))
+    }
+
+    #[test]
+    fn image_transport_modes() {
+        let url = ContentPart::Image(ImageReference::Url(ImageUrl {
+            url: "https://example.com/cat.png".to_string(),
+            detail: None,
+        }));
+        let inline = ContentPart::Image(ImageReference::Inline(ImageSource {
+            data: PNG.to_vec(),
+            mime_type: "image/png".to_string(),
+            detail: None,
+        }));
+        let content = |transport, remote_urls| {
+            transport_images(
+                images("https://example.com/cat.png"),
+                transport,
+                remote_urls,
+                "Test",
+                fetch_png,
+            )
+            .map(|messages| messages[0].content.clone())
+        };
+
+        assert_eq!(
+            content(ImageTransport::Url, true),
+            Ok(vec![url.clone(), inline.clone()])
+        );
+        assert_eq!(
+            content(ImageTransport::Inline, true),
+            Ok(vec![inline.clone(), inline.clone()])
+        );
+        assert_eq!(
+            content(ImageTransport::Auto, true),
+            Ok(vec![url.clone(), inline.clone()])
+        );
+        assert_eq!(
+            content(ImageTransport::Auto, false),
+            Ok(vec![inline.clone(), inline.clone()])
+        );
+        assert_eq!(
+            content(ImageTransport::Url, false).unwrap_err().code,
+            ErrorCode::Unsupported
+        );
+    }
+
+    #[test]
+    fn fetch_failure_is_returned() {
+        let error = inline_remote_images(image_message("https://example.com/missing"), |url| {
+            Err(Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!("Failed to download image {url}: HTTP 404 Not Found"),
+                provider_error_json: None,
+            })
+        })
+        .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(error.message.contains("https://example.com/missing"));
+    }
+}
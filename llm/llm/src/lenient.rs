@@ -0,0 +1,273 @@
+use crate::golem::llm::llm::{Error, ErrorCode};
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer};
+use serde_json::{Number, Value};
+
+/// Parses a response body, keeping the body in `provider_error_json` if it cannot be parsed. The
+/// error message names the field that failed, as far as it can be located in the body.
+pub fn parse_response_json<T: DeserializeOwned>(provider: &str, body: String) -> Result<T, Error> {
+    serde_json::from_str(&body).map_err(|err| Error {
+        code: ErrorCode::InternalError,
+        message: format!(
+            "Failed to parse {provider} API response: {}",
+            describe_json_error(&body, &err)
+        ),
+        provider_error_json: Some(body),
+    })
+}
+
+/// Prefixes the message of an error parsing `json` with the path of the field that failed. Data
+/// errors raised after the whole document was read, for example by a type deserializing a `Value`
+/// first, point at its end, so their position is left out.
+pub fn describe_json_error(json: &str, err: &serde_json::Error) -> String {
+    match json_path_at(json, err.line(), err.column()) {
+        Some(path) => format!("at {path}: {err}"),
+        None if err.is_data() => {
+            let position = format!(" at line {} column {}", err.line(), err.column());
+            let message = err.to_string();
+            message
+                .strip_suffix(&position)
+                .map(str::to_string)
+                .unwrap_or(message)
+        }
+        None => err.to_string(),
+    }
+}
+
+/// Deserializes an unsigned integer that may also be sent as a float without fraction (`12.0`) or
+/// as a string (`"12"`). `null` becomes the default value, so it can be combined with
+/// `#[serde(default)]` for fields that are sometimes left out.
+pub fn integer<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64> + Default,
+{
+    Ok(optional_integer(deserializer)?.unwrap_or_default())
+}
+
+/// Deserializes an optional unsigned integer as leniently as `integer`
+pub fn optional_integer<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    let value = Value::deserialize(deserializer)?;
+    let integer = match &value {
+        Value::Null => return Ok(None),
+        Value::Number(number) => integer_from_number(number),
+        Value::String(string) => string
+            .trim()
+            .parse::<Number>()
+            .ok()
+            .and_then(|number| integer_from_number(&number)),
+        _ => None,
+    };
+    integer
+        .and_then(|integer| T::try_from(integer).ok())
+        .map(Some)
+        .ok_or_else(|| D::Error::custom(format!("expected an unsigned integer, got {value}")))
+}
+
+/// Deserializes an optional float that may also be sent as a string (`"0.0012"`)
+pub fn optional_float<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    match &value {
+        Value::Null => Ok(None),
+        Value::Number(number) => Ok(number.as_f64()),
+        Value::String(string) => string
+            .trim()
+            .parse::<f64>()
+            .map(Some)
+            .map_err(|_| D::Error::custom(format!("expected a number, got {value}"))),
+        _ => Err(D::Error::custom(format!("expected a number, got {value}"))),
+    }
+}
+
+fn integer_from_number(number: &Number) -> Option<u64> {
+    number.as_u64().or_else(|| {
+        number
+            .as_f64()
+            .filter(|float| float.fract() == 0.0 && (0.0..=u64::MAX as f64).contains(float))
+            .map(|float| float as u64)
+    })
+}
+
+/// A container the scanner of `json_path_at` is in, with the key or index of the current value
+enum PathSegment {
+    Object(Option<String>),
+    Array(usize),
+}
+
+/// Finds the path of the value at the given one-based line and column of a JSON document, for
+/// example `choices[0].message.content`. Returns `None` for a position outside any container.
+pub fn json_path_at(json: &str, line: usize, column: usize) -> Option<String> {
+    let offset = json
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum::<usize>()
+        + column.saturating_sub(1);
+
+    let mut segments = Vec::new();
+    let mut expecting_key = false;
+    let mut chars = json.char_indices();
+    while let Some((index, char)) = chars.next() {
+        if index >= offset {
+            break;
+        }
+        match char {
+            '{' => {
+                segments.push(PathSegment::Object(None));
+                expecting_key = true;
+            }
+            '[' => {
+                segments.push(PathSegment::Array(0));
+                expecting_key = false;
+            }
+            '}' | ']' => {
+                segments.pop();
+                expecting_key = false;
+            }
+            ',' => match segments.last_mut() {
+                Some(PathSegment::Array(index)) => *index += 1,
+                Some(PathSegment::Object(_)) => expecting_key = true,
+                None => {}
+            },
+            '"' => {
+                let mut string = String::new();
+                while let Some((_, char)) = chars.next() {
+                    match char {
+                        '"' => break,
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                string.push(escaped);
+                            }
+                        }
+                        char => string.push(char),
+                    }
+                }
+                if expecting_key {
+                    if let Some(PathSegment::Object(key)) = segments.last_mut() {
+                        *key = Some(string);
+                    }
+                    expecting_key = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut path = String::new();
+    for segment in segments {
+        match segment {
+            PathSegment::Object(Some(key)) => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&key);
+            }
+            PathSegment::Object(None) => {}
+            PathSegment::Array(index) => path.push_str(&format!("[{index}]")),
+        }
+    }
+    (!path.is_empty()).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::ErrorCode;
+    use crate::lenient::{integer, optional_float, optional_integer, parse_response_json};
+    use crate::raw_body::RawResponse;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Usage {
+        #[serde(default, deserialize_with = "integer")]
+        prompt_tokens: u32,
+        #[serde(default, deserialize_with = "integer")]
+        completion_tokens: u32,
+        #[serde(default, deserialize_with = "optional_integer")]
+        cached_tokens: Option<u32>,
+        #[serde(default, deserialize_with = "optional_float")]
+        cost: Option<f64>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Choice {
+        text: String,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Response {
+        choices: Vec<Choice>,
+        usage: Usage,
+    }
+
+    #[test]
+    fn off_spec_numbers_and_missing_fields_are_tolerated() {
+        let response: Response = parse_response_json(
+            "Test",
+            r#"{
+                "choices": [{"text": "Hi", "logprobs": {"unexpected": true}}],
+                "usage": {"prompt_tokens": 12.0, "cached_tokens": "4", "cost": "0.0012"},
+                "new_field": [1, 2, 3]
+            }"#
+            .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            response,
+            Response {
+                choices: vec![Choice {
+                    text: "Hi".to_string()
+                }],
+                usage: Usage {
+                    prompt_tokens: 12,
+                    completion_tokens: 0,
+                    cached_tokens: Some(4),
+                    cost: Some(0.0012),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn unparseable_body_names_the_failing_field() {
+        let body = r#"{
+            "choices": [{"text": "Hi"}, {"text": 42}],
+            "usage": {"prompt_tokens": 12}
+        }"#;
+        let error = parse_response_json::<Response>("Test", body.to_string()).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert!(
+            error
+                .message
+                .starts_with("Failed to parse Test API response: at choices[1].text: "),
+            "{}",
+            error.message
+        );
+        assert_eq!(error.provider_error_json.as_deref(), Some(body));
+
+        let error =
+            parse_response_json::<RawResponse<Response>>("Test", body.to_string()).unwrap_err();
+        assert!(
+            error
+                .message
+                .starts_with("Failed to parse Test API response: at choices[1].text: "),
+            "{}",
+            error.message
+        );
+
+        let body = r#"{"choices": [], "usage": {"prompt_tokens": 12.5}}"#;
+        let error = parse_response_json::<Response>("Test", body.to_string()).unwrap_err();
+        assert!(
+            error.message.contains(": at usage.prompt_tokens: "),
+            "{}",
+            error.message
+        );
+    }
+}
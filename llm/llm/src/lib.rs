@@ -1,7 +1,35 @@
+pub mod bpe;
+pub mod cache;
+pub mod canonical;
+pub mod capabilities;
 pub mod chat_stream;
+pub mod concurrency;
 pub mod config;
+pub mod context;
+pub mod dry_run;
 pub mod durability;
+pub mod embeddings;
 pub mod error;
+pub mod fallback;
+pub mod fixtures;
+pub mod history;
+pub mod image;
+pub mod lenient;
+pub mod logging;
+pub mod model_alias;
+pub mod raw_body;
+pub mod request;
+pub mod safety;
+pub mod sse;
+pub mod stop_sequence;
+pub mod structured_output;
+pub mod summarize;
+pub mod template;
+#[cfg(test)]
+mod testing;
+pub mod tool_calls;
+pub mod usage;
+pub mod validation;
 
 #[allow(dead_code)]
 pub mod event_source;
@@ -22,17 +50,25 @@ use std::str::FromStr;
 
 pub struct LoggingState {
     logging_initialized: bool,
+    max_body_length: Option<usize>,
 }
 
 impl LoggingState {
-    /// Initializes WASI logging based on the `GOLEM_LLM_LOG` environment variable.
+    /// Initializes WASI logging based on the `LLM_LOG_LEVEL` environment variable, falling back to
+    /// the older `GOLEM_LLM_LOG` variable. Logged bodies are truncated to the number of characters
+    /// set in `LLM_LOG_MAX_BODY_LENGTH`, if any.
     pub fn init(&mut self) {
         if !self.logging_initialized {
             let _ = wasi_logger::Logger::install();
+            let level = std::env::var("LLM_LOG_LEVEL")
+                .or_else(|_| std::env::var("GOLEM_LLM_LOG"))
+                .unwrap_or_default();
             let max_level: log::LevelFilter =
-                log::LevelFilter::from_str(&std::env::var("GOLEM_LLM_LOG").unwrap_or_default())
-                    .unwrap_or(log::LevelFilter::Info);
+                log::LevelFilter::from_str(&level).unwrap_or(log::LevelFilter::Info);
             log::set_max_level(max_level);
+            self.max_body_length = std::env::var("LLM_LOG_MAX_BODY_LENGTH")
+                .ok()
+                .and_then(|value| value.parse().ok());
             self.logging_initialized = true;
         }
     }
@@ -42,5 +78,6 @@ thread_local! {
     /// This holds the state of our application.
     pub static LOGGING_STATE: RefCell<LoggingState> = const { RefCell::new(LoggingState {
         logging_initialized: false,
+        max_body_length: None,
     }) };
 }
@@ -0,0 +1,132 @@
+use crate::LOGGING_STATE;
+use std::fmt::{Debug, Display, Formatter};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Markers preceding secret values which are never written to the log, and whether the value ends
+/// at the next whitespace. Authorization header values contain a scheme and the credentials
+/// separated by a space, so they are redacted as a whole.
+const SECRET_MARKERS: [(&str, bool); 5] = [
+    ("authorization", false),
+    ("bearer ", true),
+    ("api-key", true),
+    ("api_key", true),
+    ("apikey", true),
+];
+
+/// Wraps a value to be logged, formatting it with its `Debug` representation while redacting bearer
+/// tokens and API key values, and truncating it to the length set by the `LLM_LOG_MAX_BODY_LENGTH`
+/// environment variable. The value is only formatted if the log line is actually emitted.
+pub struct Redacted<T>(pub T);
+
+impl<T: Debug> Display for Redacted<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let max_length = LOGGING_STATE.with_borrow(|state| state.max_body_length);
+        f.write_str(&redact(&format!("{:?}", self.0), max_length))
+    }
+}
+
+/// Redacts secrets from a log line and truncates it to at most `max_length` characters
+pub fn redact(text: &str, max_length: Option<usize>) -> String {
+    let mut result = text.to_string();
+    for (marker, ends_at_whitespace) in SECRET_MARKERS {
+        result = redact_values_after(&result, marker, ends_at_whitespace);
+    }
+
+    match max_length {
+        Some(max_length) if result.chars().count() > max_length => {
+            let truncated: String = result.chars().take(max_length).collect();
+            format!(
+                "{truncated}... ({} characters truncated)",
+                result.chars().count() - max_length
+            )
+        }
+        _ => result,
+    }
+}
+
+/// Replaces the value following every case-insensitive occurrence of `marker` with `[REDACTED]`.
+/// Quotes and `:` or `=` separators between the marker and the value are kept.
+fn redact_values_after(text: &str, marker: &str, ends_at_whitespace: bool) -> String {
+    let lowercase = text.to_ascii_lowercase();
+    let mut result = String::with_capacity(text.len());
+    let mut position = 0;
+
+    while let Some(offset) = lowercase[position..].find(marker) {
+        let value_start = skip_separators(text, position + offset + marker.len());
+        let value_end = text[value_start..]
+            .find(|c: char| {
+                (ends_at_whitespace && c.is_whitespace()) || "\"'\\,;&}])\r\n".contains(c)
+            })
+            .map(|length| value_start + length)
+            .unwrap_or(text.len());
+
+        result.push_str(&text[position..value_start]);
+        if value_end > value_start && !text[value_start..].starts_with(REDACTED) {
+            result.push_str(REDACTED);
+            position = value_end;
+        } else {
+            position = value_start;
+        }
+    }
+
+    result.push_str(&text[position..]);
+    result
+}
+
+fn skip_separators(text: &str, start: usize) -> usize {
+    text[start..]
+        .find(|c: char| !(c.is_whitespace() || "\"'\\:=".contains(c)))
+        .map(|length| start + length)
+        .unwrap_or(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logging::{redact, Redacted};
+    use std::collections::HashMap;
+
+    const KEY: &str = "sk-proj-0123456789abcdef";
+
+    #[test]
+    fn bearer_tokens_and_api_keys_are_redacted() {
+        let headers = HashMap::from([
+            ("Authorization", format!("Bearer {KEY}")),
+            ("x-api-key", KEY.to_string()),
+        ]);
+        let line = format!("Sending request with headers: {}", Redacted(&headers));
+
+        assert!(!line.contains(KEY), "{line}");
+        assert_eq!(
+            redact(&format!("Authorization: Basic {KEY}"), None),
+            "Authorization: [REDACTED]"
+        );
+        assert_eq!(
+            redact(&format!("token=Bearer {KEY}"), None),
+            "token=Bearer [REDACTED]"
+        );
+    }
+
+    #[test]
+    fn api_keys_in_bodies_and_urls_are_redacted() {
+        let json = format!(r#"{{"model":"gpt-4o","api_key":"{KEY}"}}"#);
+        let url = format!("https://example.com/v1/models?apiKey={KEY}&limit=10");
+
+        for line in [redact(&json, None), redact(&url, None)] {
+            assert!(!line.contains(KEY), "{line}");
+        }
+        assert_eq!(
+            redact(&url, None),
+            "https://example.com/v1/models?apiKey=[REDACTED]&limit=10"
+        );
+    }
+
+    #[test]
+    fn long_bodies_are_truncated() {
+        assert_eq!(
+            redact("0123456789", Some(4)),
+            "0123... (6 characters truncated)"
+        );
+        assert_eq!(redact("0123456789", Some(10)), "0123456789");
+    }
+}
@@ -0,0 +1,75 @@
+use crate::config::provider_option;
+use crate::golem::llm::llm::Config;
+use log::trace;
+use std::collections::HashMap;
+
+/// Finds the entry of a table of model name prefixes matching a model. Models are matched by the
+/// longest prefix of their name, ignoring a `vendor/` prefix as used by routers like OpenRouter.
+pub fn find_by_model_prefix<'a, T>(
+    model: &str,
+    table: &'a [T],
+    prefix: impl Fn(&T) -> &str,
+) -> Option<&'a T> {
+    let model = model.rsplit('/').next().unwrap_or(model);
+    table
+        .iter()
+        .filter(|entry| model.starts_with(prefix(entry)))
+        .max_by_key(|entry| prefix(entry).len())
+}
+
+/// Replaces a provider-agnostic model alias such as `small` or `large` in the configuration with
+/// the concrete model of the provider, so portable code does not have to name provider specific
+/// models. Models which are not an alias are kept unchanged.
+///
+/// `aliases` is the provider's default table. Entries of the `model_aliases` provider option, a
+/// JSON object mapping aliases to model ids, take precedence over it.
+pub fn resolve_model_alias(mut config: Config, aliases: &[(&str, &str)]) -> Config {
+    let overrides = provider_option(&config, "model_aliases").and_then(|model_aliases| {
+        serde_json::from_str::<HashMap<String, String>>(model_aliases)
+            .inspect_err(|err| trace!("Ignoring invalid model_aliases option: {err}"))
+            .ok()
+    });
+
+    let model = overrides
+        .as_ref()
+        .and_then(|overrides| overrides.get(&config.model).cloned())
+        .or_else(|| {
+            aliases
+                .iter()
+                .find(|(alias, _)| *alias == config.model)
+                .map(|(_, model)| model.to_string())
+        });
+
+    if let Some(model) = model {
+        trace!("Resolved model alias {} to {model}", config.model);
+        config.model = model;
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model_alias::resolve_model_alias;
+    use crate::testing::config;
+
+    const ALIASES: [(&str, &str); 2] = [("small", "gpt-4o-mini"), ("large", "gpt-4o")];
+
+    #[test]
+    fn aliases_are_resolved_and_can_be_overridden() {
+        assert_eq!(
+            resolve_model_alias(config("small", &[]), &ALIASES).model,
+            "gpt-4o-mini"
+        );
+        assert_eq!(resolve_model_alias(config("o3", &[]), &ALIASES).model, "o3");
+
+        let overrides = [("model_aliases", r#"{"small": "gpt-4.1-nano"}"#)];
+        assert_eq!(
+            resolve_model_alias(config("small", &overrides), &ALIASES).model,
+            "gpt-4.1-nano"
+        );
+        assert_eq!(
+            resolve_model_alias(config("large", &overrides), &ALIASES).model,
+            "gpt-4o"
+        );
+    }
+}
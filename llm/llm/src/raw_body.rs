@@ -0,0 +1,315 @@
+use crate::config::provider_option;
+use crate::golem::llm::llm::{ChatEvent, Config, Error, ErrorCode};
+use crate::lenient::json_path_at;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::sync::RwLock;
+
+/// Top-level fields of a request body that can only be overridden if the
+/// `allow_raw_body_override_of_protected_fields` provider option is `true`
+const PROTECTED_FIELDS: [&str; 2] = ["messages", "model"];
+
+/// Gets the JSON object from the `raw_body_override` provider option, which is deep-merged into the
+/// request body before it is sent. It is an escape hatch for provider parameters this library does
+/// not model yet.
+pub fn raw_body_override(config: &Config) -> Result<Option<Map<String, Value>>, Error> {
+    let Some(raw_body_override) = provider_option(config, "raw_body_override") else {
+        return Ok(None);
+    };
+
+    let raw_body_override = match serde_json::from_str(raw_body_override) {
+        Ok(Value::Object(fields)) => fields,
+        Ok(_) => {
+            return Err(invalid_override(
+                "raw_body_override must be a JSON object".to_string(),
+            ))
+        }
+        Err(err) => {
+            return Err(invalid_override(format!(
+                "Failed to parse raw_body_override: {err}"
+            )))
+        }
+    };
+
+    let protected_allowed =
+        provider_option(config, "allow_raw_body_override_of_protected_fields") == Some("true");
+    if let Some(field) = PROTECTED_FIELDS
+        .iter()
+        .find(|field| raw_body_override.contains_key(**field))
+    {
+        if !protected_allowed {
+            return Err(invalid_override(format!(
+                "raw_body_override cannot override '{field}' unless allow_raw_body_override_of_protected_fields is true"
+            )));
+        }
+    }
+
+    Ok(Some(raw_body_override))
+}
+
+/// Merges `raw_body_override` into `body`: nested objects are merged field by field, any other
+/// value replaces the original one
+pub fn merge_raw_body(body: &mut Map<String, Value>, raw_body_override: &Map<String, Value>) {
+    for (key, value) in raw_body_override {
+        match (body.get_mut(key), value) {
+            (Some(Value::Object(target)), Value::Object(fields)) => merge_raw_body(target, fields),
+            _ => {
+                body.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// A request body serialized with the `raw_body_override` merged into it
+pub struct RawBody<'a, T> {
+    body: &'a T,
+    raw_body_override: Option<&'a Map<String, Value>>,
+}
+
+impl<'a, T> RawBody<'a, T> {
+    pub fn new(body: &'a T, raw_body_override: Option<&'a Map<String, Value>>) -> Self {
+        Self {
+            body,
+            raw_body_override,
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for RawBody<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(raw_body_override) = self.raw_body_override else {
+            return self.body.serialize(serializer);
+        };
+
+        match serde_json::to_value(self.body).map_err(S::Error::custom)? {
+            Value::Object(mut body) => {
+                merge_raw_body(&mut body, raw_body_override);
+                body.serialize(serializer)
+            }
+            body => body.serialize(serializer),
+        }
+    }
+}
+
+/// A response body along with the JSON it was parsed from, which keeps the fields not modelled by
+/// the response type
+#[derive(Debug, Clone)]
+pub struct RawResponse<T> {
+    pub body: T,
+    pub raw: Value,
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for RawResponse<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = Value::deserialize(deserializer)?;
+        let body = T::deserialize(&raw).map_err(|err| {
+            // Errors deserializing from a `Value` have no position, so the failing field is
+            // located by parsing the text of the value again
+            let json = raw.to_string();
+            match serde_json::from_str::<T>(&json)
+                .err()
+                .and_then(|located| json_path_at(&json, located.line(), located.column()))
+            {
+                Some(path) => D::Error::custom(format!("at {path}: {err}")),
+                None => D::Error::custom(err),
+            }
+        })?;
+        Ok(Self { body, raw })
+    }
+}
+
+/// Whether the raw response body is returned in the provider metadata, requested with the
+/// `include_raw_response` provider option
+pub fn include_raw_response(config: &Config) -> bool {
+    provider_option(config, "include_raw_response") == Some("true")
+}
+
+/// Adds the raw body of a successful response to its provider metadata as `raw_response`. Nothing
+/// is added without a raw body, or to errors.
+pub fn with_raw_response(event: ChatEvent, raw: Option<Value>) -> ChatEvent {
+    let (ChatEvent::Message(mut response), Some(raw)) = (event.clone(), raw) else {
+        return event;
+    };
+
+    let mut provider_metadata = response
+        .metadata
+        .provider_metadata_json
+        .as_deref()
+        .and_then(|json| serde_json::from_str::<Map<String, Value>>(json).ok())
+        .unwrap_or_default();
+    provider_metadata.insert("raw_response".to_string(), raw);
+    response.metadata.provider_metadata_json = Some(Value::Object(provider_metadata).to_string());
+    ChatEvent::Message(response)
+}
+
+/// A function receiving the serialized body of a chat request and returning the body to send instead
+pub type PreSendHook = Box<dyn Fn(Value) -> Value + Send + Sync>;
+
+static PRE_SEND_HOOK: RwLock<Option<PreSendHook>> = RwLock::new(None);
+
+/// Registers a hook called with the JSON body of every `send`, `continue` and `stream` request
+/// right before it goes to the provider, for custom redaction or parameter tweaks. It runs after
+/// the request was built and validated, so whatever it returns is sent as is. Registering a new
+/// hook replaces the previous one.
+pub fn set_pre_send_hook(hook: impl Fn(Value) -> Value + Send + Sync + 'static) {
+    *PRE_SEND_HOOK.write().unwrap() = Some(Box::new(hook));
+}
+
+/// Removes the hook registered with `set_pre_send_hook`
+pub fn clear_pre_send_hook() {
+    *PRE_SEND_HOOK.write().unwrap() = None;
+}
+
+/// Serializes a chat request into the body that is sent, passing it through the pre-send hook if
+/// one is registered
+pub fn pre_send_body(request: &impl Serialize) -> Result<Value, Error> {
+    let body = serde_json::to_value(request).map_err(|err| Error {
+        code: ErrorCode::InternalError,
+        message: format!("Failed to serialize request body: {err}"),
+        provider_error_json: None,
+    })?;
+    Ok(match PRE_SEND_HOOK.read().unwrap().as_ref() {
+        Some(hook) => hook(body),
+        None => body,
+    })
+}
+
+fn invalid_override(message: String) -> Error {
+    Error {
+        code: ErrorCode::InvalidRequest,
+        message,
+        provider_error_json: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, ContentPart, ErrorCode, ResponseMetadata,
+    };
+    use crate::raw_body::{
+        clear_pre_send_hook, include_raw_response, merge_raw_body, pre_send_body,
+        raw_body_override, set_pre_send_hook, with_raw_response, RawBody, RawResponse,
+    };
+    use crate::testing::config;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[test]
+    fn nested_objects_are_merged() {
+        let mut body = json!({"model": "gpt-4o", "stream_options": {"include_usage": true}})
+            .as_object()
+            .unwrap()
+            .clone();
+        let raw_body_override =
+            json!({"stream_options": {"include_obfuscation": false}, "verbosity": "low"})
+                .as_object()
+                .unwrap()
+                .clone();
+
+        merge_raw_body(&mut body, &raw_body_override);
+
+        assert_eq!(
+            serde_json::Value::Object(body),
+            json!({
+                "model": "gpt-4o",
+                "stream_options": {"include_usage": true, "include_obfuscation": false},
+                "verbosity": "low"
+            })
+        );
+    }
+
+    #[test]
+    fn protected_fields_require_permission() {
+        let error = raw_body_override(&config(
+            "gpt-4o",
+            &[("raw_body_override", r#"{"model": "gpt-4.1"}"#)],
+        ))
+        .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(error.message.contains("'model'"));
+
+        assert!(raw_body_override(&config(
+            "gpt-4o",
+            &[
+                ("raw_body_override", r#"{"model": "gpt-4.1"}"#),
+                ("allow_raw_body_override_of_protected_fields", "true"),
+            ]
+        ))
+        .unwrap()
+        .is_some());
+    }
+
+    #[test]
+    fn pre_send_hook_edits_the_outgoing_body() {
+        let request = json!({"model": "gpt-4o", "user": "alice@example.com", "temperature": 0.2});
+        let raw_body_override = json!({"temperature": 0.7}).as_object().unwrap().clone();
+
+        set_pre_send_hook(|mut body| {
+            body["user"] = json!("[redacted]");
+            body
+        });
+        let body = pre_send_body(&RawBody::new(&request, Some(&raw_body_override)));
+        clear_pre_send_hook();
+
+        assert_eq!(
+            body.unwrap(),
+            json!({"model": "gpt-4o", "user": "[redacted]", "temperature": 0.7})
+        );
+        assert_eq!(pre_send_body(&request).unwrap(), request);
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Completion {
+        id: String,
+    }
+
+    #[test]
+    fn raw_response_is_only_included_when_requested() {
+        let response: RawResponse<Completion> =
+            serde_json::from_str(r#"{"id": "chatcmpl-1", "system_fingerprint": "fp_44709d6fcb"}"#)
+                .unwrap();
+        let event = ChatEvent::Message(CompleteResponse {
+            id: response.body.id,
+            content: vec![ContentPart::Text("Hello".to_string())],
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: Some(r#"{"cost": 0.001}"#.to_string()),
+                stop_sequence: None,
+                tool_calls: None,
+            },
+        });
+
+        let enabled = config("gpt-4o", &[("include_raw_response", "true")]);
+        let ChatEvent::Message(included) = with_raw_response(
+            event.clone(),
+            include_raw_response(&enabled).then_some(response.raw.clone()),
+        ) else {
+            panic!("Expected a message");
+        };
+        let metadata: serde_json::Value =
+            serde_json::from_str(&included.metadata.provider_metadata_json.unwrap()).unwrap();
+        assert_eq!(
+            metadata,
+            json!({
+                "cost": 0.001,
+                "raw_response": {"id": "chatcmpl-1", "system_fingerprint": "fp_44709d6fcb"}
+            })
+        );
+
+        let disabled = config("gpt-4o", &[]);
+        assert_eq!(
+            with_raw_response(
+                event.clone(),
+                include_raw_response(&disabled).then_some(response.raw)
+            ),
+            event
+        );
+    }
+}
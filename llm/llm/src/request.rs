@@ -0,0 +1,253 @@
+use crate::config::provider_option;
+use crate::golem::llm::llm::{Config, ContentPart, Error, ErrorCode, Message, Role};
+use crate::validation::SystemMessagePlacement;
+use serde::Serialize;
+
+/// Merges all system messages of the conversation into a single one placed first, for providers
+/// with `SystemMessagePlacement::Single`. The texts of the system messages are kept in order and
+/// separated by blank lines. Other placements leave the messages unchanged.
+pub fn normalize_system_messages(
+    messages: Vec<Message>,
+    system_placement: SystemMessagePlacement,
+) -> Vec<Message> {
+    if system_placement != SystemMessagePlacement::Single
+        || messages
+            .iter()
+            .filter(|message| message.role == Role::System)
+            .count()
+            < 2
+    {
+        return messages;
+    }
+
+    let (system_messages, mut messages): (Vec<_>, Vec<_>) = messages
+        .into_iter()
+        .partition(|message| message.role == Role::System);
+    let (texts, other_parts): (Vec<_>, Vec<_>) = system_messages
+        .into_iter()
+        .flat_map(|message| message.content)
+        .partition(|part| matches!(part, ContentPart::Text(_)));
+    let text = texts
+        .into_iter()
+        .filter_map(|part| match part {
+            ContentPart::Text(text) => Some(text),
+            ContentPart::Image(_) | ContentPart::Citation(_) => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    messages.insert(
+        0,
+        Message {
+            role: Role::System,
+            name: None,
+            content: std::iter::once(ContentPart::Text(text))
+                .chain(other_parts)
+                .collect(),
+        },
+    );
+    messages
+}
+
+/// Adds the `system` prompt of the configuration to the conversation as its first message, followed
+/// by an instruction to respond in the language of the `response_locale` provider option, if set.
+///
+/// If the conversation also contains system messages, the configured prompt precedes them, so the
+/// system messages of the conversation can refine it.
+pub fn with_system_prompt(config: &Config, mut messages: Vec<Message>) -> Vec<Message> {
+    let locale_instruction = provider_option(config, "response_locale")
+        .map(str::trim)
+        .filter(|locale| !locale.is_empty())
+        .map(locale_instruction);
+    let system = match (&config.system, locale_instruction) {
+        (Some(system), Some(instruction)) => Some(format!("{system}\n\n{instruction}")),
+        (system, instruction) => system.clone().or(instruction),
+    };
+
+    if let Some(system) = system {
+        messages.insert(
+            0,
+            Message {
+                role: Role::System,
+                name: None,
+                content: vec![ContentPart::Text(system)],
+            },
+        );
+    }
+    messages
+}
+
+/// Names of the languages of common locales, as models follow an instruction naming the language
+/// more reliably than one only giving the locale code
+const LANGUAGES: &[(&str, &str)] = &[
+    ("ar", "Arabic"),
+    ("cs", "Czech"),
+    ("da", "Danish"),
+    ("de", "German"),
+    ("el", "Greek"),
+    ("en", "English"),
+    ("es", "Spanish"),
+    ("fi", "Finnish"),
+    ("fr", "French"),
+    ("he", "Hebrew"),
+    ("hi", "Hindi"),
+    ("hu", "Hungarian"),
+    ("id", "Indonesian"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("nb", "Norwegian"),
+    ("nl", "Dutch"),
+    ("no", "Norwegian"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese"),
+    ("ro", "Romanian"),
+    ("ru", "Russian"),
+    ("sv", "Swedish"),
+    ("th", "Thai"),
+    ("tr", "Turkish"),
+    ("uk", "Ukrainian"),
+    ("vi", "Vietnamese"),
+    ("zh", "Chinese"),
+];
+
+/// The system instruction steering the response to the language of a locale like `de` or `pt-BR`
+fn locale_instruction(locale: &str) -> String {
+    let language_code = locale
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match LANGUAGES.iter().find(|(code, _)| *code == language_code) {
+        Some((_, language)) => format!(
+            "Always respond in {language} ({locale}), regardless of the language of the conversation."
+        ),
+        None => format!(
+            "Always respond in the language of the locale {locale}, regardless of the language of the conversation."
+        ),
+    }
+}
+
+/// Checks that the serialized request body is not larger than `max_request_bytes`, so an oversized
+/// request, for example with a huge inline image, fails right away instead of after a slow upload
+/// rejected by the provider with an unhelpful error
+pub fn check_request_size(
+    max_request_bytes: Option<usize>,
+    request: &impl Serialize,
+) -> Result<(), Error> {
+    let Some(max_request_bytes) = max_request_bytes else {
+        return Ok(());
+    };
+
+    let size = serde_json::to_vec(request)
+        .map_err(|err| Error {
+            code: ErrorCode::InternalError,
+            message: format!("Failed to serialize request body: {err}"),
+            provider_error_json: None,
+        })?
+        .len();
+    if size > max_request_bytes {
+        Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "Request body of about {} is larger than the limit of {}",
+                format_size(size),
+                format_size(max_request_bytes)
+            ),
+            provider_error_json: None,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+fn format_size(bytes: usize) -> String {
+    const MB: usize = 1024 * 1024;
+    if bytes >= MB {
+        format!("{:.1} MB", bytes as f64 / MB as f64)
+    } else {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{Config, ContentPart, Message, Role};
+    use crate::request::{normalize_system_messages, with_system_prompt};
+    use crate::testing::config;
+    use crate::validation::SystemMessagePlacement;
+
+    fn message(role: Role) -> Message {
+        Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text("text".to_string())],
+        }
+    }
+
+    #[test]
+    fn system_messages_are_coalesced_for_single_placement() {
+        let text_message = |role, text: &str| Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        };
+        let messages = vec![
+            text_message(Role::System, "You are a travel agent."),
+            text_message(Role::System, "Only recommend trains."),
+            text_message(Role::User, "How do I get to Vienna?"),
+            text_message(Role::System, "Prices are in euros."),
+        ];
+
+        assert_eq!(
+            normalize_system_messages(messages.clone(), SystemMessagePlacement::Single),
+            vec![
+                text_message(
+                    Role::System,
+                    "You are a travel agent.\n\nOnly recommend trains.\n\nPrices are in euros."
+                ),
+                text_message(Role::User, "How do I get to Vienna?"),
+            ]
+        );
+        assert_eq!(
+            normalize_system_messages(messages.clone(), SystemMessagePlacement::Anywhere),
+            messages
+        );
+    }
+
+    #[test]
+    fn response_locale_is_added_to_the_system_prompt() {
+        let localized = |system: Option<&str>, locale: &str| Config {
+            system: system.map(str::to_string),
+            ..config("model", &[("response_locale", locale)])
+        };
+
+        let messages = with_system_prompt(
+            &localized(Some("You are a travel agent."), "pt-BR"),
+            vec![message(Role::User)],
+        );
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0],
+            Message {
+                role: Role::System,
+                name: None,
+                content: vec![ContentPart::Text(
+                    "You are a travel agent.\n\nAlways respond in Portuguese (pt-BR), regardless of \
+                     the language of the conversation."
+                        .to_string()
+                )],
+            }
+        );
+
+        let messages = with_system_prompt(&localized(None, "kl"), vec![message(Role::User)]);
+        assert_eq!(
+            messages[0].content,
+            vec![ContentPart::Text(
+                "Always respond in the language of the locale kl, regardless of the language of the \
+                 conversation."
+                    .to_string()
+            )]
+        );
+    }
+}
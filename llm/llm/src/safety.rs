@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// A per-category content safety rating, in the shape Gemini reports them in `safetyRatings` and
+/// APIs passing Gemini responses through keep them in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafetyRating {
+    pub category: String,
+    pub probability: Option<String>,
+    pub probability_score: Option<f64>,
+    #[serde(default)]
+    pub blocked: bool,
+}
+
+/// Normalizes safety ratings to the `safety` object of the provider metadata, mapping each category
+/// to its probability, score and whether it blocked the response, so applications can apply their
+/// own thresholds independently of the provider.
+///
+/// Categories and probabilities are lower-cased and the `HARM_CATEGORY_` prefix is removed, so
+/// `HARM_CATEGORY_HARASSMENT` rated `NEGLIGIBLE` becomes `"harassment": {"probability": "negligible", ...}`.
+pub fn safety_metadata(ratings: &[SafetyRating]) -> Option<Value> {
+    if ratings.is_empty() {
+        return None;
+    }
+
+    let safety = ratings
+        .iter()
+        .map(|rating| {
+            let category = rating
+                .category
+                .strip_prefix("HARM_CATEGORY_")
+                .unwrap_or(&rating.category)
+                .to_lowercase();
+            let mut normalized = Map::new();
+            if let Some(probability) = &rating.probability {
+                normalized.insert("probability".to_string(), json!(probability.to_lowercase()));
+            }
+            if let Some(score) = rating.probability_score {
+                normalized.insert("score".to_string(), json!(score));
+            }
+            normalized.insert("blocked".to_string(), json!(rating.blocked));
+            (category, Value::Object(normalized))
+        })
+        .collect::<Map<_, _>>();
+    Some(Value::Object(safety))
+}
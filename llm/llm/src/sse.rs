@@ -0,0 +1,279 @@
+use crate::chat_stream::StreamEvents;
+use crate::golem::llm::llm::{
+    ContentPart, Error, ErrorCode, FinishReason, GuestChatStream, ImageDetail, ImageReference,
+    ResponseMetadata, StreamEvent, ToolCall, Usage,
+};
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::{json, Value};
+
+/// The line closing an SSE stream after its `finish` event, following the OpenAI convention most
+/// SSE clients of LLM APIs understand
+pub const SSE_DONE: &str = "data: [DONE]\n\n";
+
+/// Frames a stream event as an SSE `data:` line, terminated by the blank line ending the event
+pub fn sse_line(event: &StreamEvent) -> String {
+    format!("data: {}\n\n", stream_event_json(event))
+}
+
+/// Converts a stream event to its canonical JSON representation. Variants are tagged by a `type`
+/// field, and enum values use the same names as the WIT interface.
+pub fn stream_event_json(event: &StreamEvent) -> Value {
+    match event {
+        StreamEvent::Delta(delta) => json!({
+            "type": "delta",
+            "content": delta
+                .content
+                .as_ref()
+                .map(|content| content.iter().map(content_part_json).collect::<Vec<_>>()),
+            "tool_calls": delta
+                .tool_calls
+                .as_ref()
+                .map(|tool_calls| tool_calls.iter().map(tool_call_json).collect::<Vec<_>>()),
+            "reasoning": delta.reasoning,
+            "choice_index": delta.choice_index,
+        }),
+        StreamEvent::Finish(metadata) => {
+            let mut json = metadata_json(metadata);
+            json["type"] = json!("finish");
+            json
+        }
+        StreamEvent::Error(error) => {
+            let mut json = error_json(error);
+            json["type"] = json!("error");
+            json
+        }
+        StreamEvent::FinishReasonHint(finish_reason) => json!({
+            "type": "finish_reason_hint",
+            "finish_reason": finish_reason_name(finish_reason),
+        }),
+        StreamEvent::ToolCallComplete(tool_call) => json!({
+            "type": "tool_call_complete",
+            "tool_call": tool_call_json(tool_call),
+        }),
+        StreamEvent::Heartbeat => json!({ "type": "heartbeat" }),
+    }
+}
+
+fn content_part_json(part: &ContentPart) -> Value {
+    match part {
+        ContentPart::Text(text) => json!({"type": "text", "text": text}),
+        ContentPart::Image(ImageReference::Url(image)) => json!({
+            "type": "image",
+            "url": image.url,
+            "detail": image.detail.map(image_detail_name),
+        }),
+        ContentPart::Image(ImageReference::Inline(image)) => json!({
+            "type": "image",
+            "data": general_purpose::STANDARD.encode(&image.data),
+            "mime_type": image.mime_type,
+            "detail": image.detail.map(image_detail_name),
+        }),
+        ContentPart::Citation(citation) => json!({
+            "type": "citation",
+            "title": citation.title,
+            "url": citation.url,
+            "quoted_text": citation.quoted_text,
+        }),
+    }
+}
+
+fn tool_call_json(tool_call: &ToolCall) -> Value {
+    json!({
+        "id": tool_call.id,
+        "name": tool_call.name,
+        "arguments_json": tool_call.arguments_json,
+    })
+}
+
+fn metadata_json(metadata: &ResponseMetadata) -> Value {
+    json!({
+        "finish_reason": metadata.finish_reason.as_ref().map(finish_reason_name),
+        "usage": metadata.usage.as_ref().map(usage_json),
+        "provider_id": metadata.provider_id,
+        "timestamp": metadata.timestamp,
+        "provider_metadata_json": metadata.provider_metadata_json,
+        "stop_sequence": metadata.stop_sequence,
+        "tool_calls": metadata
+            .tool_calls
+            .as_ref()
+            .map(|tool_calls| tool_calls.iter().map(tool_call_json).collect::<Vec<_>>()),
+    })
+}
+
+fn usage_json(usage: &Usage) -> Value {
+    json!({
+        "input_tokens": usage.input_tokens,
+        "output_tokens": usage.output_tokens,
+        "total_tokens": usage.total_tokens,
+    })
+}
+
+fn error_json(error: &Error) -> Value {
+    json!({
+        "code": error_code_name(error.code),
+        "message": error.message,
+        "provider_error_json": error.provider_error_json,
+    })
+}
+
+fn image_detail_name(detail: ImageDetail) -> &'static str {
+    match detail {
+        ImageDetail::Low => "low",
+        ImageDetail::High => "high",
+        ImageDetail::Auto => "auto",
+    }
+}
+
+/// The WIT name of a finish reason, or the raw value reported by the provider for `other`
+pub(crate) fn finish_reason_name(finish_reason: &FinishReason) -> &str {
+    match finish_reason {
+        FinishReason::Stop => "stop",
+        FinishReason::Length => "length",
+        FinishReason::ToolCalls => "tool-calls",
+        FinishReason::ContentFilter => "content-filter",
+        FinishReason::Error => "error",
+        FinishReason::Other(raw) => raw,
+    }
+}
+
+pub(crate) fn error_code_name(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::InvalidRequest => "invalid-request",
+        ErrorCode::AuthenticationFailed => "authentication-failed",
+        ErrorCode::RateLimitExceeded => "rate-limit-exceeded",
+        ErrorCode::Overloaded => "overloaded",
+        ErrorCode::InternalError => "internal-error",
+        ErrorCode::Unsupported => "unsupported",
+        ErrorCode::Timeout => "timeout",
+        ErrorCode::ContentFilter => "content-filter",
+        ErrorCode::EmptyResponse => "empty-response",
+        ErrorCode::QuotaExceeded => "quota-exceeded",
+        ErrorCode::InvalidToolArguments => "invalid-tool-arguments",
+        ErrorCode::Unknown => "unknown",
+    }
+}
+
+/// Iterates over the events of a chat stream framed as SSE lines, for gateways re-emitting the
+/// stream to their own clients as an HTTP response body.
+///
+/// A `finish` event is followed by the `SSE_DONE` line, after which the iteration ends. An error
+/// received from the stream is the last line yielded.
+pub struct SseStream<'a, S: GuestChatStream> {
+    events: StreamEvents<'a, S>,
+    finished: bool,
+}
+
+impl<'a, S: GuestChatStream> SseStream<'a, S> {
+    pub fn new(stream: &'a S) -> Self {
+        Self {
+            events: StreamEvents::new(stream),
+            finished: false,
+        }
+    }
+}
+
+impl<S: GuestChatStream> Iterator for SseStream<'_, S> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.events.next() {
+            Some(event) => {
+                self.finished = matches!(event, StreamEvent::Finish(_));
+                Some(sse_line(&event))
+            }
+            None if self.finished => {
+                self.finished = false;
+                Some(SSE_DONE.to_string())
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ContentPart, FinishReason, GuestChatStream, ResponseMetadata, StreamDelta, StreamEvent,
+        Usage,
+    };
+    use crate::sse::{SseStream, SSE_DONE};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    struct ReplayedChatStream {
+        batches: RefCell<VecDeque<Vec<StreamEvent>>>,
+    }
+
+    impl GuestChatStream for ReplayedChatStream {
+        fn get_next(&self) -> Option<Vec<StreamEvent>> {
+            Some(self.batches.borrow_mut().pop_front().unwrap_or_default())
+        }
+
+        fn blocking_get_next(&self) -> Vec<StreamEvent> {
+            self.get_next().unwrap()
+        }
+
+        fn cancel(&self) {}
+    }
+
+    #[test]
+    fn events_are_framed_as_sse_and_finish_closes_the_stream() {
+        let stream = ReplayedChatStream {
+            batches: RefCell::new(VecDeque::from([
+                vec![StreamEvent::Delta(StreamDelta {
+                    content: Some(vec![ContentPart::Text("Hello".to_string())]),
+                    tool_calls: None,
+                    reasoning: None,
+                    choice_index: None,
+                })],
+                vec![
+                    StreamEvent::Finish(ResponseMetadata {
+                        finish_reason: Some(FinishReason::Stop),
+                        usage: Some(Usage {
+                            input_tokens: Some(3),
+                            output_tokens: Some(1),
+                            total_tokens: Some(4),
+                        }),
+                        provider_id: None,
+                        timestamp: None,
+                        provider_metadata_json: None,
+                        stop_sequence: None,
+                        tool_calls: None,
+                    }),
+                    StreamEvent::Delta(StreamDelta {
+                        content: Some(vec![ContentPart::Text("ignored".to_string())]),
+                        tool_calls: None,
+                        reasoning: None,
+                        choice_index: None,
+                    }),
+                ],
+            ])),
+        };
+
+        let lines = SseStream::new(&stream).collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines
+            .iter()
+            .all(|line| line.starts_with("data: ") && line.ends_with("\n\n")));
+
+        let delta: serde_json::Value = serde_json::from_str(&lines[0]["data: ".len()..]).unwrap();
+        assert_eq!(
+            delta,
+            serde_json::json!({
+                "type": "delta",
+                "content": [{"type": "text", "text": "Hello"}],
+                "tool_calls": null,
+                "reasoning": null,
+                "choice_index": null
+            })
+        );
+
+        let finish: serde_json::Value = serde_json::from_str(&lines[1]["data: ".len()..]).unwrap();
+        assert_eq!(finish["type"], "finish");
+        assert_eq!(finish["finish_reason"], "stop");
+        assert_eq!(finish["usage"]["total_tokens"], 4);
+
+        assert_eq!(lines[2], SSE_DONE);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::config::provider_option;
+use crate::golem::llm::llm::{Config, Error, ErrorCode};
+use log::trace;
+
+/// Gets the configured stop sequences, making sure there are at most as many as the provider
+/// supports, as providers reject requests with too many stop sequences with unhelpful errors. The
+/// `max_stop_sequences` provider option overrides the documented limit of the provider, and sets one
+/// for providers without a documented limit.
+///
+/// By default a request with too many stop sequences fails with an `invalid-request` error. If the
+/// `stop_sequence_overflow` provider option is `truncate`, the extra stop sequences are dropped
+/// instead.
+pub fn limit_stop_sequences(
+    config: &Config,
+    provider_limit: Option<usize>,
+) -> Result<Option<Vec<String>>, Error> {
+    let limit = match provider_option(config, "max_stop_sequences") {
+        None => provider_limit,
+        Some(value) => Some(value.parse::<usize>().map_err(|_| Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("Invalid max_stop_sequences option: {value}, expected a number"),
+            provider_error_json: None,
+        })?),
+    };
+    let truncate = match provider_option(config, "stop_sequence_overflow") {
+        None | Some("reject") => false,
+        Some("truncate") => true,
+        Some(other) => {
+            return Err(Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!(
+                    "Invalid stop_sequence_overflow option: {other}, expected reject or truncate"
+                ),
+                provider_error_json: None,
+            })
+        }
+    };
+
+    match (&config.stop_sequences, limit) {
+        (Some(stop_sequences), Some(limit)) if stop_sequences.len() > limit => {
+            if truncate {
+                trace!(
+                    "Dropping {} stop sequences over the provider limit of {limit}",
+                    stop_sequences.len() - limit
+                );
+                Ok(Some(stop_sequences[..limit].to_vec()))
+            } else {
+                Err(Error {
+                    code: ErrorCode::InvalidRequest,
+                    message: format!(
+                        "{} stop sequences were given, but the provider supports at most {limit}",
+                        stop_sequences.len()
+                    ),
+                    provider_error_json: None,
+                })
+            }
+        }
+        (stop_sequences, _) => Ok(stop_sequences.clone()),
+    }
+}
@@ -0,0 +1,510 @@
+use crate::config::provider_option;
+use crate::golem::llm::llm::{ChatEvent, Config, ContentPart, Error, ErrorCode, FinishReason};
+use log::trace;
+use serde_json::{Map, Value};
+
+/// What to do when a response does not match the requested JSON schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaMismatch {
+    /// Fail with an `invalid-request` error listing the validation errors
+    Error,
+    /// Send the request once more, and fail only if the second response does not match either
+    Retry,
+}
+
+/// The JSON schema the content of a response has to match, requested with the `response_format`
+/// provider option set to `json-schema`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseSchema {
+    pub schema: Value,
+    pub on_mismatch: SchemaMismatch,
+    /// Whether JSON cut off by the completion limit is completed before it is validated
+    pub repair_truncated: bool,
+}
+
+/// Gets the requested response schema from the provider options. `response_format` is either
+/// `text`, the default, or `json-schema`, in which case `response_schema` holds the schema and
+/// `response_schema_mismatch` is either `error`, the default, or `retry`. Truncated JSON is only
+/// repaired if `repair_truncated_json` is `true`.
+pub fn response_schema(config: &Config) -> Result<Option<ResponseSchema>, Error> {
+    match provider_option(config, "response_format") {
+        None | Some("text") => return Ok(None),
+        Some("json-schema") => {}
+        Some(other) => {
+            return Err(invalid_request(format!(
+                "Invalid response_format option: {other}, expected text or json-schema"
+            )))
+        }
+    }
+
+    let schema = provider_option(config, "response_schema").ok_or_else(|| {
+        invalid_request(
+            "The json-schema response format requires the response_schema option".to_string(),
+        )
+    })?;
+    let schema = serde_json::from_str::<Value>(schema)
+        .ok()
+        .filter(Value::is_object)
+        .ok_or_else(|| {
+            invalid_request(format!(
+                "Invalid response_schema option: {schema}, expected a JSON schema object"
+            ))
+        })?;
+
+    let on_mismatch = match provider_option(config, "response_schema_mismatch") {
+        None | Some("error") => SchemaMismatch::Error,
+        Some("retry") => SchemaMismatch::Retry,
+        Some(other) => {
+            return Err(invalid_request(format!(
+                "Invalid response_schema_mismatch option: {other}, expected error or retry"
+            )))
+        }
+    };
+
+    Ok(Some(ResponseSchema {
+        schema,
+        on_mismatch,
+        repair_truncated: provider_option(config, "repair_truncated_json") == Some("true"),
+    }))
+}
+
+/// Calls `send` and checks that the text of the returned message is JSON matching the requested
+/// schema. Tool requests and errors are returned unchanged, and without a schema nothing is checked.
+pub fn validate_structured_output(
+    response_schema: Option<&ResponseSchema>,
+    mut send: impl FnMut() -> ChatEvent,
+) -> ChatEvent {
+    let Some(response_schema) = response_schema else {
+        return send();
+    };
+
+    let mut send = || {
+        let response = send();
+        if response_schema.repair_truncated {
+            repair_truncated_json(response)
+        } else {
+            response
+        }
+    };
+
+    let mut response = send();
+    let mut errors = schema_errors(&response_schema.schema, &response);
+    if !errors.is_empty() && response_schema.on_mismatch == SchemaMismatch::Retry {
+        trace!("Retrying request as the response does not match the schema: {errors:?}");
+        response = send();
+        errors = schema_errors(&response_schema.schema, &response);
+    }
+
+    if errors.is_empty() {
+        response
+    } else {
+        ChatEvent::Error(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "Response does not match the requested JSON schema: {}",
+                errors.join("; ")
+            ),
+            provider_error_json: response_text(&response),
+        })
+    }
+}
+
+/// Completes the JSON of a response cut off by the completion limit, so it can be parsed. The open
+/// strings, arrays and objects are closed, after dropping the last member if it is incomplete.
+///
+/// A repaired response has its text replaced by the completed JSON and `json_repaired` set to
+/// `true` in its provider metadata. Responses that were not truncated, are valid JSON already, or
+/// cannot be completed this way are returned unchanged.
+pub fn repair_truncated_json(response: ChatEvent) -> ChatEvent {
+    let ChatEvent::Message(mut response) = response else {
+        return response;
+    };
+    if response.metadata.finish_reason != Some(FinishReason::Length) {
+        return ChatEvent::Message(response);
+    }
+    let text = response
+        .content
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::Text(text) => Some(text.as_str()),
+            ContentPart::Image(_) | ContentPart::Citation(_) => None,
+        })
+        .collect::<String>();
+    if serde_json::from_str::<Value>(&text).is_ok() {
+        return ChatEvent::Message(response);
+    }
+    let Some(repaired) = close_truncated_json(&text) else {
+        trace!("Could not repair the truncated JSON response");
+        return ChatEvent::Message(response);
+    };
+
+    trace!("Repaired the truncated JSON response");
+    response
+        .content
+        .retain(|part| !matches!(part, ContentPart::Text(_)));
+    response.content.insert(0, ContentPart::Text(repaired));
+
+    let mut provider_metadata = response
+        .metadata
+        .provider_metadata_json
+        .as_deref()
+        .and_then(|json| serde_json::from_str::<Map<String, Value>>(json).ok())
+        .unwrap_or_default();
+    provider_metadata.insert("json_repaired".to_string(), Value::Bool(true));
+    response.metadata.provider_metadata_json = Some(Value::Object(provider_metadata).to_string());
+    ChatEvent::Message(response)
+}
+
+/// Closes the strings, arrays and objects left open in truncated JSON. If the text does not parse
+/// when closed as it is, it is cut back to the end of an earlier complete member and closed again.
+fn close_truncated_json(text: &str) -> Option<String> {
+    let text = text.trim_start();
+    if !text.starts_with(['{', '[']) {
+        return None;
+    }
+
+    // The positions the text can be cut at: before a separating comma, or after a nested opening
+    // bracket, leaving the array or object empty
+    let mut cuts = Vec::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, char) in text.char_indices() {
+        if in_string {
+            match char {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match char {
+            '"' => in_string = true,
+            '{' | '[' => {
+                if depth > 0 {
+                    cuts.push(index + 1);
+                }
+                depth += 1;
+            }
+            '}' | ']' => depth -= 1,
+            ',' => cuts.push(index),
+            _ => {}
+        }
+    }
+
+    std::iter::once(text.len())
+        .chain(cuts.into_iter().rev())
+        .find_map(|cut| {
+            let closed = close_json(&text[..cut])?;
+            serde_json::from_str::<Value>(&closed).ok().map(|_| closed)
+        })
+}
+
+/// Appends the closing quote and brackets a JSON prefix is missing, dropping a dangling comma
+fn close_json(prefix: &str) -> Option<String> {
+    let mut closers = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for char in prefix.chars() {
+        if in_string {
+            match char {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match char {
+            '"' => in_string = true,
+            '{' => closers.push('}'),
+            '[' => closers.push(']'),
+            '}' | ']' => {
+                closers.pop()?;
+            }
+            _ => {}
+        }
+    }
+
+    let mut closed = prefix.to_string();
+    if in_string {
+        if escaped {
+            closed.pop();
+        }
+        closed.push('"');
+    }
+    let trimmed = closed.trim_end().trim_end_matches(',').len();
+    closed.truncate(trimmed);
+    closed.extend(closers.into_iter().rev());
+    Some(closed)
+}
+
+fn invalid_request(message: String) -> Error {
+    Error {
+        code: ErrorCode::InvalidRequest,
+        message,
+        provider_error_json: None,
+    }
+}
+
+fn response_text(response: &ChatEvent) -> Option<String> {
+    match response {
+        ChatEvent::Message(response) => Some(
+            response
+                .content
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text(text) => Some(text.as_str()),
+                    ContentPart::Image(_) | ContentPart::Citation(_) => None,
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn schema_errors(schema: &Value, response: &ChatEvent) -> Vec<String> {
+    let Some(text) = response_text(response) else {
+        return vec![];
+    };
+    match serde_json::from_str::<Value>(&text) {
+        Ok(value) => {
+            let mut errors = Vec::new();
+            validate(schema, &value, "", &mut errors);
+            errors
+        }
+        Err(err) => vec![format!("response is not valid JSON: {err}")],
+    }
+}
+
+/// Validates a value against the subset of JSON schema accepted by the structured output modes of
+/// the providers: `type`, `enum`, `const`, `properties`, `required`, `additionalProperties`,
+/// `items` and `anyOf`. Other keywords are not checked.
+fn validate(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let location = if path.is_empty() { "/" } else { path };
+
+    if let Some(expected) = schema.get("type") {
+        let types = match expected {
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            expected => expected.as_str().into_iter().collect::<Vec<_>>(),
+        };
+        if !types.iter().any(|expected| has_type(value, expected)) {
+            errors.push(format!("{location}: expected {}", types.join(" or ")));
+            return;
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            errors.push(format!(
+                "{location}: {value} is not one of the allowed values"
+            ));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            errors.push(format!("{location}: expected {expected}"));
+        }
+    }
+    if let Some(variants) = schema.get("anyOf").and_then(Value::as_array) {
+        let matches_any = variants.iter().any(|variant| {
+            let mut variant_errors = Vec::new();
+            validate(variant, value, path, &mut variant_errors);
+            variant_errors.is_empty()
+        });
+        if !matches_any {
+            errors.push(format!(
+                "{location}: does not match any of the allowed schemas"
+            ));
+        }
+    }
+
+    match value {
+        Value::Object(fields) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for required in schema
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                if !fields.contains_key(required) {
+                    errors.push(format!("{path}/{required}: missing required property"));
+                }
+            }
+            for (name, field) in fields {
+                let field_path = format!("{path}/{name}");
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(field_schema) => validate(field_schema, field, &field_path, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            errors.push(format!("{field_path}: unexpected property"))
+                        }
+                        Some(field_schema @ Value::Object(_)) => {
+                            validate(field_schema, field, &field_path, errors)
+                        }
+                        _ => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    validate(item_schema, item, &format!("{path}/{index}"), errors);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, ContentPart, ErrorCode, FinishReason, ResponseMetadata,
+    };
+    use crate::structured_output::{
+        repair_truncated_json, validate_structured_output, ResponseSchema, SchemaMismatch,
+    };
+    use serde_json::json;
+    use std::collections::VecDeque;
+
+    fn message(text: &str) -> ChatEvent {
+        ChatEvent::Message(CompleteResponse {
+            id: "1".to_string(),
+            content: vec![ContentPart::Text(text.to_string())],
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                stop_sequence: None,
+                tool_calls: None,
+            },
+        })
+    }
+
+    fn weather_schema(on_mismatch: SchemaMismatch) -> ResponseSchema {
+        ResponseSchema {
+            schema: json!({
+                "type": "object",
+                "properties": {
+                    "city": {"type": "string"},
+                    "temperature": {"type": "number"},
+                    "unit": {"enum": ["celsius", "fahrenheit"]}
+                },
+                "required": ["city", "temperature", "unit"],
+                "additionalProperties": false
+            }),
+            on_mismatch,
+            repair_truncated: false,
+        }
+    }
+
+    #[test]
+    fn conforming_response_is_returned() {
+        let response = message(r#"{"city": "Paris", "temperature": 21.5, "unit": "celsius"}"#);
+
+        assert_eq!(
+            validate_structured_output(Some(&weather_schema(SchemaMismatch::Error)), || {
+                response.clone()
+            }),
+            response
+        );
+    }
+
+    #[test]
+    fn non_conforming_response_fails_or_is_retried() {
+        let non_conforming = message(r#"{"city": "Paris", "temperature": "warm", "wind": 3}"#);
+        let conforming = message(r#"{"city": "Paris", "temperature": 21.5, "unit": "celsius"}"#);
+
+        let ChatEvent::Error(error) =
+            validate_structured_output(Some(&weather_schema(SchemaMismatch::Error)), || {
+                non_conforming.clone()
+            })
+        else {
+            panic!("Expected a schema mismatch error");
+        };
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(
+            error.message,
+            "Response does not match the requested JSON schema: /unit: missing required property; \
+             /temperature: expected number; /wind: unexpected property"
+        );
+
+        let mut responses = VecDeque::from([non_conforming, conforming.clone()]);
+        assert_eq!(
+            validate_structured_output(Some(&weather_schema(SchemaMismatch::Retry)), || {
+                responses.pop_front().unwrap()
+            }),
+            conforming
+        );
+    }
+
+    fn truncated(text: &str) -> ChatEvent {
+        let ChatEvent::Message(mut response) = message(text) else {
+            unreachable!()
+        };
+        response.metadata.finish_reason = Some(FinishReason::Length);
+        ChatEvent::Message(response)
+    }
+
+    #[test]
+    fn truncated_json_is_repaired() {
+        let ChatEvent::Message(response) = repair_truncated_json(truncated(
+            r#"{"city": "Paris", "forecast": [{"day": "Monday", "summary": "Sunny wi"#,
+        )) else {
+            panic!("Expected a message");
+        };
+        assert_eq!(
+            response.content,
+            vec![ContentPart::Text(
+                r#"{"city": "Paris", "forecast": [{"day": "Monday", "summary": "Sunny wi"}]}"#
+                    .to_string()
+            )]
+        );
+        assert_eq!(
+            response.metadata.provider_metadata_json.as_deref(),
+            Some(r#"{"json_repaired":true}"#)
+        );
+
+        let ChatEvent::Message(response) =
+            repair_truncated_json(truncated(r#"{"city": "Paris", "temperature": 21."#))
+        else {
+            panic!("Expected a message");
+        };
+        assert_eq!(
+            response.content,
+            vec![ContentPart::Text(r#"{"city": "Paris"}"#.to_string())]
+        );
+    }
+
+    #[test]
+    fn unrepairable_truncation_is_left_unchanged() {
+        let response = truncated(r#"Here is the weather: {"city": "Par"#);
+        assert_eq!(repair_truncated_json(response.clone()), response);
+
+        let response = truncated(r#"{"city": Paris"#);
+        assert_eq!(repair_truncated_json(response.clone()), response);
+
+        let response = message(r#"{"city": "Par"#);
+        assert_eq!(repair_truncated_json(response.clone()), response);
+    }
+}
@@ -0,0 +1,275 @@
+use crate::error::reject_empty_response;
+use crate::golem::llm::llm::{
+    ChatEvent, Config, ContentPart, Error, ErrorCode, ImageReference, Message, Role,
+};
+
+const SUMMARY_INSTRUCTIONS: &str = "Summarize the following conversation between a user and an \
+     assistant. Keep every fact, decision, name and number that later messages could refer to, as \
+     well as questions that are still open. Respond with the summary only.";
+
+/// Compacts a conversation by replacing its oldest messages with a summary written by the model,
+/// keeping at least the last `keep_last_n` messages verbatim.
+///
+/// Leading system messages are kept as they are, and the summary is inserted after them as
+/// another system message. The kept messages always start with a user message, so tool results
+/// are never separated from the tool calls they answer and the compacted conversation is accepted
+/// by providers requiring a user message first. If there is nothing to summarize, the messages are
+/// returned unchanged without calling the model.
+///
+/// The summary is requested with `send`, using only the model of the given configuration. Its
+/// system prompt, tools, stop sequences, token limit and provider options are meant for the
+/// conversation and could shape the summary, like a JSON response format or several choices.
+pub fn summarize_history(
+    messages: Vec<Message>,
+    config: Config,
+    keep_last_n: u32,
+    send: impl FnOnce(Vec<Message>, Config) -> ChatEvent,
+) -> Result<Vec<Message>, Error> {
+    let system_count = messages
+        .iter()
+        .take_while(|message| message.role == Role::System)
+        .count();
+    let kept_from = messages
+        .len()
+        .saturating_sub(keep_last_n as usize)
+        .max(system_count);
+    let Some(kept_from) = (system_count..=kept_from)
+        .rev()
+        .find(|&index| messages[index..].is_empty() || messages[index].role == Role::User)
+        .filter(|&kept_from| kept_from > system_count)
+    else {
+        return Ok(messages);
+    };
+
+    let config = Config {
+        model: config.model,
+        temperature: None,
+        max_tokens: None,
+        stop_sequences: None,
+        tools: vec![],
+        tool_choice: None,
+        provider_options: vec![],
+        system: None,
+    };
+    let request = vec![
+        Message {
+            role: Role::System,
+            name: None,
+            content: vec![ContentPart::Text(SUMMARY_INSTRUCTIONS.to_string())],
+        },
+        Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text(transcript(
+                &messages[system_count..kept_from],
+            ))],
+        },
+    ];
+    let summary = match reject_empty_response(send(request, config)) {
+        ChatEvent::Message(response) => response
+            .content
+            .into_iter()
+            .filter_map(|part| match part {
+                ContentPart::Text(text) => Some(text),
+                ContentPart::Image(_) | ContentPart::Citation(_) => None,
+            })
+            .collect::<String>(),
+        ChatEvent::ToolRequest(_) => {
+            return Err(Error {
+                code: ErrorCode::InternalError,
+                message: "Expected a summary of the conversation, but the model requested tools"
+                    .to_string(),
+                provider_error_json: None,
+            })
+        }
+        ChatEvent::Error(error) => return Err(error),
+    };
+
+    let mut messages = messages;
+    messages.splice(
+        system_count..kept_from,
+        [Message {
+            role: Role::System,
+            name: None,
+            content: vec![ContentPart::Text(format!(
+                "Summary of the earlier conversation:\n{}",
+                summary.trim()
+            ))],
+        }],
+    );
+    Ok(messages)
+}
+
+/// Renders messages as a plain text transcript, one paragraph per message
+fn transcript(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .map(|message| {
+            let role = match message.role {
+                Role::User => "User",
+                Role::Assistant => "Assistant",
+                Role::System => "System",
+                Role::Tool => "Tool result",
+            };
+            let content = message
+                .content
+                .iter()
+                .map(|part| match part {
+                    ContentPart::Text(text) => text.clone(),
+                    ContentPart::Image(ImageReference::Url(_) | ImageReference::Inline(_)) => {
+                        "[image]".to_string()
+                    }
+                    ContentPart::Citation(citation) => format!("[source: {}]", citation.url),
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!("{role}: {content}")
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, Config, ContentPart, Kv, Message, ResponseMetadata, Role,
+        ToolDefinition,
+    };
+    use crate::summarize::summarize_history;
+    use crate::testing::config;
+
+    fn message(role: Role, text: &str) -> Message {
+        Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        }
+    }
+
+    #[test]
+    fn oldest_messages_are_replaced_by_a_summary() {
+        let messages = vec![
+            message(Role::System, "You are a travel agent."),
+            message(Role::User, "I want to fly to Lisbon in May."),
+            message(Role::Assistant, "Which dates work for you?"),
+            message(Role::User, "The 3rd to the 10th."),
+            message(Role::Assistant, "There is a direct flight on the 3rd."),
+            message(Role::User, "Book it."),
+            message(Role::Assistant, "Done. Anything else?"),
+        ];
+
+        let mut request = None;
+        let history =
+            summarize_history(messages.clone(), config("gpt-4o", &[]), 3, |messages, _| {
+                request = Some(messages);
+                ChatEvent::Message(CompleteResponse {
+                    id: "summary".to_string(),
+                    content: vec![ContentPart::Text(
+                        "The user flies to Lisbon from May 3rd to 10th.".to_string(),
+                    )],
+                    tool_calls: vec![],
+                    metadata: ResponseMetadata {
+                        finish_reason: None,
+                        usage: None,
+                        provider_id: None,
+                        timestamp: None,
+                        provider_metadata_json: None,
+                        stop_sequence: None,
+                        tool_calls: None,
+                    },
+                })
+            })
+            .unwrap();
+
+        assert_eq!(
+            history,
+            vec![
+                message(Role::System, "You are a travel agent."),
+                message(
+                    Role::System,
+                    "Summary of the earlier conversation:\nThe user flies to Lisbon from May 3rd to 10th."
+                ),
+                message(Role::User, "The 3rd to the 10th."),
+                message(Role::Assistant, "There is a direct flight on the 3rd."),
+                message(Role::User, "Book it."),
+                message(Role::Assistant, "Done. Anything else?"),
+            ]
+        );
+        assert!(history.len() < messages.len());
+        assert_eq!(history[history.len() - 3..], messages[messages.len() - 3..]);
+        assert_eq!(
+            request.unwrap()[1].content,
+            vec![ContentPart::Text(
+                "User: I want to fly to Lisbon in May.\n\nAssistant: Which dates work for you?"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn summary_is_requested_with_only_the_model() {
+        let messages = vec![
+            message(Role::User, "I want to fly to Lisbon in May."),
+            message(Role::Assistant, "Which dates work for you?"),
+            message(Role::User, "The 3rd to the 10th."),
+        ];
+        let conversation_config = Config {
+            temperature: Some(0.2),
+            max_tokens: Some(16),
+            stop_sequences: Some(vec!["\n".to_string()]),
+            tools: vec![ToolDefinition {
+                name: "book_flight".to_string(),
+                description: None,
+                parameters_schema: "{}".to_string(),
+            }],
+            tool_choice: Some("book_flight".to_string()),
+            provider_options: vec![
+                Kv {
+                    key: "response_format".to_string(),
+                    value: "json".to_string(),
+                },
+                Kv {
+                    key: "n".to_string(),
+                    value: "3".to_string(),
+                },
+            ],
+            system: Some("Answer in JSON.".to_string()),
+            ..config("gpt-4o", &[])
+        };
+
+        let mut summary_config = None;
+        summarize_history(messages, conversation_config, 1, |_, summary| {
+            summary_config = Some(summary);
+            ChatEvent::Message(CompleteResponse {
+                id: "summary".to_string(),
+                content: vec![ContentPart::Text("The user flies to Lisbon.".to_string())],
+                tool_calls: vec![],
+                metadata: ResponseMetadata {
+                    finish_reason: None,
+                    usage: None,
+                    provider_id: None,
+                    timestamp: None,
+                    provider_metadata_json: None,
+                    stop_sequence: None,
+                    tool_calls: None,
+                },
+            })
+        })
+        .unwrap();
+
+        assert_eq!(summary_config, Some(config("gpt-4o", &[])));
+    }
+
+    #[test]
+    fn short_history_is_not_summarized() {
+        let messages = vec![
+            message(Role::User, "Hello"),
+            message(Role::Assistant, "Hi, how can I help?"),
+        ];
+
+        let history = summarize_history(messages.clone(), config("gpt-4o", &[]), 2, |_, _| {
+            panic!("Nothing should be summarized")
+        });
+        assert_eq!(history, Ok(messages));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::golem::llm::llm::{ContentPart, Error, ErrorCode};
+use std::collections::HashMap;
+
+/// Builds a text content part from a template with `{{name}}` placeholders, replacing each of them
+/// with the value of the variable of the same name. Whitespace around the name is ignored.
+///
+/// A literal `{{` is written as `\{{`. In `strict` mode a placeholder without a matching variable
+/// fails with an `invalid-request` error, otherwise it is kept in the text unchanged.
+pub fn interpolate(
+    template: &str,
+    variables: &HashMap<String, String>,
+    strict: bool,
+) -> Result<ContentPart, Error> {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        if rest[..start].ends_with('\\') {
+            text.push_str(&rest[..start - 1]);
+            text.push_str("{{");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        text.push_str(&rest[..start]);
+        let Some(length) = rest[start + 2..].find("}}") else {
+            return Err(template_error(format!(
+                "Unclosed placeholder at position {} of the template",
+                template.len() - rest.len() + start
+            )));
+        };
+
+        let placeholder = &rest[start..start + 2 + length + 2];
+        let name = placeholder[2..placeholder.len() - 2].trim();
+        match variables.get(name) {
+            Some(value) => text.push_str(value),
+            None if strict => {
+                return Err(template_error(format!(
+                    "Missing value for template variable '{name}'"
+                )))
+            }
+            None => text.push_str(placeholder),
+        }
+        rest = &rest[start + placeholder.len()..];
+    }
+    text.push_str(rest);
+
+    Ok(ContentPart::Text(text))
+}
+
+fn template_error(message: String) -> Error {
+    Error {
+        code: ErrorCode::InvalidRequest,
+        message,
+        provider_error_json: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{ContentPart, ErrorCode};
+    use crate::template::interpolate;
+    use std::collections::HashMap;
+
+    fn variables() -> HashMap<String, String> {
+        HashMap::from([
+            ("name".to_string(), "Alice".to_string()),
+            ("city".to_string(), "Paris".to_string()),
+        ])
+    }
+
+    fn text(part: ContentPart) -> String {
+        match part {
+            ContentPart::Text(text) => text,
+            _ => panic!("expected a text content part"),
+        }
+    }
+
+    #[test]
+    fn variables_are_interpolated() {
+        assert_eq!(
+            text(
+                interpolate("Hello {{name}}, welcome to {{ city }}!", &variables(), true).unwrap()
+            ),
+            "Hello Alice, welcome to Paris!"
+        );
+        assert_eq!(
+            text(interpolate("Hello {{name}} from {{country}}", &variables(), false).unwrap()),
+            "Hello Alice from {{country}}"
+        );
+    }
+
+    #[test]
+    fn missing_variable_fails_in_strict_mode() {
+        let error = interpolate("Hello {{name}} from {{country}}", &variables(), true).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(error.message.contains("'country'"));
+    }
+
+    #[test]
+    fn escaped_braces_are_kept() {
+        assert_eq!(
+            text(interpolate(r"Use \{{name}} to greet {{name}}", &variables(), true).unwrap()),
+            "Use {{name}} to greet Alice"
+        );
+    }
+}
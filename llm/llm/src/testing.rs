@@ -0,0 +1,21 @@
+use crate::golem::llm::llm::{Config, Kv};
+
+/// Builds a configuration of the given model and provider options, leaving everything else unset
+pub fn config(model: &str, provider_options: &[(&str, &str)]) -> Config {
+    Config {
+        model: model.to_string(),
+        temperature: None,
+        max_tokens: None,
+        stop_sequences: None,
+        tools: vec![],
+        tool_choice: None,
+        provider_options: provider_options
+            .iter()
+            .map(|(key, value)| Kv {
+                key: key.to_string(),
+                value: value.to_string(),
+            })
+            .collect(),
+        system: None,
+    }
+}
@@ -0,0 +1,125 @@
+use crate::golem::llm::llm::{ChatEvent, Error, ErrorCode, ImageReference, ToolCall, ToolImage};
+
+/// Gets the id of a tool call, synthesizing a stable `call_<index>` id from the position of the
+/// tool call in the response when the provider did not return one. Without an id the tool results
+/// passed to `continue` could not be matched to their tool calls.
+pub fn tool_call_id(id: Option<&str>, index: u32) -> String {
+    match id {
+        Some(id) if !id.is_empty() => id.to_string(),
+        _ => format!("call_{index}"),
+    }
+}
+
+/// Describes an image returned by a tool as text, for providers or models that only accept text
+/// as the result of a tool call
+pub fn tool_image_description(tool_image: &ToolImage) -> String {
+    let source = match &tool_image.image {
+        ImageReference::Url(image_url) => format!(" at {}", image_url.url),
+        ImageReference::Inline(image_source) => format!(
+            " ({}, {} bytes)",
+            image_source.mime_type,
+            image_source.data.len()
+        ),
+    };
+    match &tool_image.description {
+        Some(description) => format!(
+            "[Image returned by tool {}{source}]: {description}",
+            tool_image.name
+        ),
+        None => format!("[Image returned by tool {}{source}]", tool_image.name),
+    }
+}
+
+/// Checks that the arguments of a tool call are valid JSON, so a tool executor parsing them does not
+/// fail with an unclear error. Empty arguments, sent by some models for tools without parameters,
+/// are accepted. The error carries the malformed arguments as they were returned.
+pub fn validate_tool_arguments(tool_call: &ToolCall) -> Result<(), Error> {
+    if tool_call.arguments_json.trim().is_empty() {
+        return Ok(());
+    }
+    match serde_json::from_str::<serde_json::Value>(&tool_call.arguments_json) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Error {
+            code: ErrorCode::InvalidToolArguments,
+            message: format!(
+                "Tool call {} ({}) has malformed arguments: {err}",
+                tool_call.id, tool_call.name
+            ),
+            provider_error_json: Some(tool_call.arguments_json.clone()),
+        }),
+    }
+}
+
+/// Replaces a response calling a tool with arguments that are not valid JSON with an
+/// `invalid-tool-arguments` error, as the tool calls cannot be executed
+pub fn reject_malformed_tool_calls(event: ChatEvent) -> ChatEvent {
+    let tool_calls = match &event {
+        ChatEvent::Message(response) => &response.tool_calls,
+        ChatEvent::ToolRequest(tool_calls) => tool_calls,
+        ChatEvent::Error(_) => return event,
+    };
+    match tool_calls.iter().try_for_each(validate_tool_arguments) {
+        Ok(()) => event,
+        Err(error) => ChatEvent::Error(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ChatEvent, ErrorCode, ImageReference, ImageSource, ToolCall, ToolImage,
+    };
+    use crate::tool_calls::{reject_malformed_tool_calls, tool_call_id, tool_image_description};
+
+    #[test]
+    fn missing_ids_are_synthesized() {
+        assert_eq!(tool_call_id(Some("call_abc"), 1), "call_abc");
+        assert_eq!(tool_call_id(Some(""), 1), "call_1");
+        assert_eq!(tool_call_id(None, 0), "call_0");
+    }
+
+    #[test]
+    fn tool_images_are_described_as_text() {
+        let tool_image = ToolImage {
+            id: "call_1".to_string(),
+            name: "render_chart".to_string(),
+            image: ImageReference::Inline(ImageSource {
+                data: vec![0; 128],
+                mime_type: "image/png".to_string(),
+                detail: None,
+            }),
+            description: Some("Monthly revenue as a bar chart".to_string()),
+            execution_time_ms: None,
+        };
+
+        assert_eq!(
+            tool_image_description(&tool_image),
+            "[Image returned by tool render_chart (image/png, 128 bytes)]: Monthly revenue as a bar chart"
+        );
+    }
+
+    #[test]
+    fn malformed_tool_arguments_are_rejected() {
+        let tool_call = |arguments_json: &str| ToolCall {
+            id: "call_1".to_string(),
+            name: "get_weather".to_string(),
+            arguments_json: arguments_json.to_string(),
+        };
+
+        let valid = ChatEvent::ToolRequest(vec![tool_call(r#"{"city": "Berlin"}"#)]);
+        assert_eq!(reject_malformed_tool_calls(valid.clone()), valid);
+
+        let ChatEvent::Error(error) =
+            reject_malformed_tool_calls(ChatEvent::ToolRequest(vec![tool_call(
+                r#"{"city": "Ber"#,
+            )]))
+        else {
+            panic!("expected an error");
+        };
+        assert_eq!(error.code, ErrorCode::InvalidToolArguments);
+        assert_eq!(
+            error.provider_error_json.as_deref(),
+            Some(r#"{"city": "Ber"#)
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use crate::golem::llm::llm::Usage;
+
+/// Adds up the token usage of several responses, for tracking the cumulative usage of a
+/// conversation across multiple calls.
+///
+/// Each field is the sum of the usages reporting it, and stays `None` only if none of them do. A
+/// usage without `total_tokens` contributes the sum of its input and output tokens instead, when
+/// it reports both.
+pub fn sum_usage(usages: Vec<Usage>) -> Usage {
+    usages.into_iter().fold(
+        Usage {
+            input_tokens: None,
+            output_tokens: None,
+            total_tokens: None,
+        },
+        |sum, usage| {
+            let usage_total = usage.total_tokens.or_else(|| {
+                usage
+                    .input_tokens
+                    .zip(usage.output_tokens)
+                    .map(|(input, output)| input.saturating_add(output))
+            });
+            Usage {
+                input_tokens: add(sum.input_tokens, usage.input_tokens),
+                output_tokens: add(sum.output_tokens, usage.output_tokens),
+                total_tokens: add(sum.total_tokens, usage_total),
+            }
+        },
+    )
+}
+
+fn add(sum: Option<u32>, tokens: Option<u32>) -> Option<u32> {
+    match (sum, tokens) {
+        (Some(sum), Some(tokens)) => Some(sum.saturating_add(tokens)),
+        (sum, tokens) => sum.or(tokens),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::Usage;
+    use crate::usage::sum_usage;
+
+    fn usage(input: Option<u32>, output: Option<u32>, total: Option<u32>) -> Usage {
+        Usage {
+            input_tokens: input,
+            output_tokens: output,
+            total_tokens: total,
+        }
+    }
+
+    #[test]
+    fn partial_usages_are_summed() {
+        let sum = sum_usage(vec![
+            usage(Some(10), Some(5), Some(15)),
+            usage(Some(20), Some(7), None),
+            usage(Some(4), None, None),
+            usage(None, None, Some(3)),
+        ]);
+
+        assert_eq!(sum, usage(Some(34), Some(12), Some(45)));
+    }
+
+    #[test]
+    fn missing_fields_stay_missing() {
+        assert_eq!(
+            sum_usage(vec![
+                usage(Some(10), None, None),
+                usage(Some(2), None, None)
+            ]),
+            usage(Some(12), None, None)
+        );
+        assert_eq!(sum_usage(vec![]), usage(None, None, None));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::golem::llm::llm::{Error, ErrorCode, Message, Role};
+
+/// Describes where a provider accepts system messages in a conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemMessagePlacement {
+    /// System messages may appear anywhere in the conversation
+    Anywhere,
+    /// System messages must precede all other messages
+    Leading,
+    /// Only a single system message is honored, so all system messages are merged into one
+    /// preceding the conversation by `normalize_system_messages`
+    Single,
+}
+
+/// Checks the order of the roles in a conversation before it is sent to the provider, so invalid
+/// conversations fail with an `invalid-request` error pointing at the offending message instead of
+/// an opaque error response from the provider.
+pub fn validate_messages(
+    messages: &[Message],
+    system_placement: SystemMessagePlacement,
+) -> Result<(), Error> {
+    let mut previous_role = None;
+    for (position, message) in messages.iter().enumerate() {
+        match message.role {
+            Role::Tool => {
+                if !matches!(previous_role, Some(Role::Assistant) | Some(Role::Tool)) {
+                    return Err(invalid_message(
+                        position,
+                        "a tool message must follow an assistant message",
+                    ));
+                }
+            }
+            Role::System => {
+                if system_placement != SystemMessagePlacement::Anywhere
+                    && previous_role.is_some_and(|role| role != Role::System)
+                {
+                    return Err(invalid_message(
+                        position,
+                        "system messages must precede all other messages",
+                    ));
+                }
+            }
+            Role::User | Role::Assistant => {}
+        }
+        previous_role = Some(message.role);
+    }
+    Ok(())
+}
+
+fn invalid_message(position: usize, reason: &str) -> Error {
+    Error {
+        code: ErrorCode::InvalidRequest,
+        message: format!("Invalid message at position {position}: {reason}"),
+        provider_error_json: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{ContentPart, ErrorCode, Message, Role};
+    use crate::validation::{validate_messages, SystemMessagePlacement};
+
+    fn message(role: Role) -> Message {
+        Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text("text".to_string())],
+        }
+    }
+
+    #[test]
+    fn valid_conversation() {
+        let messages = vec![
+            message(Role::System),
+            message(Role::User),
+            message(Role::Assistant),
+            message(Role::Tool),
+            message(Role::Tool),
+            message(Role::User),
+        ];
+
+        assert!(validate_messages(&messages, SystemMessagePlacement::Leading).is_ok());
+    }
+
+    #[test]
+    fn orphan_tool_message() {
+        let messages = vec![
+            message(Role::System),
+            message(Role::User),
+            message(Role::Tool),
+        ];
+
+        let error = validate_messages(&messages, SystemMessagePlacement::Anywhere).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(
+            error.message,
+            "Invalid message at position 2: a tool message must follow an assistant message"
+        );
+    }
+
+    #[test]
+    fn misplaced_system_message() {
+        let messages = vec![
+            message(Role::System),
+            message(Role::User),
+            message(Role::System),
+        ];
+
+        let error = validate_messages(&messages, SystemMessagePlacement::Leading).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(
+            error.message,
+            "Invalid message at position 2: system messages must precede all other messages"
+        );
+        assert!(validate_messages(&messages, SystemMessagePlacement::Anywhere).is_ok());
+    }
+}
@@ -1,42 +1,108 @@
 use std::{fmt::Debug, fs, path::Path};
 
-use base64::{engine::general_purpose, Engine};
+use golem_llm::fixtures::{response_fixtures, with_response_fixtures, ResponseFixtures};
+use golem_llm::lenient::{optional_integer, parse_response_json};
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{pre_send_body, RawResponse};
 use golem_llm::{
+    cache::cache_scope,
+    config::user_agent,
     error::{error_code_from_status, from_event_source_error},
     event_source::EventSource,
-    golem::llm::llm::{Error, ErrorCode},
+    golem::llm::llm::{Config, Error, ErrorCode},
+    image::download_image,
 };
 use log::trace;
 use reqwest::{
-    header::{HeaderMap, HeaderValue, CONTENT_TYPE},
-    Client, Method, Response, StatusCode,
+    header::{HeaderMap, HeaderValue, CONTENT_TYPE, USER_AGENT},
+    Client, IntoUrl, Method, RequestBuilder, Response, StatusCode,
 };
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use url::Url;
 
+const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
 pub struct OllamaApi {
     default_model: String,
     base_url: String,
+    user_agent: String,
+    response_fixtures: Option<ResponseFixtures>,
     client: Client,
 }
 
 impl OllamaApi {
-    pub fn new(default_model: String) -> Self {
-        let base_url =
-            std::env::var("GOLEM_OLLAMA_BASE_URL").unwrap_or("http://localhost:11434".to_string());
+    /// Creates a client for the Ollama server set in the `OLLAMA_BASE_URL` environment variable
+    /// (or the older `GOLEM_OLLAMA_BASE_URL`), defaulting to a local server on the default port.
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let base_url = std::env::var("OLLAMA_BASE_URL")
+            .or_else(|_| std::env::var("GOLEM_OLLAMA_BASE_URL"))
+            .unwrap_or(DEFAULT_BASE_URL.to_string());
+        Ok(Self {
+            response_fixtures: response_fixtures(config),
+            ..Self::with_base_url(
+                config.model.clone(),
+                &base_url,
+                user_agent(config, "ollama"),
+            )?
+        })
+    }
+
+    pub fn with_base_url(
+        default_model: String,
+        base_url: &str,
+        user_agent: String,
+    ) -> Result<Self, Error> {
+        let invalid_base_url = |reason: String| Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("Invalid Ollama base URL '{base_url}': {reason}"),
+            provider_error_json: None,
+        };
+
+        let url = Url::parse(base_url).map_err(|err| invalid_base_url(err.to_string()))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(invalid_base_url(format!(
+                "unsupported scheme '{}'",
+                url.scheme()
+            )));
+        }
+        if !url.has_host() {
+            return Err(invalid_base_url("missing host".to_string()));
+        }
+
         let client = Client::builder()
             .build()
             .expect("Failed to initialize HTTP client");
-        Self {
+        Ok(Self {
             default_model,
-            base_url,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            user_agent,
+            response_fixtures: None,
             client,
-        }
+        })
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    /// Identifies the server and credentials of this client in response cache keys
+    pub fn cache_scope(&self) -> String {
+        cache_scope(&self.base_url, "")
+    }
+
+    /// Starts a request to the Ollama server, sent with the configured user agent
+    fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .header(USER_AGENT, &self.user_agent)
     }
 
-    pub fn send_chat(&self, params: CompletionsRequest) -> Result<CompletionsResponse, Error> {
-        trace!("Sending request to Ollama API: {params:?}");
+    pub fn send_chat(
+        &self,
+        params: CompletionsRequest,
+    ) -> Result<RawResponse<CompletionsResponse>, Error> {
+        trace!("Sending request to Ollama API: {}", Redacted(&params));
 
         let mut modified_params = params;
         modified_params.stream = Some(false);
@@ -47,20 +113,22 @@ impl OllamaApi {
         let mut headers = HeaderMap::new();
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
 
-        let url = format!("{}/api/chat", self.base_url);
-        let response: Response = self
-            .client
-            .request(Method::POST, url)
-            .headers(headers)
-            .json(&modified_params)
-            .send()
-            .map_err(|err| from_reqwest_error("Request failed", err))?;
-
-        handle_response::<CompletionsResponse>(response)
+        let body = pre_send_body(&modified_params)?;
+        with_response_fixtures(self.response_fixtures.as_ref(), "ollama", &body, || {
+            let url = self.endpoint("/api/chat");
+            let response: Response = self
+                .request(Method::POST, url)
+                .headers(headers)
+                .json(&body)
+                .send()
+                .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+            handle_response(response)
+        })
     }
 
     pub fn send_chat_stream(&self, params: CompletionsRequest) -> Result<EventSource, Error> {
-        trace!("Sending request to Ollama API: {params:?}");
+        trace!("Sending request to Ollama API: {}", Redacted(&params));
 
         let mut modified_params = params;
         modified_params.stream = Some(true);
@@ -68,19 +136,19 @@ impl OllamaApi {
             modified_params.model = Some(self.default_model.clone())
         };
 
-        let json_body = serde_json::to_string(&modified_params).map_err(|e| Error {
-            code: ErrorCode::InternalError,
-            message: format!("Failed to serialize request body: {e}"),
-            provider_error_json: None,
-        })?;
+        let json_body =
+            serde_json::to_string(&pre_send_body(&modified_params)?).map_err(|e| Error {
+                code: ErrorCode::InternalError,
+                message: format!("Failed to serialize request body: {e}"),
+                provider_error_json: None,
+            })?;
 
         let mut headers = HeaderMap::new();
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
         headers.insert("Accept", HeaderValue::from_static("application/x-ndjson"));
 
-        let url = format!("{}/api/chat", self.base_url);
+        let url = self.endpoint("/api/chat");
         let response = self
-            .client
             .request(Method::POST, url)
             .headers(headers)
             .body(json_body)
@@ -89,6 +157,37 @@ impl OllamaApi {
         EventSource::new(response)
             .map_err(|err| from_event_source_error("Failed to create EventSource stream", err))
     }
+
+    pub fn list_models(&self) -> Result<ModelsResponse, Error> {
+        trace!("Listing models from Ollama API");
+
+        let response: Response = self
+            .request(Method::GET, self.endpoint("/api/tags"))
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+        handle_response::<ModelsResponse>(response)
+    }
+
+    pub fn embed(&self, params: EmbeddingsRequest) -> Result<EmbeddingsResponse, Error> {
+        trace!(
+            "Sending embeddings request to Ollama API: {}",
+            Redacted(&params)
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        let url = self.endpoint("/api/embed");
+        let response: Response = self
+            .request(Method::POST, url)
+            .headers(headers)
+            .json(&params)
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+        handle_response::<EmbeddingsResponse>(response)
+    }
 }
 
 /// GenerateOptions is Options for generating completions
@@ -161,8 +260,9 @@ pub struct CompletionsRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<Tool>>,
 
+    /// Either `json` or a JSON schema the response has to match
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub format: Option<String>,
+    pub format: Option<serde_json::Value>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub options: Option<OllamaModelOptions>,
@@ -173,8 +273,96 @@ pub struct CompletionsRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
 
+    /// Controls how long the model will stay loaded into memory following the request
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub keep_alive: Option<String>,
+    pub keep_alive: Option<KeepAlive>,
+}
+
+/// Either a number of seconds or a duration string such as `10m`. Zero unloads the model
+/// immediately after the request, and a negative value keeps it loaded indefinitely.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum KeepAlive {
+    Seconds(i64),
+    Duration(String),
+}
+
+impl From<String> for KeepAlive {
+    fn from(value: String) -> Self {
+        match value.trim().parse::<i64>() {
+            Ok(seconds) => KeepAlive::Seconds(seconds),
+            Err(_) => KeepAlive::Duration(value),
+        }
+    }
+}
+
+/// EmbeddingsRequest is parameters for a request to the embed endpoint
+///
+/// Refer to https://github.com/ollama/ollama/blob/main/docs/api.md#generate-embeddings for more details
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmbeddingsRequest {
+    pub model: String,
+
+    pub input: EmbeddingsInput,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncate: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<OllamaModelOptions>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<KeepAlive>,
+}
+
+/// A single text or a batch of texts to embed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum EmbeddingsInput {
+    Single(String),
+    Batch(Vec<String>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmbeddingsResponse {
+    pub model: String,
+    pub embeddings: Vec<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_duration: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_duration: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_eval_count: Option<i64>,
+}
+
+/// ModelsResponse is the list of locally available models
+///
+/// Refer to https://github.com/ollama/ollama/blob/main/docs/api.md#list-local-models for more details
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModelsResponse {
+    pub models: Vec<ModelResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModelResponse {
+    pub name: String,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<ModelDetails>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModelDetails {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameter_size: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quantization_level: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -218,16 +406,22 @@ pub struct CompletionsResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub done_reason: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "optional_integer")]
     pub total_duration: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "optional_integer")]
     pub load_duration: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "optional_integer")]
     pub prompt_eval_count: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "optional_integer")]
     pub prompt_eval_duration: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "optional_integer")]
     pub eval_count: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "optional_integer")]
     pub eval_duration: Option<i64>,
 }
 
@@ -269,67 +463,58 @@ pub struct Function {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OllamaRequestError {
-    status_code: i32,
     #[serde(skip_serializing_if = "Option::is_none")]
-    status: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error_message: Option<String>,
+    error: Option<String>,
 }
 
 pub fn handle_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
     let status = response.status();
+    let raw_body = response
+        .text()
+        .map_err(|err| from_reqwest_error("Failed to receive response body", err))?;
+
+    parse_response_body(status, raw_body)
+}
 
+fn parse_response_body<T: DeserializeOwned + Debug>(
+    status: StatusCode,
+    raw_body: String,
+) -> Result<T, Error> {
     match status {
-        StatusCode::OK => {
-            let raw_body = response
-                .text()
-                .map_err(|err| from_reqwest_error("Failed to receive response body", err))?;
-
-            match serde_json::from_str::<T>(&raw_body) {
-                Ok(body) => Ok(body),
-                Err(err) => Err(Error {
-                    code: ErrorCode::InternalError,
-                    message: format!("Failed to parse response body: {err}"),
-                    provider_error_json: Some(raw_body),
-                }),
-            }
-        }
+        StatusCode::OK => parse_response_json("Ollama", raw_body),
         _ => {
-            let raw_error_body = response
-                .text()
-                .map_err(|err| from_reqwest_error("Failed to receive error response body", err))?;
-            trace!("Received {status} response from OpenRouter API: {raw_error_body:?}");
-
-            let error_body: OllamaRequestError =
-                serde_json::from_str(&raw_error_body).map_err(|err| Error {
-                    code: ErrorCode::InternalError,
-                    message: format!("Failed to parse error response body: {err}"),
-                    provider_error_json: Some(raw_error_body),
-                })?;
+            trace!(
+                "Received {status} response from Ollama API: {}",
+                Redacted(&raw_body)
+            );
+
+            let message = serde_json::from_str::<OllamaRequestError>(&raw_body)
+                .ok()
+                .and_then(|error_body| error_body.error)
+                .unwrap_or_else(|| format!("Ollama API error: HTTP {status}"));
 
             Err(Error {
                 code: error_code_from_status(status),
-                message: error_body.status.unwrap_or_default(),
-                provider_error_json: error_body.error_message,
+                message,
+                provider_error_json: Some(raw_body),
             })
         }
     }
 }
 
-pub fn image_to_base64(source: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let bytes = if Url::parse(source).is_ok() {
-        let client = Client::new();
-        let response = client.get(source).send()?;
-
-        response.bytes()?.to_vec()
+/// Fetches an image passed by URL, which for Ollama can also be the path of a local file
+pub fn fetch_image(source: &str) -> Result<(Vec<u8>, Option<String>), Error> {
+    if Url::parse(source).is_ok() {
+        download_image(source)
     } else {
-        let path = Path::new(source);
-
-        fs::read(path)?
-    };
-
-    let base64_data = general_purpose::STANDARD.encode(&bytes);
-    Ok(base64_data)
+        fs::read(Path::new(source))
+            .map(|data| (data, None))
+            .map_err(|err| Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!("Failed to read image {source}: {err}"),
+                provider_error_json: None,
+            })
+    }
 }
 
 pub fn from_reqwest_error(context: &str, err: reqwest::Error) -> Error {
@@ -339,3 +524,74 @@ pub fn from_reqwest_error(context: &str, err: reqwest::Error) -> Error {
         provider_error_json: None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{parse_response_body, ModelsResponse, OllamaApi};
+    use golem_llm::golem::llm::llm::ErrorCode;
+    use reqwest::header::USER_AGENT;
+    use reqwest::{Method, StatusCode};
+
+    #[test]
+    fn endpoints_use_overridden_base_url() {
+        let api = OllamaApi::with_base_url(
+            "llama3.2".to_string(),
+            "http://gpu-box:8080/",
+            "golem-llm".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(api.endpoint("/api/chat"), "http://gpu-box:8080/api/chat");
+        assert_eq!(api.endpoint("/api/embed"), "http://gpu-box:8080/api/embed");
+    }
+
+    #[test]
+    fn malformed_base_url() {
+        for base_url in ["localhost:11434", "ftp://localhost:11434", "not a url"] {
+            let error =
+                OllamaApi::with_base_url("llama3.2".to_string(), base_url, "golem-llm".to_string())
+                    .err()
+                    .unwrap();
+            assert_eq!(error.code, ErrorCode::InvalidRequest);
+            assert!(error.message.contains(base_url));
+        }
+    }
+
+    #[test]
+    fn successful_models_response() {
+        let result = parse_response_body::<ModelsResponse>(
+            StatusCode::OK,
+            r#"{"models":[{"name":"llama3.2:latest","model":"llama3.2:latest"}]}"#.to_string(),
+        );
+
+        assert_eq!(result.unwrap().models.len(), 1);
+    }
+
+    #[test]
+    fn unauthorized_response() {
+        let error = parse_response_body::<ModelsResponse>(
+            StatusCode::UNAUTHORIZED,
+            r#"{"error":"unauthorized"}"#.to_string(),
+        )
+        .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::AuthenticationFailed);
+        assert_eq!(error.message, "unauthorized");
+    }
+
+    #[test]
+    fn requests_carry_the_user_agent() {
+        let api = OllamaApi::with_base_url(
+            "llama3.2".to_string(),
+            "http://localhost:11434",
+            "my-app/1.0".to_string(),
+        )
+        .unwrap();
+
+        let request = api
+            .request(Method::POST, api.endpoint("/api/chat"))
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[USER_AGENT], "my-app/1.0");
+    }
+}
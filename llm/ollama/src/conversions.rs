@@ -1,22 +1,61 @@
 use std::collections::HashMap;
 
 use crate::client::{
-    image_to_base64, CompletionsRequest, CompletionsResponse, FunctionTool, MessageRequest,
-    MessageRole, OllamaModelOptions, Tool,
+    fetch_image, CompletionsRequest, CompletionsResponse, EmbeddingsInput, EmbeddingsRequest,
+    EmbeddingsResponse, FunctionTool, KeepAlive, MessageRequest, MessageRole, ModelsResponse,
+    OllamaModelOptions, Tool,
 };
 use base64::{engine::general_purpose, Engine};
+use golem_llm::capabilities::capabilities_by_prefix;
+use golem_llm::config::{max_tokens, reject_invalid_certs_option, temperature};
+use golem_llm::context::{fit_context, fit_max_tokens, reserve_context_for_tool_results};
+use golem_llm::error::unsupported;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason,
-    ImageReference, Message, ResponseMetadata, Role, ToolCall as golem_llm_ToolCall, ToolResult,
-    Usage,
+    CapabilitySet, ChatEvent, CompleteResponse, Config, ContentPart, EmbeddingResponse, Error,
+    ErrorCode, FinishReason, ImageReference, Message, ModelInfo, ResponseMetadata, Role,
+    ToolCall as golem_llm_ToolCall, ToolResult, Usage,
 };
-use log::trace;
+use golem_llm::image::{image_transport, transport_images};
+use golem_llm::model_alias::resolve_model_alias;
+use golem_llm::request::with_system_prompt;
+use golem_llm::stop_sequence::limit_stop_sequences;
+use golem_llm::structured_output::response_schema;
+use golem_llm::tool_calls::{tool_call_id, tool_image_description};
+use golem_llm::validation::{validate_messages, SystemMessagePlacement};
+
+/// The Ollama models the provider-agnostic `small` and `large` model aliases resolve to
+const MODEL_ALIASES: &[(&str, &str)] = &[("small", "llama3.2"), ("large", "llama3.3")];
+
+/// The temperature Ollama applies to models that do not set one in their Modelfile
+const DEFAULT_TEMPERATURE: f32 = 0.8;
 
 pub fn messages_to_request(
     messages: Vec<Message>,
     config: Config,
     tool_results: Option<Vec<(golem_llm_ToolCall, ToolResult)>>,
 ) -> Result<CompletionsRequest, Error> {
+    let config = resolve_model_alias(config, MODEL_ALIASES);
+    // The tool results are appended after the conversation is fitted, so room is kept for them
+    let config = match &tool_results {
+        Some(tool_results) => reserve_context_for_tool_results(config, tool_results),
+        None => config,
+    };
+    let messages = fit_context(with_system_prompt(&config, messages), &config)?;
+    validate_messages(&messages, SystemMessagePlacement::Anywhere)?;
+
+    // Ollama only accepts inline images, so images passed by URL are always downloaded
+    let messages = transport_images(
+        messages,
+        image_transport(&config)?,
+        false,
+        "Ollama",
+        fetch_image,
+    )?;
+    let max_tokens = fit_max_tokens(max_tokens(&config), &messages, &config);
+    let temperature = temperature(&config, DEFAULT_TEMPERATURE)?;
+    let response_schema = response_schema(&config)?;
+    reject_invalid_certs_option(&config)?;
+    let stop = limit_stop_sequences(&config, None)?;
     let options = config
         .provider_options
         .into_iter()
@@ -46,19 +85,18 @@ pub fn messages_to_request(
                 }
                 ContentPart::Image(reference) => match reference {
                     ImageReference::Url(image_url) => {
-                        let url = &image_url.url;
-                        match image_to_base64(url) {
-                            Ok(image) => attached_image.push(image),
-                            Err(err) => {
-                                trace!("Failed to encode image: {url}\nError: {err}\n");
-                            }
-                        }
+                        return Err(unsupported(format!(
+                            "Image {} was not inlined, Ollama cannot fetch images by URL",
+                            image_url.url
+                        )))
                     }
                     ImageReference::Inline(image_source) => {
                         let base64_data = general_purpose::STANDARD.encode(&image_source.data);
                         attached_image.push(base64_data);
                     }
                 },
+                // Citations are only returned in responses
+                ContentPart::Citation(_) => {}
             }
         }
 
@@ -97,11 +135,12 @@ pub fn messages_to_request(
 
     let ollama_options = OllamaModelOptions {
         min_p: parse_option(&options, "min_p"),
-        temperature: config.temperature,
+        temperature,
         top_p: parse_option(&options, "top_p"),
         top_k: parse_option(&options, "top_k"),
-        num_predict: parse_option(&options, "num_predict"),
-        stop: config.stop_sequences.clone(),
+        num_predict: parse_option(&options, "num_predict")
+            .or(max_tokens.map(|max_tokens| max_tokens as i32)),
+        stop,
         repeat_penalty: parse_option(&options, "repeat_penalty"),
         num_ctx: parse_option(&options, "num_ctx"),
         seed: parse_option(&options, "seed"),
@@ -126,13 +165,80 @@ pub fn messages_to_request(
         model: Some(config.model),
         messages: Some(request_message),
         tools: Some(tools),
-        format: options.get("format").cloned(),
+        // A JSON schema takes the place of the plain `json` format
+        format: match response_schema {
+            Some(response_schema) => Some(response_schema.schema),
+            None => options
+                .get("format")
+                .cloned()
+                .map(serde_json::Value::String),
+        },
         options: Some(ollama_options),
-        keep_alive: options.get("keep_alive").cloned(),
+        keep_alive: options.get("keep_alive").cloned().map(KeepAlive::from),
         stream: Some(false),
     })
 }
 
+/// The model used for embeddings if the configuration does not specify one
+const DEFAULT_EMBEDDING_MODEL: &str = "nomic-embed-text";
+
+/// The number of inputs embedded with a single request if the `embedding_batch_size` option is
+/// not set, keeping the requests to the Ollama server reasonably sized
+pub const DEFAULT_EMBEDDING_BATCH_SIZE: usize = 256;
+
+pub fn embeddings_to_request(inputs: Vec<String>, config: Config) -> EmbeddingsRequest {
+    let options = config
+        .provider_options
+        .into_iter()
+        .map(|kv| (kv.key, kv.value))
+        .collect::<HashMap<_, _>>();
+
+    let model = if config.model.is_empty() {
+        DEFAULT_EMBEDDING_MODEL.to_string()
+    } else {
+        config.model
+    };
+
+    let input = match <[String; 1]>::try_from(inputs) {
+        Ok([input]) => EmbeddingsInput::Single(input),
+        Err(inputs) => EmbeddingsInput::Batch(inputs),
+    };
+
+    EmbeddingsRequest {
+        model,
+        input,
+        truncate: parse_option(&options, "truncate"),
+        options: None,
+        keep_alive: options.get("keep_alive").cloned().map(KeepAlive::from),
+    }
+}
+
+pub fn process_embeddings_response(response: EmbeddingsResponse) -> EmbeddingResponse {
+    let input_tokens = response.prompt_eval_count.map(|c| c as u32);
+
+    EmbeddingResponse {
+        embeddings: response.embeddings,
+        usage: Some(Usage {
+            input_tokens,
+            output_tokens: None,
+            total_tokens: input_tokens,
+        }),
+        model: response.model,
+    }
+}
+
+pub fn process_models_response(response: ModelsResponse) -> Vec<ModelInfo> {
+    response
+        .models
+        .into_iter()
+        .map(|model| ModelInfo {
+            id: model.model,
+            context_length: None,
+            capabilities: None,
+        })
+        .collect()
+}
+
 fn tool_results_to_messages(
     tool_results: Vec<(golem_llm_ToolCall, ToolResult)>,
 ) -> Vec<MessageRequest> {
@@ -144,6 +250,7 @@ fn tool_results_to_messages(
                 format!("[ToolCall Result]: Successed , [ToolCall ID]: {}, [ToolCall Name]: {}, [Result]: {}] ",success.id,success.name,success.result_json )
             },
             ToolResult::Error(error) => format!("[ToolCall Result]: Failed, [ToolCall ID]: {}, [ErrorName]: {}, [ErrorCode]: {}, [Error]: {}",error.id, error.name, error.error_code.unwrap_or_default(), error.error_message),
+            ToolResult::Image(tool_image) => format!("[ToolCall Result]: Successed , [ToolCall ID]: {}, [ToolCall Name]: {}, [Result]: {}] ", tool_image.id, tool_image.name, tool_image_description(&tool_image)),
         };
         messages.push(MessageRequest {
             role: MessageRole::Assistant,
@@ -180,9 +287,10 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
         }
 
         if let Some(ref message_tool_calls) = message.tool_calls {
-            for tool_call in message_tool_calls {
+            // Ollama does not return tool call ids
+            for (index, tool_call) in message_tool_calls.iter().enumerate() {
                 tool_calls.push(golem_llm_ToolCall {
-                    id: format!("ollama-{}", response.created_at.clone()),
+                    id: tool_call_id(None, index as u32),
                     name: tool_call.name.clone().unwrap_or_default(),
                     arguments_json: tool_call.function.as_ref().unwrap().arguments.to_string(),
                 });
@@ -211,6 +319,8 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
             provider_id: Some("ollama".to_string()),
             timestamp: Some(timestamp.clone()),
             provider_metadata_json: Some(get_provider_metadata(&response)),
+            stop_sequence: None,
+            tool_calls: None,
         };
 
         ChatEvent::Message(CompleteResponse {
@@ -244,3 +354,274 @@ pub fn get_provider_metadata(response: &CompletionsResponse) -> String {
         response.eval_count.unwrap_or(0)
     )
 }
+
+/// Most models served by Ollama support tool calling and the `format` option
+const TEXT: CapabilitySet = CapabilitySet {
+    images: false,
+    tools: true,
+    streaming: true,
+    json_mode: true,
+};
+
+const VISION: CapabilitySet = CapabilitySet {
+    images: true,
+    tools: true,
+    streaming: true,
+    json_mode: true,
+};
+
+/// Vision models without tool calling support
+const VISION_WITHOUT_TOOLS: CapabilitySet = CapabilitySet {
+    images: true,
+    tools: false,
+    streaming: true,
+    json_mode: true,
+};
+
+const MODEL_CAPABILITIES: &[(&str, CapabilitySet)] = &[
+    ("bakllava", VISION_WITHOUT_TOOLS),
+    ("gemma3", VISION_WITHOUT_TOOLS),
+    ("llama3.2-vision", VISION_WITHOUT_TOOLS),
+    ("llama4", VISION),
+    ("llava", VISION_WITHOUT_TOOLS),
+    ("minicpm-v", VISION_WITHOUT_TOOLS),
+    ("mistral-small3.1", VISION),
+    ("moondream", VISION_WITHOUT_TOOLS),
+    ("qwen2.5vl", VISION),
+];
+
+pub fn model_capabilities(model: &str) -> CapabilitySet {
+    capabilities_by_prefix(model, MODEL_CAPABILITIES, TEXT)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{EmbeddingsResponse, ModelsResponse};
+    use crate::conversions::{
+        embeddings_to_request, messages_to_request, process_embeddings_response,
+        process_models_response,
+    };
+    use crate::OllamaComponent;
+    use golem_llm::durability::ExtendedGuest;
+    use golem_llm::golem::llm::llm::{
+        Config, ContentPart, Kv, Message, ModelInfo, Role, StreamDelta, Usage,
+    };
+    use serde_json::json;
+
+    fn request_json(
+        max_tokens: Option<u32>,
+        provider_options: Vec<(&str, &str)>,
+    ) -> serde_json::Value {
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("Hello".to_string())],
+        }];
+        let config = Config {
+            model: "llama3.2".to_string(),
+            temperature: None,
+            max_tokens,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: provider_options
+                .into_iter()
+                .map(|(key, value)| Kv {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+            system: None,
+        };
+        serde_json::to_value(messages_to_request(messages, config, None).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn context_size_options() {
+        let request = request_json(
+            None,
+            vec![
+                ("num_ctx", "8192"),
+                ("num_predict", "256"),
+                ("num_keep", "5"),
+                ("top_k", "40"),
+                ("use_mmap", "false"),
+            ],
+        );
+
+        assert_eq!(
+            request["options"],
+            json!({
+                "num_ctx": 8192,
+                "num_predict": 256,
+                "num_keep": 5,
+                "top_k": 40,
+                "use_mmap": false
+            })
+        );
+    }
+
+    #[test]
+    fn max_tokens_as_num_predict() {
+        let request = request_json(Some(100), vec![]);
+        assert_eq!(request["options"], json!({"num_predict": 100}));
+
+        let request = request_json(Some(100), vec![("num_predict", "50")]);
+        assert_eq!(request["options"], json!({"num_predict": 50}));
+    }
+
+    #[test]
+    fn keep_alive_option() {
+        assert_eq!(
+            request_json(None, vec![("keep_alive", "10m")])["keep_alive"],
+            json!("10m")
+        );
+        assert_eq!(
+            request_json(None, vec![("keep_alive", "0")])["keep_alive"],
+            json!(0)
+        );
+        assert_eq!(
+            request_json(None, vec![("keep_alive", "-1")])["keep_alive"],
+            json!(-1)
+        );
+        assert!(request_json(None, vec![]).get("keep_alive").is_none());
+    }
+
+    fn embedding_config(model: &str) -> Config {
+        Config {
+            model: model.to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![],
+            system: None,
+        }
+    }
+
+    #[test]
+    fn single_and_batch_embedding_inputs() {
+        let single = embeddings_to_request(vec!["Hello".to_string()], embedding_config(""));
+        assert_eq!(
+            serde_json::to_value(single).unwrap(),
+            json!({"model": "nomic-embed-text", "input": "Hello"})
+        );
+
+        let batch = embeddings_to_request(
+            vec!["Hello".to_string(), "World".to_string()],
+            embedding_config("all-minilm"),
+        );
+        assert_eq!(
+            serde_json::to_value(batch).unwrap(),
+            json!({"model": "all-minilm", "input": ["Hello", "World"]})
+        );
+    }
+
+    #[test]
+    fn embeddings_response() {
+        let response: EmbeddingsResponse = serde_json::from_value(json!({
+            "model": "nomic-embed-text",
+            "embeddings": [
+                [0.010071029, -0.0017594862, 0.05007221, 0.04692972],
+                [-0.0098027075, 0.06042469, 0.025900453, -0.006364387]
+            ],
+            "total_duration": 14143917,
+            "load_duration": 1019500,
+            "prompt_eval_count": 8
+        }))
+        .unwrap();
+
+        let result = process_embeddings_response(response);
+
+        assert_eq!(result.model, "nomic-embed-text");
+        assert_eq!(result.embeddings.len(), 2);
+        assert!(result.embeddings.iter().all(|vector| vector.len() == 4));
+        assert_eq!(
+            result.usage,
+            Some(Usage {
+                input_tokens: Some(8),
+                output_tokens: None,
+                total_tokens: Some(8),
+            })
+        );
+    }
+
+    #[test]
+    fn models_response() {
+        let response: ModelsResponse = serde_json::from_value(json!({
+            "models": [
+                {
+                    "name": "llama3.2:latest",
+                    "model": "llama3.2:latest",
+                    "modified_at": "2025-05-01T10:00:00.000000+02:00",
+                    "size": 2019393189_i64,
+                    "digest": "a80c4f17acd55265feec403c7aef86be0c25983ab279d83f3bcd3abbcb5b8b72",
+                    "details": {
+                        "format": "gguf",
+                        "family": "llama",
+                        "parameter_size": "3.2B",
+                        "quantization_level": "Q4_K_M"
+                    }
+                },
+                {
+                    "name": "nomic-embed-text:latest",
+                    "model": "nomic-embed-text:latest",
+                    "size": 274302450_i64
+                }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            process_models_response(response),
+            vec![
+                ModelInfo {
+                    id: "llama3.2:latest".to_string(),
+                    context_length: None,
+                    capabilities: None,
+                },
+                ModelInfo {
+                    id: "nomic-embed-text:latest".to_string(),
+                    context_length: None,
+                    capabilities: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn retry_prompt_is_accepted() {
+        let message = |role: Role, text: &str| Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        };
+        let original_messages = vec![
+            message(Role::System, "Answer briefly"),
+            message(Role::User, "What is the weather like?"),
+            message(Role::Assistant, "Which city?"),
+            message(Role::User, "Berlin"),
+        ];
+        let partial_result = vec![StreamDelta {
+            content: Some(vec![ContentPart::Text("It is sunny".to_string())]),
+            tool_calls: None,
+            reasoning: None,
+            choice_index: None,
+        }];
+
+        let messages = OllamaComponent::retry_prompt(&original_messages, &partial_result);
+
+        let config = Config {
+            model: "llama3.2".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![],
+            system: None,
+        };
+        assert!(messages_to_request(messages, config, None).is_ok());
+    }
+}
@@ -1,15 +1,33 @@
 use std::cell::{Ref, RefCell, RefMut};
+use std::time::Duration;
 
 use client::{CompletionsRequest, OllamaApi};
-use conversions::{messages_to_request, process_response};
+use conversions::{
+    embeddings_to_request, messages_to_request, model_capabilities, process_embeddings_response,
+    process_models_response, process_response, DEFAULT_EMBEDDING_BATCH_SIZE,
+};
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{include_raw_response, with_raw_response};
+use golem_llm::structured_output::{response_schema, validate_structured_output, ResponseSchema};
+use golem_llm::summarize::summarize_history;
+use golem_llm::tool_calls::reject_malformed_tool_calls;
 use golem_llm::{
+    cache::{cache_ttl, cached_response, response_cache_key},
     chat_stream::{LlmChatStream, LlmChatStreamState},
-    durability::{DurableLLM, ExtendedGuest},
+    concurrency::acquire_request_slot,
+    config::{reject_invalid_certs_option, stream_replay_file},
+    dry_run::{dry_run_response, is_dry_run},
+    durability::{retry_prompt_with_style, DurableLLM, ExtendedGuest, RetryPromptStyle},
+    embeddings::{embed_in_batches, embedding_batch_size},
+    error::{reject_empty_response, unsupported},
     event_source::EventSource,
     golem::llm::llm::{
-        ChatEvent, ChatStream, Config, ContentPart, Error, FinishReason, Guest, Message,
-        ResponseMetadata, Role, StreamDelta, StreamEvent, ToolCall, ToolResult, Usage,
+        BatchInfo, BatchRequest, BatchResult, CapabilitySet, ChatEvent, ChatStream, Config,
+        ContentPart, EmbeddingResponse, Error, FinishReason, Guest, ImageReference, Message,
+        ModelInfo, ResponseMetadata, StreamDelta, StreamEvent, ToolCall, ToolResult, Usage,
     },
+    tool_calls::tool_call_id,
+    usage::sum_usage,
     LOGGING_STATE,
 };
 use golem_rust::wasm_rpc::Pollable;
@@ -40,6 +58,14 @@ impl OllamaChatStream {
             finished: RefCell::new(false),
         })
     }
+
+    pub fn replayed() -> LlmChatStream<Self> {
+        LlmChatStream::new(OllamaChatStream {
+            stream: RefCell::new(None),
+            failure: None,
+            finished: RefCell::new(false),
+        })
+    }
 }
 
 impl LlmChatStreamState for OllamaChatStream {
@@ -63,7 +89,7 @@ impl LlmChatStreamState for OllamaChatStream {
     }
 
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
-        trace!("Parsing NDJSON line: {raw}");
+        trace!("Parsing NDJSON line: {}", Redacted(&raw));
         let json: serde_json::Value =
             serde_json::from_str(raw.trim()).map_err(|e| format!("JSON parse error: {e}"))?;
 
@@ -119,6 +145,8 @@ impl LlmChatStreamState for OllamaChatStream {
                 provider_id: Some("ollama".to_string()),
                 timestamp,
                 provider_metadata_json: Some(provider_metadata),
+                stop_sequence: None,
+                tool_calls: None,
             })));
         }
 
@@ -133,7 +161,8 @@ impl LlmChatStreamState for OllamaChatStream {
             }
 
             if let Some(calls) = message.get("tool_calls").and_then(|tc| tc.as_array()) {
-                for call in calls {
+                // Ollama does not return tool call ids
+                for (index, call) in calls.iter().enumerate() {
                     if let Some(function) = call.get("function") {
                         let name = function
                             .get("name")
@@ -144,15 +173,8 @@ impl LlmChatStreamState for OllamaChatStream {
                             .get("arguments")
                             .cloned()
                             .unwrap_or(serde_json::json!({}));
-                        let id = format!(
-                            "ollama-{}",
-                            json.get("created_at")
-                                .and_then(|v| v.as_str())
-                                .map(|s| s.to_string())
-                                .unwrap_or_default()
-                        );
                         tool_calls.push(ToolCall {
-                            id,
+                            id: tool_call_id(None, index as u32),
                             name,
                             arguments_json: args_json.to_string(),
                         });
@@ -171,6 +193,12 @@ impl LlmChatStreamState for OllamaChatStream {
                 } else {
                     Some(tool_calls)
                 },
+                reasoning: message
+                    .get("thinking")
+                    .and_then(|thinking| thinking.as_str())
+                    .filter(|thinking| !thinking.is_empty())
+                    .map(str::to_string),
+                choice_index: None,
             })));
         }
         Ok(None)
@@ -180,11 +208,32 @@ impl LlmChatStreamState for OllamaChatStream {
 struct OllamaComponent;
 
 impl OllamaComponent {
-    fn request(client: &OllamaApi, request: CompletionsRequest) -> ChatEvent {
-        match client.send_chat(request) {
-            Ok(response) => process_response(response),
-            Err(err) => ChatEvent::Error(err),
+    fn request(
+        client: &OllamaApi,
+        request: CompletionsRequest,
+        dry_run: bool,
+        include_raw_response: bool,
+        cache_ttl: Option<Duration>,
+        response_schema: Option<ResponseSchema>,
+    ) -> ChatEvent {
+        if dry_run {
+            return dry_run_response("ollama", &request);
         }
+
+        let cache_key = response_cache_key(cache_ttl, &client.cache_scope(), &request);
+        cached_response(cache_key, || {
+            validate_structured_output(response_schema.as_ref(), || {
+                match client.send_chat(request.clone()) {
+                    Ok(response) => with_raw_response(
+                        reject_malformed_tool_calls(reject_empty_response(process_response(
+                            response.body,
+                        ))),
+                        include_raw_response.then_some(response.raw),
+                    ),
+                    Err(err) => ChatEvent::Error(err),
+                }
+            })
+        })
     }
 
     fn streaming_request(
@@ -205,9 +254,20 @@ impl Guest for OllamaComponent {
     fn send(messages: Vec<Message>, config: Config) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
-        let client = OllamaApi::new(config.model.clone());
+        let client = match OllamaApi::new(&config) {
+            Ok(client) => client,
+            Err(err) => return ChatEvent::Error(err),
+        };
+        let _permit = acquire_request_slot(&config, "ollama", "");
         match messages_to_request(messages, config.clone(), None) {
-            Ok(request) => Self::request(&client, request),
+            Ok(request) => Self::request(
+                &client,
+                request,
+                is_dry_run(&config),
+                include_raw_response(&config),
+                cache_ttl(&config),
+                response_schema(&config).ok().flatten(),
+            ),
             Err(err) => ChatEvent::Error(err),
         }
     }
@@ -219,10 +279,21 @@ impl Guest for OllamaComponent {
     ) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
-        let client = OllamaApi::new(config.model.clone());
+        let client = match OllamaApi::new(&config) {
+            Ok(client) => client,
+            Err(err) => return ChatEvent::Error(err),
+        };
+        let _permit = acquire_request_slot(&config, "ollama", "");
 
         match messages_to_request(messages, config.clone(), Some(tool_results)) {
-            Ok(request) => Self::request(&client, request),
+            Ok(request) => Self::request(
+                &client,
+                request,
+                is_dry_run(&config),
+                include_raw_response(&config),
+                cache_ttl(&config),
+                response_schema(&config).ok().flatten(),
+            ),
             Err(err) => ChatEvent::Error(err),
         }
     }
@@ -230,75 +301,115 @@ impl Guest for OllamaComponent {
     fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
         ChatStream::new(Self::unwrapped_stream(messages, config.clone()))
     }
+
+    fn embed(inputs: Vec<String>, config: Config) -> Result<EmbeddingResponse, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        reject_invalid_certs_option(&config)?;
+        let client = OllamaApi::new(&config)?;
+        let batch_size = embedding_batch_size(&config, DEFAULT_EMBEDDING_BATCH_SIZE);
+        embed_in_batches(inputs, batch_size, |batch| {
+            let request = embeddings_to_request(batch, config.clone());
+            client.embed(request).map(process_embeddings_response)
+        })
+    }
+
+    fn generate_image(_prompt: String, _config: Config) -> Result<Vec<ImageReference>, Error> {
+        Err(unsupported(
+            "Image generation is not supported by the Ollama provider",
+        ))
+    }
+
+    fn list_models(config: Config) -> Result<Vec<ModelInfo>, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        let client = OllamaApi::new(&config)?;
+        client.list_models().map(process_models_response)
+    }
+
+    fn validate_credentials(config: Config) -> Result<(), Error> {
+        Self::list_models(config).map(|_| ())
+    }
+
+    fn capabilities(config: Config) -> CapabilitySet {
+        model_capabilities(&config.model)
+    }
+
+    fn batch_submit(_requests: Vec<BatchRequest>, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Ollama provider",
+        ))
+    }
+
+    fn batch_status(_batch_id: String, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Ollama provider",
+        ))
+    }
+
+    fn batch_results(_batch_id: String, _config: Config) -> Result<Vec<BatchResult>, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Ollama provider",
+        ))
+    }
+
+    fn summarize_history(
+        messages: Vec<Message>,
+        config: Config,
+        keep_last_n: u32,
+    ) -> Result<Vec<Message>, Error> {
+        summarize_history(messages, config, keep_last_n, Self::send)
+    }
+
+    fn sum_usage(usages: Vec<Usage>) -> Usage {
+        sum_usage(usages)
+    }
 }
 
 impl ExtendedGuest for OllamaComponent {
     fn unwrapped_stream(messages: Vec<Message>, config: Config) -> LlmChatStream<OllamaChatStream> {
+        Self::unwrapped_continue_stream(messages, Vec::new(), config)
+    }
+
+    fn unwrapped_continue_stream(
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
+    ) -> LlmChatStream<OllamaChatStream> {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
-        let client = OllamaApi::new(config.model.clone());
-        match messages_to_request(messages, config.clone(), None) {
-            Ok(request) => Self::streaming_request(&client, request),
+        if stream_replay_file(&config).is_some() {
+            return OllamaChatStream::replayed().with_stream_options(&config);
+        }
+
+        let client = match OllamaApi::new(&config) {
+            Ok(client) => client,
+            Err(err) => return OllamaChatStream::failed(err),
+        };
+        let permit = acquire_request_slot(&config, "ollama", "");
+        match messages_to_request(messages, config.clone(), Some(tool_results)) {
+            Ok(request) => Self::streaming_request(&client, request)
+                .with_request_permit(permit)
+                .with_stream_options(&config),
             Err(err) => OllamaChatStream::failed(err),
         }
     }
 
     fn retry_prompt(original_messages: &[Message], partial_result: &[StreamDelta]) -> Vec<Message> {
-        let mut extended_messages = Vec::new();
-
-        extended_messages.push(Message {
-            role: Role::System,
-            name: None,
-            content: vec![ContentPart::Text(
-                "You were asked the same question previously, but the response was interrupted before completion. \
-                 Please continue your response from where you left off. \
-                 Do not include the part of the response that was already seen."
-                    .to_string(),
-            )],
-        });
-
-        extended_messages.push(Message {
-            role: Role::User,
-            name: None,
-            content: vec![ContentPart::Text(
-                "Here is the original question:".to_string(),
-            )],
-        });
-
-        extended_messages.extend_from_slice(original_messages);
-
-        let mut partial_result_as_content = Vec::new();
-        for delta in partial_result {
-            if let Some(contents) = &delta.content {
-                partial_result_as_content.extend_from_slice(contents);
-            }
-            if let Some(tool_calls) = &delta.tool_calls {
-                for tool_call in tool_calls {
-                    partial_result_as_content.push(ContentPart::Text(format!(
-                        "<tool-call id=\"{}\" name=\"{}\" arguments=\"{}\"/>",
-                        tool_call.id, tool_call.name, tool_call.arguments_json,
-                    )));
-                }
-            }
-        }
-
-        extended_messages.push(Message {
-            role: Role::User,
-            name: None,
-            content: vec![ContentPart::Text(
-                "Here is the partial response that was successfully received:".to_string(),
-            )]
-            .into_iter()
-            .chain(partial_result_as_content)
-            .collect(),
-        });
-
-        extended_messages
+        retry_prompt_with_style(
+            original_messages,
+            partial_result,
+            RetryPromptStyle::UserMessage,
+        )
     }
 
     fn subscribe(stream: &Self::ChatStream) -> Pollable {
         stream.subscribe()
     }
+
+    fn idle_timer(stream: &Self::ChatStream) -> Option<Pollable> {
+        Some(stream.idle_timer())
+    }
 }
 
 type DurableOllamaComponent = DurableLLM<OllamaComponent>;
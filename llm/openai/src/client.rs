@@ -1,41 +1,137 @@
-use golem_llm::error::{error_code_from_status, from_event_source_error, from_reqwest_error};
+use golem_llm::cache::cache_scope;
+use golem_llm::config::{max_request_bytes, user_agent};
+use golem_llm::error::{error_from_status, from_event_source_error, from_reqwest_error};
 use golem_llm::event_source::EventSource;
-use golem_llm::golem::llm::llm::Error;
+use golem_llm::fixtures::{response_fixtures, with_response_fixtures, ResponseFixtures};
+use golem_llm::golem::llm::llm::{Config, Error};
+use golem_llm::lenient::{integer, optional_integer, parse_response_json};
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{pre_send_body, RawBody, RawResponse};
+use golem_llm::request::check_request_size;
 use log::trace;
-use reqwest::header::HeaderValue;
-use reqwest::{Client, Method, Response};
+use reqwest::header::{HeaderValue, USER_AGENT};
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Response, StatusCode};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
 use std::fmt::Debug;
 
 const BASE_URL: &str = "https://api.openai.com";
 
+/// The largest total payload OpenAI accepts in a chat completion request
+const MAX_REQUEST_BYTES: usize = 50 * 1024 * 1024;
+
 /// The OpenAI Chat Completions API client.
 ///
 /// Based on https://platform.openai.com/docs/api-reference/chat/create
 pub struct CompletionsApi {
     openai_api_key: String,
+    user_agent: String,
+    max_request_bytes: Option<usize>,
+    response_fixtures: Option<ResponseFixtures>,
     client: Client,
 }
 
 impl CompletionsApi {
-    pub fn new(openai_api_key: String) -> Self {
+    pub fn new(openai_api_key: String, config: &Config) -> Self {
         let client = Client::builder()
             .build()
             .expect("Failed to initialize HTTP client");
         Self {
             openai_api_key,
+            user_agent: user_agent(config, "openai"),
+            max_request_bytes: max_request_bytes(config, Some(MAX_REQUEST_BYTES)),
+            response_fixtures: response_fixtures(config),
             client,
         }
     }
 
-    pub fn send_messages(&self, request: CompletionsRequest) -> Result<CompletionsResponse, Error> {
-        trace!("Sending request to OpenAI API: {request:?}");
+    /// Identifies the server and credentials of this client in response cache keys
+    pub fn cache_scope(&self) -> String {
+        cache_scope(BASE_URL, &self.openai_api_key)
+    }
 
-        let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/v1/chat/completions"))
+    /// Starts an authenticated request to the OpenAI API, identified by the configured user agent
+    fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .header(USER_AGENT, &self.user_agent)
             .bearer_auth(&self.openai_api_key)
+    }
+
+    pub fn send_messages(
+        &self,
+        request: CompletionsRequest,
+    ) -> Result<RawResponse<CompletionsResponse>, Error> {
+        trace!("Sending request to OpenAI API: {}", Redacted(&request));
+        let body = pre_send_body(&request.body())?;
+        check_request_size(self.max_request_bytes, &body)?;
+
+        with_response_fixtures(self.response_fixtures.as_ref(), "openai", &body, || {
+            let response: Response = self
+                .request(Method::POST, format!("{BASE_URL}/v1/chat/completions"))
+                .json(&body)
+                .send()
+                .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+            parse_response(response)
+        })
+    }
+
+    pub fn list_models(&self) -> Result<ModelsResponse, Error> {
+        trace!("Listing models from OpenAI API");
+
+        let response: Response = self
+            .request(Method::GET, format!("{BASE_URL}/v1/models"))
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+        parse_response(response)
+    }
+
+    pub fn generate_image(
+        &self,
+        request: ImageGenerationRequest,
+    ) -> Result<ImageGenerationResponse, Error> {
+        trace!(
+            "Sending image generation request to OpenAI API: {}",
+            Redacted(&request)
+        );
+
+        let response: Response = self
+            .request(Method::POST, format!("{BASE_URL}/v1/images/generations"))
+            .json(&request)
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+        parse_response(response)
+    }
+
+    pub fn upload_batch_file(&self, jsonl: String) -> Result<FileObject, Error> {
+        trace!(
+            "Uploading batch input file to OpenAI API: {}",
+            Redacted(&jsonl)
+        );
+
+        let response: Response = self
+            .request(Method::POST, format!("{BASE_URL}/v1/files"))
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={MULTIPART_BOUNDARY}"),
+            )
+            .body(batch_file_multipart_body(&jsonl))
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+        parse_response(response)
+    }
+
+    pub fn create_batch(&self, request: CreateBatchRequest) -> Result<Batch, Error> {
+        trace!("Creating batch in OpenAI API: {}", Redacted(&request));
+
+        let response: Response = self
+            .request(Method::POST, format!("{BASE_URL}/v1/batches"))
             .json(&request)
             .send()
             .map_err(|err| from_reqwest_error("Request failed", err))?;
@@ -43,18 +139,57 @@ impl CompletionsApi {
         parse_response(response)
     }
 
+    pub fn retrieve_batch(&self, batch_id: &str) -> Result<Batch, Error> {
+        trace!("Retrieving batch {batch_id} from OpenAI API");
+
+        let response: Response = self
+            .request(Method::GET, format!("{BASE_URL}/v1/batches/{batch_id}"))
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+        parse_response(response)
+    }
+
+    pub fn file_content(&self, file_id: &str) -> Result<String, Error> {
+        trace!("Downloading file {file_id} from OpenAI API");
+
+        let response: Response = self
+            .request(
+                Method::GET,
+                format!("{BASE_URL}/v1/files/{file_id}/content"),
+            )
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+        let status = response.status();
+        let body = response
+            .text()
+            .map_err(|err| from_reqwest_error("Failed to read response body", err))?;
+
+        if status.is_success() {
+            trace!("File content: {}", Redacted(&body));
+            Ok(body)
+        } else {
+            Err(error_from_status(
+                status,
+                format!("OpenAI API error: HTTP {status}"),
+                Some(body),
+            ))
+        }
+    }
+
     pub fn stream_send_messages(&self, request: CompletionsRequest) -> Result<EventSource, Error> {
-        trace!("Sending request to OpenAI API: {request:?}");
+        trace!("Sending request to OpenAI API: {}", Redacted(&request));
+        let body = pre_send_body(&request.body())?;
+        check_request_size(self.max_request_bytes, &body)?;
 
         let response: Response = self
-            .client
             .request(Method::POST, format!("{BASE_URL}/v1/chat/completions"))
-            .bearer_auth(&self.openai_api_key)
             .header(
                 reqwest::header::ACCEPT,
                 HeaderValue::from_static("text/event-stream"),
             )
-            .json(&request)
+            .json(&body)
             .send()
             .map_err(|err| from_reqwest_error("Request failed", err))?;
 
@@ -70,18 +205,34 @@ pub struct CompletionsRequest {
     pub messages: Vec<Message>,
     pub model: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioOutput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub frequency_penalty: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<HashMap<u32, i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_completion_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub modalities: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub n: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub presence_penalty: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub seed: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub stop: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream_options: Option<StreamOptions>,
@@ -97,6 +248,52 @@ pub struct CompletionsRequest {
     pub top_p: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+    #[serde(skip)]
+    pub raw_body_override: Option<Map<String, Value>>,
+    #[serde(skip)]
+    pub parallel_tool_calls_enforcement: ParallelToolCallsEnforcement,
+}
+
+impl CompletionsRequest {
+    /// The body sent to OpenAI, with the `raw_body_override` provider option merged into it
+    pub fn body(&self) -> RawBody<'_, Self> {
+        RawBody::new(self, self.raw_body_override.as_ref())
+    }
+}
+
+/// What to do when a response has more than one tool call although `parallel_tool_calls` was
+/// disabled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParallelToolCallsEnforcement {
+    /// The tool calls are returned as sent by OpenAI
+    #[default]
+    None,
+    /// The response is replaced with an error
+    Error,
+    /// Only the first tool call is kept
+    First,
+}
+
+/// The structured output mode of the Chat Completions API, used for the `json-schema` response
+/// format
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ResponseFormat {
+    #[serde(rename = "json_schema")]
+    JsonSchema { json_schema: JsonSchema },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonSchema {
+    pub name: String,
+    pub schema: serde_json::Value,
+}
+
+/// The voice and format of the audio output, required when the `audio` modality is requested
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioOutput {
+    pub voice: String,
+    pub format: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -193,6 +390,7 @@ pub enum ToolCall {
     #[serde(rename = "function")]
     Function {
         function: FunctionCall,
+        #[serde(default)]
         id: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         index: Option<u32>,
@@ -208,9 +406,11 @@ pub struct FunctionCall {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompletionsResponse {
     pub choices: Vec<Choice>,
+    #[serde(default, deserialize_with = "integer")]
     pub created: u64,
     pub id: String,
     pub model: String,
+    pub service_tier: Option<String>,
     pub system_fingerprint: Option<String>,
     pub usage: Option<Usage>,
 }
@@ -232,26 +432,63 @@ pub enum FinishReason {
     ToolCalls,
     #[serde(rename = "content_filter")]
     ContentFilter,
+    /// Any reason not known when this was written, kept as reported
+    #[serde(untagged)]
+    Other(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponseMessage {
-    pub content: Option<String>,
+    pub content: Option<Content>,
     pub refusal: Option<String>,
     pub role: String,
     pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<ResponseAudio>,
+}
+
+/// The audio output of a response. Only its transcript is used, the audio data itself cannot be
+/// returned as a content part.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseAudio {
+    pub transcript: String,
+}
+
+/// An annotation of the response text, only web search citations are used
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Annotation {
+    #[serde(rename = "url_citation")]
+    UrlCitation { url_citation: UrlCitation },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UrlCitation {
+    pub url: String,
+    pub title: Option<String>,
+    /// Character offsets of the cited span in the response text
+    pub start_index: Option<usize>,
+    pub end_index: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Usage {
+    #[serde(default, deserialize_with = "integer")]
     pub completion_tokens: u32,
+    #[serde(default, deserialize_with = "integer")]
     pub prompt_tokens: u32,
-    pub total_tokens: u32,
+    #[serde(default, deserialize_with = "optional_integer")]
+    pub total_tokens: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionChunk {
     pub id: String,
+    #[serde(default, deserialize_with = "integer")]
     pub created: u64,
     pub model: String,
     pub choices: Vec<ChoiceChunk>,
@@ -269,36 +506,281 @@ pub struct ChoiceChunk {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChoiceDelta {
     pub content: Option<String>,
-    pub tool_calls: Option<Vec<ToolCall>>,
+    pub tool_calls: Option<Vec<ToolCallChunk>>,
     pub role: Option<String>,
 }
 
+/// A fragment of a streamed tool call. Only the first fragment of a call carries its id, type and
+/// function name, the following ones just the index and the next part of the arguments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallChunk {
+    #[serde(default)]
+    pub index: u32,
+    pub id: Option<String>,
+    pub function: Option<FunctionCallChunk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCallChunk {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelsResponse {
+    pub data: Vec<Model>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub id: String,
+    pub created: Option<u64>,
+    pub owned_by: Option<String>,
+}
+
+/// Based on https://platform.openai.com/docs/api-reference/images/create
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageGenerationRequest {
+    pub model: String,
+    pub prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageGenerationResponse {
+    pub created: u64,
+    pub data: Vec<GeneratedImage>,
+    /// The format of inline images, only reported by the GPT image models
+    pub output_format: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeneratedImage {
+    pub url: Option<String>,
+    pub b64_json: Option<String>,
+    pub revised_prompt: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BatchRequestLine<'a> {
+    pub custom_id: String,
+    pub method: String,
+    pub url: String,
+    pub body: RawBody<'a, CompletionsRequest>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileObject {
+    pub id: String,
+    pub bytes: Option<u64>,
+    pub filename: Option<String>,
+    pub purpose: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateBatchRequest {
+    pub input_file_id: String,
+    pub endpoint: String,
+    pub completion_window: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Batch {
+    pub id: String,
+    pub status: BatchStatus,
+    pub input_file_id: Option<String>,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+    pub created_at: Option<u64>,
+    pub request_counts: Option<RequestCounts>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchStatus {
+    #[serde(rename = "validating")]
+    Validating,
+    #[serde(rename = "failed")]
+    Failed,
+    #[serde(rename = "in_progress")]
+    InProgress,
+    #[serde(rename = "finalizing")]
+    Finalizing,
+    #[serde(rename = "completed")]
+    Completed,
+    #[serde(rename = "expired")]
+    Expired,
+    #[serde(rename = "cancelling")]
+    Cancelling,
+    #[serde(rename = "cancelled")]
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestCounts {
+    pub total: u32,
+    pub completed: u32,
+    pub failed: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResponseLine {
+    pub custom_id: String,
+    pub response: Option<BatchLineResponse>,
+    pub error: Option<BatchLineError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchLineResponse {
+    pub status_code: u16,
+    pub body: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchLineError {
+    pub code: Option<String>,
+    pub message: String,
+}
+
+const MULTIPART_BOUNDARY: &str = "golem-llm-batch-input-boundary";
+
+/// Builds the `multipart/form-data` body of a batch input file upload
+fn batch_file_multipart_body(jsonl: &str) -> String {
+    format!(
+        "--{MULTIPART_BOUNDARY}\r\n\
+         Content-Disposition: form-data; name=\"purpose\"\r\n\r\n\
+         batch\r\n\
+         --{MULTIPART_BOUNDARY}\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"batch.jsonl\"\r\n\
+         Content-Type: application/jsonl\r\n\r\n\
+         {jsonl}\r\n\
+         --{MULTIPART_BOUNDARY}--\r\n"
+    )
+}
+
 fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
-    trace!(
-        "Received response from OpenAI API, status: {}",
-        response.status()
-    );
-
-    if !response.status().is_success() {
-        return Err(Error {
-            code: error_code_from_status(response.status()),
-            message: format!("OpenAI API error: HTTP {}", response.status()),
-            provider_error_json: None,
-        });
-    }
+    let status = response.status();
+    trace!("Received response from OpenAI API, status: {status}");
 
     let body = response
         .text()
         .map_err(|err| from_reqwest_error("Failed to read response body", err))?;
 
-    trace!("Response body: {body}");
+    parse_response_body(status, body)
+}
+
+fn parse_response_body<T: DeserializeOwned + Debug>(
+    status: StatusCode,
+    body: String,
+) -> Result<T, Error> {
+    if !status.is_success() {
+        return Err(error_from_status(
+            status,
+            format!("OpenAI API error: HTTP {status}"),
+            Some(body),
+        ));
+    }
 
-    let result: T = serde_json::from_str(&body).map_err(|err| Error {
-        code: golem_llm::golem::llm::llm::ErrorCode::InternalError,
-        message: format!("Failed to parse OpenAI API response: {err}"),
-        provider_error_json: Some(body),
-    })?;
+    trace!("Response body: {}", Redacted(&body));
 
-    trace!("Parsed response: {result:?}");
+    let result: T = parse_response_json("OpenAI", body)?;
+
+    trace!("Parsed response: {}", Redacted(&result));
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{
+        parse_response_body, CompletionsApi, CompletionsResponse, ModelsResponse, BASE_URL,
+    };
+    use golem_llm::golem::llm::llm::{Config, ErrorCode, Kv};
+    use reqwest::header::USER_AGENT;
+    use reqwest::{Method, StatusCode};
+
+    #[test]
+    fn successful_models_response() {
+        let result = parse_response_body::<ModelsResponse>(
+            StatusCode::OK,
+            r#"{"object":"list","data":[{"id":"gpt-4o","object":"model","created":1715367049,"owned_by":"system"}]}"#.to_string(),
+        );
+
+        assert_eq!(result.unwrap().data.len(), 1);
+    }
+
+    #[test]
+    fn unauthorized_response() {
+        let body = r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error","code":"invalid_api_key"}}"#;
+        let error =
+            parse_response_body::<ModelsResponse>(StatusCode::UNAUTHORIZED, body.to_string())
+                .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::AuthenticationFailed);
+        assert_eq!(error.provider_error_json.as_deref(), Some(body));
+    }
+
+    #[test]
+    fn off_spec_completion_response() {
+        let body = r#"{"id":"chatcmpl-1","created":"1715367049","model":"gpt-4o","choices":[{"finish_reason":"stop","index":0,"message":{"role":"assistant","content":"Hi"}}],"usage":{"prompt_tokens":12.0,"completion_tokens":"3"}}"#;
+        let response =
+            parse_response_body::<CompletionsResponse>(StatusCode::OK, body.to_string()).unwrap();
+        let usage = response.usage.unwrap();
+
+        assert_eq!(response.created, 1715367049);
+        assert_eq!(
+            (
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                usage.total_tokens
+            ),
+            (12, 3, None)
+        );
+
+        let body = r#"{"id":"chatcmpl-1","created":1715367049,"model":"gpt-4o","choices":[{"finish_reason":"stop","index":"first","message":{"role":"assistant","content":"Hi"}}]}"#;
+        let error = parse_response_body::<CompletionsResponse>(StatusCode::OK, body.to_string())
+            .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert!(
+            error.message.contains("at choices[0].index: "),
+            "{}",
+            error.message
+        );
+        assert_eq!(error.provider_error_json.as_deref(), Some(body));
+    }
+
+    #[test]
+    fn requests_carry_the_user_agent() {
+        let config = Config {
+            model: "gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![Kv {
+                key: "user_agent".to_string(),
+                value: "my-app/1.0".to_string(),
+            }],
+            system: None,
+        };
+        let api = CompletionsApi::new("api-key".to_string(), &config);
+
+        let request = api
+            .request(Method::POST, format!("{BASE_URL}/v1/chat/completions"))
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[USER_AGENT], "my-app/1.0");
+    }
+}
@@ -1,37 +1,137 @@
-use crate::client::{CompletionsRequest, CompletionsResponse, Detail, Function, Tool};
+use crate::client::{
+    Annotation, AudioOutput, Batch, BatchRequestLine, BatchResponseLine, BatchStatus,
+    CompletionsRequest, CompletionsResponse, Detail, Function, ImageGenerationRequest,
+    ImageGenerationResponse, JsonSchema, ModelsResponse, ParallelToolCallsEnforcement,
+    ResponseFormat, Tool,
+};
 use base64::{engine::general_purpose, Engine as _};
+use golem_llm::bpe::Bpe;
+use golem_llm::capabilities::capabilities_by_prefix;
+use golem_llm::config::{
+    max_tokens, parallel_tool_calls, reject_invalid_certs_option, temperature,
+};
+use golem_llm::context::{fit_context, fit_max_tokens};
+use golem_llm::error::error_code_from_response;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason, ImageDetail,
-    ImageReference, Message, ResponseMetadata, Role, ToolCall, ToolDefinition, ToolResult, Usage,
+    BatchInfo, BatchRequest, BatchResult, BatchState, CapabilitySet, ChatEvent, Citation,
+    CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason, ImageDetail,
+    ImageReference, ImageSource, ImageUrl, Message, ModelInfo, ResponseMetadata, Role, ToolCall,
+    ToolDefinition, ToolResult, Usage,
 };
+use golem_llm::image::{
+    download_image, image_transport, transport_images, validate_image_limits,
+    validate_image_mime_type, ImageLimits,
+};
+use golem_llm::model_alias::resolve_model_alias;
+use golem_llm::raw_body::raw_body_override;
+use golem_llm::request::with_system_prompt;
+use golem_llm::stop_sequence::limit_stop_sequences;
+use golem_llm::structured_output::response_schema;
+use golem_llm::tool_calls::{tool_call_id, tool_image_description};
+use golem_llm::validation::{validate_messages, SystemMessagePlacement};
+use log::trace;
+use reqwest::StatusCode;
+use serde_json::{json, Map, Value};
 use std::collections::HashMap;
 
+/// The maximum number of stop sequences accepted by the Chat Completions API
+const MAX_STOP_SEQUENCES: usize = 4;
+
+/// The OpenAI models the provider-agnostic `small` and `large` model aliases resolve to
+const MODEL_ALIASES: &[(&str, &str)] = &[("small", "gpt-4o-mini"), ("large", "gpt-4o")];
+
+/// The default temperature of the Chat Completions API
+const DEFAULT_TEMPERATURE: f32 = 1.0;
+
+/// OpenAI accepts up to 500 images per request, each at most 20 MB
+const IMAGE_LIMITS: ImageLimits = ImageLimits {
+    max_count: Some(500),
+    max_bytes: Some(20 * 1024 * 1024),
+};
+
 pub fn create_request(messages: Vec<Message>, config: Config) -> Result<CompletionsRequest, Error> {
+    let config = resolve_model_alias(config, MODEL_ALIASES);
+    let messages = fit_context(with_system_prompt(&config, messages), &config)?;
+    validate_messages(&messages, SystemMessagePlacement::Anywhere)?;
+    let messages = transport_images(
+        messages,
+        image_transport(&config)?,
+        true,
+        "OpenAI",
+        download_image,
+    )?;
+    validate_image_limits(&messages, IMAGE_LIMITS, "OpenAI")?;
+
+    let max_tokens = fit_max_tokens(max_tokens(&config), &messages, &config);
+    let temperature = temperature(&config, DEFAULT_TEMPERATURE)?;
+    let response_format =
+        response_schema(&config)?.map(|response_schema| ResponseFormat::JsonSchema {
+            json_schema: JsonSchema {
+                name: "response".to_string(),
+                schema: response_schema.schema,
+            },
+        });
+    let raw_body_override = raw_body_override(&config)?;
+    reject_invalid_certs_option(&config)?;
+    let stop = limit_stop_sequences(&config, Some(MAX_STOP_SEQUENCES))?;
+    let parallel_tool_calls = parallel_tool_calls(&config)?;
     let options = config
         .provider_options
         .into_iter()
         .map(|kv| (kv.key, kv.value))
         .collect::<HashMap<_, _>>();
 
+    let name_handling = NameHandling::from_options(&options)?;
+    let (modalities, audio) = parse_output_modalities(&options, &config.model)?;
+    let service_tier = options
+        .get("service_tier")
+        .map(|service_tier| parse_service_tier(service_tier))
+        .transpose()?;
+    let metadata = options
+        .get("metadata")
+        .map(|metadata| parse_metadata(metadata))
+        .transpose()?;
+    let logit_bias = options
+        .get("logit_bias")
+        .map(|logit_bias| parse_logit_bias(logit_bias))
+        .transpose()?;
+    let logit_bias = match options.get("banned_phrases") {
+        Some(banned_phrases) => Some(ban_phrases(
+            logit_bias.unwrap_or_default(),
+            banned_phrases,
+            options.get("tokenizer_vocabulary"),
+        )?),
+        None => logit_bias,
+    };
+    let parallel_tool_calls_enforcement = parse_parallel_tool_calls_enforcement(&options)?;
+    if options.contains_key("top_k") {
+        trace!("Ignoring the top_k option, it is not supported by the OpenAI API");
+    }
+    let user = options
+        .get("user_id")
+        .cloned()
+        .or_else(|| derived_user(&messages, &options));
+
     let mut completion_messages = Vec::new();
     for message in messages {
+        let name = convert_name(message.name, name_handling)?;
         match message.role {
             Role::User => completion_messages.push(crate::client::Message::User {
-                name: message.name,
-                content: convert_content_parts(message.content),
+                name,
+                content: convert_content_parts(message.content)?,
             }),
             Role::Assistant => completion_messages.push(crate::client::Message::Assistant {
-                name: message.name,
-                content: Some(convert_content_parts(message.content)),
+                name,
+                content: Some(convert_content_parts(message.content)?),
                 tool_calls: None,
             }),
             Role::System => completion_messages.push(crate::client::Message::System {
-                name: message.name,
-                content: convert_content_parts(message.content),
+                name,
+                content: convert_content_parts(message.content)?,
             }),
             Role::Tool => completion_messages.push(crate::client::Message::Tool {
-                name: message.name,
-                content: convert_content_parts(message.content),
+                name,
+                content: convert_content_parts(message.content)?,
                 tool_call_id: "unknown".to_string(), // This should be set properly in tool_results_to_messages
             }),
         }
@@ -45,21 +145,31 @@ pub fn create_request(messages: Vec<Message>, config: Config) -> Result<Completi
     Ok(CompletionsRequest {
         messages: completion_messages,
         model: config.model,
+        audio,
         frequency_penalty: options
             .get("frequency_penalty")
             .and_then(|fp_s| fp_s.parse::<f32>().ok()),
-        max_completion_tokens: config.max_tokens,
+        logit_bias,
+        max_completion_tokens: max_tokens,
+        modalities,
         n: options.get("n").and_then(|n_s| n_s.parse::<u32>().ok()),
+        parallel_tool_calls,
         presence_penalty: options
             .get("presence_penalty")
             .and_then(|pp_s| pp_s.parse::<f32>().ok()),
+        response_format,
         seed: options
             .get("seed")
             .and_then(|seed_s| seed_s.parse::<u32>().ok()),
-        stop: config.stop_sequences,
+        metadata,
+        service_tier,
+        stop,
+        store: options
+            .get("store")
+            .and_then(|store_s| store_s.parse::<bool>().ok()),
         stream: Some(false),
         stream_options: None,
-        temperature: config.temperature,
+        temperature,
         tool_choice: config.tool_choice,
         tools,
         top_logprobs: options
@@ -68,10 +178,349 @@ pub fn create_request(messages: Vec<Message>, config: Config) -> Result<Completi
         top_p: options
             .get("top_p")
             .and_then(|top_p_s| top_p_s.parse::<f32>().ok()),
-        user: options.get("user_id").cloned(),
+        user,
+        raw_body_override,
+        parallel_tool_calls_enforcement,
     })
 }
 
+/// Reads the `parallel_tool_calls_enforcement` provider option, deciding how responses breaking
+/// `parallel_tool_calls=false` are handled
+fn parse_parallel_tool_calls_enforcement(
+    options: &HashMap<String, String>,
+) -> Result<ParallelToolCallsEnforcement, Error> {
+    match options
+        .get("parallel_tool_calls_enforcement")
+        .map(|value| value.as_str())
+    {
+        None | Some("none") => Ok(ParallelToolCallsEnforcement::None),
+        Some("error") => Ok(ParallelToolCallsEnforcement::Error),
+        Some("first") => Ok(ParallelToolCallsEnforcement::First),
+        Some(other) => Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "Invalid parallel_tool_calls_enforcement option: {other}, expected none, error or first"
+            ),
+            provider_error_json: None,
+        }),
+    }
+}
+
+/// Holds OpenAI to `parallel_tool_calls=false`, which the model does not always respect, by
+/// failing or keeping only the first call when a response has more than one
+pub fn enforce_parallel_tool_calls(event: ChatEvent, request: &CompletionsRequest) -> ChatEvent {
+    if request.parallel_tool_calls != Some(false) {
+        return event;
+    }
+    let tool_call_count = match &event {
+        ChatEvent::Message(response) => response.tool_calls.len(),
+        ChatEvent::ToolRequest(tool_calls) => tool_calls.len(),
+        ChatEvent::Error(_) => 0,
+    };
+    if tool_call_count <= 1 {
+        return event;
+    }
+
+    match request.parallel_tool_calls_enforcement {
+        ParallelToolCallsEnforcement::None => event,
+        ParallelToolCallsEnforcement::Error => ChatEvent::Error(Error {
+            code: ErrorCode::InternalError,
+            message: format!(
+                "OpenAI returned {tool_call_count} tool calls although parallel_tool_calls is disabled"
+            ),
+            provider_error_json: None,
+        }),
+        ParallelToolCallsEnforcement::First => {
+            trace!("Keeping the first of {tool_call_count} tool calls, as parallel_tool_calls is disabled");
+            match event {
+                ChatEvent::Message(mut response) => {
+                    response.tool_calls.truncate(1);
+                    ChatEvent::Message(response)
+                }
+                ChatEvent::ToolRequest(mut tool_calls) => {
+                    tool_calls.truncate(1);
+                    ChatEvent::ToolRequest(tool_calls)
+                }
+                event => event,
+            }
+        }
+    }
+}
+
+/// Checks the `service_tier` provider option, which selects the latency and price tier OpenAI
+/// processes the request with
+fn parse_service_tier(service_tier: &str) -> Result<String, Error> {
+    match service_tier {
+        "auto" | "default" | "flex" => Ok(service_tier.to_string()),
+        other => Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "Invalid service_tier option: {other}, expected auto, default or flex"
+            ),
+            provider_error_json: None,
+        }),
+    }
+}
+
+/// The audio formats OpenAI can generate audio output in
+const AUDIO_FORMATS: &[&str] = &["wav", "mp3", "flac", "opus", "pcm16"];
+
+/// Parses the `modalities` provider option, a comma separated list of the output modalities, and
+/// the `audio_voice` and `audio_format` options configuring the audio output.
+///
+/// OpenAI accepts text alone, or text and audio from its audio models, in which case the voice and
+/// the format of the audio have to be set as well.
+fn parse_output_modalities(
+    options: &HashMap<String, String>,
+    model: &str,
+) -> Result<(Option<Vec<String>>, Option<AudioOutput>), Error> {
+    let invalid = |message: String| Error {
+        code: ErrorCode::InvalidRequest,
+        message,
+        provider_error_json: None,
+    };
+
+    let modalities = options
+        .get("modalities")
+        .map(|modalities| {
+            let mut parsed = modalities
+                .split(',')
+                .map(|modality| modality.trim())
+                .collect::<Vec<_>>();
+            parsed.sort();
+            parsed.dedup();
+            match parsed.as_slice() {
+                ["text"] => Ok(vec!["text".to_string()]),
+                ["audio", "text"] => Ok(vec!["text".to_string(), "audio".to_string()]),
+                _ => Err(invalid(format!(
+                    "Invalid modalities option: {modalities}, expected text or text,audio"
+                ))),
+            }
+        })
+        .transpose()?;
+    let audio_requested = modalities
+        .as_ref()
+        .is_some_and(|modalities| modalities.iter().any(|modality| modality == "audio"));
+
+    let audio = match (options.get("audio_voice"), options.get("audio_format")) {
+        (None, None) if !audio_requested => None,
+        _ if !audio_requested => {
+            return Err(invalid(
+                "The audio_voice and audio_format options require the audio modality".to_string(),
+            ))
+        }
+        (Some(voice), Some(format)) => {
+            if !AUDIO_FORMATS.contains(&format.as_str()) {
+                return Err(invalid(format!(
+                    "Invalid audio_format option: {format}, expected {}",
+                    AUDIO_FORMATS.join(", ")
+                )));
+            }
+            Some(AudioOutput {
+                voice: voice.clone(),
+                format: format.clone(),
+            })
+        }
+        _ => {
+            return Err(invalid(
+                "The audio modality requires the audio_voice and audio_format options".to_string(),
+            ))
+        }
+    };
+    if audio.is_some() && !model.contains("audio") {
+        return Err(Error {
+            code: ErrorCode::Unsupported,
+            message: format!("Model {model} does not support audio output"),
+            provider_error_json: None,
+        });
+    }
+
+    Ok((modalities, audio))
+}
+
+/// The limits OpenAI puts on the `metadata` attached to a stored request
+const MAX_METADATA_PAIRS: usize = 16;
+const MAX_METADATA_KEY_LENGTH: usize = 64;
+const MAX_METADATA_VALUE_LENGTH: usize = 512;
+
+/// Parses the `metadata` provider option, a JSON object of strings, and checks it against OpenAI's
+/// limits so an invalid one fails before the request is sent
+fn parse_metadata(metadata: &str) -> Result<HashMap<String, String>, Error> {
+    let invalid = |message: String| Error {
+        code: ErrorCode::InvalidRequest,
+        message,
+        provider_error_json: None,
+    };
+
+    let metadata: HashMap<String, String> = serde_json::from_str(metadata).map_err(|err| {
+        invalid(format!(
+            "Invalid metadata option, expected a JSON object of strings: {err}"
+        ))
+    })?;
+
+    if metadata.len() > MAX_METADATA_PAIRS {
+        return Err(invalid(format!(
+            "OpenAI accepts at most {MAX_METADATA_PAIRS} metadata pairs, got {}",
+            metadata.len()
+        )));
+    }
+    for (key, value) in &metadata {
+        if key.chars().count() > MAX_METADATA_KEY_LENGTH {
+            return Err(invalid(format!(
+                "Metadata key '{key}' is longer than {MAX_METADATA_KEY_LENGTH} characters"
+            )));
+        }
+        if value.chars().count() > MAX_METADATA_VALUE_LENGTH {
+            return Err(invalid(format!(
+                "Value of metadata key '{key}' is longer than {MAX_METADATA_VALUE_LENGTH} characters"
+            )));
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Parses the `logit_bias` option, a JSON object mapping token ids to a bias between -100, banning
+/// the token, and 100, making it the only choice
+fn parse_logit_bias(logit_bias: &str) -> Result<HashMap<u32, i32>, Error> {
+    let invalid = |message: String| Error {
+        code: ErrorCode::InvalidRequest,
+        message,
+        provider_error_json: None,
+    };
+
+    let logit_bias: HashMap<u32, i32> = serde_json::from_str(logit_bias).map_err(|err| {
+        invalid(format!(
+            "Invalid logit_bias option, expected a JSON object mapping token ids to integers: {err}"
+        ))
+    })?;
+    if let Some((token, bias)) = logit_bias
+        .iter()
+        .find(|(_, bias)| !(-100..=100).contains(*bias))
+    {
+        return Err(invalid(format!(
+            "Invalid logit_bias of token {token}: {bias}, expected a value between -100 and 100"
+        )));
+    }
+
+    Ok(logit_bias)
+}
+
+/// Adds a bias of -100 for every token of the phrases in the `banned_phrases` option, a JSON array
+/// of strings, to the given logit bias. The phrases are encoded with the tiktoken vocabulary at the
+/// path in the `tokenizer_vocabulary` option, both as is and with a leading space, as words are
+/// usually preceded by one. Banning every token of a multi-token phrase is an approximation, as it
+/// also bans those tokens outside the phrase. Biases given in `logit_bias` take precedence.
+fn ban_phrases(
+    mut logit_bias: HashMap<u32, i32>,
+    banned_phrases: &str,
+    tokenizer_vocabulary: Option<&String>,
+) -> Result<HashMap<u32, i32>, Error> {
+    let invalid = |message: String| Error {
+        code: ErrorCode::InvalidRequest,
+        message,
+        provider_error_json: None,
+    };
+
+    let banned_phrases: Vec<String> = serde_json::from_str(banned_phrases).map_err(|err| {
+        invalid(format!(
+            "Invalid banned_phrases option, expected a JSON array of strings: {err}"
+        ))
+    })?;
+    let tokenizer_vocabulary = tokenizer_vocabulary.ok_or_else(|| {
+        invalid("The banned_phrases option requires the tokenizer_vocabulary option, the path of the tiktoken vocabulary of the model".to_string())
+    })?;
+    let vocabulary = std::fs::read_to_string(tokenizer_vocabulary).map_err(|err| {
+        invalid(format!(
+            "Failed to read the tokenizer vocabulary {tokenizer_vocabulary}: {err}"
+        ))
+    })?;
+    let bpe = Bpe::from_tiktoken(&vocabulary).map_err(|err| {
+        invalid(format!(
+            "Failed to parse the tokenizer vocabulary {tokenizer_vocabulary}: {err}"
+        ))
+    })?;
+
+    for phrase in banned_phrases.iter().filter(|phrase| !phrase.is_empty()) {
+        let tokens = bpe
+            .encode(phrase)
+            .into_iter()
+            .chain(bpe.encode(&format!(" {}", phrase.trim_start())));
+        for token in tokens {
+            logit_bias.entry(token).or_insert(-100);
+        }
+    }
+    Ok(logit_bias)
+}
+
+/// How message names not matching `^[a-zA-Z0-9_-]+$`, the only names accepted by OpenAI, are
+/// handled. Set by the `name_handling` provider option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NameHandling {
+    /// Invalid characters are replaced with underscores
+    Sanitize,
+    /// The request fails with an `invalid-request` error
+    Reject,
+}
+
+impl NameHandling {
+    fn from_options(options: &HashMap<String, String>) -> Result<Self, Error> {
+        match options.get("name_handling").map(|value| value.as_str()) {
+            None | Some("sanitize") => Ok(NameHandling::Sanitize),
+            Some("reject") => Ok(NameHandling::Reject),
+            Some(other) => Err(Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!(
+                    "Invalid name_handling option: {other}, expected sanitize or reject"
+                ),
+                provider_error_json: None,
+            }),
+        }
+    }
+}
+
+/// Uses the name of the first user message as the end-user identifier for OpenAI's abuse
+/// monitoring, if enabled by the `derive_user_from_name` option
+fn derived_user(messages: &[Message], options: &HashMap<String, String>) -> Option<String> {
+    if options
+        .get("derive_user_from_name")
+        .map(|value| value.as_str())
+        != Some("true")
+    {
+        return None;
+    }
+    messages
+        .iter()
+        .find(|message| message.role == Role::User)
+        .and_then(|message| message.name.clone())
+}
+
+fn is_valid_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+fn convert_name(name: Option<String>, handling: NameHandling) -> Result<Option<String>, Error> {
+    match name {
+        Some(name) if name.is_empty() || !name.chars().all(is_valid_name_char) => match handling {
+            NameHandling::Sanitize => {
+                let sanitized = name
+                    .chars()
+                    .map(|c| if is_valid_name_char(c) { c } else { '_' })
+                    .collect::<String>();
+                Ok(Some(sanitized).filter(|name| !name.is_empty()))
+            }
+            NameHandling::Reject => Err(Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!(
+                    "Invalid message name {name:?}, OpenAI only accepts names matching ^[a-zA-Z0-9_-]+$"
+                ),
+                provider_error_json: None,
+            }),
+        },
+        name => Ok(name),
+    }
+}
+
 pub fn tool_results_to_messages(
     tool_results: Vec<(ToolCall, ToolResult)>,
 ) -> Vec<crate::client::Message> {
@@ -89,6 +538,9 @@ pub fn tool_results_to_messages(
                 index: None,
             }]),
         });
+        // OpenAI only accepts text in tool messages, so an image returned by a tool is described
+        // there and sent to the model in a user message following it
+        let mut image = None;
         let content = match tool_result {
             ToolResult::Success(success) => crate::client::ContentPart::TextInput {
                 text: success.result_json,
@@ -96,16 +548,71 @@ pub fn tool_results_to_messages(
             ToolResult::Error(failure) => crate::client::ContentPart::TextInput {
                 text: failure.error_message,
             },
+            ToolResult::Image(tool_image) => {
+                let text = tool_image_description(&tool_image);
+                match convert_content_parts(vec![ContentPart::Image(tool_image.image)]) {
+                    Ok(content) => image = Some(content),
+                    Err(err) => trace!("Sending tool image as text: {}", err.message),
+                }
+                crate::client::ContentPart::TextInput { text }
+            }
         };
         messages.push(crate::client::Message::Tool {
             name: None,
             content: crate::client::Content::List(vec![content]),
             tool_call_id: tool_call.id,
         });
+        if let Some(content) = image {
+            messages.push(crate::client::Message::User {
+                name: None,
+                content,
+            });
+        }
     }
     messages
 }
 
+/// Collects the execution times of the successful tool results, keyed by the tool call ids
+pub fn tool_execution_times(tool_results: &[(ToolCall, ToolResult)]) -> Vec<(String, u32)> {
+    tool_results
+        .iter()
+        .filter_map(|(tool_call, tool_result)| match tool_result {
+            ToolResult::Success(success) => success
+                .execution_time_ms
+                .map(|execution_time_ms| (tool_call.id.clone(), execution_time_ms)),
+            ToolResult::Image(tool_image) => tool_image
+                .execution_time_ms
+                .map(|execution_time_ms| (tool_call.id.clone(), execution_time_ms)),
+            ToolResult::Error(_) => None,
+        })
+        .collect()
+}
+
+/// Adds the execution times of the tools to the response's provider metadata, as OpenAI has no
+/// field to send them with the tool results
+pub fn add_tool_execution_times(
+    metadata: &mut ResponseMetadata,
+    execution_times: &[(String, u32)],
+) {
+    if execution_times.is_empty() {
+        return;
+    }
+
+    let mut provider_metadata = metadata
+        .provider_metadata_json
+        .as_deref()
+        .and_then(|json| serde_json::from_str::<Map<String, Value>>(json).ok())
+        .unwrap_or_default();
+
+    let times = execution_times
+        .iter()
+        .map(|(id, execution_time_ms)| (id.clone(), json!(execution_time_ms)))
+        .collect::<Map<String, Value>>();
+    provider_metadata.insert("tool_execution_times_ms".to_string(), Value::Object(times));
+
+    metadata.provider_metadata_json = Some(Value::Object(provider_metadata).to_string());
+}
+
 fn tool_definition_to_tool(tool: ToolDefinition) -> Result<Tool, Error> {
     match serde_json::from_str(&tool.parameters_schema) {
         Ok(value) => Ok(Tool::Function {
@@ -130,12 +637,43 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
         let mut tool_calls = Vec::new();
 
         if let Some(content) = &choice.message.content {
-            contents.push(ContentPart::Text(content.clone()));
+            contents.extend(convert_response_content(content));
+            let text = contents
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text(text) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<String>();
+            contents.extend(convert_annotations(&choice.message.annotations, &text));
+        } else if let Some(audio) = &choice.message.audio {
+            contents.push(ContentPart::Text(audio.transcript.clone()));
+        }
+
+        // A refusal comes without an answer, though the content may be set to an empty string
+        if let Some(refusal) = &choice.message.refusal {
+            let blank = contents
+                .iter()
+                .all(|part| matches!(part, ContentPart::Text(text) if text.trim().is_empty()));
+            if blank {
+                return ChatEvent::Error(Error {
+                    code: ErrorCode::ContentFilter,
+                    message: refusal.clone(),
+                    provider_error_json: None,
+                });
+            }
         }
 
         let empty = Vec::new();
-        for tool_call in choice.message.tool_calls.as_ref().unwrap_or(&empty) {
-            tool_calls.push(convert_tool_call(tool_call));
+        for (position, tool_call) in choice
+            .message
+            .tool_calls
+            .as_ref()
+            .unwrap_or(&empty)
+            .iter()
+            .enumerate()
+        {
+            tool_calls.push(convert_tool_call(tool_call, position));
         }
 
         if contents.is_empty() && !tool_calls.is_empty() {
@@ -146,7 +684,12 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
                 usage: response.usage.as_ref().map(convert_usage),
                 provider_id: Some(response.id.clone()),
                 timestamp: Some(response.created.to_string()),
-                provider_metadata_json: None,
+                provider_metadata_json: response
+                    .service_tier
+                    .as_ref()
+                    .map(|service_tier| json!({ "service_tier": service_tier }).to_string()),
+                stop_sequence: None,
+                tool_calls: None,
             };
 
             ChatEvent::Message(CompleteResponse {
@@ -165,17 +708,61 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
     }
 }
 
-pub fn convert_tool_call(tool_call: &crate::client::ToolCall) -> ToolCall {
+fn convert_response_content(content: &crate::client::Content) -> Vec<ContentPart> {
+    match content {
+        crate::client::Content::TextInput(text) => vec![ContentPart::Text(text.clone())],
+        crate::client::Content::List(parts) => parts
+            .iter()
+            .map(|part| match part {
+                crate::client::ContentPart::TextInput { text } => ContentPart::Text(text.clone()),
+                crate::client::ContentPart::ImageInput { image_url } => {
+                    ContentPart::Image(ImageReference::Url(ImageUrl {
+                        url: image_url.url.clone(),
+                        detail: image_url.detail.clone().map(|d| d.into()),
+                    }))
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Converts the web search citations of a response, quoting the span of the response text each of
+/// them refers to
+fn convert_annotations(annotations: &[Annotation], text: &str) -> Vec<ContentPart> {
+    annotations
+        .iter()
+        .filter_map(|annotation| match annotation {
+            Annotation::UrlCitation { url_citation } => Some(ContentPart::Citation(Citation {
+                title: url_citation.title.clone(),
+                url: url_citation.url.clone(),
+                quoted_text: url_citation
+                    .start_index
+                    .zip(url_citation.end_index)
+                    .filter(|(start, end)| start < end)
+                    .map(|(start, end)| text.chars().skip(start).take(end - start).collect()),
+            })),
+            Annotation::Other => None,
+        })
+        .collect()
+}
+
+/// Converts a tool call of the response, synthesizing its id from its index, or from its
+/// `position` in the response if that is missing too, when the provider did not return one
+pub fn convert_tool_call(tool_call: &crate::client::ToolCall, position: usize) -> ToolCall {
     match tool_call {
-        crate::client::ToolCall::Function { function, id, .. } => ToolCall {
-            id: id.clone(),
+        crate::client::ToolCall::Function {
+            function,
+            id,
+            index,
+        } => ToolCall {
+            id: tool_call_id(Some(id), index.unwrap_or(position as u32)),
             name: function.name.clone(),
             arguments_json: function.arguments.clone(),
         },
     }
 }
 
-fn convert_content_parts(contents: Vec<ContentPart>) -> crate::client::Content {
+fn convert_content_parts(contents: Vec<ContentPart>) -> Result<crate::client::Content, Error> {
     let mut result = Vec::new();
     for content in contents {
         match content {
@@ -185,24 +772,26 @@ fn convert_content_parts(contents: Vec<ContentPart>) -> crate::client::Content {
                     result.push(crate::client::ContentPart::ImageInput {
                         image_url: crate::client::ImageUrl {
                             url: image_url.url,
-                            detail: image_url.detail.map(|d| d.into()),
+                            detail: Some(image_url.detail.unwrap_or(ImageDetail::Auto).into()),
                         },
                     })
                 }
                 ImageReference::Inline(image_source) => {
+                    validate_image_mime_type(&image_source.mime_type)?;
                     let base64_data = general_purpose::STANDARD.encode(&image_source.data);
                     let media_type = &image_source.mime_type;
                     result.push(crate::client::ContentPart::ImageInput {
                         image_url: crate::client::ImageUrl {
                             url: format!("data:{media_type};base64,{base64_data}"),
-                            detail: image_source.detail.map(|d| d.into()),
+                            detail: Some(image_source.detail.unwrap_or(ImageDetail::Auto).into()),
                         },
                     });
                 }
             },
+            ContentPart::Citation(_) => {}
         }
     }
-    crate::client::Content::List(result)
+    Ok(crate::client::Content::List(result))
 }
 
 impl From<ImageDetail> for Detail {
@@ -215,19 +804,1724 @@ impl From<ImageDetail> for Detail {
     }
 }
 
+impl From<Detail> for ImageDetail {
+    fn from(value: Detail) -> Self {
+        match value {
+            Detail::Auto => Self::Auto,
+            Detail::Low => Self::Low,
+            Detail::High => Self::High,
+        }
+    }
+}
+
 pub fn convert_finish_reason(value: &crate::client::FinishReason) -> FinishReason {
     match value {
         crate::client::FinishReason::Stop => FinishReason::Stop,
         crate::client::FinishReason::Length => FinishReason::Length,
         crate::client::FinishReason::ToolCalls => FinishReason::ToolCalls,
         crate::client::FinishReason::ContentFilter => FinishReason::ContentFilter,
+        crate::client::FinishReason::Other(value) => FinishReason::Other(value.clone()),
     }
 }
 
+/// Converts the token usage of a response. Responses without a total, like those of some
+/// OpenAI-compatible servers, get the sum of the prompt and completion tokens instead.
 pub fn convert_usage(value: &crate::client::Usage) -> Usage {
     Usage {
         input_tokens: Some(value.prompt_tokens),
         output_tokens: Some(value.completion_tokens),
-        total_tokens: Some(value.total_tokens),
+        total_tokens: value
+            .total_tokens
+            .or_else(|| value.prompt_tokens.checked_add(value.completion_tokens)),
+    }
+}
+
+pub fn process_models_response(response: ModelsResponse) -> Vec<ModelInfo> {
+    response
+        .data
+        .into_iter()
+        .map(|model| ModelInfo {
+            id: model.id,
+            context_length: None,
+            capabilities: None,
+        })
+        .collect()
+}
+
+/// The OpenAI model images are generated with if the configuration does not set one
+const DEFAULT_IMAGE_MODEL: &str = "dall-e-3";
+
+/// Creates an image generation request from the prompt and the `size`, `quality`, `style`,
+/// `image_response_format` and `n` provider options. `size` and `quality` are passed as they are,
+/// as their accepted values differ between the image models.
+pub fn create_image_request(
+    prompt: String,
+    config: Config,
+) -> Result<ImageGenerationRequest, Error> {
+    let invalid = |message: String| Error {
+        code: ErrorCode::InvalidRequest,
+        message,
+        provider_error_json: None,
+    };
+    let options = config
+        .provider_options
+        .into_iter()
+        .map(|kv| (kv.key, kv.value))
+        .collect::<HashMap<_, _>>();
+
+    let style = options
+        .get("style")
+        .map(|style| match style.as_str() {
+            "vivid" | "natural" => Ok(style.clone()),
+            other => Err(invalid(format!(
+                "Invalid style option: {other}, expected vivid or natural"
+            ))),
+        })
+        .transpose()?;
+    let response_format = options
+        .get("image_response_format")
+        .map(|response_format| match response_format.as_str() {
+            "url" => Ok("url".to_string()),
+            "inline" => Ok("b64_json".to_string()),
+            other => Err(invalid(format!(
+                "Invalid image_response_format option: {other}, expected url or inline"
+            ))),
+        })
+        .transpose()?;
+
+    Ok(ImageGenerationRequest {
+        model: if config.model.is_empty() {
+            DEFAULT_IMAGE_MODEL.to_string()
+        } else {
+            config.model
+        },
+        prompt,
+        n: options.get("n").and_then(|n_s| n_s.parse::<u32>().ok()),
+        quality: options.get("quality").cloned(),
+        response_format,
+        size: options.get("size").cloned(),
+        style,
+        user: options.get("user_id").cloned(),
+    })
+}
+
+/// Converts the generated images to image references, by URL or inline depending on how OpenAI
+/// returned them
+pub fn process_image_response(
+    response: ImageGenerationResponse,
+) -> Result<Vec<ImageReference>, Error> {
+    let mime_type = format!(
+        "image/{}",
+        response.output_format.as_deref().unwrap_or("png")
+    );
+
+    let mut images = Vec::new();
+    for image in response.data {
+        match (image.url, image.b64_json) {
+            (Some(url), _) => images.push(ImageReference::Url(ImageUrl { url, detail: None })),
+            (None, Some(b64_json)) => {
+                let data = general_purpose::STANDARD
+                    .decode(&b64_json)
+                    .map_err(|err| Error {
+                        code: ErrorCode::InternalError,
+                        message: format!("Failed to decode generated image: {err}"),
+                        provider_error_json: None,
+                    })?;
+                images.push(ImageReference::Inline(ImageSource {
+                    data,
+                    mime_type: mime_type.clone(),
+                    detail: None,
+                }));
+            }
+            (None, None) => {
+                return Err(Error {
+                    code: ErrorCode::InternalError,
+                    message: "Generated image has neither a URL nor inline data".to_string(),
+                    provider_error_json: None,
+                })
+            }
+        }
+    }
+    Ok(images)
+}
+
+/// OpenAI rejects prompts violating its content policy as invalid requests, with this error code
+const CONTENT_POLICY_VIOLATION: &str = "content_policy_violation";
+
+/// Reports image generation requests rejected by the content policy as `content-filter` errors
+pub fn image_generation_error(error: Error) -> Error {
+    let violates_policy = error
+        .provider_error_json
+        .as_deref()
+        .and_then(|body| serde_json::from_str::<Value>(body).ok())
+        .is_some_and(|body| body["error"]["code"] == CONTENT_POLICY_VIOLATION);
+    if violates_policy {
+        Error {
+            code: ErrorCode::ContentFilter,
+            ..error
+        }
+    } else {
+        error
+    }
+}
+
+/// Serializes the chat requests of a batch to the JSONL format of OpenAI's batch input files
+pub fn create_batch_input(requests: Vec<BatchRequest>, config: Config) -> Result<String, Error> {
+    let mut lines = Vec::new();
+    for request in requests {
+        let body = create_request(request.messages, config.clone())?;
+        let line = BatchRequestLine {
+            custom_id: request.custom_id,
+            method: "POST".to_string(),
+            url: "/v1/chat/completions".to_string(),
+            body: body.body(),
+        };
+        lines.push(serde_json::to_string(&line).map_err(|err| Error {
+            code: ErrorCode::InternalError,
+            message: format!("Failed to serialize batch request: {err}"),
+            provider_error_json: None,
+        })?);
+    }
+    Ok(lines.join("\n"))
+}
+
+pub fn process_batch(batch: Batch) -> BatchInfo {
+    BatchInfo {
+        id: batch.id,
+        state: convert_batch_status(batch.status),
+        total_requests: batch.request_counts.as_ref().map(|counts| counts.total),
+        completed_requests: batch.request_counts.as_ref().map(|counts| counts.completed),
+        failed_requests: batch.request_counts.as_ref().map(|counts| counts.failed),
+    }
+}
+
+fn convert_batch_status(status: BatchStatus) -> BatchState {
+    match status {
+        BatchStatus::Validating => BatchState::Validating,
+        BatchStatus::Failed => BatchState::Failed,
+        BatchStatus::InProgress => BatchState::InProgress,
+        BatchStatus::Finalizing => BatchState::Finalizing,
+        BatchStatus::Completed => BatchState::Completed,
+        BatchStatus::Expired => BatchState::Expired,
+        BatchStatus::Cancelling => BatchState::Cancelling,
+        BatchStatus::Cancelled => BatchState::Cancelled,
+    }
+}
+
+/// Parses the JSONL content of a batch output or error file
+pub fn process_batch_output(content: &str) -> Result<Vec<BatchResult>, Error> {
+    let mut results = Vec::new();
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let response_line: BatchResponseLine = serde_json::from_str(line).map_err(|err| Error {
+            code: ErrorCode::InternalError,
+            message: format!("Failed to parse batch output line: {err}"),
+            provider_error_json: Some(line.to_string()),
+        })?;
+        results.push(BatchResult {
+            custom_id: response_line.custom_id.clone(),
+            event: batch_response_line_to_event(response_line),
+        });
+    }
+    Ok(results)
+}
+
+fn batch_response_line_to_event(line: BatchResponseLine) -> ChatEvent {
+    if let Some(error) = line.error {
+        return ChatEvent::Error(Error {
+            code: ErrorCode::InternalError,
+            message: error.message.clone(),
+            provider_error_json: serde_json::to_string(&error).ok(),
+        });
+    }
+
+    match line.response {
+        Some(response) => {
+            let status = StatusCode::from_u16(response.status_code)
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            if status.is_success() {
+                match serde_json::from_value::<CompletionsResponse>(response.body.clone()) {
+                    Ok(completion) => process_response(completion),
+                    Err(err) => ChatEvent::Error(Error {
+                        code: ErrorCode::InternalError,
+                        message: format!("Failed to parse batch response: {err}"),
+                        provider_error_json: Some(response.body.to_string()),
+                    }),
+                }
+            } else {
+                let body = response.body.to_string();
+                ChatEvent::Error(Error {
+                    code: error_code_from_response(status, Some(&body)),
+                    message: format!("OpenAI API error: HTTP {status}"),
+                    provider_error_json: Some(body),
+                })
+            }
+        }
+        None => ChatEvent::Error(Error {
+            code: ErrorCode::InternalError,
+            message: "Batch output line has neither a response nor an error".to_string(),
+            provider_error_json: None,
+        }),
+    }
+}
+
+const TEXT: CapabilitySet = CapabilitySet {
+    images: false,
+    tools: true,
+    streaming: true,
+    json_mode: true,
+};
+
+const VISION: CapabilitySet = CapabilitySet {
+    images: true,
+    tools: true,
+    streaming: true,
+    json_mode: true,
+};
+
+/// The first reasoning models accept neither images nor tools
+const EARLY_REASONING: CapabilitySet = CapabilitySet {
+    images: false,
+    tools: false,
+    streaming: true,
+    json_mode: false,
+};
+
+const MODEL_CAPABILITIES: &[(&str, CapabilitySet)] = &[
+    ("gpt-4-turbo", VISION),
+    ("gpt-4o", VISION),
+    ("gpt-4.1", VISION),
+    ("gpt-4.5", VISION),
+    ("o1", VISION),
+    ("o1-mini", EARLY_REASONING),
+    ("o1-preview", EARLY_REASONING),
+    ("o3", VISION),
+    ("o3-mini", TEXT),
+    ("o4-mini", VISION),
+];
+
+pub fn model_capabilities(model: &str) -> CapabilitySet {
+    capabilities_by_prefix(model, MODEL_CAPABILITIES, TEXT)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ModelsResponse;
+    use crate::client::{CompletionsApi, CompletionsResponse, ImageGenerationResponse};
+    use crate::conversions::{
+        add_tool_execution_times, convert_usage, create_batch_input, create_request,
+        enforce_parallel_tool_calls, image_generation_error, process_batch_output,
+        process_image_response, process_response, tool_execution_times, tool_results_to_messages,
+    };
+    use crate::conversions::{model_capabilities, process_models_response};
+    use crate::OpenAIComponent;
+    use base64::{engine::general_purpose, Engine as _};
+    use golem_llm::canonical::canonical_json;
+    use golem_llm::dry_run::{dry_run_response, is_dry_run};
+    use golem_llm::durability::ExtendedGuest;
+    use golem_llm::error::reject_empty_response;
+    use golem_llm::fixtures::{with_response_fixtures, ResponseFixtures};
+    use golem_llm::golem::llm::llm::{
+        BatchRequest, ChatEvent, Citation, Config, ContentPart, Error, ErrorCode, FinishReason,
+        ImageDetail, ImageReference, ImageSource, ImageUrl, Kv, Message, ResponseMetadata, Role,
+        StreamDelta, ToolCall, ToolDefinition, ToolFailure, ToolResult, ToolSuccess, Usage,
+    };
+    use golem_llm::golem::llm::llm::{CapabilitySet, ModelInfo};
+    use golem_llm::history::response_to_message;
+    use golem_llm::raw_body::RawResponse;
+
+    #[test]
+    fn missing_total_tokens_are_the_sum_of_prompt_and_completion_tokens() {
+        let usage = |json: &str| {
+            convert_usage(&serde_json::from_str::<crate::client::Usage>(json).unwrap())
+        };
+
+        assert_eq!(
+            usage(r#"{"prompt_tokens": 12, "completion_tokens": 3}"#),
+            Usage {
+                input_tokens: Some(12),
+                output_tokens: Some(3),
+                total_tokens: Some(15),
+            }
+        );
+        assert_eq!(
+            usage(r#"{"prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 20}"#)
+                .total_tokens,
+            Some(20)
+        );
+    }
+
+    #[test]
+    fn dry_run_returns_constructed_request() {
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text(
+                "What is the weather in Berlin?".to_string(),
+            )],
+        }];
+        let config = Config {
+            model: "gpt-4o-mini".to_string(),
+            temperature: Some(0.5),
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![ToolDefinition {
+                name: "get_weather".to_string(),
+                description: Some("Gets the current weather".to_string()),
+                parameters_schema: r#"{"type":"object","properties":{"city":{"type":"string"}}}"#
+                    .to_string(),
+            }],
+            tool_choice: Some("auto".to_string()),
+            provider_options: vec![Kv {
+                key: "dry_run".to_string(),
+                value: "true".to_string(),
+            }],
+            system: None,
+        };
+        assert!(is_dry_run(&config));
+
+        let request = create_request(messages, config).unwrap();
+        let ChatEvent::Message(response) = dry_run_response("openai", &request) else {
+            panic!("dry run should return a message");
+        };
+        let returned: serde_json::Value =
+            serde_json::from_str(&response.metadata.provider_metadata_json.unwrap()).unwrap();
+
+        assert_eq!(returned, serde_json::to_value(&request).unwrap());
+        assert_eq!(returned["tools"][0]["function"]["name"], "get_weather");
+        assert_eq!(returned["tool_choice"], "auto");
+    }
+
+    #[test]
+    fn response_with_tool_calls_continues_the_conversation() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-1",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "system_fingerprint": null,
+                "usage": null,
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "tool_calls",
+                    "message": {
+                        "role": "assistant",
+                        "refusal": null,
+                        "content": "Let me check the weather.",
+                        "tool_calls": [{
+                            "type": "function",
+                            "id": "call_1",
+                            "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+                        }]
+                    }
+                }]
+            }"#,
+        )
+        .unwrap();
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message response");
+        };
+        let tool_calls = response.tool_calls.clone();
+
+        let messages = vec![
+            Message {
+                role: Role::User,
+                name: None,
+                content: vec![ContentPart::Text(
+                    "What is the weather in Paris?".to_string(),
+                )],
+            },
+            response_to_message(response),
+        ];
+        let mut request = create_request(messages, config()).unwrap();
+        request.messages.extend(tool_results_to_messages(vec![(
+            tool_calls[0].clone(),
+            ToolResult::Success(ToolSuccess {
+                id: "call_1".to_string(),
+                name: "get_weather".to_string(),
+                result_json: r#"{"forecast":"sunny"}"#.to_string(),
+                execution_time_ms: None,
+            }),
+        )]));
+        let request = serde_json::to_value(request).unwrap();
+
+        let messages = request["messages"].as_array().unwrap();
+        assert_eq!(messages[1]["role"], "assistant");
+        assert_eq!(
+            messages[1]["content"],
+            serde_json::json!([{"type": "text", "text": "Let me check the weather."}])
+        );
+        assert_eq!(messages[2]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(messages[3]["tool_call_id"], "call_1");
+    }
+
+    #[test]
+    fn process_response_preserves_mixed_content_parts() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-123",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "system_fingerprint": null,
+                "usage": null,
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {
+                        "role": "assistant",
+                        "refusal": null,
+                        "tool_calls": null,
+                        "content": [
+                            {"type": "text", "text": "Here is the first image:"},
+                            {"type": "image_url", "image_url": {"url": "https://example.com/1.png"}},
+                            {"type": "text", "text": "And the second one:"},
+                            {"type": "image_url", "image_url": {"url": "https://example.com/2.png", "detail": "high"}}
+                        ]
+                    }
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message response");
+        };
+        assert_eq!(
+            response.content,
+            vec![
+                ContentPart::Text("Here is the first image:".to_string()),
+                ContentPart::Image(ImageReference::Url(ImageUrl {
+                    url: "https://example.com/1.png".to_string(),
+                    detail: None,
+                })),
+                ContentPart::Text("And the second one:".to_string()),
+                ContentPart::Image(ImageReference::Url(ImageUrl {
+                    url: "https://example.com/2.png".to_string(),
+                    detail: Some(ImageDetail::High),
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn url_citations_become_citation_parts() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1743724800,
+            "model": "gpt-4o-search-preview",
+            "system_fingerprint": null,
+            "usage": null,
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "Golem 1.3 added agents (golem.cloud).",
+                    "refusal": null,
+                    "annotations": [
+                        {
+                            "type": "url_citation",
+                            "url_citation": {
+                                "url": "https://golem.cloud/blog/golem-1-3",
+                                "title": "Golem 1.3 release",
+                                "start_index": 0,
+                                "end_index": 22
+                            }
+                        },
+                        {"type": "file_citation"}
+                    ]
+                },
+                "finish_reason": "stop"
+            }]
+        }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message response");
+        };
+        assert_eq!(
+            response.content[1..],
+            [ContentPart::Citation(Citation {
+                title: Some("Golem 1.3 release".to_string()),
+                url: "https://golem.cloud/blog/golem-1-3".to_string(),
+                quoted_text: Some("Golem 1.3 added agents".to_string()),
+            })]
+        );
+    }
+
+    #[test]
+    fn unknown_finish_reason_is_kept_as_other() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-789",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "system_fingerprint": null,
+                "usage": null,
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "function_call",
+                    "message": {
+                        "role": "assistant",
+                        "refusal": null,
+                        "tool_calls": null,
+                        "content": "Calling the function."
+                    }
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message");
+        };
+        assert_eq!(
+            response.metadata.finish_reason,
+            Some(FinishReason::Other("function_call".to_string()))
+        );
+    }
+
+    #[test]
+    fn process_response_surfaces_refusal() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-456",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "system_fingerprint": null,
+                "usage": null,
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {
+                        "role": "assistant",
+                        "refusal": "I'm sorry, I can't help with that.",
+                        "tool_calls": null,
+                        "content": null
+                    }
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::Error(error) = process_response(response) else {
+            panic!("expected an error response");
+        };
+        assert_eq!(error.code, ErrorCode::ContentFilter);
+        assert_eq!(error.message, "I'm sorry, I can't help with that.");
+    }
+
+    #[test]
+    fn refusal_with_blank_content_is_a_content_filter_error() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-457",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "system_fingerprint": null,
+                "usage": null,
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {
+                        "role": "assistant",
+                        "refusal": "I'm sorry, I can't help with that.",
+                        "tool_calls": null,
+                        "content": ""
+                    }
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::Error(error) = process_response(response) else {
+            panic!("expected an error response");
+        };
+        assert_eq!(error.code, ErrorCode::ContentFilter);
+        assert_eq!(error.message, "I'm sorry, I can't help with that.");
+    }
+
+    #[test]
+    fn empty_stop_response_is_an_empty_response_error() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-789",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "system_fingerprint": null,
+                "usage": null,
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {"role": "assistant", "refusal": null, "tool_calls": null, "content": " \n "}
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::Error(error) = reject_empty_response(process_response(response)) else {
+            panic!("expected an error response");
+        };
+        assert_eq!(error.code, ErrorCode::EmptyResponse);
+    }
+
+    #[test]
+    fn tool_call_without_id_gets_synthetic_id() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-789",
+                "created": 1700000000,
+                "model": "llama3.2",
+                "system_fingerprint": null,
+                "usage": null,
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "tool_calls",
+                    "message": {
+                        "role": "assistant",
+                        "refusal": null,
+                        "content": null,
+                        "tool_calls": [{
+                            "type": "function",
+                            "function": {"name": "get_weather", "arguments": "{\"city\": \"Berlin\"}"}
+                        }]
+                    }
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::ToolRequest(tool_calls) = process_response(response) else {
+            panic!("expected a tool request");
+        };
+        assert_eq!(tool_calls[0].id, "call_0");
+
+        let tool_result = ToolResult::Success(ToolSuccess {
+            id: tool_calls[0].id.clone(),
+            name: "get_weather".to_string(),
+            result_json: "{\"temperature\": 20}".to_string(),
+            execution_time_ms: None,
+        });
+        let messages = serde_json::to_value(tool_results_to_messages(vec![(
+            tool_calls[0].clone(),
+            tool_result,
+        )]))
+        .unwrap();
+        assert_eq!(messages[0]["tool_calls"][0]["id"], "call_0");
+        assert_eq!(messages[1]["tool_call_id"], "call_0");
+    }
+
+    #[test]
+    fn models_response() {
+        let response: ModelsResponse = serde_json::from_str(
+            r#"{
+            "object": "list",
+            "data": [
+                {"id": "gpt-4o", "object": "model", "created": 1715367049, "owned_by": "system"},
+                {"id": "text-embedding-3-small", "object": "model", "created": 1705948997, "owned_by": "system"}
+            ]
+        }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            process_models_response(response),
+            vec![
+                ModelInfo {
+                    id: "gpt-4o".to_string(),
+                    context_length: None,
+                    capabilities: None,
+                },
+                ModelInfo {
+                    id: "text-embedding-3-small".to_string(),
+                    context_length: None,
+                    capabilities: None,
+                },
+            ]
+        );
+    }
+
+    fn image_message(image: ImageReference) -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Image(image)],
+        }]
+    }
+
+    fn config() -> Config {
+        Config {
+            model: "gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![],
+            system: None,
+        }
+    }
+
+    #[test]
+    fn image_detail_defaults_to_auto() {
+        let messages = image_message(ImageReference::Url(ImageUrl {
+            url: "https://example.com/cat.png".to_string(),
+            detail: None,
+        }));
+
+        let request = serde_json::to_value(create_request(messages, config()).unwrap()).unwrap();
+
+        assert_eq!(
+            request["messages"][0]["content"][0]["image_url"]["detail"],
+            "auto"
+        );
+    }
+
+    #[test]
+    fn oversized_request_fails_before_it_is_sent() {
+        let config = Config {
+            provider_options: vec![Kv {
+                key: "max_request_bytes".to_string(),
+                value: "65536".to_string(),
+            }],
+            ..config()
+        };
+        let messages = image_message(ImageReference::Inline(ImageSource {
+            data: vec![0; 100_000],
+            mime_type: "image/png".to_string(),
+            detail: None,
+        }));
+
+        let client = CompletionsApi::new("sk-test".to_string(), &config);
+        let request = create_request(messages, config).unwrap();
+        let error = client.send_messages(request).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(error.message.ends_with("larger than the limit of 64.0 KB"));
+    }
+
+    #[test]
+    fn json_schema_response_format_is_sent() {
+        let config = Config {
+            provider_options: vec![
+                Kv {
+                    key: "response_format".to_string(),
+                    value: "json-schema".to_string(),
+                },
+                Kv {
+                    key: "response_schema".to_string(),
+                    value: r#"{"type": "object", "properties": {"city": {"type": "string"}}}"#
+                        .to_string(),
+                },
+            ],
+            ..config()
+        };
+
+        let request = serde_json::to_value(create_request(vec![], config).unwrap()).unwrap();
+
+        assert_eq!(
+            request["response_format"],
+            serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "response",
+                    "schema": {"type": "object", "properties": {"city": {"type": "string"}}}
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn unsupported_image_mime_type() {
+        let messages = image_message(ImageReference::Inline(ImageSource {
+            data: vec![0x42, 0x4d],
+            mime_type: "image/bmp".to_string(),
+            detail: None,
+        }));
+
+        let error = create_request(messages, config()).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(error.message.contains("image/bmp"));
+    }
+
+    #[test]
+    fn batch_input_is_jsonl() {
+        let requests = vec![
+            BatchRequest {
+                custom_id: "request-1".to_string(),
+                messages: vec![Message {
+                    role: Role::User,
+                    name: None,
+                    content: vec![ContentPart::Text("What is 2 + 2?".to_string())],
+                }],
+            },
+            BatchRequest {
+                custom_id: "request-2".to_string(),
+                messages: vec![
+                    Message {
+                        role: Role::System,
+                        name: None,
+                        content: vec![ContentPart::Text("Answer briefly".to_string())],
+                    },
+                    Message {
+                        role: Role::User,
+                        name: None,
+                        content: vec![ContentPart::Text("Name a color".to_string())],
+                    },
+                ],
+            },
+        ];
+
+        let jsonl = create_batch_input(requests, config()).unwrap();
+        let lines = jsonl
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["custom_id"], "request-1");
+        assert_eq!(lines[0]["method"], "POST");
+        assert_eq!(lines[0]["url"], "/v1/chat/completions");
+        assert_eq!(lines[0]["body"]["model"], "gpt-4o");
+        assert_eq!(
+            lines[0]["body"]["messages"],
+            serde_json::json!([{"role": "user", "content": [{"type": "text", "text": "What is 2 + 2?"}]}])
+        );
+        assert_eq!(lines[1]["custom_id"], "request-2");
+        assert_eq!(lines[1]["body"]["messages"][0]["role"], "system");
+        assert_eq!(lines[1]["body"]["messages"][1]["role"], "user");
+    }
+
+    #[test]
+    fn batch_output_lines() {
+        let content = r#"{"id": "batch_req_1", "custom_id": "request-1", "response": {"status_code": 200, "request_id": "req_1", "body": {"id": "chatcmpl-1", "created": 1700000000, "model": "gpt-4o", "system_fingerprint": null, "usage": null, "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "refusal": null, "tool_calls": null, "content": "4"}}]}}, "error": null}
+{"id": "batch_req_2", "custom_id": "request-2", "response": {"status_code": 429, "request_id": "req_2", "body": {"error": {"message": "Rate limit reached"}}}, "error": null}
+"#;
+
+        let results = process_batch_output(content).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].custom_id, "request-1");
+        let ChatEvent::Message(response) = &results[0].event else {
+            panic!("expected a message response");
+        };
+        assert_eq!(response.content, vec![ContentPart::Text("4".to_string())]);
+        assert_eq!(results[1].custom_id, "request-2");
+        let ChatEvent::Error(error) = &results[1].event else {
+            panic!("expected an error response");
+        };
+        assert_eq!(error.code, ErrorCode::RateLimitExceeded);
+    }
+
+    #[test]
+    fn tool_execution_times_in_metadata() {
+        let tool_call = |id: &str| ToolCall {
+            id: id.to_string(),
+            name: "get_weather".to_string(),
+            arguments_json: "{}".to_string(),
+        };
+        let tool_results = vec![
+            (
+                tool_call("call_1"),
+                ToolResult::Success(ToolSuccess {
+                    id: "call_1".to_string(),
+                    name: "get_weather".to_string(),
+                    result_json: "{\"temperature\": 20}".to_string(),
+                    execution_time_ms: Some(150),
+                }),
+            ),
+            (
+                tool_call("call_2"),
+                ToolResult::Error(ToolFailure {
+                    id: "call_2".to_string(),
+                    name: "get_weather".to_string(),
+                    error_message: "Unknown city".to_string(),
+                    error_code: None,
+                }),
+            ),
+        ];
+        let mut metadata = ResponseMetadata {
+            finish_reason: None,
+            usage: None,
+            provider_id: Some("chatcmpl-123".to_string()),
+            timestamp: None,
+            provider_metadata_json: None,
+            stop_sequence: None,
+            tool_calls: None,
+        };
+
+        add_tool_execution_times(&mut metadata, &tool_execution_times(&tool_results));
+
+        let provider_metadata: serde_json::Value =
+            serde_json::from_str(metadata.provider_metadata_json.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            provider_metadata,
+            serde_json::json!({"tool_execution_times_ms": {"call_1": 150}})
+        );
+    }
+
+    fn named_message(name: &str) -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: Some(name.to_string()),
+            content: vec![ContentPart::Text("Hello".to_string())],
+        }]
+    }
+
+    #[test]
+    fn message_name_with_spaces_is_sanitized() {
+        let request =
+            serde_json::to_value(create_request(named_message("vigoo dev"), config()).unwrap())
+                .unwrap();
+
+        assert_eq!(request["messages"][0]["name"], "vigoo_dev");
+    }
+
+    #[test]
+    fn message_name_with_spaces_is_rejected() {
+        let config = Config {
+            provider_options: vec![Kv {
+                key: "name_handling".to_string(),
+                value: "reject".to_string(),
+            }],
+            ..config()
+        };
+
+        let error = create_request(named_message("vigoo dev"), config).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(error.message.contains("vigoo dev"));
+    }
+
+    #[test]
+    fn user_is_derived_from_message_name() {
+        let option = |key: &str, value: &str| Kv {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let user = |provider_options: Vec<Kv>| {
+            let config = Config {
+                provider_options,
+                ..config()
+            };
+            create_request(named_message("alice"), config).unwrap().user
+        };
+
+        assert_eq!(user(vec![]), None);
+        assert_eq!(
+            user(vec![option("derive_user_from_name", "true")]),
+            Some("alice".to_string())
+        );
+        assert_eq!(
+            user(vec![
+                option("derive_user_from_name", "true"),
+                option("user_id", "user-123"),
+            ]),
+            Some("user-123".to_string())
+        );
+    }
+
+    fn config_with_stop_sequences(count: usize, overflow: Option<&str>) -> Config {
+        Config {
+            stop_sequences: Some((1..=count).map(|i| format!("STOP{i}")).collect()),
+            provider_options: overflow
+                .map(|value| Kv {
+                    key: "stop_sequence_overflow".to_string(),
+                    value: value.to_string(),
+                })
+                .into_iter()
+                .collect(),
+            ..config()
+        }
+    }
+
+    #[test]
+    fn stop_sequences_over_the_limit_are_truncated() {
+        let request = create_request(
+            named_message("alice"),
+            config_with_stop_sequences(6, Some("truncate")),
+        )
+        .unwrap();
+
+        assert_eq!(
+            request.stop,
+            Some(vec![
+                "STOP1".to_string(),
+                "STOP2".to_string(),
+                "STOP3".to_string(),
+                "STOP4".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn stop_sequences_over_the_limit_are_rejected() {
+        let error = create_request(named_message("alice"), config_with_stop_sequences(5, None))
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+
+        let request = create_request(
+            named_message("alice"),
+            config_with_stop_sequences(4, Some("reject")),
+        )
+        .unwrap();
+        assert_eq!(request.stop.map(|stop| stop.len()), Some(4));
+    }
+
+    #[test]
+    fn system_prompt_is_sent_as_first_message() {
+        let config = Config {
+            system: Some("You are a helpful assistant.".to_string()),
+            ..config()
+        };
+
+        let request =
+            serde_json::to_value(create_request(named_message("alice"), config).unwrap()).unwrap();
+
+        assert_eq!(request["messages"][0]["role"], "system");
+        assert_eq!(
+            request["messages"][0]["content"][0]["text"],
+            "You are a helpful assistant."
+        );
+        assert_eq!(request["messages"][1]["role"], "user");
+    }
+
+    #[test]
+    fn multiple_system_messages_are_sent_separately() {
+        let config = Config {
+            system: Some("You are a helpful assistant.".to_string()),
+            ..config()
+        };
+        let mut messages = named_message("alice");
+        messages.push(Message {
+            role: Role::System,
+            name: None,
+            content: vec![ContentPart::Text("Answer in one sentence.".to_string())],
+        });
+
+        let request = serde_json::to_value(create_request(messages, config).unwrap()).unwrap();
+
+        let roles = request["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["role"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(roles, vec!["system", "user", "system"]);
+        assert_eq!(
+            request["messages"][2]["content"][0]["text"],
+            "Answer in one sentence."
+        );
+    }
+
+    #[test]
+    fn small_model_alias_resolves_to_an_openai_model() {
+        let config = Config {
+            model: "small".to_string(),
+            ..config()
+        };
+
+        let request = create_request(named_message("alice"), config).unwrap();
+
+        assert_eq!(request.model, "gpt-4o-mini");
+    }
+
+    #[test]
+    fn store_and_metadata_are_serialized() {
+        let config = Config {
+            provider_options: vec![
+                Kv {
+                    key: "store".to_string(),
+                    value: "true".to_string(),
+                },
+                Kv {
+                    key: "metadata".to_string(),
+                    value: r#"{"session": "abc-123"}"#.to_string(),
+                },
+            ],
+            ..config()
+        };
+
+        let request =
+            serde_json::to_value(create_request(named_message("alice"), config).unwrap()).unwrap();
+
+        assert_eq!(request["store"], true);
+        assert_eq!(
+            request["metadata"],
+            serde_json::json!({"session": "abc-123"})
+        );
+    }
+
+    #[test]
+    fn service_tier_is_sent_and_returned() {
+        let config = |service_tier: &str| Config {
+            provider_options: vec![Kv {
+                key: "service_tier".to_string(),
+                value: service_tier.to_string(),
+            }],
+            ..config()
+        };
+
+        let request =
+            serde_json::to_value(create_request(named_message("alice"), config("flex")).unwrap())
+                .unwrap();
+        assert_eq!(request["service_tier"], "flex");
+
+        let error = create_request(named_message("alice"), config("priority")).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-1",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "service_tier": "flex",
+                "system_fingerprint": null,
+                "usage": null,
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {"role": "assistant", "refusal": null, "tool_calls": null, "content": "4"}
+                }]
+            }"#,
+        )
+        .unwrap();
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message response");
+        };
+        assert_eq!(
+            response.metadata.provider_metadata_json.as_deref(),
+            Some(r#"{"service_tier":"flex"}"#)
+        );
+    }
+
+    #[test]
+    fn equivalent_requests_have_identical_canonical_json() {
+        let store = Kv {
+            key: "store".to_string(),
+            value: "true".to_string(),
+        };
+        let metadata = |metadata: &str| Kv {
+            key: "metadata".to_string(),
+            value: metadata.to_string(),
+        };
+        let first = Config {
+            provider_options: vec![store.clone(), metadata(r#"{"a": "1", "b": "2", "c": "3"}"#)],
+            ..config()
+        };
+        let second = Config {
+            provider_options: vec![metadata(r#"{"c": "3", "b": "2", "a": "1"}"#), store],
+            ..config()
+        };
+
+        let first = create_request(named_message("alice"), first).unwrap();
+        let second = create_request(named_message("alice"), second).unwrap();
+
+        let canonical = canonical_json(&first.body()).unwrap();
+        assert_eq!(canonical, canonical_json(&second.body()).unwrap());
+        assert!(canonical.contains(r#""metadata":{"a":"1","b":"2","c":"3"}"#));
+    }
+
+    #[test]
+    fn over_long_metadata_is_rejected() {
+        let config = Config {
+            provider_options: vec![Kv {
+                key: "metadata".to_string(),
+                value: serde_json::json!({"session": "x".repeat(513)}).to_string(),
+            }],
+            ..config()
+        };
+
+        let error = create_request(named_message("alice"), config).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(error.message.contains("'session'"));
+    }
+
+    #[test]
+    fn raw_body_override_is_merged_into_the_request_body() {
+        let config = Config {
+            provider_options: vec![Kv {
+                key: "raw_body_override".to_string(),
+                value: r#"{"reasoning_effort": "low", "top_p": 0.5}"#.to_string(),
+            }],
+            ..config()
+        };
+
+        let request = create_request(named_message("alice"), config).unwrap();
+        let body = serde_json::to_value(request.body()).unwrap();
+
+        assert_eq!(body["reasoning_effort"], "low");
+        assert_eq!(body["top_p"], 0.5);
+        assert_eq!(body["model"], request.model);
+        assert_eq!(body["messages"][0]["name"], "alice");
+    }
+
+    #[test]
+    fn top_k_is_not_sent_to_openai() {
+        let config = Config {
+            provider_options: vec![Kv {
+                key: "top_k".to_string(),
+                value: "40".to_string(),
+            }],
+            ..config()
+        };
+
+        let request = create_request(named_message("alice"), config).unwrap();
+        let body = serde_json::to_value(request.body()).unwrap();
+
+        assert!(body.get("top_k").is_none());
+    }
+
+    #[test]
+    fn logit_bias_is_sent_and_validated() {
+        let with_option = |key: &str, value: &str| Config {
+            provider_options: vec![Kv {
+                key: key.to_string(),
+                value: value.to_string(),
+            }],
+            ..config()
+        };
+
+        let request = create_request(
+            named_message("alice"),
+            with_option("logit_bias", r#"{"50256": -100, "1734": 5}"#),
+        )
+        .unwrap();
+        let body = serde_json::to_value(request.body()).unwrap();
+        assert_eq!(
+            body["logit_bias"],
+            serde_json::json!({"50256": -100, "1734": 5})
+        );
+
+        let error = create_request(
+            named_message("alice"),
+            with_option("logit_bias", r#"{"50256": -101}"#),
+        )
+        .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn banned_phrases_are_sent_as_negative_logit_bias() {
+        let vocabulary = [
+            "a", "n", " ", "I", "A", "s", "an", " a", " an", " A", " I", "as", " as",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(rank, token)| format!("{} {rank}", general_purpose::STANDARD.encode(token)))
+        .collect::<Vec<_>>()
+        .join("\n");
+        let path = std::env::temp_dir().join(format!(
+            "openai-banned-phrases-{}.tiktoken",
+            std::process::id()
+        ));
+        std::fs::write(&path, vocabulary).unwrap();
+        let with_options = |options: &[(&str, &str)]| Config {
+            provider_options: options
+                .iter()
+                .map(|(key, value)| Kv {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+            ..config()
+        };
+
+        let request = create_request(
+            named_message("alice"),
+            with_options(&[
+                ("banned_phrases", r#"["as an AI"]"#),
+                ("tokenizer_vocabulary", path.to_str().unwrap()),
+                ("logit_bias", r#"{"3": 5}"#),
+            ]),
+        )
+        .unwrap();
+        let body = serde_json::to_value(request.body()).unwrap();
+        // "as an AI" is encoded as "as", " an", " A", "I" and " as an AI" as " as", " an", " A", "I"
+        assert_eq!(
+            body["logit_bias"],
+            serde_json::json!({"11": -100, "12": -100, "8": -100, "9": -100, "3": 5})
+        );
+
+        let error = create_request(
+            named_message("alice"),
+            with_options(&[("banned_phrases", r#"["as an AI"]"#)]),
+        )
+        .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn temperature_is_stripped_for_reasoning_models() {
+        let config = Config {
+            model: "o3-mini".to_string(),
+            temperature: Some(0.2),
+            ..config()
+        };
+        let request = create_request(named_message("alice"), config.clone()).unwrap();
+        let body = serde_json::to_value(request.body()).unwrap();
+        assert!(body.get("temperature").is_none());
+
+        let config = Config {
+            model: "gpt-4o".to_string(),
+            temperature: None,
+            provider_options: vec![Kv {
+                key: "default_temperature".to_string(),
+                value: "provider".to_string(),
+            }],
+            ..config
+        };
+        let request = create_request(named_message("alice"), config).unwrap();
+        let body = serde_json::to_value(request.body()).unwrap();
+        assert_eq!(body["temperature"], 1.0);
+    }
+
+    #[test]
+    fn parallel_tool_calls_false_is_enforced_on_the_response() {
+        let with_enforcement = |enforcement: &str| Config {
+            tools: vec![ToolDefinition {
+                name: "get_weather".to_string(),
+                description: None,
+                parameters_schema: r#"{"type":"object"}"#.to_string(),
+            }],
+            provider_options: vec![
+                Kv {
+                    key: "parallel_tool_calls".to_string(),
+                    value: "false".to_string(),
+                },
+                Kv {
+                    key: "parallel_tool_calls_enforcement".to_string(),
+                    value: enforcement.to_string(),
+                },
+            ],
+            ..config()
+        };
+        let tool_call = |city: &str| ToolCall {
+            id: format!("call_{city}"),
+            name: "get_weather".to_string(),
+            arguments_json: format!(r#"{{"city": "{city}"}}"#),
+        };
+        let two_calls = ChatEvent::ToolRequest(vec![tool_call("Berlin"), tool_call("Paris")]);
+
+        let request = create_request(named_message("alice"), with_enforcement("error")).unwrap();
+        let body = serde_json::to_value(request.body()).unwrap();
+        assert_eq!(body["parallel_tool_calls"], false);
+        assert!(body.get("parallel_tool_calls_enforcement").is_none());
+        match enforce_parallel_tool_calls(two_calls.clone(), &request) {
+            ChatEvent::Error(error) => {
+                assert_eq!(error.code, ErrorCode::InternalError);
+                assert!(error.message.contains("2 tool calls"));
+            }
+            other => panic!("Expected an error, got {other:?}"),
+        }
+
+        let request = create_request(named_message("alice"), with_enforcement("first")).unwrap();
+        assert_eq!(
+            enforce_parallel_tool_calls(two_calls.clone(), &request),
+            ChatEvent::ToolRequest(vec![tool_call("Berlin")])
+        );
+
+        let request = create_request(named_message("alice"), with_enforcement("none")).unwrap();
+        assert_eq!(
+            enforce_parallel_tool_calls(two_calls.clone(), &request),
+            two_calls
+        );
+
+        let error = create_request(named_message("alice"), with_enforcement("drop")).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn recorded_response_is_replayed_offline() {
+        let dir = std::env::temp_dir().join(format!("golem-llm-fixtures-{}", std::process::id()));
+        let request = create_request(named_message("alice"), config()).unwrap();
+        let body = request.body();
+
+        let recorded = with_response_fixtures(
+            Some(&ResponseFixtures::Record(dir.clone())),
+            "openai",
+            &body,
+            || {
+                Ok(serde_json::from_str::<RawResponse<CompletionsResponse>>(
+                    r#"{
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "created": 1743724800,
+                    "model": "gpt-4o",
+                    "system_fingerprint": null,
+                    "usage": {"prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15},
+                    "choices": [{
+                        "index": 0,
+                        "message": {"role": "assistant", "content": "Hello, Alice!", "refusal": null},
+                        "finish_reason": "stop"
+                    }]
+                }"#,
+                )
+                .unwrap())
+            },
+        )
+        .unwrap();
+        let replayed = with_response_fixtures(
+            Some(&ResponseFixtures::Replay(dir.clone())),
+            "openai",
+            &body,
+            || panic!("a replayed request must not be sent"),
+        );
+        let other_request = create_request(named_message("bob"), config()).unwrap();
+        let missing = with_response_fixtures::<CompletionsResponse>(
+            Some(&ResponseFixtures::Replay(dir.clone())),
+            "openai",
+            &other_request.body(),
+            || panic!("a replayed request must not be sent"),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            process_response(replayed.unwrap().body),
+            process_response(recorded.body)
+        );
+        assert!(missing
+            .unwrap_err()
+            .message
+            .starts_with("No recorded response for this request"));
+    }
+
+    #[test]
+    fn generated_images_become_image_references() {
+        let response: ImageGenerationResponse = serde_json::from_str(
+            r#"{
+                "created": 1713833628,
+                "data": [
+                    {
+                        "url": "https://oaidalleapiprodscus.blob.core.windows.net/private/img-1.png",
+                        "revised_prompt": "A watercolor painting of a lighthouse at dawn"
+                    },
+                    {"b64_json": "iVBORw0K"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            process_image_response(response).unwrap(),
+            vec![
+                ImageReference::Url(ImageUrl {
+                    url: "https://oaidalleapiprodscus.blob.core.windows.net/private/img-1.png"
+                        .to_string(),
+                    detail: None,
+                }),
+                ImageReference::Inline(ImageSource {
+                    data: vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a],
+                    mime_type: "image/png".to_string(),
+                    detail: None,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn content_policy_violation_is_a_content_filter_error() {
+        let error = image_generation_error(Error {
+            code: ErrorCode::InvalidRequest,
+            message: "OpenAI API error: HTTP 400 Bad Request".to_string(),
+            provider_error_json: Some(
+                r#"{"error":{"code":"content_policy_violation","message":"Your request was rejected as a result of our safety system.","type":"invalid_request_error"}}"#
+                    .to_string(),
+            ),
+        });
+
+        assert_eq!(error.code, ErrorCode::ContentFilter);
+    }
+
+    #[test]
+    fn capabilities_of_vision_and_text_only_models() {
+        assert_eq!(
+            model_capabilities("gpt-4o-2024-08-06"),
+            CapabilitySet {
+                images: true,
+                tools: true,
+                streaming: true,
+                json_mode: true,
+            }
+        );
+        assert_eq!(
+            model_capabilities("gpt-3.5-turbo"),
+            CapabilitySet {
+                images: false,
+                tools: true,
+                streaming: true,
+                json_mode: true,
+            }
+        );
+        assert_eq!(
+            model_capabilities("o1-mini"),
+            CapabilitySet {
+                images: false,
+                tools: false,
+                streaming: true,
+                json_mode: false,
+            }
+        );
+    }
+
+    fn config_with_options(model: &str, options: &[(&str, &str)]) -> Config {
+        Config {
+            model: model.to_string(),
+            provider_options: options
+                .iter()
+                .map(|(key, value)| Kv {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+            ..config()
+        }
+    }
+
+    #[test]
+    fn audio_modality_is_sent_with_its_voice_and_format() {
+        let request = serde_json::to_value(
+            create_request(
+                named_message("alice"),
+                config_with_options(
+                    "gpt-4o-audio-preview",
+                    &[
+                        ("modalities", "audio, text"),
+                        ("audio_voice", "alloy"),
+                        ("audio_format", "mp3"),
+                    ],
+                ),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(request["modalities"], serde_json::json!(["text", "audio"]));
+        assert_eq!(
+            request["audio"],
+            serde_json::json!({"voice": "alloy", "format": "mp3"})
+        );
+
+        let request = serde_json::to_value(
+            create_request(
+                named_message("alice"),
+                config_with_options("gpt-4o", &[("modalities", "text")]),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(request["modalities"], serde_json::json!(["text"]));
+        assert!(request.get("audio").is_none());
+
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-1",
+                "created": 1700000000,
+                "model": "gpt-4o-audio-preview",
+                "system_fingerprint": null,
+                "usage": null,
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {
+                        "role": "assistant",
+                        "refusal": null,
+                        "tool_calls": null,
+                        "content": null,
+                        "audio": {"id": "audio_1", "data": "UklGRg==", "expires_at": 1700003600, "transcript": "Hello Alice!"}
+                    }
+                }]
+            }"#,
+        )
+        .unwrap();
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message response");
+        };
+        assert_eq!(
+            response.content,
+            vec![ContentPart::Text("Hello Alice!".to_string())]
+        );
+    }
+
+    #[test]
+    fn invalid_modality_and_audio_combinations_are_rejected() {
+        let error = |model: &str, options: &[(&str, &str)]| {
+            create_request(named_message("alice"), config_with_options(model, options))
+                .unwrap_err()
+                .code
+        };
+
+        let audio_model = "gpt-4o-audio-preview";
+        assert_eq!(
+            error(audio_model, &[("modalities", "audio")]),
+            ErrorCode::InvalidRequest
+        );
+        assert_eq!(
+            error(audio_model, &[("modalities", "text,video")]),
+            ErrorCode::InvalidRequest
+        );
+        assert_eq!(
+            error(
+                audio_model,
+                &[("modalities", "text,audio"), ("audio_voice", "alloy")]
+            ),
+            ErrorCode::InvalidRequest
+        );
+        assert_eq!(
+            error(
+                audio_model,
+                &[
+                    ("modalities", "text,audio"),
+                    ("audio_voice", "alloy"),
+                    ("audio_format", "ogg")
+                ]
+            ),
+            ErrorCode::InvalidRequest
+        );
+        assert_eq!(
+            error(
+                audio_model,
+                &[("audio_voice", "alloy"), ("audio_format", "mp3")]
+            ),
+            ErrorCode::InvalidRequest
+        );
+        assert_eq!(
+            error(
+                "gpt-4o",
+                &[
+                    ("modalities", "text,audio"),
+                    ("audio_voice", "alloy"),
+                    ("audio_format", "mp3")
+                ]
+            ),
+            ErrorCode::Unsupported
+        );
+    }
+
+    #[test]
+    fn retry_prompt_is_accepted() {
+        let message = |role: Role, text: &str| Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        };
+        let original_messages = vec![
+            message(Role::System, "Answer briefly"),
+            message(Role::User, "What is the weather like?"),
+            message(Role::Assistant, "Which city?"),
+            message(Role::User, "Berlin"),
+        ];
+        let partial_result = vec![StreamDelta {
+            content: Some(vec![ContentPart::Text("It is sunny".to_string())]),
+            tool_calls: None,
+            reasoning: None,
+            choice_index: None,
+        }];
+
+        let messages = OpenAIComponent::retry_prompt(&original_messages, &partial_result);
+
+        assert!(create_request(messages, config()).is_ok());
     }
 }
@@ -1,24 +1,43 @@
 mod client;
 mod conversions;
 
-use crate::client::{ChatCompletionChunk, CompletionsApi, CompletionsRequest};
+use crate::client::FinishReason as OpenAIFinishReason;
+use crate::client::{
+    BatchStatus, ChatCompletionChunk, CompletionsApi, CompletionsRequest, CreateBatchRequest,
+};
 use crate::conversions::{
-    convert_finish_reason, convert_usage, create_request, process_response,
-    tool_results_to_messages,
+    add_tool_execution_times, convert_finish_reason, convert_usage, create_batch_input,
+    create_image_request, create_request, enforce_parallel_tool_calls, image_generation_error,
+    model_capabilities, process_batch, process_batch_output, process_image_response,
+    process_models_response, process_response, tool_execution_times, tool_results_to_messages,
 };
+use golem_llm::cache::{cache_ttl, cached_response, response_cache_key};
 use golem_llm::chat_stream::{LlmChatStream, LlmChatStreamState};
-use golem_llm::config::with_config_key;
-use golem_llm::durability::{DurableLLM, ExtendedGuest};
+use golem_llm::concurrency::acquire_request_slot;
+use golem_llm::config::{provider_option, stream_replay_file, with_config_key};
+use golem_llm::context::reserve_context_for_tool_results;
+use golem_llm::dry_run::{dry_run_response, is_dry_run, with_api_key_unless_dry_run};
+use golem_llm::durability::{retry_prompt_with_style, DurableLLM, ExtendedGuest, RetryPromptStyle};
+use golem_llm::error::{reject_empty_response, unsupported};
 use golem_llm::event_source::EventSource;
+use golem_llm::fallback::{FallbackLLM, FallbackProvider, FallbackProviders};
 use golem_llm::golem::llm::llm::{
-    ChatEvent, ChatStream, Config, ContentPart, Error, FinishReason, Guest, Message,
-    ResponseMetadata, Role, StreamDelta, StreamEvent, ToolCall, ToolResult,
+    BatchInfo, BatchRequest, BatchResult, CapabilitySet, ChatEvent, ChatStream, Config,
+    ContentPart, EmbeddingResponse, Error, ErrorCode, FinishReason, Guest, ImageReference, Message,
+    ModelInfo, ResponseMetadata, StreamDelta, StreamEvent, ToolCall, ToolResult, Usage,
 };
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{include_raw_response, with_raw_response};
+use golem_llm::structured_output::{response_schema, validate_structured_output, ResponseSchema};
+use golem_llm::summarize::summarize_history;
+use golem_llm::tool_calls::{reject_malformed_tool_calls, tool_call_id};
+use golem_llm::usage::sum_usage;
 use golem_llm::LOGGING_STATE;
 use golem_rust::wasm_rpc::Pollable;
 use log::trace;
 use std::cell::{Ref, RefCell, RefMut};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::time::Duration;
 
 #[derive(Default)]
 struct JsonFragment {
@@ -31,41 +50,53 @@ struct OpenAIChatStream {
     stream: RefCell<Option<EventSource>>,
     failure: Option<Error>,
     finished: RefCell<bool>,
-    finish_reason: RefCell<Option<FinishReason>>,
-    json_fragments: RefCell<HashMap<u32, JsonFragment>>,
+    /// The number of completions requested with `n`. Deltas are only tagged with the index of
+    /// their choice if there is more than one.
+    choices: u32,
+    /// The finish reasons received so far by the index of their choice
+    finish_reasons: RefCell<BTreeMap<u32, OpenAIFinishReason>>,
+    /// Tool call fragments by the index of their choice and their index within it
+    json_fragments: RefCell<BTreeMap<(u32, u32), JsonFragment>>,
 }
 
 impl OpenAIChatStream {
-    pub fn new(stream: EventSource) -> LlmChatStream<Self> {
-        LlmChatStream::new(OpenAIChatStream {
-            stream: RefCell::new(Some(stream)),
-            failure: None,
+    fn state(stream: Option<EventSource>, failure: Option<Error>, choices: u32) -> Self {
+        OpenAIChatStream {
+            stream: RefCell::new(stream),
+            failure,
             finished: RefCell::new(false),
-            finish_reason: RefCell::new(None),
-            json_fragments: RefCell::new(HashMap::new()),
-        })
+            choices,
+            finish_reasons: RefCell::new(BTreeMap::new()),
+            json_fragments: RefCell::new(BTreeMap::new()),
+        }
     }
 
-    pub fn failed(error: Error) -> LlmChatStream<Self> {
-        LlmChatStream::new(OpenAIChatStream {
-            stream: RefCell::new(None),
-            failure: Some(error),
-            finished: RefCell::new(false),
-            finish_reason: RefCell::new(None),
-            json_fragments: RefCell::new(HashMap::new()),
-        })
+    pub fn new(stream: EventSource, choices: u32) -> LlmChatStream<Self> {
+        LlmChatStream::new(Self::state(Some(stream), None, choices))
     }
 
-    fn set_finished(&self) {
-        *self.finished.borrow_mut() = true;
+    pub fn failed(error: Error) -> LlmChatStream<Self> {
+        LlmChatStream::new(Self::state(None, Some(error), 1))
     }
 
-    fn set_finish_reason(&self, finish_reason: FinishReason) {
-        *self.finish_reason.borrow_mut() = Some(finish_reason);
+    pub fn replayed(choices: u32) -> LlmChatStream<Self> {
+        LlmChatStream::new(Self::state(None, None, choices))
     }
 
+    /// The finish reason of the first completion, which is the one `send` returns
     fn get_finish_reason(&self) -> Option<FinishReason> {
-        *self.finish_reason.borrow()
+        self.finish_reasons
+            .borrow()
+            .get(&0)
+            .map(convert_finish_reason)
+    }
+
+    /// The finish reasons of all completions, if more than one was requested
+    fn choice_finish_reasons(&self) -> Option<String> {
+        (self.choices > 1).then(|| {
+            serde_json::json!({ "choice_finish_reasons": *self.finish_reasons.borrow() })
+                .to_string()
+        })
     }
 }
 
@@ -91,35 +122,32 @@ impl LlmChatStreamState for OpenAIChatStream {
     }
 
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
-        trace!("Received raw stream event: {raw}");
-
-        if raw.starts_with("data: [DONE]") {
-            self.set_finished();
-            return Ok(None);
-        }
-
-        if !raw.starts_with("data: ") {
-            return Ok(None);
-        }
+        trace!("Received raw stream event: {}", Redacted(&raw));
 
-        let json_str = &raw[6..];
+        let json_str = raw.strip_prefix("data: ").unwrap_or(raw);
         let json: serde_json::Value = serde_json::from_str(json_str)
             .map_err(|err| format!("Failed to parse stream event JSON: {err}"))?;
 
         let chunk: ChatCompletionChunk = serde_json::from_value(json)
             .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
 
+        // With more than one choice requested by `n`, each chunk carries a delta of one of them
         if let Some(choice) = chunk.choices.into_iter().next() {
             if let Some(finish_reason) = choice.finish_reason {
-                self.set_finish_reason(convert_finish_reason(&finish_reason));
+                self.finish_reasons
+                    .borrow_mut()
+                    .insert(choice.index, finish_reason);
             }
 
+            let choice_index = (self.choices > 1).then_some(choice.index);
             let delta = &choice.delta;
 
             if let Some(content) = &delta.content {
                 return Ok(Some(StreamEvent::Delta(StreamDelta {
                     content: Some(vec![ContentPart::Text(content.clone())]),
                     tool_calls: None,
+                    reasoning: None,
+                    choice_index,
                 })));
             }
 
@@ -128,33 +156,37 @@ impl LlmChatStreamState for OpenAIChatStream {
                 let mut result_tool_calls = Vec::new();
 
                 for tool_call in tool_calls {
-                    match tool_call {
-                        crate::client::ToolCall::Function {
-                            function,
+                    let idx = tool_call.index;
+                    let function = tool_call.function.as_ref();
+                    let fragment = fragments.entry((choice.index, idx)).or_insert_with(|| {
+                        // Synthesized ids of further choices are kept apart from the ones of the
+                        // first choice
+                        let id = match (choice.index, tool_call.id.as_deref()) {
+                            (0, id) => tool_call_id(id, idx),
+                            (_, Some(id)) if !id.is_empty() => id.to_string(),
+                            (choice_index, _) => format!("call_{choice_index}_{idx}"),
+                        };
+                        JsonFragment {
                             id,
-                            index,
-                        } => {
-                            let idx = index.unwrap_or(0);
-
-                            let fragment = fragments.entry(idx).or_insert_with(|| JsonFragment {
-                                id: id.clone(),
-                                name: function.name.clone(),
-                                json: String::new(),
-                            });
-
-                            if !function.arguments.is_empty() {
-                                fragment.json.push_str(&function.arguments);
-                            }
+                            name: String::new(),
+                            json: String::new(),
+                        }
+                    });
 
-                            // Only emit when we have content to add
-                            if !fragment.id.is_empty() && !fragment.name.is_empty() {
-                                result_tool_calls.push(ToolCall {
-                                    id: fragment.id.clone(),
-                                    name: fragment.name.clone(),
-                                    arguments_json: fragment.json.clone(),
-                                });
-                            }
+                    if let Some(function) = function {
+                        if let Some(name) = &function.name {
+                            fragment.name.clone_from(name);
                         }
+                        fragment.json.push_str(&function.arguments);
+                    }
+
+                    // Only emit when we have content to add
+                    if !fragment.id.is_empty() && !fragment.name.is_empty() {
+                        result_tool_calls.push(ToolCall {
+                            id: fragment.id.clone(),
+                            name: fragment.name.clone(),
+                            arguments_json: fragment.json.clone(),
+                        });
                     }
                 }
 
@@ -162,6 +194,8 @@ impl LlmChatStreamState for OpenAIChatStream {
                     return Ok(Some(StreamEvent::Delta(StreamDelta {
                         content: None,
                         tool_calls: Some(result_tool_calls),
+                        reasoning: None,
+                        choice_index,
                     })));
                 }
             }
@@ -174,12 +208,34 @@ impl LlmChatStreamState for OpenAIChatStream {
                 usage: Some(convert_usage(&usage)),
                 provider_id: Some(chunk.id),
                 timestamp: Some(chunk.created.to_string()),
-                provider_metadata_json: None,
+                provider_metadata_json: self.choice_finish_reasons(),
+                stop_sequence: None,
+                tool_calls: None,
             })));
         }
 
         Ok(None)
     }
+
+    fn pending_finish(&self) -> ResponseMetadata {
+        ResponseMetadata {
+            finish_reason: self.get_finish_reason(),
+            usage: None,
+            provider_id: None,
+            timestamp: None,
+            provider_metadata_json: self.choice_finish_reasons(),
+            stop_sequence: None,
+            tool_calls: None,
+        }
+    }
+
+    fn tool_call_order(&self) -> Vec<String> {
+        self.json_fragments
+            .borrow()
+            .values()
+            .map(|fragment| fragment.id.clone())
+            .collect()
+    }
 }
 
 struct OpenAIComponent;
@@ -187,11 +243,42 @@ struct OpenAIComponent;
 impl OpenAIComponent {
     const ENV_VAR_NAME: &'static str = "OPENAI_API_KEY";
 
-    fn request(client: CompletionsApi, request: CompletionsRequest) -> ChatEvent {
-        match client.send_messages(request) {
-            Ok(response) => process_response(response),
-            Err(error) => ChatEvent::Error(error),
+    fn request(
+        client: CompletionsApi,
+        request: CompletionsRequest,
+        dry_run: bool,
+        include_raw_response: bool,
+        cache_ttl: Option<Duration>,
+        response_schema: Option<ResponseSchema>,
+    ) -> ChatEvent {
+        if dry_run {
+            return dry_run_response("openai", &request.body());
         }
+
+        let cache_key = response_cache_key(cache_ttl, &client.cache_scope(), &request.body());
+        cached_response(cache_key, || {
+            validate_structured_output(response_schema.as_ref(), || {
+                Self::send_request(&client, &request, include_raw_response)
+            })
+        })
+    }
+
+    /// Sends a single request and runs its response through the post-processing steps, in order
+    fn send_request(
+        client: &CompletionsApi,
+        request: &CompletionsRequest,
+        include_raw_response: bool,
+    ) -> ChatEvent {
+        let response = match client.send_messages(request.clone()) {
+            Ok(response) => response,
+            Err(error) => return ChatEvent::Error(error),
+        };
+
+        let event = process_response(response.body);
+        let event = reject_empty_response(event);
+        let event = reject_malformed_tool_calls(event);
+        let event = enforce_parallel_tool_calls(event, request);
+        with_raw_response(event, include_raw_response.then_some(response.raw))
     }
 
     fn streaming_request(
@@ -199,8 +286,9 @@ impl OpenAIComponent {
         mut request: CompletionsRequest,
     ) -> LlmChatStream<OpenAIChatStream> {
         request.stream = Some(true);
+        let choices = request.n.unwrap_or(1);
         match client.stream_send_messages(request) {
-            Ok(stream) => OpenAIChatStream::new(stream),
+            Ok(stream) => OpenAIChatStream::new(stream, choices),
             Err(error) => OpenAIChatStream::failed(error),
         }
     }
@@ -211,15 +299,32 @@ impl Guest for OpenAIComponent {
 
     fn send(messages: Vec<Message>, config: Config) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
+        let dry_run = is_dry_run(&config);
+        let include_raw_response = include_raw_response(&config);
+        let cache_ttl = cache_ttl(&config);
+        let response_schema = response_schema(&config).ok().flatten();
 
-        with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |openai_api_key| {
-            let client = CompletionsApi::new(openai_api_key);
+        with_api_key_unless_dry_run(
+            Self::ENV_VAR_NAME,
+            dry_run,
+            ChatEvent::Error,
+            |openai_api_key| {
+                let _permit = acquire_request_slot(&config, "openai", &openai_api_key);
+                let client = CompletionsApi::new(openai_api_key, &config);
 
-            match create_request(messages, config) {
-                Ok(request) => Self::request(client, request),
-                Err(err) => ChatEvent::Error(err),
-            }
-        })
+                match create_request(messages, config) {
+                    Ok(request) => Self::request(
+                        client,
+                        request,
+                        dry_run,
+                        include_raw_response,
+                        cache_ttl,
+                        response_schema,
+                    ),
+                    Err(err) => ChatEvent::Error(err),
+                }
+            },
+        )
     }
 
     fn continue_(
@@ -228,39 +333,198 @@ impl Guest for OpenAIComponent {
         config: Config,
     ) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
+        let dry_run = is_dry_run(&config);
+        let include_raw_response = include_raw_response(&config);
+        let cache_ttl = cache_ttl(&config);
+        let response_schema = response_schema(&config).ok().flatten();
 
-        with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |openai_api_key| {
-            let client = CompletionsApi::new(openai_api_key);
-
-            match create_request(messages, config) {
-                Ok(mut request) => {
-                    request
-                        .messages
-                        .extend(tool_results_to_messages(tool_results));
-                    Self::request(client, request)
+        with_api_key_unless_dry_run(
+            Self::ENV_VAR_NAME,
+            dry_run,
+            ChatEvent::Error,
+            |openai_api_key| {
+                let _permit = acquire_request_slot(&config, "openai", &openai_api_key);
+                let client = CompletionsApi::new(openai_api_key, &config);
+
+                match create_request(
+                    messages,
+                    reserve_context_for_tool_results(config, &tool_results),
+                ) {
+                    Ok(mut request) => {
+                        let execution_times = tool_execution_times(&tool_results);
+                        request
+                            .messages
+                            .extend(tool_results_to_messages(tool_results));
+                        match Self::request(
+                            client,
+                            request,
+                            dry_run,
+                            include_raw_response,
+                            cache_ttl,
+                            response_schema,
+                        ) {
+                            ChatEvent::Message(mut response) if !dry_run => {
+                                add_tool_execution_times(&mut response.metadata, &execution_times);
+                                ChatEvent::Message(response)
+                            }
+                            event => event,
+                        }
+                    }
+                    Err(err) => ChatEvent::Error(err),
                 }
-                Err(err) => ChatEvent::Error(err),
-            }
-        })
+            },
+        )
     }
 
     fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
         ChatStream::new(Self::unwrapped_stream(messages, config))
     }
+
+    fn embed(_inputs: Vec<String>, _config: Config) -> Result<EmbeddingResponse, Error> {
+        Err(unsupported(
+            "Embeddings are not supported by the OpenAI provider",
+        ))
+    }
+
+    fn generate_image(prompt: String, config: Config) -> Result<Vec<ImageReference>, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        with_config_key(Self::ENV_VAR_NAME, Err, |openai_api_key| {
+            let client = CompletionsApi::new(openai_api_key, &config);
+            let request = create_image_request(prompt, config)?;
+            let response = client
+                .generate_image(request)
+                .map_err(image_generation_error)?;
+            process_image_response(response)
+        })
+    }
+
+    fn list_models(config: Config) -> Result<Vec<ModelInfo>, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        with_config_key(Self::ENV_VAR_NAME, Err, |openai_api_key| {
+            let client = CompletionsApi::new(openai_api_key, &config);
+            client.list_models().map(process_models_response)
+        })
+    }
+
+    fn validate_credentials(config: Config) -> Result<(), Error> {
+        Self::list_models(config).map(|_| ())
+    }
+
+    fn capabilities(config: Config) -> CapabilitySet {
+        model_capabilities(&config.model)
+    }
+
+    fn batch_submit(requests: Vec<BatchRequest>, config: Config) -> Result<BatchInfo, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        with_config_key(Self::ENV_VAR_NAME, Err, |openai_api_key| {
+            let client = CompletionsApi::new(openai_api_key, &config);
+            let input = create_batch_input(requests, config)?;
+            let file = client.upload_batch_file(input)?;
+            client
+                .create_batch(CreateBatchRequest {
+                    input_file_id: file.id,
+                    endpoint: "/v1/chat/completions".to_string(),
+                    completion_window: "24h".to_string(),
+                })
+                .map(process_batch)
+        })
+    }
+
+    fn batch_status(batch_id: String, config: Config) -> Result<BatchInfo, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        with_config_key(Self::ENV_VAR_NAME, Err, |openai_api_key| {
+            let client = CompletionsApi::new(openai_api_key, &config);
+            client.retrieve_batch(&batch_id).map(process_batch)
+        })
+    }
+
+    fn batch_results(batch_id: String, config: Config) -> Result<Vec<BatchResult>, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        with_config_key(Self::ENV_VAR_NAME, Err, |openai_api_key| {
+            let client = CompletionsApi::new(openai_api_key, &config);
+            let batch = client.retrieve_batch(&batch_id)?;
+            if matches!(
+                batch.status,
+                BatchStatus::Validating
+                    | BatchStatus::InProgress
+                    | BatchStatus::Finalizing
+                    | BatchStatus::Cancelling
+            ) {
+                return Err(Error {
+                    code: ErrorCode::InvalidRequest,
+                    message: format!("Batch {batch_id} has not finished yet"),
+                    provider_error_json: None,
+                });
+            }
+
+            let mut results = Vec::new();
+            for file_id in [batch.output_file_id, batch.error_file_id]
+                .into_iter()
+                .flatten()
+            {
+                let content = client.file_content(&file_id)?;
+                results.extend(process_batch_output(&content)?);
+            }
+            Ok(results)
+        })
+    }
+
+    fn summarize_history(
+        messages: Vec<Message>,
+        config: Config,
+        keep_last_n: u32,
+    ) -> Result<Vec<Message>, Error> {
+        summarize_history(messages, config, keep_last_n, Self::send)
+    }
+
+    fn sum_usage(usages: Vec<Usage>) -> Usage {
+        sum_usage(usages)
+    }
 }
 
 impl ExtendedGuest for OpenAIComponent {
     fn unwrapped_stream(messages: Vec<Message>, config: Config) -> LlmChatStream<OpenAIChatStream> {
+        Self::unwrapped_continue_stream(messages, Vec::new(), config)
+    }
+
+    fn unwrapped_continue_stream(
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
+    ) -> LlmChatStream<OpenAIChatStream> {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
+        if stream_replay_file(&config).is_some() {
+            let choices = provider_option(&config, "n")
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(1);
+            return OpenAIChatStream::replayed(choices).with_stream_options(&config);
+        }
+
         with_config_key(
             Self::ENV_VAR_NAME,
             OpenAIChatStream::failed,
             |openai_api_key| {
-                let client = CompletionsApi::new(openai_api_key);
-
-                match create_request(messages, config) {
-                    Ok(request) => Self::streaming_request(client, request),
+                let permit = acquire_request_slot(&config, "openai", &openai_api_key);
+                let client = CompletionsApi::new(openai_api_key, &config);
+
+                match create_request(
+                    messages,
+                    reserve_context_for_tool_results(config.clone(), &tool_results),
+                ) {
+                    Ok(mut request) => {
+                        request
+                            .messages
+                            .extend(tool_results_to_messages(tool_results));
+                        Self::streaming_request(client, request)
+                            .with_request_permit(permit)
+                            .with_stream_options(&config)
+                    }
                     Err(err) => OpenAIChatStream::failed(err),
                 }
             },
@@ -268,59 +532,197 @@ impl ExtendedGuest for OpenAIComponent {
     }
 
     fn retry_prompt(original_messages: &[Message], partial_result: &[StreamDelta]) -> Vec<Message> {
-        let mut extended_messages = Vec::new();
-        extended_messages.push(Message {
-            role: Role::System,
-            name: None,
-            content: vec![
-                ContentPart::Text(
-                    "You were asked the same question previously, but the response was interrupted before completion. \
-                     Please continue your response from where you left off. \
-                     Do not include the part of the response that was already seen.".to_string()),
-            ],
-        });
-        extended_messages.push(Message {
-            role: Role::User,
-            name: None,
-            content: vec![ContentPart::Text(
-                "Here is the original question:".to_string(),
-            )],
-        });
-        extended_messages.extend_from_slice(original_messages);
-
-        let mut partial_result_as_content = Vec::new();
-        for delta in partial_result {
-            if let Some(contents) = &delta.content {
-                partial_result_as_content.extend_from_slice(contents);
-            }
-            if let Some(tool_calls) = &delta.tool_calls {
-                for tool_call in tool_calls {
-                    partial_result_as_content.push(ContentPart::Text(format!(
-                        "<tool-call id=\"{}\" name=\"{}\" arguments=\"{}\"/>",
-                        tool_call.id, tool_call.name, tool_call.arguments_json,
-                    )));
-                }
-            }
-        }
-
-        extended_messages.push(Message {
-            role: Role::User,
-            name: None,
-            content: vec![ContentPart::Text(
-                "Here is the partial response that was successfully received:".to_string(),
-            )]
-            .into_iter()
-            .chain(partial_result_as_content)
-            .collect(),
-        });
-        extended_messages
+        retry_prompt_with_style(
+            original_messages,
+            partial_result,
+            RetryPromptStyle::SystemMessage,
+        )
     }
 
     fn subscribe(stream: &Self::ChatStream) -> Pollable {
         stream.subscribe()
     }
+
+    fn idle_timer(stream: &Self::ChatStream) -> Option<Pollable> {
+        Some(stream.idle_timer())
+    }
+}
+
+impl FallbackProviders for OpenAIComponent {
+    fn fallback_providers() -> Vec<FallbackProvider> {
+        vec![FallbackProvider::new::<Self>("openai")]
+    }
 }
 
-type DurableOpenAIComponent = DurableLLM<OpenAIComponent>;
+type DurableOpenAIComponent = DurableLLM<FallbackLLM<OpenAIComponent>>;
 
 golem_llm::export_llm!(DurableOpenAIComponent with_types_in golem_llm);
+
+#[cfg(test)]
+mod tests {
+    use crate::OpenAIChatStream;
+    use golem_llm::chat_stream::{collect_stream, LlmChatStreamState};
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, ContentPart, FinishReason, GuestChatStream, ResponseMetadata, StreamEvent,
+        ToolCall,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn deltas_of_several_choices_are_tagged_with_their_index() {
+        let stream = OpenAIChatStream::state(None, None, 2);
+
+        let mut texts = HashMap::<u32, String>::new();
+        for raw in [
+            r#"{"id":"chatcmpl-1","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","created":1700000000,"model":"gpt-4o","choices":[{"index":1,"delta":{"role":"assistant","content":""},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","created":1700000000,"model":"gpt-4o","choices":[{"index":1,"delta":{"content":"Bonjour"},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"content":" world"},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","created":1700000000,"model":"gpt-4o","choices":[{"index":1,"delta":{"content":" le monde"},"finish_reason":null}]}"#,
+        ] {
+            let Some(StreamEvent::Delta(delta)) = stream.decode_message(raw).unwrap() else {
+                panic!("expected a delta");
+            };
+            for part in delta.content.unwrap_or_default() {
+                if let ContentPart::Text(text) = part {
+                    texts
+                        .entry(delta.choice_index.unwrap())
+                        .or_default()
+                        .push_str(&text);
+                }
+            }
+        }
+
+        assert_eq!(texts[&0], "Hello world");
+        assert_eq!(texts[&1], "Bonjour le monde");
+    }
+
+    #[test]
+    fn deltas_of_a_single_choice_are_not_tagged() {
+        let stream = OpenAIChatStream::state(None, None, 1);
+
+        let event = stream
+            .decode_message(
+                r#"{"id":"chatcmpl-1","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#,
+            )
+            .unwrap();
+
+        assert!(matches!(
+            event,
+            Some(StreamEvent::Delta(delta)) if delta.choice_index.is_none()
+        ));
+    }
+
+    #[test]
+    fn streamed_tool_call_is_collected_once() {
+        let recording_path =
+            std::env::temp_dir().join(format!("openai-collect-{}.sse", std::process::id()));
+        let chunks = [
+            r#"{"index":0,"delta":{"role":"assistant","tool_calls":[{"index":0,"id":"call_abc","type":"function","function":{"name":"get_weather","arguments":""}}]},"finish_reason":null}"#,
+            r#"{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"city\":"}}]},"finish_reason":null}"#,
+            r#"{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"Paris\"}"}}]},"finish_reason":null}"#,
+            r#"{"index":0,"delta":{},"finish_reason":"tool_calls"}"#,
+        ];
+        let mut recording = chunks
+            .iter()
+            .map(|choice| {
+                format!(
+                    r#"data: {{"id":"chatcmpl-1","created":1700000000,"model":"gpt-4o","choices":[{choice}]}}"#
+                ) + "\n\n"
+            })
+            .collect::<String>();
+        recording.push_str("data: [DONE]\n\n");
+        std::fs::write(&recording_path, recording).unwrap();
+
+        let stream = OpenAIChatStream::replayed(1).with_replay(recording_path.clone());
+        let collected = collect_stream(&stream);
+        std::fs::remove_file(recording_path).unwrap();
+
+        assert_eq!(
+            collected,
+            ChatEvent::ToolRequest(vec![ToolCall {
+                id: "call_abc".to_string(),
+                name: "get_weather".to_string(),
+                arguments_json: r#"{"city":"Paris"}"#.to_string(),
+            }])
+        );
+    }
+
+    #[test]
+    fn tool_calls_of_several_choices_are_kept_apart() {
+        let recording_path =
+            std::env::temp_dir().join(format!("openai-choices-{}.sse", std::process::id()));
+        let chunks = [
+            r#"{"index":0,"delta":{"role":"assistant","tool_calls":[{"index":0,"id":"call_abc","type":"function","function":{"name":"get_weather","arguments":""}}]},"finish_reason":null}"#,
+            r#"{"index":1,"delta":{"role":"assistant","tool_calls":[{"index":0,"type":"function","function":{"name":"get_time","arguments":""}}]},"finish_reason":null}"#,
+            r#"{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"city\":"}}]},"finish_reason":null}"#,
+            r#"{"index":1,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"zone\":\"UTC\"}"}}]},"finish_reason":null}"#,
+            r#"{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"Paris\"}"}}]},"finish_reason":null}"#,
+            r#"{"index":1,"delta":{},"finish_reason":"stop"}"#,
+            r#"{"index":0,"delta":{},"finish_reason":"tool_calls"}"#,
+        ];
+        let mut recording = chunks
+            .iter()
+            .map(|choice| {
+                format!(
+                    r#"data: {{"id":"chatcmpl-1","created":1700000000,"model":"gpt-4o","choices":[{choice}]}}"#
+                ) + "\n\n"
+            })
+            .collect::<String>();
+        recording.push_str("data: [DONE]\n\n");
+        std::fs::write(&recording_path, recording).unwrap();
+
+        let stream = OpenAIChatStream::replayed(2).with_replay(recording_path.clone());
+        let mut events = Vec::new();
+        loop {
+            match stream.get_next() {
+                Some(batch) if batch.is_empty() => break,
+                Some(batch) => events.extend(batch),
+                None => {}
+            }
+        }
+        std::fs::remove_file(recording_path).unwrap();
+
+        let second_choice_ids = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::Delta(delta) if delta.choice_index == Some(1) => {
+                    delta.tool_calls.as_ref()
+                }
+                _ => None,
+            })
+            .flatten()
+            .map(|tool_call| tool_call.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(second_choice_ids, vec!["call_1_0", "call_1_0"]);
+
+        let weather = ToolCall {
+            id: "call_abc".to_string(),
+            name: "get_weather".to_string(),
+            arguments_json: r#"{"city":"Paris"}"#.to_string(),
+        };
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| matches!(event, StreamEvent::ToolCallComplete(_)))
+                .collect::<Vec<_>>(),
+            vec![&StreamEvent::ToolCallComplete(weather.clone())]
+        );
+        let Some(StreamEvent::Finish(ResponseMetadata {
+            finish_reason,
+            provider_metadata_json,
+            tool_calls,
+            ..
+        })) = events.last()
+        else {
+            panic!("expected the stream to finish");
+        };
+        assert_eq!(finish_reason, &Some(FinishReason::ToolCalls));
+        assert_eq!(tool_calls, &Some(vec![weather]));
+        assert_eq!(
+            provider_metadata_json.as_deref(),
+            Some(r#"{"choice_finish_reasons":{"0":"tool_calls","1":"stop"}}"#)
+        );
+    }
+}
@@ -1,9 +1,17 @@
-use golem_llm::error::{error_code_from_status, from_event_source_error, from_reqwest_error};
+use golem_llm::cache::cache_scope;
+use golem_llm::config::{max_request_bytes, user_agent};
+use golem_llm::error::{error_from_status, from_event_source_error, from_reqwest_error};
 use golem_llm::event_source::EventSource;
-use golem_llm::golem::llm::llm::{Error, ErrorCode};
+use golem_llm::fixtures::{response_fixtures, with_response_fixtures, ResponseFixtures};
+use golem_llm::golem::llm::llm::{Config, Error, ErrorCode};
+use golem_llm::lenient::{integer, optional_float, optional_integer, parse_response_json};
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{pre_send_body, RawResponse};
+use golem_llm::request::check_request_size;
+use golem_llm::safety::SafetyRating;
 use log::trace;
-use reqwest::header::HeaderValue;
-use reqwest::{Client, Method, Response, StatusCode};
+use reqwest::header::{HeaderValue, USER_AGENT};
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Response, StatusCode};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
@@ -13,25 +21,82 @@ const BASE_URL: &str = "https://openrouter.ai";
 /// The Completions API client for creating model responses.
 pub struct CompletionsApi {
     api_key: String,
+    user_agent: String,
+    max_request_bytes: Option<usize>,
+    response_fixtures: Option<ResponseFixtures>,
     client: Client,
 }
 
 impl CompletionsApi {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(api_key: String, config: &Config) -> Self {
         let client = Client::builder()
             .build()
             .expect("Failed to initialize HTTP client");
-        Self { api_key, client }
+        Self {
+            api_key,
+            user_agent: user_agent(config, "openrouter"),
+            max_request_bytes: max_request_bytes(config, None),
+            response_fixtures: response_fixtures(config),
+            client,
+        }
     }
 
-    pub fn send_messages(&self, request: CompletionsRequest) -> Result<CompletionsResponse, Error> {
-        trace!("Sending request to OpenRouter API: {request:?}");
+    /// Identifies the server and credentials of this client in response cache keys
+    pub fn cache_scope(&self) -> String {
+        cache_scope(BASE_URL, &self.api_key)
+    }
+
+    /// Starts a request to OpenRouter carrying the user agent and the API key of this client
+    fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .header(USER_AGENT, &self.user_agent)
+            .bearer_auth(&self.api_key)
+    }
+
+    pub fn send_messages(
+        &self,
+        request: CompletionsRequest,
+    ) -> Result<RawResponse<CompletionsResponse>, Error> {
+        trace!("Sending request to OpenRouter API: {}", Redacted(&request));
+        let body = pre_send_body(&request)?;
+        check_request_size(self.max_request_bytes, &body)?;
+
+        with_response_fixtures(self.response_fixtures.as_ref(), "openrouter", &body, || {
+            let response: Response = self
+                .request(Method::POST, format!("{BASE_URL}/api/v1/chat/completions"))
+                .json(&body)
+                .send()
+                .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+            parse_response(response)
+        })
+    }
+
+    /// Gets the statistics of a finished generation, including its actual cost
+    ///
+    /// Based on https://openrouter.ai/docs/api-reference/get-a-generation
+    pub fn get_generation(&self, id: &str) -> Result<GenerationResponse, Error> {
+        trace!("Getting generation {id} from OpenRouter API");
 
         let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/api/v1/chat/completions"))
-            .bearer_auth(self.api_key.clone())
-            .json(&request)
+            .generation_request(id)
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+        parse_response(response)
+    }
+
+    fn generation_request(&self, id: &str) -> RequestBuilder {
+        self.request(Method::GET, format!("{BASE_URL}/api/v1/generation"))
+            .query(&[("id", id)])
+    }
+
+    pub fn list_models(&self) -> Result<ModelsResponse, Error> {
+        trace!("Listing models from OpenRouter API");
+
+        let response: Response = self
+            .request(Method::GET, format!("{BASE_URL}/api/v1/models"))
             .send()
             .map_err(|err| from_reqwest_error("Request failed", err))?;
 
@@ -39,17 +104,17 @@ impl CompletionsApi {
     }
 
     pub fn stream_send_messages(&self, request: CompletionsRequest) -> Result<EventSource, Error> {
-        trace!("Sending request to OpenRouter API: {request:?}");
+        trace!("Sending request to OpenRouter API: {}", Redacted(&request));
+        let body = pre_send_body(&request)?;
+        check_request_size(self.max_request_bytes, &body)?;
 
         let response: Response = self
-            .client
             .request(Method::POST, format!("{BASE_URL}/api/v1/chat/completions"))
-            .bearer_auth(self.api_key.clone())
             .header(
                 reqwest::header::ACCEPT,
                 HeaderValue::from_static("text/event-stream"),
             )
-            .json(&request)
+            .json(&body)
             .send()
             .map_err(|err| from_reqwest_error("Request failed", err))?;
 
@@ -73,6 +138,8 @@ pub struct CompletionsRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub repetition_penalty: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub seed: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stop: Option<Vec<String>>,
@@ -92,6 +159,41 @@ pub struct CompletionsRequest {
     pub min_p: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_a: Option<f32>,
+    /// Fallback models to try in order if the primary model is unavailable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub models: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<ProviderPreferences>,
+}
+
+/// Structured output, passed on to the routed provider. Providers without structured output
+/// support are skipped by OpenRouter.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ResponseFormat {
+    #[serde(rename = "json_schema")]
+    JsonSchema { json_schema: JsonSchema },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonSchema {
+    pub name: String,
+    pub schema: serde_json::Value,
+}
+
+/// Provider routing preferences
+///
+/// Based on https://openrouter.ai/docs/features/provider-routing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderPreferences {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub only: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignore: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_fallbacks: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -218,8 +320,10 @@ pub struct FunctionCall {
 pub struct CompletionsResponse {
     pub id: String,
     pub choices: Vec<Choice>,
+    #[serde(default, deserialize_with = "integer")]
     pub created: u64,
     pub model: String,
+    pub provider: Option<String>,
     pub system_fingerprint: Option<String>,
     pub usage: Option<Usage>,
 }
@@ -230,6 +334,13 @@ pub struct Choice {
     pub native_finish_reason: Option<FinishReason>,
     pub message: ResponseMessage,
     pub error: Option<ErrorResponse>,
+    /// Passed through from Gemini models
+    #[serde(
+        default,
+        rename = "safetyRatings",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub safety_ratings: Vec<SafetyRating>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -244,6 +355,9 @@ pub enum FinishReason {
     ContentFilter,
     #[serde(rename = "error")]
     Error,
+    /// Any reason not known when this was written, kept as reported
+    #[serde(untagged)]
+    Other(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -251,6 +365,26 @@ pub struct ResponseMessage {
     pub content: Option<String>,
     pub role: String,
     pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
+}
+
+/// Annotations are added to responses by the web search plugin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Annotation {
+    #[serde(rename = "url_citation")]
+    UrlCitation { url_citation: UrlCitation },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UrlCitation {
+    pub url: String,
+    pub title: Option<String>,
+    /// An excerpt of the cited page
+    pub content: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -267,16 +401,23 @@ pub struct ErrorResponseBody {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Usage {
+    #[serde(default, deserialize_with = "integer")]
     pub completion_tokens: u32,
+    #[serde(default, deserialize_with = "integer")]
     pub prompt_tokens: u32,
-    pub total_tokens: u32,
+    #[serde(default, deserialize_with = "optional_integer")]
+    pub total_tokens: Option<u32>,
+    #[serde(default, deserialize_with = "optional_float")]
+    pub cost: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionChunk {
     pub id: String,
+    #[serde(default, deserialize_with = "integer")]
     pub created: u64,
     pub model: String,
+    pub provider: Option<String>,
     pub choices: Vec<ChoiceChunk>,
     pub usage: Option<Usage>,
     pub system_fingerprint: Option<String>,
@@ -294,46 +435,90 @@ pub struct ChoiceChunk {
 pub struct ChoiceDelta {
     pub content: Option<String>,
     pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default)]
+    pub reasoning: Option<String>,
     pub role: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerationResponse {
+    pub data: GenerationData,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerationData {
+    pub id: String,
+    pub total_cost: Option<f64>,
+    pub provider_name: Option<String>,
+    pub native_tokens_prompt: Option<u32>,
+    pub native_tokens_completion: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelsResponse {
+    pub data: Vec<Model>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub id: String,
+    pub name: Option<String>,
+    pub context_length: Option<u32>,
+    pub architecture: Option<ModelArchitecture>,
+    #[serde(default)]
+    pub supported_parameters: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelArchitecture {
+    #[serde(default)]
+    pub input_modalities: Vec<String>,
+    #[serde(default)]
+    pub output_modalities: Vec<String>,
+}
+
 fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
     let status = response.status();
     if status.is_success() {
         let raw_body = response
             .text()
             .map_err(|err| from_reqwest_error("Failed to receive response body", err))?;
-        trace!("Received response from OpenRouter API: {raw_body:?}");
+        trace!(
+            "Received response from OpenRouter API: {}",
+            Redacted(&raw_body)
+        );
 
         if let Ok(body) = serde_json::from_str::<T>(&raw_body) {
-            trace!("Received response from OpenRouter API: {body:?}");
+            trace!("Received response from OpenRouter API: {}", Redacted(&body));
             Ok(body)
         } else {
-            let error_body: ErrorResponseBody =
-                serde_json::from_str(&raw_body).map_err(|err| Error {
-                    code: ErrorCode::InternalError,
-                    message: format!("Failed to parse response body: {err}"),
-                    provider_error_json: Some(raw_body),
-                })?;
+            // OpenRouter reports some errors with a success status, so the body is only a
+            // malformed response if it is not an error either
+            let Ok(error_body) = serde_json::from_str::<ErrorResponseBody>(&raw_body) else {
+                return parse_response_json("OpenRouter", raw_body);
+            };
 
             let status = TryInto::<u16>::try_into(error_body.error.code)
                 .ok()
                 .and_then(|code| StatusCode::from_u16(code).ok())
                 .unwrap_or(status);
-            Err(Error {
-                code: error_code_from_status(status),
-                message: error_body.error.message,
-                provider_error_json: error_body
+            Err(error_from_status(
+                status,
+                error_body.error.message,
+                error_body
                     .error
                     .metadata
                     .map(|value| serde_json::to_string(&value).unwrap()),
-            })
+            ))
         }
     } else {
         let raw_error_body = response
             .text()
             .map_err(|err| from_reqwest_error("Failed to receive error response body", err))?;
-        trace!("Received {status} response from OpenRouter API: {raw_error_body:?}");
+        trace!(
+            "Received {status} response from OpenRouter API: {}",
+            Redacted(&raw_error_body)
+        );
 
         let error_body: ErrorResponseBody =
             serde_json::from_str(&raw_error_body).map_err(|err| Error {
@@ -342,13 +527,66 @@ fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T,
                 provider_error_json: Some(raw_error_body),
             })?;
 
-        Err(Error {
-            code: error_code_from_status(status),
-            message: error_body.error.message,
-            provider_error_json: error_body
+        Err(error_from_status(
+            status,
+            error_body.error.message,
+            error_body
                 .error
                 .metadata
                 .map(|value| serde_json::to_string(&value).unwrap()),
-        })
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{CompletionsApi, BASE_URL};
+    use golem_llm::golem::llm::llm::{Config, Kv};
+    use reqwest::header::USER_AGENT;
+    use reqwest::Method;
+
+    #[test]
+    fn generation_id_is_encoded_in_the_url() {
+        let config = Config {
+            model: "openai/gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![],
+            system: None,
+        };
+        let api = CompletionsApi::new("api-key".to_string(), &config);
+
+        let request = api.generation_request("gen-1&id=gen 2").build().unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "https://openrouter.ai/api/v1/generation?id=gen-1%26id%3Dgen+2"
+        );
+    }
+
+    #[test]
+    fn requests_carry_the_user_agent() {
+        let config = Config {
+            model: "openai/gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![Kv {
+                key: "user_agent".to_string(),
+                value: "my-app/1.0".to_string(),
+            }],
+            system: None,
+        };
+        let api = CompletionsApi::new("api-key".to_string(), &config);
+
+        let request = api
+            .request(Method::POST, format!("{BASE_URL}/api/v1/chat/completions"))
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[USER_AGENT], "my-app/1.0");
     }
 }
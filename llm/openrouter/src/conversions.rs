@@ -1,17 +1,60 @@
 use crate::client::{
-    CompletionsRequest, CompletionsResponse, Detail, FunctionName, ToolChoiceFunction,
+    Annotation, CompletionsRequest, CompletionsResponse, Detail, FunctionName, GenerationData,
+    JsonSchema, ModelsResponse, ProviderPreferences, ResponseFormat, ToolChoiceFunction,
 };
 use base64::{engine::general_purpose, Engine as _};
+use golem_llm::capabilities::capabilities_by_prefix;
+use golem_llm::config::{max_tokens, provider_option, reject_invalid_certs_option, temperature};
+use golem_llm::context::{fit_context, fit_max_tokens};
 use golem_llm::golem::llm::llm::{
-    ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason, ImageDetail,
-    ImageReference, Message, ResponseMetadata, Role, ToolCall, ToolDefinition, ToolResult, Usage,
+    CapabilitySet, ChatEvent, Citation, CompleteResponse, Config, ContentPart, Error, ErrorCode,
+    FinishReason, ImageDetail, ImageReference, Message, ModelInfo, ResponseMetadata, Role,
+    ToolCall, ToolDefinition, ToolResult, Usage,
 };
+use golem_llm::image::{download_image, image_transport, transport_images};
+use golem_llm::model_alias::resolve_model_alias;
+use golem_llm::request::with_system_prompt;
+use golem_llm::safety::{safety_metadata, SafetyRating};
+use golem_llm::stop_sequence::limit_stop_sequences;
+use golem_llm::structured_output::response_schema;
+use golem_llm::tool_calls::{tool_call_id, tool_image_description};
+use golem_llm::validation::{validate_messages, SystemMessagePlacement};
+use serde_json::{json, Map, Value};
 use std::collections::HashMap;
 
+/// The OpenRouter models the provider-agnostic `small` and `large` model aliases resolve to
+const MODEL_ALIASES: &[(&str, &str)] =
+    &[("small", "openai/gpt-4o-mini"), ("large", "openai/gpt-4o")];
+
+/// OpenRouter's default temperature, passed on to the routed provider
+const DEFAULT_TEMPERATURE: f32 = 1.0;
+
 pub fn messages_to_request(
     messages: Vec<Message>,
     config: Config,
 ) -> Result<CompletionsRequest, Error> {
+    let config = resolve_model_alias(config, MODEL_ALIASES);
+    let messages = fit_context(with_system_prompt(&config, messages), &config)?;
+    validate_messages(&messages, SystemMessagePlacement::Anywhere)?;
+    let messages = transport_images(
+        messages,
+        image_transport(&config)?,
+        true,
+        "OpenRouter",
+        download_image,
+    )?;
+
+    let max_tokens = fit_max_tokens(max_tokens(&config), &messages, &config);
+    let temperature = temperature(&config, DEFAULT_TEMPERATURE)?;
+    let response_format =
+        response_schema(&config)?.map(|response_schema| ResponseFormat::JsonSchema {
+            json_schema: JsonSchema {
+                name: "response".to_string(),
+                schema: response_schema.schema,
+            },
+        });
+    reject_invalid_certs_option(&config)?;
+    let stop = limit_stop_sequences(&config, None)?;
     let options = config
         .provider_options
         .into_iter()
@@ -53,19 +96,20 @@ pub fn messages_to_request(
         frequency_penalty: options
             .get("frequency_penalty")
             .and_then(|fp_s| fp_s.parse::<f32>().ok()),
-        max_tokens: config.max_tokens,
+        max_tokens,
         presence_penalty: options
             .get("presence_penalty")
             .and_then(|pp_s| pp_s.parse::<f32>().ok()),
         repetition_penalty: options
             .get("repetition_penalty")
             .and_then(|rp_s| rp_s.parse::<f32>().ok()),
+        response_format,
         seed: options
             .get("seed")
             .and_then(|seed_s| seed_s.parse::<u32>().ok()),
-        stop: config.stop_sequences,
+        stop,
         stream: Some(false),
-        temperature: config.temperature,
+        temperature,
         tool_choice: config.tool_choice.map(convert_tool_choice),
         tools,
         top_p: options
@@ -80,9 +124,108 @@ pub fn messages_to_request(
         top_a: options
             .get("top_a")
             .and_then(|top_a_s| top_a_s.parse::<f32>().ok()),
+        models: parse_list_option(&options, "openrouter_models_fallback"),
+        provider: provider_preferences(&options),
     })
 }
 
+/// Checks whether the `fetch_cost` provider option is enabled, in which case the actual cost of a
+/// generation is queried from OpenRouter after the response has been received.
+pub fn fetch_cost_enabled(config: &Config) -> bool {
+    provider_option(config, "fetch_cost").is_some_and(|value| value == "true")
+}
+
+/// Creates the provider metadata JSON containing the generation id, which can be used to query
+/// the generation's statistics, and the cost if it was included in the usage.
+pub fn provider_metadata(
+    generation_id: &str,
+    model: &str,
+    provider: Option<&str>,
+    usage: Option<&crate::client::Usage>,
+    safety_ratings: &[SafetyRating],
+) -> String {
+    let mut metadata = Map::new();
+    metadata.insert("generation_id".to_string(), json!(generation_id));
+    metadata.insert("model".to_string(), json!(model));
+    if let Some(provider) = provider {
+        metadata.insert("provider".to_string(), json!(provider));
+    }
+    if let Some(cost) = usage.and_then(|usage| usage.cost) {
+        metadata.insert("cost".to_string(), json!(cost));
+    }
+    if let Some(safety) = safety_metadata(safety_ratings) {
+        metadata.insert("safety".to_string(), safety);
+    }
+    Value::Object(metadata).to_string()
+}
+
+/// Adds the statistics of a generation fetched from OpenRouter to the response's provider metadata
+pub fn add_generation_stats(metadata: &mut ResponseMetadata, generation: &GenerationData) {
+    let mut provider_metadata = metadata
+        .provider_metadata_json
+        .as_deref()
+        .and_then(|json| serde_json::from_str::<Map<String, Value>>(json).ok())
+        .unwrap_or_default();
+
+    if let Some(total_cost) = generation.total_cost {
+        provider_metadata.insert("total_cost".to_string(), json!(total_cost));
+    }
+    if let Some(provider_name) = &generation.provider_name {
+        provider_metadata.insert("provider".to_string(), json!(provider_name));
+    }
+    if let Some(native_tokens_prompt) = generation.native_tokens_prompt {
+        provider_metadata.insert(
+            "native_tokens_prompt".to_string(),
+            json!(native_tokens_prompt),
+        );
+    }
+    if let Some(native_tokens_completion) = generation.native_tokens_completion {
+        provider_metadata.insert(
+            "native_tokens_completion".to_string(),
+            json!(native_tokens_completion),
+        );
+    }
+
+    metadata.provider_metadata_json = Some(Value::Object(provider_metadata).to_string());
+}
+
+fn provider_preferences(options: &HashMap<String, String>) -> Option<ProviderPreferences> {
+    let preferences = ProviderPreferences {
+        order: parse_list_option(options, "openrouter_provider_order"),
+        only: parse_list_option(options, "openrouter_provider_only"),
+        ignore: parse_list_option(options, "openrouter_provider_ignore"),
+        allow_fallbacks: options
+            .get("openrouter_allow_fallbacks")
+            .and_then(|af_s| af_s.parse::<bool>().ok()),
+    };
+
+    if preferences.order.is_none()
+        && preferences.only.is_none()
+        && preferences.ignore.is_none()
+        && preferences.allow_fallbacks.is_none()
+    {
+        None
+    } else {
+        Some(preferences)
+    }
+}
+
+/// Parses a comma-separated provider option into a list, ignoring empty entries
+fn parse_list_option(options: &HashMap<String, String>, key: &str) -> Option<Vec<String>> {
+    let values = options
+        .get(key)?
+        .split(',')
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect::<Vec<_>>();
+
+    if values.is_empty() {
+        None
+    } else {
+        Some(values)
+    }
+}
+
 pub fn process_response(response: CompletionsResponse) -> ChatEvent {
     let choice = response.choices.first();
     if let Some(choice) = choice {
@@ -91,11 +234,30 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
 
         if let Some(content) = &choice.message.content {
             contents.push(ContentPart::Text(content.clone()));
+            contents.extend(choice.message.annotations.iter().filter_map(|annotation| {
+                match annotation {
+                    Annotation::UrlCitation { url_citation } => {
+                        Some(ContentPart::Citation(Citation {
+                            title: url_citation.title.clone(),
+                            url: url_citation.url.clone(),
+                            quoted_text: url_citation.content.clone(),
+                        }))
+                    }
+                    Annotation::Other => None,
+                }
+            }));
         }
 
         let empty = Vec::new();
-        for tool_call in choice.message.tool_calls.as_ref().unwrap_or(&empty) {
-            tool_calls.push(convert_tool_call(tool_call));
+        for (position, tool_call) in choice
+            .message
+            .tool_calls
+            .as_ref()
+            .unwrap_or(&empty)
+            .iter()
+            .enumerate()
+        {
+            tool_calls.push(convert_tool_call(tool_call, position));
         }
 
         if contents.is_empty() {
@@ -106,7 +268,15 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
                 usage: response.usage.as_ref().map(convert_usage),
                 provider_id: None,
                 timestamp: Some(response.created.to_string()),
-                provider_metadata_json: None,
+                provider_metadata_json: Some(provider_metadata(
+                    &response.id,
+                    &response.model,
+                    response.provider.as_deref(),
+                    response.usage.as_ref(),
+                    &choice.safety_ratings,
+                )),
+                stop_sequence: None,
+                tool_calls: None,
             };
 
             ChatEvent::Message(CompleteResponse {
@@ -145,6 +315,7 @@ pub fn tool_results_to_messages(
         let content = match tool_result {
             ToolResult::Success(success) => success.result_json,
             ToolResult::Error(failure) => failure.error_message,
+            ToolResult::Image(tool_image) => tool_image_description(&tool_image),
         };
         messages.push(crate::client::Message::Tool {
             name: None,
@@ -155,10 +326,14 @@ pub fn tool_results_to_messages(
     messages
 }
 
-pub fn convert_tool_call(tool_call: &crate::client::ToolCall) -> ToolCall {
+pub fn convert_tool_call(tool_call: &crate::client::ToolCall, position: usize) -> ToolCall {
     match tool_call {
-        crate::client::ToolCall::Function { function, id, .. } => ToolCall {
-            id: id.clone().unwrap_or_default(),
+        crate::client::ToolCall::Function {
+            function,
+            id,
+            index,
+        } => ToolCall {
+            id: tool_call_id(id.as_deref(), index.unwrap_or(position as u32)),
             name: function.name.clone().unwrap_or_default(),
             arguments_json: function.arguments.clone(),
         },
@@ -190,6 +365,7 @@ fn convert_content_parts(contents: Vec<ContentPart>) -> crate::client::Content {
                     });
                 }
             },
+            ContentPart::Citation(_) => {}
         }
     }
     crate::client::Content::List(result)
@@ -201,6 +377,7 @@ fn convert_content_parts_to_string(contents: Vec<ContentPart>) -> String {
         match content {
             ContentPart::Text(text) => result.push_str(&text),
             ContentPart::Image(_) => {} // Correctly ignores any image content
+            ContentPart::Citation(_) => {}
         }
     }
     result
@@ -223,6 +400,7 @@ pub fn convert_finish_reason(value: &crate::client::FinishReason) -> FinishReaso
         crate::client::FinishReason::ContentFilter => FinishReason::ContentFilter,
         crate::client::FinishReason::ToolCalls => FinishReason::ToolCalls,
         crate::client::FinishReason::Error => FinishReason::Error,
+        crate::client::FinishReason::Other(value) => FinishReason::Other(value.clone()),
     }
 }
 
@@ -230,7 +408,9 @@ pub fn convert_usage(value: &crate::client::Usage) -> Usage {
     Usage {
         input_tokens: Some(value.prompt_tokens),
         output_tokens: Some(value.completion_tokens),
-        total_tokens: Some(value.total_tokens),
+        total_tokens: value
+            .total_tokens
+            .or_else(|| value.prompt_tokens.checked_add(value.completion_tokens)),
     }
 }
 
@@ -259,3 +439,439 @@ fn convert_tool_choice(tool_choice: String) -> crate::client::ToolChoice {
         }),
     }
 }
+
+pub fn process_models_response(response: ModelsResponse) -> Vec<ModelInfo> {
+    response
+        .data
+        .into_iter()
+        .map(|model| {
+            let mut capabilities = Vec::new();
+            if model
+                .architecture
+                .as_ref()
+                .is_some_and(|arch| arch.input_modalities.iter().any(|m| m == "image"))
+            {
+                capabilities.push("vision".to_string());
+            }
+            if model.supported_parameters.iter().any(|p| p == "tools") {
+                capabilities.push("tools".to_string());
+            }
+            if model.supported_parameters.iter().any(|p| p == "reasoning") {
+                capabilities.push("reasoning".to_string());
+            }
+
+            ModelInfo {
+                id: model.id,
+                context_length: model.context_length,
+                capabilities: Some(capabilities),
+            }
+        })
+        .collect()
+}
+
+const TEXT: CapabilitySet = CapabilitySet {
+    images: false,
+    tools: true,
+    streaming: true,
+    json_mode: true,
+};
+
+const VISION: CapabilitySet = CapabilitySet {
+    images: true,
+    tools: true,
+    streaming: true,
+    json_mode: true,
+};
+
+/// Models of the other vendors are matched by their name without the `vendor/` prefix
+const MODEL_CAPABILITIES: &[(&str, CapabilitySet)] = &[
+    ("claude-3", VISION),
+    ("claude-opus-4", VISION),
+    ("claude-sonnet-4", VISION),
+    ("gemini", VISION),
+    ("gpt-4-turbo", VISION),
+    ("gpt-4.1", VISION),
+    ("gpt-4o", VISION),
+    ("grok-2-vision", VISION),
+    ("llama-3.2-11b-vision", VISION),
+    ("llama-3.2-90b-vision", VISION),
+    ("llama-4", VISION),
+    ("pixtral", VISION),
+    ("qwen-2.5-vl", VISION),
+];
+
+pub fn model_capabilities(model: &str) -> CapabilitySet {
+    capabilities_by_prefix(model, MODEL_CAPABILITIES, TEXT)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ModelsResponse;
+    use crate::client::{CompletionsResponse, GenerationResponse};
+    use crate::conversions::process_models_response;
+    use crate::conversions::{add_generation_stats, messages_to_request, process_response};
+    use crate::OpenRouterComponent;
+    use golem_llm::durability::ExtendedGuest;
+    use golem_llm::golem::llm::llm::ModelInfo;
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, Citation, Config, ContentPart, FinishReason, Kv, Message, Role, StreamDelta,
+    };
+    use serde_json::json;
+
+    fn request_json(provider_options: Vec<(&str, &str)>) -> serde_json::Value {
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("Hello".to_string())],
+        }];
+        let config = Config {
+            model: "openai/gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: provider_options
+                .into_iter()
+                .map(|(key, value)| Kv {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+            system: None,
+        };
+        serde_json::to_value(messages_to_request(messages, config).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn provider_order_and_fallback_models() {
+        let request = request_json(vec![
+            ("openrouter_provider_order", "anthropic, openai"),
+            (
+                "openrouter_models_fallback",
+                "anthropic/claude-3.5-sonnet,gryphe/mythomax-l2-13b",
+            ),
+        ]);
+
+        assert_eq!(
+            request["provider"],
+            json!({"order": ["anthropic", "openai"]})
+        );
+        assert_eq!(
+            request["models"],
+            json!(["anthropic/claude-3.5-sonnet", "gryphe/mythomax-l2-13b"])
+        );
+    }
+
+    #[test]
+    fn provider_allow_and_deny_lists() {
+        let request = request_json(vec![
+            ("openrouter_provider_only", "azure"),
+            ("openrouter_provider_ignore", "deepinfra,,together"),
+            ("openrouter_allow_fallbacks", "false"),
+        ]);
+
+        assert_eq!(
+            request["provider"],
+            json!({
+                "only": ["azure"],
+                "ignore": ["deepinfra", "together"],
+                "allow_fallbacks": false
+            })
+        );
+        assert!(request.get("models").is_none());
+    }
+
+    #[test]
+    fn no_routing_options() {
+        let request = request_json(vec![]);
+
+        assert!(request.get("provider").is_none());
+        assert!(request.get("models").is_none());
+    }
+
+    fn completion_response() -> CompletionsResponse {
+        serde_json::from_value(json!({
+            "id": "gen-1234",
+            "created": 1700000000,
+            "model": "openai/gpt-4o",
+            "provider": "OpenAI",
+            "system_fingerprint": null,
+            "choices": [{
+                "finish_reason": "stop",
+                "native_finish_reason": "stop",
+                "error": null,
+                "message": {
+                    "role": "assistant",
+                    "content": "Hello!",
+                    "tool_calls": null
+                }
+            }],
+            "usage": {
+                "prompt_tokens": 10,
+                "completion_tokens": 2,
+                "total_tokens": 12,
+                "cost": 0.00015
+            }
+        }))
+        .unwrap()
+    }
+
+    fn provider_metadata(event: &ChatEvent) -> serde_json::Value {
+        let ChatEvent::Message(response) = event else {
+            panic!("expected a message response");
+        };
+        serde_json::from_str(response.metadata.provider_metadata_json.as_ref().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn generation_id_and_cost_metadata() {
+        let event = process_response(completion_response());
+
+        assert_eq!(
+            provider_metadata(&event),
+            json!({
+                "generation_id": "gen-1234",
+                "model": "openai/gpt-4o",
+                "provider": "OpenAI",
+                "cost": 0.00015
+            })
+        );
+    }
+
+    #[test]
+    fn gemini_safety_ratings_are_normalized() {
+        let response: CompletionsResponse = serde_json::from_value(json!({
+            "id": "gen-9012",
+            "created": 1700000000,
+            "model": "google/gemini-2.0-flash-001",
+            "provider": "Google",
+            "system_fingerprint": null,
+            "choices": [{
+                "finish_reason": "stop",
+                "native_finish_reason": "stop",
+                "error": null,
+                "message": {
+                    "role": "assistant",
+                    "content": "Here is a gentle bedtime story.",
+                    "tool_calls": null
+                },
+                "safetyRatings": [
+                    {
+                        "category": "HARM_CATEGORY_HARASSMENT",
+                        "probability": "NEGLIGIBLE",
+                        "probabilityScore": 0.05
+                    },
+                    {
+                        "category": "HARM_CATEGORY_DANGEROUS_CONTENT",
+                        "probability": "LOW",
+                        "blocked": false
+                    }
+                ]
+            }],
+            "usage": null
+        }))
+        .unwrap();
+
+        assert_eq!(
+            provider_metadata(&process_response(response))["safety"],
+            json!({
+                "harassment": {"probability": "negligible", "score": 0.05, "blocked": false},
+                "dangerous_content": {"probability": "low", "blocked": false}
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_finish_reason_is_kept_as_other() {
+        let response: CompletionsResponse = serde_json::from_value(json!({
+            "id": "gen-3456",
+            "created": 1700000000,
+            "model": "google/gemini-2.0-flash-001",
+            "provider": "Google",
+            "system_fingerprint": null,
+            "choices": [{
+                "finish_reason": "safety",
+                "native_finish_reason": "SAFETY",
+                "error": null,
+                "message": {
+                    "role": "assistant",
+                    "content": "I can't continue with this story.",
+                    "tool_calls": null
+                }
+            }],
+            "usage": null
+        }))
+        .unwrap();
+
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message");
+        };
+        assert_eq!(
+            response.metadata.finish_reason,
+            Some(FinishReason::Other("safety".to_string()))
+        );
+    }
+
+    #[test]
+    fn fetched_generation_cost_metadata() {
+        let generation: GenerationResponse = serde_json::from_value(json!({
+            "data": {
+                "id": "gen-1234",
+                "total_cost": 0.0002,
+                "provider_name": "OpenAI",
+                "native_tokens_prompt": 11,
+                "native_tokens_completion": 3
+            }
+        }))
+        .unwrap();
+
+        let mut event = process_response(completion_response());
+        if let ChatEvent::Message(response) = &mut event {
+            add_generation_stats(&mut response.metadata, &generation.data);
+        }
+
+        assert_eq!(
+            provider_metadata(&event),
+            json!({
+                "generation_id": "gen-1234",
+                "model": "openai/gpt-4o",
+                "provider": "OpenAI",
+                "cost": 0.00015,
+                "total_cost": 0.0002,
+                "native_tokens_prompt": 11,
+                "native_tokens_completion": 3
+            })
+        );
+    }
+
+    #[test]
+    fn web_search_annotations_become_citation_parts() {
+        let response: CompletionsResponse = serde_json::from_value(json!({
+            "id": "gen-5678",
+            "created": 1700000000,
+            "model": "openai/gpt-4o:online",
+            "provider": "OpenAI",
+            "system_fingerprint": null,
+            "choices": [{
+                "finish_reason": "stop",
+                "native_finish_reason": "stop",
+                "error": null,
+                "message": {
+                    "role": "assistant",
+                    "content": "Golem 1.3 added agents.",
+                    "tool_calls": null,
+                    "annotations": [{
+                        "type": "url_citation",
+                        "url_citation": {
+                            "url": "https://golem.cloud/blog/golem-1-3",
+                            "title": "Golem 1.3 release",
+                            "content": "Golem 1.3 introduces agents",
+                            "start_index": 0,
+                            "end_index": 22
+                        }
+                    }]
+                }
+            }],
+            "usage": null
+        }))
+        .unwrap();
+
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message response");
+        };
+        assert_eq!(
+            response.content,
+            vec![
+                ContentPart::Text("Golem 1.3 added agents.".to_string()),
+                ContentPart::Citation(Citation {
+                    title: Some("Golem 1.3 release".to_string()),
+                    url: "https://golem.cloud/blog/golem-1-3".to_string(),
+                    quoted_text: Some("Golem 1.3 introduces agents".to_string()),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn models_response() {
+        let response: ModelsResponse = serde_json::from_str(
+            r#"{
+            "data": [
+                {
+                    "id": "openai/gpt-4o",
+                    "name": "OpenAI: GPT-4o",
+                    "context_length": 128000,
+                    "architecture": {
+                        "input_modalities": ["text", "image"],
+                        "output_modalities": ["text"]
+                    },
+                    "supported_parameters": ["tools", "tool_choice", "temperature"]
+                },
+                {
+                    "id": "gryphe/mythomax-l2-13b",
+                    "name": "MythoMax 13B",
+                    "context_length": 4096,
+                    "architecture": {
+                        "input_modalities": ["text"],
+                        "output_modalities": ["text"]
+                    },
+                    "supported_parameters": ["temperature"]
+                }
+            ]
+        }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            process_models_response(response),
+            vec![
+                ModelInfo {
+                    id: "openai/gpt-4o".to_string(),
+                    context_length: Some(128000),
+                    capabilities: Some(vec!["vision".to_string(), "tools".to_string()]),
+                },
+                ModelInfo {
+                    id: "gryphe/mythomax-l2-13b".to_string(),
+                    context_length: Some(4096),
+                    capabilities: Some(vec![]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn retry_prompt_is_accepted() {
+        let message = |role: Role, text: &str| Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        };
+        let original_messages = vec![
+            message(Role::System, "Answer briefly"),
+            message(Role::User, "What is the weather like?"),
+            message(Role::Assistant, "Which city?"),
+            message(Role::User, "Berlin"),
+        ];
+        let partial_result = vec![StreamDelta {
+            content: Some(vec![ContentPart::Text("It is sunny".to_string())]),
+            tool_calls: None,
+            reasoning: None,
+            choice_index: None,
+        }];
+
+        let messages = OpenRouterComponent::retry_prompt(&original_messages, &partial_result);
+
+        let config = Config {
+            model: "openai/gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![],
+            system: None,
+        };
+        assert!(messages_to_request(messages, config).is_ok());
+    }
+}
@@ -3,24 +3,37 @@ mod conversions;
 
 use crate::client::{ChatCompletionChunk, CompletionsApi, CompletionsRequest, FunctionCall};
 use crate::conversions::{
-    convert_finish_reason, convert_usage, messages_to_request, process_response,
-    tool_results_to_messages,
+    add_generation_stats, convert_finish_reason, convert_usage, fetch_cost_enabled,
+    messages_to_request, model_capabilities, process_models_response, process_response,
+    provider_metadata, tool_results_to_messages,
 };
+use golem_llm::cache::{cache_ttl, cached_response, response_cache_key};
 use golem_llm::chat_stream::{LlmChatStream, LlmChatStreamState};
-use golem_llm::config::with_config_key;
-use golem_llm::durability::{DurableLLM, ExtendedGuest};
-use golem_llm::error::error_code_from_status;
+use golem_llm::concurrency::acquire_request_slot;
+use golem_llm::config::{stream_replay_file, with_config_key};
+use golem_llm::context::reserve_context_for_tool_results;
+use golem_llm::dry_run::{dry_run_response, is_dry_run, with_api_key_unless_dry_run};
+use golem_llm::durability::{retry_prompt_with_style, DurableLLM, ExtendedGuest, RetryPromptStyle};
+use golem_llm::error::{error_code_from_status, reject_empty_response, unsupported};
 use golem_llm::event_source::EventSource;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, ChatStream, Config, ContentPart, Error, FinishReason, Guest, Message,
-    ResponseMetadata, Role, StreamDelta, StreamEvent, ToolCall, ToolResult,
+    BatchInfo, BatchRequest, BatchResult, CapabilitySet, ChatEvent, ChatStream, Config,
+    ContentPart, EmbeddingResponse, Error, FinishReason, Guest, ImageReference, Message, ModelInfo,
+    ResponseMetadata, StreamDelta, StreamEvent, ToolCall, ToolResult, Usage,
 };
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{include_raw_response, with_raw_response};
+use golem_llm::structured_output::{response_schema, validate_structured_output, ResponseSchema};
+use golem_llm::summarize::summarize_history;
+use golem_llm::tool_calls::{reject_malformed_tool_calls, tool_call_id};
+use golem_llm::usage::sum_usage;
 use golem_llm::LOGGING_STATE;
 use golem_rust::wasm_rpc::Pollable;
 use log::trace;
 use reqwest::StatusCode;
 use std::cell::{Ref, RefCell, RefMut};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
 
 #[derive(Default)]
 struct JsonFragment {
@@ -34,7 +47,7 @@ struct OpenRouterChatStream {
     failure: Option<Error>,
     finished: RefCell<bool>,
     finish_reason: RefCell<Option<FinishReason>>,
-    json_fragments: RefCell<HashMap<u32, JsonFragment>>,
+    json_fragments: RefCell<BTreeMap<u32, JsonFragment>>,
 }
 
 impl OpenRouterChatStream {
@@ -44,7 +57,7 @@ impl OpenRouterChatStream {
             failure: None,
             finished: RefCell::new(false),
             finish_reason: RefCell::new(None),
-            json_fragments: RefCell::new(HashMap::new()),
+            json_fragments: RefCell::new(BTreeMap::new()),
         })
     }
 
@@ -54,7 +67,17 @@ impl OpenRouterChatStream {
             failure: Some(error),
             finished: RefCell::new(false),
             finish_reason: RefCell::new(None),
-            json_fragments: RefCell::new(HashMap::new()),
+            json_fragments: RefCell::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn replayed() -> LlmChatStream<Self> {
+        LlmChatStream::new(OpenRouterChatStream {
+            stream: RefCell::new(None),
+            failure: None,
+            finished: RefCell::new(false),
+            finish_reason: RefCell::new(None),
+            json_fragments: RefCell::new(BTreeMap::new()),
         })
     }
 }
@@ -81,7 +104,7 @@ impl LlmChatStreamState for OpenRouterChatStream {
     }
 
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
-        trace!("Received raw stream event: {raw}");
+        trace!("Received raw stream event: {}", Redacted(&raw));
         if raw.starts_with(": ") {
             Ok(None) // comment
         } else {
@@ -99,11 +122,19 @@ impl LlmChatStreamState for OpenRouterChatStream {
                     if let Some(usage) = message.usage {
                         let finish_reason = self.finish_reason.borrow();
                         Ok(Some(StreamEvent::Finish(ResponseMetadata {
-                            finish_reason: *finish_reason,
+                            finish_reason: finish_reason.clone(),
                             usage: Some(convert_usage(&usage)),
                             provider_id: None,
                             timestamp: Some(message.created.to_string()),
-                            provider_metadata_json: None,
+                            provider_metadata_json: Some(provider_metadata(
+                                &message.id,
+                                &message.model,
+                                message.provider.as_deref(),
+                                Some(&usage),
+                                &[],
+                            )),
+                            stop_sequence: None,
+                            tool_calls: None,
                         })))
                     } else if let Some(choice) = message.choices.into_iter().next() {
                         if let Some(finish_reason) = choice.finish_reason {
@@ -133,10 +164,16 @@ impl LlmChatStreamState for OpenRouterChatStream {
                             let mut tool_calls = Vec::new();
                             let mut json_fragments = self.json_fragments.borrow_mut();
 
-                            for tool_call in choice.delta.tool_calls.unwrap_or_default() {
+                            for (position, tool_call) in choice
+                                .delta
+                                .tool_calls
+                                .unwrap_or_default()
+                                .into_iter()
+                                .enumerate()
+                            {
                                 match tool_call {
                                     client::ToolCall::Function {
-                                        id: Some(id),
+                                        id,
                                         function:
                                             FunctionCall {
                                                 name: Some(name),
@@ -146,13 +183,13 @@ impl LlmChatStreamState for OpenRouterChatStream {
                                     } => {
                                         // Full tool call
                                         tool_calls.push(ToolCall {
-                                            id,
+                                            id: tool_call_id(id.as_deref(), position as u32),
                                             name,
                                             arguments_json: arguments,
                                         });
                                     }
                                     client::ToolCall::Function {
-                                        id: Some(id),
+                                        id,
                                         function:
                                             FunctionCall {
                                                 name: Some(name),
@@ -164,7 +201,7 @@ impl LlmChatStreamState for OpenRouterChatStream {
                                         json_fragments.insert(
                                             index,
                                             JsonFragment {
-                                                id,
+                                                id: tool_call_id(id.as_deref(), index),
                                                 name,
                                                 json: arguments,
                                             },
@@ -210,6 +247,11 @@ impl LlmChatStreamState for OpenRouterChatStream {
                                 } else {
                                     Some(tool_calls)
                                 },
+                                reasoning: choice
+                                    .delta
+                                    .reasoning
+                                    .filter(|reasoning| !reasoning.is_empty()),
+                                choice_index: None,
                             })))
                         }
                     } else {
@@ -223,6 +265,26 @@ impl LlmChatStreamState for OpenRouterChatStream {
             }
         }
     }
+
+    fn pending_finish(&self) -> ResponseMetadata {
+        ResponseMetadata {
+            finish_reason: self.finish_reason.borrow().clone(),
+            usage: None,
+            provider_id: None,
+            timestamp: None,
+            provider_metadata_json: None,
+            stop_sequence: None,
+            tool_calls: None,
+        }
+    }
+
+    fn tool_call_order(&self) -> Vec<String> {
+        self.json_fragments
+            .borrow()
+            .values()
+            .map(|fragment| fragment.id.clone())
+            .collect()
+    }
 }
 
 struct OpenRouterComponent;
@@ -230,10 +292,58 @@ struct OpenRouterComponent;
 impl OpenRouterComponent {
     const ENV_VAR_NAME: &'static str = "OPENROUTER_API_KEY";
 
-    fn request(client: CompletionsApi, request: CompletionsRequest) -> ChatEvent {
-        match client.send_messages(request) {
-            Ok(response) => process_response(response),
-            Err(err) => ChatEvent::Error(err),
+    fn request(
+        client: CompletionsApi,
+        request: CompletionsRequest,
+        dry_run: bool,
+        include_raw_response: bool,
+        fetch_cost: bool,
+        cache_ttl: Option<Duration>,
+        response_schema: Option<ResponseSchema>,
+    ) -> ChatEvent {
+        if dry_run {
+            return dry_run_response("openrouter", &request);
+        }
+
+        let cache_key = response_cache_key(cache_ttl, &client.cache_scope(), &request);
+        cached_response(cache_key, || {
+            validate_structured_output(response_schema.as_ref(), || {
+                match client.send_messages(request.clone()) {
+                    Ok(response) => {
+                        let event = with_raw_response(
+                            reject_malformed_tool_calls(reject_empty_response(process_response(
+                                response.body,
+                            ))),
+                            include_raw_response.then_some(response.raw),
+                        );
+                        if fetch_cost {
+                            Self::with_generation_stats(&client, event)
+                        } else {
+                            event
+                        }
+                    }
+                    Err(err) => ChatEvent::Error(err),
+                }
+            })
+        })
+    }
+
+    fn with_generation_stats(client: &CompletionsApi, event: ChatEvent) -> ChatEvent {
+        match event {
+            ChatEvent::Message(mut response) => {
+                match client.get_generation(&response.id) {
+                    Ok(generation) => {
+                        add_generation_stats(&mut response.metadata, &generation.data)
+                    }
+                    Err(err) => trace!(
+                        "Failed to fetch generation stats for {}: {}",
+                        response.id,
+                        err.message
+                    ),
+                }
+                ChatEvent::Message(response)
+            }
+            other => other,
         }
     }
 
@@ -254,15 +364,34 @@ impl Guest for OpenRouterComponent {
 
     fn send(messages: Vec<Message>, config: Config) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
+        let dry_run = is_dry_run(&config);
+        let include_raw_response = include_raw_response(&config);
+        let cache_ttl = cache_ttl(&config);
+        let response_schema = response_schema(&config).ok().flatten();
+        let fetch_cost = fetch_cost_enabled(&config);
 
-        with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |openrouter_api_key| {
-            let client = CompletionsApi::new(openrouter_api_key);
+        with_api_key_unless_dry_run(
+            Self::ENV_VAR_NAME,
+            dry_run,
+            ChatEvent::Error,
+            |openrouter_api_key| {
+                let _permit = acquire_request_slot(&config, "openrouter", &openrouter_api_key);
+                let client = CompletionsApi::new(openrouter_api_key, &config);
 
-            match messages_to_request(messages, config) {
-                Ok(request) => Self::request(client, request),
-                Err(err) => ChatEvent::Error(err),
-            }
-        })
+                match messages_to_request(messages, config) {
+                    Ok(request) => Self::request(
+                        client,
+                        request,
+                        dry_run,
+                        include_raw_response,
+                        fetch_cost,
+                        cache_ttl,
+                        response_schema,
+                    ),
+                    Err(err) => ChatEvent::Error(err),
+                }
+            },
+        )
     }
 
     fn continue_(
@@ -271,42 +400,146 @@ impl Guest for OpenRouterComponent {
         config: Config,
     ) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
+        let dry_run = is_dry_run(&config);
+        let include_raw_response = include_raw_response(&config);
+        let cache_ttl = cache_ttl(&config);
+        let response_schema = response_schema(&config).ok().flatten();
+        let fetch_cost = fetch_cost_enabled(&config);
 
-        with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |openrouter_api_key| {
-            let client = CompletionsApi::new(openrouter_api_key);
-
-            match messages_to_request(messages, config) {
-                Ok(mut request) => {
-                    request
-                        .messages
-                        .extend(tool_results_to_messages(tool_results));
-                    Self::request(client, request)
+        with_api_key_unless_dry_run(
+            Self::ENV_VAR_NAME,
+            dry_run,
+            ChatEvent::Error,
+            |openrouter_api_key| {
+                let _permit = acquire_request_slot(&config, "openrouter", &openrouter_api_key);
+                let client = CompletionsApi::new(openrouter_api_key, &config);
+
+                match messages_to_request(
+                    messages,
+                    reserve_context_for_tool_results(config, &tool_results),
+                ) {
+                    Ok(mut request) => {
+                        request
+                            .messages
+                            .extend(tool_results_to_messages(tool_results));
+                        Self::request(
+                            client,
+                            request,
+                            dry_run,
+                            include_raw_response,
+                            fetch_cost,
+                            cache_ttl,
+                            response_schema,
+                        )
+                    }
+                    Err(err) => ChatEvent::Error(err),
                 }
-                Err(err) => ChatEvent::Error(err),
-            }
-        })
+            },
+        )
     }
 
     fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
         ChatStream::new(Self::unwrapped_stream(messages, config))
     }
+
+    fn embed(_inputs: Vec<String>, _config: Config) -> Result<EmbeddingResponse, Error> {
+        Err(unsupported(
+            "Embeddings are not supported by the OpenRouter provider",
+        ))
+    }
+
+    fn generate_image(_prompt: String, _config: Config) -> Result<Vec<ImageReference>, Error> {
+        Err(unsupported(
+            "Image generation is not supported by the OpenRouter provider",
+        ))
+    }
+
+    fn list_models(config: Config) -> Result<Vec<ModelInfo>, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        with_config_key(Self::ENV_VAR_NAME, Err, |openrouter_api_key| {
+            let client = CompletionsApi::new(openrouter_api_key, &config);
+            client.list_models().map(process_models_response)
+        })
+    }
+
+    fn validate_credentials(config: Config) -> Result<(), Error> {
+        Self::list_models(config).map(|_| ())
+    }
+
+    fn capabilities(config: Config) -> CapabilitySet {
+        model_capabilities(&config.model)
+    }
+
+    fn batch_submit(_requests: Vec<BatchRequest>, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the OpenRouter provider",
+        ))
+    }
+
+    fn batch_status(_batch_id: String, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the OpenRouter provider",
+        ))
+    }
+
+    fn batch_results(_batch_id: String, _config: Config) -> Result<Vec<BatchResult>, Error> {
+        Err(unsupported(
+            "Batches are not supported by the OpenRouter provider",
+        ))
+    }
+
+    fn summarize_history(
+        messages: Vec<Message>,
+        config: Config,
+        keep_last_n: u32,
+    ) -> Result<Vec<Message>, Error> {
+        summarize_history(messages, config, keep_last_n, Self::send)
+    }
+
+    fn sum_usage(usages: Vec<Usage>) -> Usage {
+        sum_usage(usages)
+    }
 }
 
 impl ExtendedGuest for OpenRouterComponent {
     fn unwrapped_stream(
         messages: Vec<Message>,
         config: Config,
+    ) -> LlmChatStream<OpenRouterChatStream> {
+        Self::unwrapped_continue_stream(messages, Vec::new(), config)
+    }
+
+    fn unwrapped_continue_stream(
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
     ) -> LlmChatStream<OpenRouterChatStream> {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
+        if stream_replay_file(&config).is_some() {
+            return OpenRouterChatStream::replayed().with_stream_options(&config);
+        }
+
         with_config_key(
             Self::ENV_VAR_NAME,
             OpenRouterChatStream::failed,
             |openrouter_api_key| {
-                let client = CompletionsApi::new(openrouter_api_key);
-
-                match messages_to_request(messages, config) {
-                    Ok(request) => Self::streaming_request(client, request),
+                let permit = acquire_request_slot(&config, "openrouter", &openrouter_api_key);
+                let client = CompletionsApi::new(openrouter_api_key, &config);
+
+                match messages_to_request(
+                    messages,
+                    reserve_context_for_tool_results(config.clone(), &tool_results),
+                ) {
+                    Ok(mut request) => {
+                        request
+                            .messages
+                            .extend(tool_results_to_messages(tool_results));
+                        Self::streaming_request(client, request)
+                            .with_request_permit(permit)
+                            .with_stream_options(&config)
+                    }
                     Err(err) => OpenRouterChatStream::failed(err),
                 }
             },
@@ -314,57 +547,20 @@ impl ExtendedGuest for OpenRouterComponent {
     }
 
     fn retry_prompt(original_messages: &[Message], partial_result: &[StreamDelta]) -> Vec<Message> {
-        let mut extended_messages = Vec::new();
-        extended_messages.push(Message {
-            role: Role::System,
-            name: None,
-            content: vec![
-                ContentPart::Text(
-                    "You were asked the same question previously, but the response was interrupted before completion. \
-                     Please continue your response from where you left off. \
-                     Do not include the part of the response that was already seen.".to_string()),
-            ],
-        });
-        extended_messages.push(Message {
-            role: Role::User,
-            name: None,
-            content: vec![ContentPart::Text(
-                "Here is the original question:".to_string(),
-            )],
-        });
-        extended_messages.extend_from_slice(original_messages);
-
-        let mut partial_result_as_content = Vec::new();
-        for delta in partial_result {
-            if let Some(contents) = &delta.content {
-                partial_result_as_content.extend_from_slice(contents);
-            }
-            if let Some(tool_calls) = &delta.tool_calls {
-                for tool_call in tool_calls {
-                    partial_result_as_content.push(ContentPart::Text(format!(
-                        "<tool-call id=\"{}\" name=\"{}\" arguments=\"{}\"/>",
-                        tool_call.id, tool_call.name, tool_call.arguments_json,
-                    )));
-                }
-            }
-        }
-
-        extended_messages.push(Message {
-            role: Role::User,
-            name: None,
-            content: vec![ContentPart::Text(
-                "Here is the partial response that was successfully received:".to_string(),
-            )]
-            .into_iter()
-            .chain(partial_result_as_content)
-            .collect(),
-        });
-        extended_messages
+        retry_prompt_with_style(
+            original_messages,
+            partial_result,
+            RetryPromptStyle::UserMessage,
+        )
     }
 
     fn subscribe(stream: &Self::ChatStream) -> Pollable {
         stream.subscribe()
     }
+
+    fn idle_timer(stream: &Self::ChatStream) -> Option<Pollable> {
+        Some(stream.idle_timer())
+    }
 }
 
 type DurableOpenRouterComponent = DurableLLM<OpenRouterComponent>;
@@ -0,0 +1,342 @@
+use golem_llm::cache::cache_scope;
+use golem_llm::config::{max_request_bytes, user_agent};
+use golem_llm::error::{error_from_status, from_event_source_error, from_reqwest_error};
+use golem_llm::event_source::EventSource;
+use golem_llm::fixtures::{response_fixtures, with_response_fixtures, ResponseFixtures};
+use golem_llm::golem::llm::llm::{Config, Error};
+use golem_llm::lenient::{integer, optional_integer, parse_response_json};
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{pre_send_body, RawResponse};
+use golem_llm::request::check_request_size;
+use log::trace;
+use reqwest::header::{HeaderValue, USER_AGENT};
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::str::FromStr;
+
+const BASE_URL: &str = "https://api.perplexity.ai";
+
+/// The Perplexity Chat Completions API client.
+///
+/// Based on https://docs.perplexity.ai/api-reference/chat-completions
+pub struct CompletionsApi {
+    api_key: String,
+    user_agent: String,
+    max_request_bytes: Option<usize>,
+    response_fixtures: Option<ResponseFixtures>,
+    client: Client,
+}
+
+impl CompletionsApi {
+    pub fn new(api_key: String, config: &Config) -> Self {
+        let client = Client::builder()
+            .build()
+            .expect("Failed to initialize HTTP client");
+        Self {
+            api_key,
+            user_agent: user_agent(config, "perplexity"),
+            max_request_bytes: max_request_bytes(config, None),
+            response_fixtures: response_fixtures(config),
+            client,
+        }
+    }
+
+    /// Identifies the server and credentials of this client in response cache keys
+    pub fn cache_scope(&self) -> String {
+        cache_scope(BASE_URL, &self.api_key)
+    }
+
+    /// Starts a request to the Perplexity API with the user agent and bearer token of this client
+    fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .header(USER_AGENT, &self.user_agent)
+            .bearer_auth(&self.api_key)
+    }
+
+    pub fn send_messages(
+        &self,
+        request: CompletionsRequest,
+    ) -> Result<RawResponse<CompletionsResponse>, Error> {
+        trace!("Sending request to Perplexity API: {}", Redacted(&request));
+        let body = pre_send_body(&request)?;
+        check_request_size(self.max_request_bytes, &body)?;
+
+        with_response_fixtures(self.response_fixtures.as_ref(), "perplexity", &body, || {
+            let response: Response = self
+                .request(Method::POST, format!("{BASE_URL}/chat/completions"))
+                .json(&body)
+                .send()
+                .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+            parse_response(response)
+        })
+    }
+
+    pub fn stream_send_messages(&self, request: CompletionsRequest) -> Result<EventSource, Error> {
+        trace!("Sending request to Perplexity API: {}", Redacted(&request));
+        let body = pre_send_body(&request)?;
+        check_request_size(self.max_request_bytes, &body)?;
+
+        let response: Response = self
+            .request(Method::POST, format!("{BASE_URL}/chat/completions"))
+            .header(
+                reqwest::header::ACCEPT,
+                HeaderValue::from_static("text/event-stream"),
+            )
+            .json(&body)
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+        trace!("Initializing SSE stream");
+
+        EventSource::new(response)
+            .map_err(|err| from_event_source_error("Failed to create SSE stream", err))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionsRequest {
+    pub messages: Vec<Message>,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_images: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_related_questions: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_domain_filter: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_recency_filter: Option<SearchRecencyFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+}
+
+/// Structured output. Perplexity only needs the schema, without naming it like OpenAI.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ResponseFormat {
+    #[serde(rename = "json_schema")]
+    JsonSchema { json_schema: JsonSchema },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonSchema {
+    pub schema: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SearchRecencyFilter {
+    #[serde(rename = "hour")]
+    Hour,
+    #[serde(rename = "day")]
+    Day,
+    #[serde(rename = "week")]
+    Week,
+    #[serde(rename = "month")]
+    Month,
+    #[serde(rename = "year")]
+    Year,
+}
+
+impl FromStr for SearchRecencyFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hour" => Ok(SearchRecencyFilter::Hour),
+            "day" => Ok(SearchRecencyFilter::Day),
+            "week" => Ok(SearchRecencyFilter::Week),
+            "month" => Ok(SearchRecencyFilter::Month),
+            "year" => Ok(SearchRecencyFilter::Year),
+            _ => Err(format!(
+                "Invalid search_recency_filter value: {s}, expected hour, day, week, month or year"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub role: String,
+    pub content: Vec<ContentPart>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ContentPart {
+    #[serde(rename = "text")]
+    TextInput { text: String },
+    #[serde(rename = "image_url")]
+    ImageInput { image_url: ImageUrl },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionsResponse {
+    pub id: String,
+    pub model: String,
+    #[serde(default, deserialize_with = "integer")]
+    pub created: u64,
+    pub choices: Vec<Choice>,
+    pub usage: Option<Usage>,
+    #[serde(default)]
+    pub citations: Vec<String>,
+    #[serde(default)]
+    pub search_results: Vec<SearchResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Choice {
+    pub index: u32,
+    pub finish_reason: Option<FinishReason>,
+    pub message: ResponseMessage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FinishReason {
+    #[serde(rename = "stop")]
+    Stop,
+    #[serde(rename = "length")]
+    Length,
+    /// Any reason not known when this was written, kept as reported
+    #[serde(untagged)]
+    Other(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseMessage {
+    pub role: String,
+    pub content: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(default, deserialize_with = "integer")]
+    pub prompt_tokens: u32,
+    #[serde(default, deserialize_with = "integer")]
+    pub completion_tokens: u32,
+    #[serde(default, deserialize_with = "optional_integer")]
+    pub total_tokens: Option<u32>,
+    #[serde(default, deserialize_with = "optional_integer")]
+    pub citation_tokens: Option<u32>,
+    #[serde(default, deserialize_with = "optional_integer")]
+    pub num_search_queries: Option<u32>,
+    pub search_context_size: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub title: Option<String>,
+    pub url: String,
+    pub date: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub model: String,
+    #[serde(default, deserialize_with = "integer")]
+    pub created: u64,
+    pub choices: Vec<ChoiceChunk>,
+    pub usage: Option<Usage>,
+    #[serde(default)]
+    pub citations: Vec<String>,
+    #[serde(default)]
+    pub search_results: Vec<SearchResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChoiceChunk {
+    pub index: u32,
+    pub delta: ChoiceDelta,
+    pub finish_reason: Option<FinishReason>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChoiceDelta {
+    pub role: Option<String>,
+    pub content: Option<String>,
+}
+
+fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
+    let status = response.status();
+    if status.is_success() {
+        let body = response
+            .text()
+            .map_err(|err| from_reqwest_error("Failed to receive response body", err))?;
+        let body = parse_response_json::<T>("Perplexity", body)?;
+
+        trace!("Received response from Perplexity API: {}", Redacted(&body));
+
+        Ok(body)
+    } else {
+        let error_body = response
+            .text()
+            .map_err(|err| from_reqwest_error("Failed to receive error response body", err))?;
+
+        trace!(
+            "Received {status} response from Perplexity API: {}",
+            Redacted(&error_body)
+        );
+
+        Err(error_from_status(
+            status,
+            format!("Request failed with {status}"),
+            Some(error_body),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{CompletionsApi, BASE_URL};
+    use golem_llm::golem::llm::llm::{Config, Kv};
+    use reqwest::header::USER_AGENT;
+    use reqwest::Method;
+
+    #[test]
+    fn requests_carry_the_user_agent() {
+        let config = Config {
+            model: "sonar".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![Kv {
+                key: "user_agent".to_string(),
+                value: "my-app/1.0".to_string(),
+            }],
+            system: None,
+        };
+        let api = CompletionsApi::new("api-key".to_string(), &config);
+
+        let request = api
+            .request(Method::POST, format!("{BASE_URL}/chat/completions"))
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[USER_AGENT], "my-app/1.0");
+    }
+}
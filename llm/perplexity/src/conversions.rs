@@ -0,0 +1,521 @@
+use crate::client::{
+    CompletionsRequest, CompletionsResponse, JsonSchema, ResponseFormat, SearchRecencyFilter,
+    SearchResult,
+};
+use base64::{engine::general_purpose, Engine as _};
+use golem_llm::capabilities::capabilities_by_prefix;
+use golem_llm::config::{max_tokens, reject_invalid_certs_option, temperature};
+use golem_llm::context::{fit_context, fit_max_tokens};
+use golem_llm::error::unsupported;
+use golem_llm::golem::llm::llm::{
+    CapabilitySet, ChatEvent, Citation, CompleteResponse, Config, ContentPart, Error, ErrorCode,
+    FinishReason, ImageReference, Message, ModelInfo, ResponseMetadata, Role, Usage,
+};
+use golem_llm::image::{
+    download_image, image_transport, transport_images, validate_image_mime_type,
+};
+use golem_llm::model_alias::resolve_model_alias;
+use golem_llm::request::{normalize_system_messages, with_system_prompt};
+use golem_llm::stop_sequence::limit_stop_sequences;
+use golem_llm::structured_output::response_schema;
+use golem_llm::validation::{validate_messages, SystemMessagePlacement};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// The Sonar models available through the Perplexity API, which has no endpoint for listing them
+const MODELS: &[(&str, u32)] = &[
+    ("sonar", 128000),
+    ("sonar-pro", 200000),
+    ("sonar-reasoning", 128000),
+    ("sonar-reasoning-pro", 128000),
+    ("sonar-deep-research", 128000),
+];
+
+/// The Perplexity models the provider-agnostic `small` and `large` model aliases resolve to
+const MODEL_ALIASES: &[(&str, &str)] = &[("small", "sonar"), ("large", "sonar-pro")];
+
+/// Perplexity's default temperature, low to keep answers close to the search results
+const DEFAULT_TEMPERATURE: f32 = 0.2;
+
+pub fn messages_to_request(
+    messages: Vec<Message>,
+    config: Config,
+) -> Result<CompletionsRequest, Error> {
+    let config = resolve_model_alias(config, MODEL_ALIASES);
+    let messages = normalize_system_messages(
+        fit_context(with_system_prompt(&config, messages), &config)?,
+        SystemMessagePlacement::Single,
+    );
+    validate_messages(&messages, SystemMessagePlacement::Single)?;
+    let messages = transport_images(
+        messages,
+        image_transport(&config)?,
+        true,
+        "Perplexity",
+        download_image,
+    )?;
+
+    let max_tokens = fit_max_tokens(max_tokens(&config), &messages, &config);
+    let temperature = temperature(&config, DEFAULT_TEMPERATURE)?;
+    let response_format =
+        response_schema(&config)?.map(|response_schema| ResponseFormat::JsonSchema {
+            json_schema: JsonSchema {
+                schema: response_schema.schema,
+            },
+        });
+    reject_invalid_certs_option(&config)?;
+    let stop = limit_stop_sequences(&config, None)?;
+    let options = config
+        .provider_options
+        .into_iter()
+        .map(|kv| (kv.key, kv.value))
+        .collect::<HashMap<_, _>>();
+
+    if !config.tools.is_empty() {
+        return Err(unsupported(
+            "Tools are not supported by the Perplexity provider",
+        ));
+    }
+
+    let mut completion_messages = Vec::new();
+    for message in messages {
+        let role = match message.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::System => "system",
+            Role::Tool => {
+                return Err(unsupported(
+                    "Tool messages are not supported by the Perplexity provider",
+                ))
+            }
+        };
+        completion_messages.push(crate::client::Message {
+            role: role.to_string(),
+            content: convert_content_parts(message.content)?,
+        });
+    }
+
+    let search_recency_filter = match options.get("search_recency_filter") {
+        Some(value) => Some(
+            value
+                .parse::<SearchRecencyFilter>()
+                .map_err(|message| Error {
+                    code: ErrorCode::InvalidRequest,
+                    message,
+                    provider_error_json: None,
+                })?,
+        ),
+        None => None,
+    };
+
+    Ok(CompletionsRequest {
+        messages: completion_messages,
+        model: config.model,
+        frequency_penalty: options
+            .get("frequency_penalty")
+            .and_then(|fp_s| fp_s.parse::<f32>().ok()),
+        max_tokens,
+        presence_penalty: options
+            .get("presence_penalty")
+            .and_then(|pp_s| pp_s.parse::<f32>().ok()),
+        response_format,
+        return_images: options
+            .get("return_images")
+            .and_then(|ri_s| ri_s.parse::<bool>().ok()),
+        return_related_questions: options
+            .get("return_related_questions")
+            .and_then(|rrq_s| rrq_s.parse::<bool>().ok()),
+        search_domain_filter: options.get("search_domain_filter").map(|domains| {
+            domains
+                .split(',')
+                .map(|domain| domain.trim().to_string())
+                .filter(|domain| !domain.is_empty())
+                .collect()
+        }),
+        search_recency_filter,
+        stop,
+        stream: Some(false),
+        temperature,
+        top_k: options
+            .get("top_k")
+            .and_then(|top_k_s| top_k_s.parse::<u32>().ok()),
+        top_p: options
+            .get("top_p")
+            .and_then(|top_p_s| top_p_s.parse::<f32>().ok()),
+    })
+}
+
+fn convert_content_parts(
+    contents: Vec<ContentPart>,
+) -> Result<Vec<crate::client::ContentPart>, Error> {
+    let mut result = Vec::new();
+    for content in contents {
+        match content {
+            ContentPart::Text(text) => result.push(crate::client::ContentPart::TextInput { text }),
+            ContentPart::Image(ImageReference::Url(image_url)) => {
+                result.push(crate::client::ContentPart::ImageInput {
+                    image_url: crate::client::ImageUrl { url: image_url.url },
+                })
+            }
+            ContentPart::Image(ImageReference::Inline(image_source)) => {
+                validate_image_mime_type(&image_source.mime_type)?;
+                let base64_data = general_purpose::STANDARD.encode(&image_source.data);
+                result.push(crate::client::ContentPart::ImageInput {
+                    image_url: crate::client::ImageUrl {
+                        url: format!("data:{};base64,{base64_data}", image_source.mime_type),
+                    },
+                });
+            }
+            ContentPart::Citation(_) => {}
+        }
+    }
+    Ok(result)
+}
+
+pub fn process_response(response: CompletionsResponse) -> ChatEvent {
+    let choice = response.choices.first();
+    if let Some(choice) = choice {
+        let content = choice
+            .message
+            .content
+            .iter()
+            .map(|text| ContentPart::Text(text.clone()))
+            .chain(citations(&response.citations, &response.search_results))
+            .collect();
+
+        let metadata = ResponseMetadata {
+            finish_reason: choice.finish_reason.as_ref().map(convert_finish_reason),
+            usage: response.usage.as_ref().map(convert_usage),
+            provider_id: Some(response.id.clone()),
+            timestamp: Some(response.created.to_string()),
+            provider_metadata_json: provider_metadata(
+                &response.citations,
+                &response.search_results,
+            ),
+            stop_sequence: None,
+            tool_calls: None,
+        };
+
+        ChatEvent::Message(CompleteResponse {
+            id: response.id,
+            content,
+            tool_calls: Vec::new(),
+            metadata,
+        })
+    } else {
+        ChatEvent::Error(Error {
+            code: ErrorCode::InternalError,
+            message: "No choices in response".to_string(),
+            provider_error_json: None,
+        })
+    }
+}
+
+/// Converts the cited URLs of a response to citation content parts, titled by the search result
+/// with the same URL. Responses without a list of citations cite their search results.
+fn citations(citations: &[String], search_results: &[SearchResult]) -> Vec<ContentPart> {
+    if citations.is_empty() {
+        return search_results
+            .iter()
+            .map(|search_result| {
+                ContentPart::Citation(Citation {
+                    title: search_result.title.clone(),
+                    url: search_result.url.clone(),
+                    quoted_text: None,
+                })
+            })
+            .collect();
+    }
+
+    citations
+        .iter()
+        .map(|url| {
+            ContentPart::Citation(Citation {
+                title: search_results
+                    .iter()
+                    .find(|search_result| &search_result.url == url)
+                    .and_then(|search_result| search_result.title.clone()),
+                url: url.clone(),
+                quoted_text: None,
+            })
+        })
+        .collect()
+}
+
+/// Builds the provider metadata JSON holding the online search citations of a response, if there
+/// were any
+pub fn provider_metadata(citations: &[String], search_results: &[SearchResult]) -> Option<String> {
+    let mut metadata = Map::new();
+    if !citations.is_empty() {
+        metadata.insert("citations".to_string(), json!(citations));
+    }
+    if !search_results.is_empty() {
+        metadata.insert("search_results".to_string(), json!(search_results));
+    }
+
+    if metadata.is_empty() {
+        None
+    } else {
+        Some(Value::Object(metadata).to_string())
+    }
+}
+
+pub fn convert_finish_reason(value: &crate::client::FinishReason) -> FinishReason {
+    match value {
+        crate::client::FinishReason::Stop => FinishReason::Stop,
+        crate::client::FinishReason::Length => FinishReason::Length,
+        crate::client::FinishReason::Other(value) => FinishReason::Other(value.clone()),
+    }
+}
+
+pub fn convert_usage(value: &crate::client::Usage) -> Usage {
+    Usage {
+        input_tokens: Some(value.prompt_tokens),
+        output_tokens: Some(value.completion_tokens),
+        total_tokens: value
+            .total_tokens
+            .or_else(|| value.prompt_tokens.checked_add(value.completion_tokens)),
+    }
+}
+
+pub fn known_models() -> Vec<ModelInfo> {
+    MODELS
+        .iter()
+        .map(|(id, context_length)| ModelInfo {
+            id: id.to_string(),
+            context_length: Some(*context_length),
+            capabilities: None,
+        })
+        .collect()
+}
+
+/// The Sonar models accept images and structured outputs, but tools are not supported by the
+/// Perplexity API
+const SONAR: CapabilitySet = CapabilitySet {
+    images: true,
+    tools: false,
+    streaming: true,
+    json_mode: true,
+};
+
+const REASONING: CapabilitySet = CapabilitySet {
+    images: false,
+    tools: false,
+    streaming: true,
+    json_mode: false,
+};
+
+const MODEL_CAPABILITIES: &[(&str, CapabilitySet)] = &[("r1-1776", REASONING)];
+
+pub fn model_capabilities(model: &str) -> CapabilitySet {
+    capabilities_by_prefix(model, MODEL_CAPABILITIES, SONAR)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::CompletionsResponse;
+    use crate::conversions::{messages_to_request, process_response};
+    use crate::PerplexityComponent;
+    use golem_llm::durability::ExtendedGuest;
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, Citation, Config, ContentPart, ErrorCode, FinishReason, Kv, Message, Role,
+        StreamDelta,
+    };
+
+    fn config(provider_options: Vec<Kv>) -> Config {
+        Config {
+            model: "sonar".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options,
+            system: None,
+        }
+    }
+
+    #[test]
+    fn response_with_citations() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{
+                "id": "3c90c3cc-0d44-4b50-8888-8dd25736052a",
+                "model": "sonar",
+                "object": "chat.completion",
+                "created": 1724369245,
+                "citations": [
+                    "https://www.golem.cloud/",
+                    "https://learn.golem.cloud/"
+                ],
+                "search_results": [
+                    {"title": "Golem Cloud", "url": "https://www.golem.cloud/", "date": "2025-05-01"}
+                ],
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {
+                        "role": "assistant",
+                        "content": "Golem is a durable computing platform [1][2]."
+                    },
+                    "delta": {"role": "assistant", "content": ""}
+                }],
+                "usage": {
+                    "prompt_tokens": 12,
+                    "completion_tokens": 20,
+                    "total_tokens": 32,
+                    "search_context_size": "low"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let ChatEvent::Message(response) = process_response(response) else {
+            panic!("expected a message response");
+        };
+        assert_eq!(
+            response.content,
+            vec![
+                ContentPart::Text("Golem is a durable computing platform [1][2].".to_string()),
+                ContentPart::Citation(Citation {
+                    title: Some("Golem Cloud".to_string()),
+                    url: "https://www.golem.cloud/".to_string(),
+                    quoted_text: None,
+                }),
+                ContentPart::Citation(Citation {
+                    title: None,
+                    url: "https://learn.golem.cloud/".to_string(),
+                    quoted_text: None,
+                }),
+            ]
+        );
+        assert_eq!(response.metadata.finish_reason, Some(FinishReason::Stop));
+        let provider_metadata: serde_json::Value =
+            serde_json::from_str(response.metadata.provider_metadata_json.as_deref().unwrap())
+                .unwrap();
+        assert_eq!(
+            provider_metadata["citations"],
+            serde_json::json!(["https://www.golem.cloud/", "https://learn.golem.cloud/"])
+        );
+        assert_eq!(
+            provider_metadata["search_results"][0]["title"],
+            "Golem Cloud"
+        );
+    }
+
+    #[test]
+    fn search_recency_filter_option() {
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("What happened today?".to_string())],
+        }];
+        let request = messages_to_request(
+            messages.clone(),
+            config(vec![Kv {
+                key: "search_recency_filter".to_string(),
+                value: "day".to_string(),
+            }]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(request).unwrap()["search_recency_filter"],
+            "day"
+        );
+
+        let error = messages_to_request(
+            messages,
+            config(vec![Kv {
+                key: "search_recency_filter".to_string(),
+                value: "decade".to_string(),
+            }]),
+        )
+        .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn provider_default_temperature_is_injected_on_request() {
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("What happened today?".to_string())],
+        }];
+
+        let request =
+            serde_json::to_value(messages_to_request(messages.clone(), config(vec![])).unwrap())
+                .unwrap();
+        assert!(request.get("temperature").is_none());
+
+        let request = serde_json::to_value(
+            messages_to_request(
+                messages,
+                config(vec![Kv {
+                    key: "default_temperature".to_string(),
+                    value: "provider".to_string(),
+                }]),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(request["temperature"], 0.2f32);
+    }
+
+    #[test]
+    fn system_messages_are_coalesced() {
+        let config = Config {
+            system: Some("You are a research assistant.".to_string()),
+            ..config(vec![])
+        };
+        let messages = vec![
+            Message {
+                role: Role::System,
+                name: None,
+                content: vec![ContentPart::Text("Cite peer-reviewed sources.".to_string())],
+            },
+            Message {
+                role: Role::User,
+                name: None,
+                content: vec![ContentPart::Text("Is coffee healthy?".to_string())],
+            },
+        ];
+
+        let request = serde_json::to_value(messages_to_request(messages, config).unwrap()).unwrap();
+
+        assert_eq!(request["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(request["messages"][0]["role"], "system");
+        assert_eq!(
+            request["messages"][0]["content"],
+            serde_json::json!([{
+                "type": "text",
+                "text": "You are a research assistant.\n\nCite peer-reviewed sources."
+            }])
+        );
+    }
+
+    #[test]
+    fn retry_prompt_is_accepted() {
+        let message = |role: Role, text: &str| Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        };
+        let original_messages = vec![
+            message(Role::System, "Answer briefly"),
+            message(Role::User, "What is the weather like?"),
+            message(Role::Assistant, "Which city?"),
+            message(Role::User, "Berlin"),
+        ];
+        let partial_result = vec![StreamDelta {
+            content: Some(vec![ContentPart::Text("It is sunny".to_string())]),
+            tool_calls: None,
+            reasoning: None,
+            choice_index: None,
+        }];
+
+        let messages = PerplexityComponent::retry_prompt(&original_messages, &partial_result);
+
+        assert!(messages_to_request(messages, config(vec![])).is_ok());
+    }
+}
@@ -0,0 +1,363 @@
+mod client;
+mod conversions;
+
+use crate::client::{ChatCompletionChunk, CompletionsApi, CompletionsRequest, SearchResult, Usage};
+use crate::conversions::{
+    convert_finish_reason, convert_usage, known_models, messages_to_request, model_capabilities,
+    process_response, provider_metadata,
+};
+use golem_llm::cache::{cache_ttl, cached_response, response_cache_key};
+use golem_llm::chat_stream::{LlmChatStream, LlmChatStreamState};
+use golem_llm::concurrency::acquire_request_slot;
+use golem_llm::config::{stream_replay_file, with_config_key};
+use golem_llm::dry_run::{dry_run_response, is_dry_run, with_api_key_unless_dry_run};
+use golem_llm::durability::{retry_prompt_with_style, DurableLLM, ExtendedGuest, RetryPromptStyle};
+use golem_llm::error::{reject_empty_response, unsupported};
+use golem_llm::event_source::EventSource;
+use golem_llm::golem::llm::llm::{
+    BatchInfo, BatchRequest, BatchResult, CapabilitySet, ChatEvent, ChatStream, Config,
+    ContentPart, EmbeddingResponse, Error, FinishReason, Guest, ImageReference, Message, ModelInfo,
+    ResponseMetadata, StreamDelta, StreamEvent, ToolCall, ToolResult,
+};
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{include_raw_response, with_raw_response};
+use golem_llm::structured_output::{response_schema, validate_structured_output, ResponseSchema};
+use golem_llm::summarize::summarize_history;
+use golem_llm::tool_calls::reject_malformed_tool_calls;
+use golem_llm::usage::sum_usage;
+use golem_llm::LOGGING_STATE;
+use golem_rust::wasm_rpc::Pollable;
+use log::trace;
+use std::cell::{Ref, RefCell, RefMut};
+use std::time::Duration;
+
+struct PerplexityChatStream {
+    stream: RefCell<Option<EventSource>>,
+    failure: Option<Error>,
+    finished: RefCell<bool>,
+    id: RefCell<Option<String>>,
+    finish_reason: RefCell<Option<FinishReason>>,
+    usage: RefCell<Option<Usage>>,
+    citations: RefCell<Vec<String>>,
+    search_results: RefCell<Vec<SearchResult>>,
+}
+
+impl PerplexityChatStream {
+    pub fn new(stream: EventSource) -> LlmChatStream<Self> {
+        LlmChatStream::new(PerplexityChatStream {
+            stream: RefCell::new(Some(stream)),
+            failure: None,
+            finished: RefCell::new(false),
+            id: RefCell::new(None),
+            finish_reason: RefCell::new(None),
+            usage: RefCell::new(None),
+            citations: RefCell::new(Vec::new()),
+            search_results: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn failed(error: Error) -> LlmChatStream<Self> {
+        LlmChatStream::new(PerplexityChatStream {
+            stream: RefCell::new(None),
+            failure: Some(error),
+            finished: RefCell::new(false),
+            id: RefCell::new(None),
+            finish_reason: RefCell::new(None),
+            usage: RefCell::new(None),
+            citations: RefCell::new(Vec::new()),
+            search_results: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn replayed() -> LlmChatStream<Self> {
+        LlmChatStream::new(PerplexityChatStream {
+            stream: RefCell::new(None),
+            failure: None,
+            finished: RefCell::new(false),
+            id: RefCell::new(None),
+            finish_reason: RefCell::new(None),
+            usage: RefCell::new(None),
+            citations: RefCell::new(Vec::new()),
+            search_results: RefCell::new(Vec::new()),
+        })
+    }
+}
+
+impl LlmChatStreamState for PerplexityChatStream {
+    fn failure(&self) -> &Option<Error> {
+        &self.failure
+    }
+
+    fn is_finished(&self) -> bool {
+        *self.finished.borrow()
+    }
+
+    fn set_finished(&self) {
+        *self.finished.borrow_mut() = true;
+    }
+
+    fn stream(&self) -> Ref<Option<EventSource>> {
+        self.stream.borrow()
+    }
+
+    fn stream_mut(&self) -> RefMut<Option<EventSource>> {
+        self.stream.borrow_mut()
+    }
+
+    fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
+        trace!("Received raw stream event: {}", Redacted(&raw));
+        let chunk: ChatCompletionChunk = serde_json::from_str(raw)
+            .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
+
+        // Every chunk repeats the citations and usage collected so far, they are reported in
+        // the finish event emitted when the stream ends
+        *self.id.borrow_mut() = Some(chunk.id);
+        if !chunk.citations.is_empty() {
+            *self.citations.borrow_mut() = chunk.citations;
+        }
+        if !chunk.search_results.is_empty() {
+            *self.search_results.borrow_mut() = chunk.search_results;
+        }
+        if let Some(usage) = chunk.usage {
+            *self.usage.borrow_mut() = Some(usage);
+        }
+
+        if let Some(choice) = chunk.choices.into_iter().next() {
+            if let Some(finish_reason) = choice.finish_reason {
+                *self.finish_reason.borrow_mut() = Some(convert_finish_reason(&finish_reason));
+            }
+            if let Some(content) = choice.delta.content.filter(|content| !content.is_empty()) {
+                return Ok(Some(StreamEvent::Delta(StreamDelta {
+                    content: Some(vec![ContentPart::Text(content)]),
+                    tool_calls: None,
+                    reasoning: None,
+                    choice_index: None,
+                })));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn pending_finish(&self) -> ResponseMetadata {
+        ResponseMetadata {
+            finish_reason: self.finish_reason.borrow().clone(),
+            usage: self.usage.borrow().as_ref().map(convert_usage),
+            provider_id: self.id.borrow().clone(),
+            timestamp: None,
+            provider_metadata_json: provider_metadata(
+                &self.citations.borrow(),
+                &self.search_results.borrow(),
+            ),
+            stop_sequence: None,
+            tool_calls: None,
+        }
+    }
+}
+
+struct PerplexityComponent;
+
+impl PerplexityComponent {
+    const ENV_VAR_NAME: &'static str = "PERPLEXITY_API_KEY";
+
+    fn request(
+        client: CompletionsApi,
+        request: CompletionsRequest,
+        dry_run: bool,
+        include_raw_response: bool,
+        cache_ttl: Option<Duration>,
+        response_schema: Option<ResponseSchema>,
+    ) -> ChatEvent {
+        if dry_run {
+            return dry_run_response("perplexity", &request);
+        }
+
+        let cache_key = response_cache_key(cache_ttl, &client.cache_scope(), &request);
+        cached_response(cache_key, || {
+            validate_structured_output(response_schema.as_ref(), || {
+                match client.send_messages(request.clone()) {
+                    Ok(response) => with_raw_response(
+                        reject_malformed_tool_calls(reject_empty_response(process_response(
+                            response.body,
+                        ))),
+                        include_raw_response.then_some(response.raw),
+                    ),
+                    Err(err) => ChatEvent::Error(err),
+                }
+            })
+        })
+    }
+
+    fn streaming_request(
+        client: CompletionsApi,
+        mut request: CompletionsRequest,
+    ) -> LlmChatStream<PerplexityChatStream> {
+        request.stream = Some(true);
+        match client.stream_send_messages(request) {
+            Ok(stream) => PerplexityChatStream::new(stream),
+            Err(err) => PerplexityChatStream::failed(err),
+        }
+    }
+
+    fn tools_unsupported() -> Error {
+        unsupported("Tools are not supported by the Perplexity provider")
+    }
+}
+
+impl Guest for PerplexityComponent {
+    type ChatStream = LlmChatStream<PerplexityChatStream>;
+
+    fn send(messages: Vec<Message>, config: Config) -> ChatEvent {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+        let dry_run = is_dry_run(&config);
+        let include_raw_response = include_raw_response(&config);
+        let cache_ttl = cache_ttl(&config);
+        let response_schema = response_schema(&config).ok().flatten();
+
+        with_api_key_unless_dry_run(
+            Self::ENV_VAR_NAME,
+            dry_run,
+            ChatEvent::Error,
+            |perplexity_api_key| {
+                let _permit = acquire_request_slot(&config, "perplexity", &perplexity_api_key);
+                let client = CompletionsApi::new(perplexity_api_key, &config);
+
+                match messages_to_request(messages, config) {
+                    Ok(request) => Self::request(
+                        client,
+                        request,
+                        dry_run,
+                        include_raw_response,
+                        cache_ttl,
+                        response_schema,
+                    ),
+                    Err(err) => ChatEvent::Error(err),
+                }
+            },
+        )
+    }
+
+    fn continue_(
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
+    ) -> ChatEvent {
+        if !tool_results.is_empty() {
+            return ChatEvent::Error(Self::tools_unsupported());
+        }
+
+        Self::send(messages, config)
+    }
+
+    fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
+        ChatStream::new(Self::unwrapped_stream(messages, config))
+    }
+
+    fn embed(_inputs: Vec<String>, _config: Config) -> Result<EmbeddingResponse, Error> {
+        Err(unsupported(
+            "Embeddings are not supported by the Perplexity provider",
+        ))
+    }
+
+    fn generate_image(_prompt: String, _config: Config) -> Result<Vec<ImageReference>, Error> {
+        Err(unsupported(
+            "Image generation is not supported by the Perplexity provider",
+        ))
+    }
+
+    fn list_models(_config: Config) -> Result<Vec<ModelInfo>, Error> {
+        Ok(known_models())
+    }
+
+    fn validate_credentials(_config: Config) -> Result<(), Error> {
+        Err(unsupported(
+            "Credential validation is not supported by the Perplexity provider",
+        ))
+    }
+
+    fn capabilities(config: Config) -> CapabilitySet {
+        model_capabilities(&config.model)
+    }
+
+    fn batch_submit(_requests: Vec<BatchRequest>, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Perplexity provider",
+        ))
+    }
+
+    fn batch_status(_batch_id: String, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Perplexity provider",
+        ))
+    }
+
+    fn batch_results(_batch_id: String, _config: Config) -> Result<Vec<BatchResult>, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Perplexity provider",
+        ))
+    }
+
+    fn summarize_history(
+        messages: Vec<Message>,
+        config: Config,
+        keep_last_n: u32,
+    ) -> Result<Vec<Message>, Error> {
+        summarize_history(messages, config, keep_last_n, Self::send)
+    }
+
+    fn sum_usage(
+        usages: Vec<golem_llm::golem::llm::llm::Usage>,
+    ) -> golem_llm::golem::llm::llm::Usage {
+        sum_usage(usages)
+    }
+}
+
+impl ExtendedGuest for PerplexityComponent {
+    fn unwrapped_stream(
+        messages: Vec<Message>,
+        config: Config,
+    ) -> LlmChatStream<PerplexityChatStream> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        if stream_replay_file(&config).is_some() {
+            return PerplexityChatStream::replayed().with_stream_options(&config);
+        }
+
+        with_config_key(
+            Self::ENV_VAR_NAME,
+            PerplexityChatStream::failed,
+            |perplexity_api_key| {
+                let permit = acquire_request_slot(&config, "perplexity", &perplexity_api_key);
+                let client = CompletionsApi::new(perplexity_api_key, &config);
+
+                match messages_to_request(messages, config.clone()) {
+                    Ok(request) => Self::streaming_request(client, request)
+                        .with_request_permit(permit)
+                        .with_stream_options(&config),
+                    Err(err) => PerplexityChatStream::failed(err),
+                }
+            },
+        )
+    }
+
+    fn retry_prompt(original_messages: &[Message], partial_result: &[StreamDelta]) -> Vec<Message> {
+        // Perplexity requires user and assistant messages to alternate after the system messages
+        retry_prompt_with_style(
+            original_messages,
+            partial_result,
+            RetryPromptStyle::UserMessage,
+        )
+    }
+
+    fn subscribe(stream: &Self::ChatStream) -> Pollable {
+        stream.subscribe()
+    }
+
+    fn idle_timer(stream: &Self::ChatStream) -> Option<Pollable> {
+        Some(stream.idle_timer())
+    }
+}
+
+type DurablePerplexityComponent = DurableLLM<PerplexityComponent>;
+
+golem_llm::export_llm!(DurablePerplexityComponent with_types_in golem_llm);
@@ -0,0 +1,442 @@
+use golem_llm::cache::cache_scope;
+use golem_llm::config::{max_request_bytes, user_agent};
+use golem_llm::error::{error_from_status, from_event_source_error, from_reqwest_error};
+use golem_llm::event_source::EventSource;
+use golem_llm::fixtures::{response_fixtures, with_response_fixtures, ResponseFixtures};
+use golem_llm::golem::llm::llm::{Config, Error};
+use golem_llm::lenient::{integer, optional_integer, parse_response_json};
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{pre_send_body, RawResponse};
+use golem_llm::request::check_request_size;
+use log::trace;
+use reqwest::header::{HeaderValue, USER_AGENT};
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+const DEFAULT_BASE_URL: &str = "https://api.together.xyz";
+
+/// The Together AI Chat Completions API client.
+///
+/// Based on https://docs.together.ai/reference/chat-completions-1
+pub struct CompletionsApi {
+    api_key: String,
+    base_url: String,
+    user_agent: String,
+    max_request_bytes: Option<usize>,
+    response_fixtures: Option<ResponseFixtures>,
+    client: Client,
+}
+
+impl CompletionsApi {
+    /// Creates a client for the Together AI API, or for the compatible server set in the
+    /// `TOGETHER_BASE_URL` environment variable.
+    pub fn new(api_key: String, config: &Config) -> Self {
+        let base_url = std::env::var("TOGETHER_BASE_URL").unwrap_or(DEFAULT_BASE_URL.to_string());
+        Self {
+            max_request_bytes: max_request_bytes(config, None),
+            response_fixtures: response_fixtures(config),
+            ..Self::with_base_url(api_key, &base_url, user_agent(config, "together"))
+        }
+    }
+
+    pub fn with_base_url(api_key: String, base_url: &str, user_agent: String) -> Self {
+        let client = Client::builder()
+            .build()
+            .expect("Failed to initialize HTTP client");
+        Self {
+            api_key,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            user_agent,
+            max_request_bytes: None,
+            response_fixtures: None,
+            client,
+        }
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    /// Identifies the server and credentials of this client in response cache keys
+    pub fn cache_scope(&self) -> String {
+        cache_scope(&self.base_url, &self.api_key)
+    }
+
+    /// Starts a request to Together AI, authenticated with the API key and sent with the user agent
+    fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .header(USER_AGENT, &self.user_agent)
+            .bearer_auth(&self.api_key)
+    }
+
+    pub fn send_messages(
+        &self,
+        request: CompletionsRequest,
+    ) -> Result<RawResponse<CompletionsResponse>, Error> {
+        trace!("Sending request to Together AI API: {}", Redacted(&request));
+        let body = pre_send_body(&request)?;
+        check_request_size(self.max_request_bytes, &body)?;
+
+        with_response_fixtures(self.response_fixtures.as_ref(), "together", &body, || {
+            let response: Response = self
+                .request(Method::POST, self.endpoint("/v1/chat/completions"))
+                .json(&body)
+                .send()
+                .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+            parse_response(response)
+        })
+    }
+
+    pub fn list_models(&self) -> Result<Vec<Model>, Error> {
+        trace!("Listing models from Together AI API");
+
+        let response: Response = self
+            .request(Method::GET, self.endpoint("/v1/models"))
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+        parse_response(response)
+    }
+
+    pub fn stream_send_messages(&self, request: CompletionsRequest) -> Result<EventSource, Error> {
+        trace!("Sending request to Together AI API: {}", Redacted(&request));
+        let body = pre_send_body(&request)?;
+        check_request_size(self.max_request_bytes, &body)?;
+
+        let response: Response = self
+            .request(Method::POST, self.endpoint("/v1/chat/completions"))
+            .header(
+                reqwest::header::ACCEPT,
+                HeaderValue::from_static("text/event-stream"),
+            )
+            .json(&body)
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))?;
+
+        trace!("Initializing SSE stream");
+
+        EventSource::new(response)
+            .map_err(|err| from_event_source_error("Failed to create SSE stream", err))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionsRequest {
+    pub messages: Vec<Message>,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repetition_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+}
+
+/// Structured output. Unlike OpenAI, Together expects the schema directly in the format object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ResponseFormat {
+    #[serde(rename = "json_schema")]
+    JsonSchema { schema: serde_json::Value },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Tool {
+    #[serde(rename = "function")]
+    Function { function: Function },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Function {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "role")]
+pub enum Message {
+    #[serde(rename = "system")]
+    System {
+        content: Content,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    #[serde(rename = "user")]
+    User {
+        content: Content,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    #[serde(rename = "assistant")]
+    Assistant {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        content: Option<Content>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tool_calls: Option<Vec<ToolCall>>,
+    },
+    #[serde(rename = "tool")]
+    Tool {
+        content: Content,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tool_call_id: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    TextInput(String),
+    List(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ContentPart {
+    #[serde(rename = "text")]
+    TextInput { text: String },
+    #[serde(rename = "image_url")]
+    ImageInput { image_url: ImageUrl },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ToolCall {
+    #[serde(rename = "function")]
+    Function {
+        function: FunctionCall,
+        #[serde(default)]
+        id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    #[serde(default)]
+    pub arguments: String,
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionsResponse {
+    pub id: String,
+    #[serde(default, deserialize_with = "integer")]
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<Choice>,
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Choice {
+    pub index: u32,
+    pub finish_reason: Option<FinishReason>,
+    pub message: ResponseMessage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FinishReason {
+    #[serde(rename = "stop")]
+    Stop,
+    #[serde(rename = "eos")]
+    Eos,
+    #[serde(rename = "length")]
+    Length,
+    #[serde(rename = "tool_calls")]
+    ToolCalls,
+    #[serde(rename = "function_call")]
+    FunctionCall,
+    /// Any reason not known when this was written, kept as reported
+    #[serde(untagged)]
+    Other(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseMessage {
+    pub role: String,
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCall>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(default, deserialize_with = "integer")]
+    pub prompt_tokens: u32,
+    #[serde(default, deserialize_with = "integer")]
+    pub completion_tokens: u32,
+    #[serde(default, deserialize_with = "optional_integer")]
+    pub total_tokens: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    #[serde(default, deserialize_with = "integer")]
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChoiceChunk>,
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChoiceChunk {
+    pub index: u32,
+    pub delta: ChoiceDelta,
+    pub finish_reason: Option<FinishReason>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChoiceDelta {
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCallChunk>>,
+}
+
+/// A fragment of a streamed tool call. The id and function name are only sent with the first
+/// fragment of a call, the following ones are identified by their index alone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallChunk {
+    #[serde(default)]
+    pub index: u32,
+    pub id: Option<String>,
+    pub function: Option<FunctionCallChunk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCallChunk {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub model_type: Option<String>,
+    pub context_length: Option<u32>,
+}
+
+fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
+    let status = response.status();
+    if status.is_success() {
+        let body = response
+            .text()
+            .map_err(|err| from_reqwest_error("Failed to receive response body", err))?;
+        let body = parse_response_json::<T>("Together AI", body)?;
+
+        trace!(
+            "Received response from Together AI API: {}",
+            Redacted(&body)
+        );
+
+        Ok(body)
+    } else {
+        let error_body = response
+            .text()
+            .map_err(|err| from_reqwest_error("Failed to receive error response body", err))?;
+
+        trace!(
+            "Received {status} response from Together AI API: {}",
+            Redacted(&error_body)
+        );
+
+        Err(error_from_status(
+            status,
+            format!("Request failed with {status}"),
+            Some(error_body),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::CompletionsApi;
+    use golem_llm::golem::llm::llm::{Config, Kv};
+    use reqwest::header::USER_AGENT;
+    use reqwest::Method;
+
+    #[test]
+    fn endpoints_use_overridden_base_url() {
+        let api = CompletionsApi::with_base_url(
+            "api-key".to_string(),
+            "https://together.example.com/",
+            "golem-llm".to_string(),
+        );
+
+        assert_eq!(
+            api.endpoint("/v1/chat/completions"),
+            "https://together.example.com/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn user_agent_can_be_overridden() {
+        let mut config = Config {
+            model: "meta-llama/Llama-3.3-70B-Instruct-Turbo".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: vec![],
+            system: None,
+        };
+        let user_agent = |config: &Config| {
+            let api = CompletionsApi::new("api-key".to_string(), config);
+            let request = api
+                .request(Method::POST, api.endpoint("/v1/chat/completions"))
+                .build()
+                .unwrap();
+            request.headers()[USER_AGENT].to_str().unwrap().to_string()
+        };
+        assert!(user_agent(&config).starts_with("golem-llm/"));
+        assert!(user_agent(&config).ends_with("(together)"));
+
+        config.provider_options.push(Kv {
+            key: "user_agent".to_string(),
+            value: "my-app/1.0".to_string(),
+        });
+        assert_eq!(user_agent(&config), "my-app/1.0");
+    }
+}
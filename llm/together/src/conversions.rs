@@ -0,0 +1,459 @@
+use crate::client::{
+    CompletionsRequest, CompletionsResponse, Function, Model, ResponseFormat, Tool,
+};
+use base64::{engine::general_purpose, Engine as _};
+use golem_llm::capabilities::capabilities_by_prefix;
+use golem_llm::config::{max_tokens, reject_invalid_certs_option, temperature};
+use golem_llm::context::{fit_context, fit_max_tokens};
+use golem_llm::golem::llm::llm::{
+    CapabilitySet, ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode,
+    FinishReason, ImageReference, Message, ModelInfo, ResponseMetadata, Role, ToolCall,
+    ToolDefinition, ToolResult, Usage,
+};
+use golem_llm::image::{
+    download_image, image_transport, transport_images, validate_image_mime_type,
+};
+use golem_llm::model_alias::resolve_model_alias;
+use golem_llm::request::{normalize_system_messages, with_system_prompt};
+use golem_llm::stop_sequence::limit_stop_sequences;
+use golem_llm::structured_output::response_schema;
+use golem_llm::tool_calls::{tool_call_id, tool_image_description};
+use golem_llm::validation::{validate_messages, SystemMessagePlacement};
+use std::collections::HashMap;
+
+/// The Together AI models the provider-agnostic `small` and `large` model aliases resolve to
+const MODEL_ALIASES: &[(&str, &str)] = &[
+    ("small", "meta-llama/Llama-3.2-3B-Instruct-Turbo"),
+    ("large", "meta-llama/Llama-3.3-70B-Instruct-Turbo"),
+];
+
+/// The temperature recommended for the chat models hosted by Together AI
+const DEFAULT_TEMPERATURE: f32 = 0.7;
+
+pub fn messages_to_request(
+    messages: Vec<Message>,
+    config: Config,
+) -> Result<CompletionsRequest, Error> {
+    let config = resolve_model_alias(config, MODEL_ALIASES);
+    let messages = normalize_system_messages(
+        fit_context(with_system_prompt(&config, messages), &config)?,
+        SystemMessagePlacement::Single,
+    );
+    validate_messages(&messages, SystemMessagePlacement::Single)?;
+    let messages = transport_images(
+        messages,
+        image_transport(&config)?,
+        true,
+        "Together AI",
+        download_image,
+    )?;
+
+    let max_tokens = fit_max_tokens(max_tokens(&config), &messages, &config);
+    let temperature = temperature(&config, DEFAULT_TEMPERATURE)?;
+    let response_format =
+        response_schema(&config)?.map(|response_schema| ResponseFormat::JsonSchema {
+            schema: response_schema.schema,
+        });
+    reject_invalid_certs_option(&config)?;
+    let stop = limit_stop_sequences(&config, None)?;
+    let options = config
+        .provider_options
+        .into_iter()
+        .map(|kv| (kv.key, kv.value))
+        .collect::<HashMap<_, _>>();
+
+    let mut completion_messages = Vec::new();
+    for message in messages {
+        match message.role {
+            Role::User => completion_messages.push(crate::client::Message::User {
+                name: message.name,
+                content: convert_content_parts(message.content)?,
+            }),
+            Role::Assistant => completion_messages.push(crate::client::Message::Assistant {
+                name: message.name,
+                content: Some(convert_content_parts(message.content)?),
+                tool_calls: None,
+            }),
+            Role::System => completion_messages.push(crate::client::Message::System {
+                name: message.name,
+                content: convert_content_parts(message.content)?,
+            }),
+            Role::Tool => completion_messages.push(crate::client::Message::Tool {
+                name: message.name,
+                content: convert_content_parts(message.content)?,
+                tool_call_id: None,
+            }),
+        }
+    }
+
+    let mut tools = Vec::new();
+    for tool in config.tools {
+        tools.push(tool_definition_to_tool(tool)?)
+    }
+
+    Ok(CompletionsRequest {
+        messages: completion_messages,
+        model: config.model,
+        frequency_penalty: options
+            .get("frequency_penalty")
+            .and_then(|fp_s| fp_s.parse::<f32>().ok()),
+        max_tokens,
+        min_p: options
+            .get("min_p")
+            .and_then(|min_p_s| min_p_s.parse::<f32>().ok()),
+        presence_penalty: options
+            .get("presence_penalty")
+            .and_then(|pp_s| pp_s.parse::<f32>().ok()),
+        repetition_penalty: options
+            .get("repetition_penalty")
+            .and_then(|rp_s| rp_s.parse::<f32>().ok()),
+        response_format,
+        seed: options
+            .get("seed")
+            .and_then(|seed_s| seed_s.parse::<u32>().ok()),
+        stop,
+        stream: Some(false),
+        temperature,
+        tool_choice: config.tool_choice,
+        tools,
+        top_k: options
+            .get("top_k")
+            .and_then(|top_k_s| top_k_s.parse::<u32>().ok()),
+        top_p: options
+            .get("top_p")
+            .and_then(|top_p_s| top_p_s.parse::<f32>().ok()),
+    })
+}
+
+pub fn process_response(response: CompletionsResponse) -> ChatEvent {
+    let choice = response.choices.first();
+    if let Some(choice) = choice {
+        let mut contents = Vec::new();
+        let mut tool_calls = Vec::new();
+
+        if let Some(content) = &choice.message.content {
+            if !content.is_empty() {
+                contents.push(ContentPart::Text(content.clone()));
+            }
+        }
+
+        let empty = Vec::new();
+        for (position, tool_call) in choice
+            .message
+            .tool_calls
+            .as_ref()
+            .unwrap_or(&empty)
+            .iter()
+            .enumerate()
+        {
+            tool_calls.push(convert_tool_call(tool_call, position));
+        }
+
+        if contents.is_empty() && !tool_calls.is_empty() {
+            ChatEvent::ToolRequest(tool_calls)
+        } else {
+            let metadata = ResponseMetadata {
+                finish_reason: choice.finish_reason.as_ref().map(convert_finish_reason),
+                usage: response.usage.as_ref().map(convert_usage),
+                provider_id: Some(response.id.clone()),
+                timestamp: Some(response.created.to_string()),
+                provider_metadata_json: None,
+                stop_sequence: None,
+                tool_calls: None,
+            };
+
+            ChatEvent::Message(CompleteResponse {
+                id: response.id,
+                content: contents,
+                tool_calls,
+                metadata,
+            })
+        }
+    } else {
+        ChatEvent::Error(Error {
+            code: ErrorCode::InternalError,
+            message: "No choices in response".to_string(),
+            provider_error_json: None,
+        })
+    }
+}
+
+pub fn tool_results_to_messages(
+    tool_results: Vec<(ToolCall, ToolResult)>,
+) -> Vec<crate::client::Message> {
+    let mut messages = Vec::new();
+    for (tool_call, tool_result) in tool_results {
+        messages.push(crate::client::Message::Assistant {
+            content: None,
+            name: None,
+            tool_calls: Some(vec![crate::client::ToolCall::Function {
+                function: crate::client::FunctionCall {
+                    arguments: tool_call.arguments_json,
+                    name: tool_call.name,
+                },
+                id: tool_call.id.clone(),
+                index: None,
+            }]),
+        });
+        let content = match tool_result {
+            ToolResult::Success(success) => success.result_json,
+            ToolResult::Error(failure) => failure.error_message,
+            ToolResult::Image(tool_image) => tool_image_description(&tool_image),
+        };
+        messages.push(crate::client::Message::Tool {
+            name: None,
+            content: crate::client::Content::TextInput(content),
+            tool_call_id: Some(tool_call.id),
+        });
+    }
+    messages
+}
+
+pub fn convert_tool_call(tool_call: &crate::client::ToolCall, position: usize) -> ToolCall {
+    match tool_call {
+        crate::client::ToolCall::Function {
+            function,
+            id,
+            index,
+        } => ToolCall {
+            id: tool_call_id(Some(id), index.unwrap_or(position as u32)),
+            name: function.name.clone(),
+            arguments_json: function.arguments.clone(),
+        },
+    }
+}
+
+/// Converts the content parts of a message. A single text part is sent as a plain string, as
+/// not all models hosted by Together AI accept the list form.
+fn convert_content_parts(contents: Vec<ContentPart>) -> Result<crate::client::Content, Error> {
+    if let [ContentPart::Text(text)] = contents.as_slice() {
+        return Ok(crate::client::Content::TextInput(text.clone()));
+    }
+
+    let mut result = Vec::new();
+    for content in contents {
+        match content {
+            ContentPart::Text(text) => result.push(crate::client::ContentPart::TextInput { text }),
+            ContentPart::Image(ImageReference::Url(image_url)) => {
+                result.push(crate::client::ContentPart::ImageInput {
+                    image_url: crate::client::ImageUrl { url: image_url.url },
+                })
+            }
+            ContentPart::Image(ImageReference::Inline(image_source)) => {
+                validate_image_mime_type(&image_source.mime_type)?;
+                let base64_data = general_purpose::STANDARD.encode(&image_source.data);
+                result.push(crate::client::ContentPart::ImageInput {
+                    image_url: crate::client::ImageUrl {
+                        url: format!("data:{};base64,{base64_data}", image_source.mime_type),
+                    },
+                });
+            }
+            ContentPart::Citation(_) => {}
+        }
+    }
+    Ok(crate::client::Content::List(result))
+}
+
+pub fn convert_finish_reason(value: &crate::client::FinishReason) -> FinishReason {
+    match value {
+        crate::client::FinishReason::Stop | crate::client::FinishReason::Eos => FinishReason::Stop,
+        crate::client::FinishReason::Length => FinishReason::Length,
+        crate::client::FinishReason::ToolCalls | crate::client::FinishReason::FunctionCall => {
+            FinishReason::ToolCalls
+        }
+        crate::client::FinishReason::Other(value) => FinishReason::Other(value.clone()),
+    }
+}
+
+pub fn convert_usage(value: &crate::client::Usage) -> Usage {
+    Usage {
+        input_tokens: Some(value.prompt_tokens),
+        output_tokens: Some(value.completion_tokens),
+        total_tokens: value
+            .total_tokens
+            .or_else(|| value.prompt_tokens.checked_add(value.completion_tokens)),
+    }
+}
+
+fn tool_definition_to_tool(tool: ToolDefinition) -> Result<Tool, Error> {
+    match serde_json::from_str(&tool.parameters_schema) {
+        Ok(value) => Ok(Tool::Function {
+            function: Function {
+                name: tool.name,
+                description: tool.description,
+                parameters: Some(value),
+            },
+        }),
+        Err(error) => Err(Error {
+            code: ErrorCode::InternalError,
+            message: format!("Failed to parse tool parameters for {}: {error}", tool.name),
+            provider_error_json: None,
+        }),
+    }
+}
+
+pub fn process_models_response(models: Vec<Model>) -> Vec<ModelInfo> {
+    models
+        .into_iter()
+        .map(|model| ModelInfo {
+            id: model.id,
+            context_length: model.context_length,
+            capabilities: model.model_type.map(|model_type| vec![model_type]),
+        })
+        .collect()
+}
+
+const TEXT: CapabilitySet = CapabilitySet {
+    images: false,
+    tools: true,
+    streaming: true,
+    json_mode: true,
+};
+
+const VISION: CapabilitySet = CapabilitySet {
+    images: true,
+    tools: true,
+    streaming: true,
+    json_mode: true,
+};
+
+const MODEL_CAPABILITIES: &[(&str, CapabilitySet)] = &[
+    ("Llama-3.2-11B-Vision", VISION),
+    ("Llama-3.2-90B-Vision", VISION),
+    ("Llama-4", VISION),
+    ("Qwen2-VL", VISION),
+    ("Qwen2.5-VL", VISION),
+];
+
+pub fn model_capabilities(model: &str) -> CapabilitySet {
+    capabilities_by_prefix(model, MODEL_CAPABILITIES, TEXT)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::Model;
+    use crate::conversions::{messages_to_request, process_models_response};
+    use crate::TogetherComponent;
+    use golem_llm::durability::ExtendedGuest;
+    use golem_llm::golem::llm::llm::{
+        Config, ContentPart, Kv, Message, ModelInfo, Role, StreamDelta,
+    };
+
+    fn config(provider_options: Vec<(&str, &str)>) -> Config {
+        Config {
+            model: "meta-llama/Llama-3.3-70B-Instruct-Turbo".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stop_sequences: None,
+            tools: vec![],
+            tool_choice: None,
+            provider_options: provider_options
+                .into_iter()
+                .map(|(key, value)| Kv {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+            system: None,
+        }
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("Tell me a story".to_string())],
+        }]
+    }
+
+    #[test]
+    fn sampling_options() {
+        let request = messages_to_request(
+            messages(),
+            config(vec![
+                ("repetition_penalty", "1.1"),
+                ("min_p", "0.05"),
+                ("top_k", "40"),
+                ("top_p", "0.9"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(request.repetition_penalty, Some(1.1));
+        assert_eq!(request.min_p, Some(0.05));
+        assert_eq!(request.top_k, Some(40));
+        assert_eq!(request.top_p, Some(0.9));
+
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("repetition_penalty").is_some());
+        assert!(json.get("min_p").is_some());
+        assert_eq!(json["top_k"], 40);
+        assert_eq!(json["messages"][0]["content"], "Tell me a story");
+    }
+
+    #[test]
+    fn sampling_options_are_omitted_when_unset() {
+        let request = messages_to_request(
+            messages(),
+            config(vec![("repetition_penalty", "not a number")]),
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("repetition_penalty").is_none());
+        assert!(json.get("min_p").is_none());
+        assert!(json.get("top_k").is_none());
+    }
+
+    #[test]
+    fn models_response() {
+        let models: Vec<Model> = serde_json::from_str(
+            r#"[
+                {
+                    "id": "meta-llama/Llama-3.3-70B-Instruct-Turbo",
+                    "object": "model",
+                    "created": 1733443200,
+                    "type": "chat",
+                    "display_name": "Meta Llama 3.3 70B Instruct Turbo",
+                    "organization": "Meta",
+                    "context_length": 131072
+                }
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            process_models_response(models),
+            vec![ModelInfo {
+                id: "meta-llama/Llama-3.3-70B-Instruct-Turbo".to_string(),
+                context_length: Some(131072),
+                capabilities: Some(vec!["chat".to_string()]),
+            }]
+        );
+    }
+
+    #[test]
+    fn retry_prompt_is_accepted() {
+        let message = |role: Role, text: &str| Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        };
+        let original_messages = vec![
+            message(Role::System, "Answer briefly"),
+            message(Role::User, "What is the weather like?"),
+            message(Role::Assistant, "Which city?"),
+            message(Role::User, "Berlin"),
+        ];
+        let partial_result = vec![StreamDelta {
+            content: Some(vec![ContentPart::Text("It is sunny".to_string())]),
+            tool_calls: None,
+            reasoning: None,
+            choice_index: None,
+        }];
+
+        let messages = TogetherComponent::retry_prompt(&original_messages, &partial_result);
+
+        assert!(messages_to_request(messages, config(vec![])).is_ok());
+    }
+}
@@ -0,0 +1,513 @@
+mod client;
+mod conversions;
+
+use crate::client::{ChatCompletionChunk, CompletionsApi, CompletionsRequest, Usage};
+use crate::conversions::{
+    convert_finish_reason, convert_usage, messages_to_request, model_capabilities,
+    process_models_response, process_response, tool_results_to_messages,
+};
+use golem_llm::cache::{cache_ttl, cached_response, response_cache_key};
+use golem_llm::chat_stream::{LlmChatStream, LlmChatStreamState};
+use golem_llm::concurrency::acquire_request_slot;
+use golem_llm::config::{stream_replay_file, with_config_key};
+use golem_llm::context::reserve_context_for_tool_results;
+use golem_llm::dry_run::{dry_run_response, is_dry_run, with_api_key_unless_dry_run};
+use golem_llm::durability::{retry_prompt_with_style, DurableLLM, ExtendedGuest, RetryPromptStyle};
+use golem_llm::error::{reject_empty_response, unsupported};
+use golem_llm::event_source::EventSource;
+use golem_llm::golem::llm::llm::{
+    BatchInfo, BatchRequest, BatchResult, CapabilitySet, ChatEvent, ChatStream, Config,
+    ContentPart, EmbeddingResponse, Error, FinishReason, Guest, ImageReference, Message, ModelInfo,
+    ResponseMetadata, StreamDelta, StreamEvent, ToolCall, ToolResult,
+};
+use golem_llm::logging::Redacted;
+use golem_llm::raw_body::{include_raw_response, with_raw_response};
+use golem_llm::structured_output::{response_schema, validate_structured_output, ResponseSchema};
+use golem_llm::summarize::summarize_history;
+use golem_llm::tool_calls::{reject_malformed_tool_calls, tool_call_id};
+use golem_llm::usage::sum_usage;
+use golem_llm::LOGGING_STATE;
+use golem_rust::wasm_rpc::Pollable;
+use log::trace;
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+struct JsonFragment {
+    id: String,
+    name: String,
+    json: String,
+}
+
+struct TogetherChatStream {
+    stream: RefCell<Option<EventSource>>,
+    failure: Option<Error>,
+    finished: RefCell<bool>,
+    id: RefCell<Option<String>>,
+    finish_reason: RefCell<Option<FinishReason>>,
+    usage: RefCell<Option<Usage>>,
+    /// Tool call fragments received so far by the index of their call
+    json_fragments: RefCell<BTreeMap<u32, JsonFragment>>,
+}
+
+impl TogetherChatStream {
+    pub fn new(stream: EventSource) -> LlmChatStream<Self> {
+        LlmChatStream::new(TogetherChatStream {
+            stream: RefCell::new(Some(stream)),
+            failure: None,
+            finished: RefCell::new(false),
+            id: RefCell::new(None),
+            finish_reason: RefCell::new(None),
+            usage: RefCell::new(None),
+            json_fragments: RefCell::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn failed(error: Error) -> LlmChatStream<Self> {
+        LlmChatStream::new(TogetherChatStream {
+            stream: RefCell::new(None),
+            failure: Some(error),
+            finished: RefCell::new(false),
+            id: RefCell::new(None),
+            finish_reason: RefCell::new(None),
+            usage: RefCell::new(None),
+            json_fragments: RefCell::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn replayed() -> LlmChatStream<Self> {
+        LlmChatStream::new(TogetherChatStream {
+            stream: RefCell::new(None),
+            failure: None,
+            finished: RefCell::new(false),
+            id: RefCell::new(None),
+            finish_reason: RefCell::new(None),
+            usage: RefCell::new(None),
+            json_fragments: RefCell::new(BTreeMap::new()),
+        })
+    }
+}
+
+impl LlmChatStreamState for TogetherChatStream {
+    fn failure(&self) -> &Option<Error> {
+        &self.failure
+    }
+
+    fn is_finished(&self) -> bool {
+        *self.finished.borrow()
+    }
+
+    fn set_finished(&self) {
+        *self.finished.borrow_mut() = true;
+    }
+
+    fn stream(&self) -> Ref<Option<EventSource>> {
+        self.stream.borrow()
+    }
+
+    fn stream_mut(&self) -> RefMut<Option<EventSource>> {
+        self.stream.borrow_mut()
+    }
+
+    fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
+        trace!("Received raw stream event: {}", Redacted(&raw));
+        let chunk: ChatCompletionChunk = serde_json::from_str(raw)
+            .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
+
+        // Together AI sends the usage together with the finish reason in the last chunk before
+        // the stream is terminated, they are reported in the finish event emitted at its end
+        *self.id.borrow_mut() = Some(chunk.id);
+        if let Some(usage) = chunk.usage {
+            *self.usage.borrow_mut() = Some(usage);
+        }
+
+        if let Some(choice) = chunk.choices.into_iter().next() {
+            if let Some(finish_reason) = choice.finish_reason {
+                *self.finish_reason.borrow_mut() = Some(convert_finish_reason(&finish_reason));
+            }
+
+            let content = choice
+                .delta
+                .content
+                .filter(|content| !content.is_empty())
+                .map(|text| vec![ContentPart::Text(text)]);
+            let tool_calls = choice.delta.tool_calls.map(|calls| {
+                let mut fragments = self.json_fragments.borrow_mut();
+                calls
+                    .iter()
+                    .filter_map(|call| {
+                        let fragment =
+                            fragments.entry(call.index).or_insert_with(|| JsonFragment {
+                                id: tool_call_id(call.id.as_deref(), call.index),
+                                name: String::new(),
+                                json: String::new(),
+                            });
+                        if let Some(function) = &call.function {
+                            if let Some(name) = &function.name {
+                                fragment.name.clone_from(name);
+                            }
+                            fragment.json.push_str(&function.arguments);
+                        }
+                        (!fragment.name.is_empty()).then(|| ToolCall {
+                            id: fragment.id.clone(),
+                            name: fragment.name.clone(),
+                            arguments_json: fragment.json.clone(),
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            });
+            let tool_calls = tool_calls.filter(|calls| !calls.is_empty());
+
+            if content.is_some() || tool_calls.is_some() {
+                return Ok(Some(StreamEvent::Delta(StreamDelta {
+                    content,
+                    tool_calls,
+                    reasoning: None,
+                    choice_index: None,
+                })));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn pending_finish(&self) -> ResponseMetadata {
+        ResponseMetadata {
+            finish_reason: self.finish_reason.borrow().clone(),
+            usage: self.usage.borrow().as_ref().map(convert_usage),
+            provider_id: self.id.borrow().clone(),
+            timestamp: None,
+            provider_metadata_json: None,
+            stop_sequence: None,
+            tool_calls: None,
+        }
+    }
+
+    fn tool_call_order(&self) -> Vec<String> {
+        self.json_fragments
+            .borrow()
+            .values()
+            .map(|fragment| fragment.id.clone())
+            .collect()
+    }
+}
+
+struct TogetherComponent;
+
+impl TogetherComponent {
+    const ENV_VAR_NAME: &'static str = "TOGETHER_API_KEY";
+
+    fn request(
+        client: CompletionsApi,
+        request: CompletionsRequest,
+        dry_run: bool,
+        include_raw_response: bool,
+        cache_ttl: Option<Duration>,
+        response_schema: Option<ResponseSchema>,
+    ) -> ChatEvent {
+        if dry_run {
+            return dry_run_response("together", &request);
+        }
+
+        let cache_key = response_cache_key(cache_ttl, &client.cache_scope(), &request);
+        cached_response(cache_key, || {
+            validate_structured_output(response_schema.as_ref(), || {
+                match client.send_messages(request.clone()) {
+                    Ok(response) => with_raw_response(
+                        reject_malformed_tool_calls(reject_empty_response(process_response(
+                            response.body,
+                        ))),
+                        include_raw_response.then_some(response.raw),
+                    ),
+                    Err(err) => ChatEvent::Error(err),
+                }
+            })
+        })
+    }
+
+    fn streaming_request(
+        client: CompletionsApi,
+        mut request: CompletionsRequest,
+    ) -> LlmChatStream<TogetherChatStream> {
+        request.stream = Some(true);
+        match client.stream_send_messages(request) {
+            Ok(stream) => TogetherChatStream::new(stream),
+            Err(err) => TogetherChatStream::failed(err),
+        }
+    }
+}
+
+impl Guest for TogetherComponent {
+    type ChatStream = LlmChatStream<TogetherChatStream>;
+
+    fn send(messages: Vec<Message>, config: Config) -> ChatEvent {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+        let dry_run = is_dry_run(&config);
+        let include_raw_response = include_raw_response(&config);
+        let cache_ttl = cache_ttl(&config);
+        let response_schema = response_schema(&config).ok().flatten();
+
+        with_api_key_unless_dry_run(
+            Self::ENV_VAR_NAME,
+            dry_run,
+            ChatEvent::Error,
+            |together_api_key| {
+                let _permit = acquire_request_slot(&config, "together", &together_api_key);
+                let client = CompletionsApi::new(together_api_key, &config);
+
+                match messages_to_request(messages, config) {
+                    Ok(request) => Self::request(
+                        client,
+                        request,
+                        dry_run,
+                        include_raw_response,
+                        cache_ttl,
+                        response_schema,
+                    ),
+                    Err(err) => ChatEvent::Error(err),
+                }
+            },
+        )
+    }
+
+    fn continue_(
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
+    ) -> ChatEvent {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+        let dry_run = is_dry_run(&config);
+        let include_raw_response = include_raw_response(&config);
+        let cache_ttl = cache_ttl(&config);
+        let response_schema = response_schema(&config).ok().flatten();
+
+        with_api_key_unless_dry_run(
+            Self::ENV_VAR_NAME,
+            dry_run,
+            ChatEvent::Error,
+            |together_api_key| {
+                let _permit = acquire_request_slot(&config, "together", &together_api_key);
+                let client = CompletionsApi::new(together_api_key, &config);
+
+                match messages_to_request(
+                    messages,
+                    reserve_context_for_tool_results(config, &tool_results),
+                ) {
+                    Ok(mut request) => {
+                        request
+                            .messages
+                            .extend(tool_results_to_messages(tool_results));
+                        Self::request(
+                            client,
+                            request,
+                            dry_run,
+                            include_raw_response,
+                            cache_ttl,
+                            response_schema,
+                        )
+                    }
+                    Err(err) => ChatEvent::Error(err),
+                }
+            },
+        )
+    }
+
+    fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
+        ChatStream::new(Self::unwrapped_stream(messages, config))
+    }
+
+    fn embed(_inputs: Vec<String>, _config: Config) -> Result<EmbeddingResponse, Error> {
+        Err(unsupported(
+            "Embeddings are not supported by the Together AI provider",
+        ))
+    }
+
+    fn generate_image(_prompt: String, _config: Config) -> Result<Vec<ImageReference>, Error> {
+        Err(unsupported(
+            "Image generation is not supported by the Together AI provider",
+        ))
+    }
+
+    fn list_models(config: Config) -> Result<Vec<ModelInfo>, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        with_config_key(Self::ENV_VAR_NAME, Err, |together_api_key| {
+            let client = CompletionsApi::new(together_api_key, &config);
+            client.list_models().map(process_models_response)
+        })
+    }
+
+    fn validate_credentials(config: Config) -> Result<(), Error> {
+        Self::list_models(config).map(|_| ())
+    }
+
+    fn capabilities(config: Config) -> CapabilitySet {
+        model_capabilities(&config.model)
+    }
+
+    fn batch_submit(_requests: Vec<BatchRequest>, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Together AI provider",
+        ))
+    }
+
+    fn batch_status(_batch_id: String, _config: Config) -> Result<BatchInfo, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Together AI provider",
+        ))
+    }
+
+    fn batch_results(_batch_id: String, _config: Config) -> Result<Vec<BatchResult>, Error> {
+        Err(unsupported(
+            "Batches are not supported by the Together AI provider",
+        ))
+    }
+
+    fn summarize_history(
+        messages: Vec<Message>,
+        config: Config,
+        keep_last_n: u32,
+    ) -> Result<Vec<Message>, Error> {
+        summarize_history(messages, config, keep_last_n, Self::send)
+    }
+
+    fn sum_usage(
+        usages: Vec<golem_llm::golem::llm::llm::Usage>,
+    ) -> golem_llm::golem::llm::llm::Usage {
+        sum_usage(usages)
+    }
+}
+
+impl ExtendedGuest for TogetherComponent {
+    fn unwrapped_stream(
+        messages: Vec<Message>,
+        config: Config,
+    ) -> LlmChatStream<TogetherChatStream> {
+        Self::unwrapped_continue_stream(messages, Vec::new(), config)
+    }
+
+    fn unwrapped_continue_stream(
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
+    ) -> LlmChatStream<TogetherChatStream> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        if stream_replay_file(&config).is_some() {
+            return TogetherChatStream::replayed().with_stream_options(&config);
+        }
+
+        with_config_key(
+            Self::ENV_VAR_NAME,
+            TogetherChatStream::failed,
+            |together_api_key| {
+                let permit = acquire_request_slot(&config, "together", &together_api_key);
+                let client = CompletionsApi::new(together_api_key, &config);
+
+                match messages_to_request(
+                    messages,
+                    reserve_context_for_tool_results(config.clone(), &tool_results),
+                ) {
+                    Ok(mut request) => {
+                        request
+                            .messages
+                            .extend(tool_results_to_messages(tool_results));
+                        Self::streaming_request(client, request)
+                            .with_request_permit(permit)
+                            .with_stream_options(&config)
+                    }
+                    Err(err) => TogetherChatStream::failed(err),
+                }
+            },
+        )
+    }
+
+    fn retry_prompt(original_messages: &[Message], partial_result: &[StreamDelta]) -> Vec<Message> {
+        // The chat templates of many open models hosted by Together AI only accept a single
+        // leading system message
+        retry_prompt_with_style(
+            original_messages,
+            partial_result,
+            RetryPromptStyle::UserMessage,
+        )
+    }
+
+    fn subscribe(stream: &Self::ChatStream) -> Pollable {
+        stream.subscribe()
+    }
+
+    fn idle_timer(stream: &Self::ChatStream) -> Option<Pollable> {
+        Some(stream.idle_timer())
+    }
+}
+
+type DurableTogetherComponent = DurableLLM<TogetherComponent>;
+
+golem_llm::export_llm!(DurableTogetherComponent with_types_in golem_llm);
+
+#[cfg(test)]
+mod tests {
+    use crate::TogetherChatStream;
+    use golem_llm::golem::llm::llm::{FinishReason, GuestChatStream, StreamEvent, ToolCall};
+
+    #[test]
+    fn tool_call_fragments_are_assembled_by_index() {
+        let recording_path =
+            std::env::temp_dir().join(format!("together-tool-call-{}.sse", std::process::id()));
+        let chunks = [
+            r#"{"index":0,"delta":{"role":"assistant","tool_calls":[{"index":0,"id":"call_7f3a","type":"function","function":{"name":"get_weather","arguments":""}}]},"finish_reason":null}"#,
+            r#"{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"city\":"}}]},"finish_reason":null}"#,
+            r#"{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"Paris\"}"}}]},"finish_reason":null}"#,
+            r#"{"index":0,"delta":{},"finish_reason":"tool_calls"}"#,
+        ];
+        let mut recording = chunks
+            .iter()
+            .map(|choice| {
+                format!(
+                    r#"data: {{"id":"chat-1","model":"meta-llama/Llama-3.3-70B-Instruct-Turbo","choices":[{choice}]}}"#
+                ) + "\n\n"
+            })
+            .collect::<String>();
+        recording.push_str("data: [DONE]\n\n");
+        std::fs::write(&recording_path, recording).unwrap();
+
+        let stream = TogetherChatStream::replayed().with_replay(recording_path.clone());
+        let mut events = Vec::new();
+        loop {
+            match stream.get_next() {
+                Some(batch) if batch.is_empty() => break,
+                Some(batch) => events.extend(batch),
+                None => {}
+            }
+        }
+        std::fs::remove_file(recording_path).unwrap();
+
+        let delta_ids = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::Delta(delta) => delta.tool_calls.as_ref(),
+                _ => None,
+            })
+            .flatten()
+            .map(|tool_call| tool_call.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(delta_ids, vec!["call_7f3a"; 3]);
+
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| matches!(event, StreamEvent::ToolCallComplete(_)))
+                .collect::<Vec<_>>(),
+            vec![&StreamEvent::ToolCallComplete(ToolCall {
+                id: "call_7f3a".to_string(),
+                name: "get_weather".to_string(),
+                arguments_json: r#"{"city":"Paris"}"#.to_string(),
+            })]
+        );
+        assert!(matches!(
+            events.last(),
+            Some(StreamEvent::Finish(metadata))
+                if metadata.finish_reason == Some(FinishReason::ToolCalls)
+        ));
+    }
+}
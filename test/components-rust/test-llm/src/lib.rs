@@ -19,6 +19,10 @@ const MODEL: &'static str = "grok-3-beta";
 const MODEL: &'static str = "openrouter/auto";
 #[cfg(feature = "ollama")]  
 const MODEL: &'static str = "qwen3:1.7b";
+#[cfg(feature = "perplexity")]
+const MODEL: &'static str = "sonar";
+#[cfg(feature = "together")]
+const MODEL: &'static str = "meta-llama/Llama-3.3-70B-Instruct-Turbo";
 
 #[cfg(feature = "openai")]
 const IMAGE_MODEL: &'static str = "gpt-4o-mini";
@@ -30,6 +34,10 @@ const IMAGE_MODEL: &'static str = "grok-2-vision-latest";
 const IMAGE_MODEL: &'static str = "openrouter/auto";
 #[cfg(feature = "ollama")]  
 const IMAGE_MODEL: &'static str = "gemma3:4b";
+#[cfg(feature = "perplexity")]
+const IMAGE_MODEL: &'static str = "sonar-pro";
+#[cfg(feature = "together")]
+const IMAGE_MODEL: &'static str = "meta-llama/Llama-4-Maverick-17B-128E-Instruct-FP8";
 
 impl Guest for Component {
     /// test1 demonstrates a simple, non-streaming text question-answer interaction with the LLM.